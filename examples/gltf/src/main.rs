@@ -64,6 +64,8 @@ fn main() {
         vsync: rend3::VSyncMode::On,
         size: [window_size.width, window_size.height],
         ambient: glam::Vec4::default(),
+        shadow_cascades: rend3::ShadowCascadeOptions::default(),
+        shadow_filter: rend3::ShadowFilter::default(),
     };
 
     let renderer = pollster::block_on(rend3::RendererBuilder::new(options.clone()).window(&window).build()).unwrap();
@@ -85,6 +87,8 @@ fn main() {
             // Need to flip gltf's coords and winding order
             transform: glam::Mat4::from_scale(glam::Vec3::new(1.0, 1.0, -1.0)),
         },
+        skeleton: None,
+        morph_weights: Vec::new(),
     };
     let _object_handle = renderer.add_object(object);
 
@@ -105,6 +109,9 @@ fn main() {
         intensity: 10.0,
         // Direction will be normalized
         direction: glam::Vec3::new(-1.0, -4.0, 2.0),
+        shadow_resolution: 2048,
+        depth_bias: 1.0,
+        normal_bias: 1.0,
     });
 
     event_loop.run(move |event, _, control| match event {
@@ -133,7 +140,12 @@ fn main() {
             let internal_renderbuffer_size = options.size;
 
             // Default set of rendering commands using the default shaders.
-            let render_list = rend3_list::default_render_list(renderer.mode(), internal_renderbuffer_size, &pipelines);
+            let render_list = rend3_list::default_render_list(
+                renderer.mode(),
+                internal_renderbuffer_size,
+                &pipelines,
+                rend3_list::TransparencyMode::Sorted,
+            );
 
             // Dispatch a render!
             let handle = renderer.render(render_list, rend3::RendererOutput::InternalSwapchain);
@@ -70,6 +70,8 @@ fn main() {
         vsync: rend3::VSyncMode::Off,
         size: [window_size.width, window_size.height],
         ambient: glam::Vec4::default(),
+        shadow_cascades: rend3::ShadowCascadeOptions::default(),
+        shadow_filter: rend3::ShadowFilter::default(),
     };
 
     // We want to control the swapchain, so we don't hand rend3 a window, we hand it an image later.
@@ -138,6 +140,8 @@ fn main() {
         transform: rend3::datatypes::AffineTransform {
             transform: glam::Mat4::IDENTITY,
         },
+        skeleton: None,
+        morph_weights: Vec::new(),
     };
     let _object_handle = renderer.add_object(object);
 
@@ -158,6 +162,9 @@ fn main() {
         intensity: 10.0,
         // Direction will be normalized
         direction: glam::Vec3::new(-1.0, -4.0, 2.0),
+        shadow_resolution: 2048,
+        depth_bias: 1.0,
+        normal_bias: 1.0,
     });
 
     let mut last_frame = Instant::now();
@@ -214,8 +221,12 @@ fn main() {
                 let internal_renderbuffer_size = options.size;
 
                 // Default set of rendering commands using the default shaders.
-                let render_list =
-                    rend3_list::default_render_list(renderer.mode(), internal_renderbuffer_size, &pipelines);
+                let render_list = rend3_list::default_render_list(
+                    renderer.mode(),
+                    internal_renderbuffer_size,
+                    &pipelines,
+                    rend3_list::TransparencyMode::Sorted,
+                );
 
                 // Get our swapchain image
                 let image = Arc::new(swapchain.get_current_frame().unwrap());
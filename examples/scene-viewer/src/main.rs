@@ -2,7 +2,7 @@ use fnv::FnvBuildHasher;
 use glam::{Vec3, Vec3A};
 use pico_args::Arguments;
 use rend3::{
-    datatypes::{Camera, CameraProjection, DirectionalLight, RendererTextureFormat, Texture},
+    datatypes::{Camera, CameraProjection, DirectionalLight, MipmapMode, RendererTextureFormat, Texture},
     Renderer,
 };
 use rend3_list::{DefaultPipelines, DefaultShaders};
@@ -47,6 +47,7 @@ fn load_skybox(renderer: &Renderer) -> Result<(), Box<dyn std::error::Error>> {
         data: image,
         label: Some("background".into()),
         mip_levels: mips,
+        mip_mode: MipmapMode::Manual,
     });
     renderer.set_background_texture(handle);
     Ok(())
@@ -54,34 +55,20 @@ fn load_skybox(renderer: &Renderer) -> Result<(), Box<dyn std::error::Error>> {
 
 fn load_gltf(renderer: &Renderer, location: String) -> rend3_gltf::LoadedGltfScene {
     let path = Path::new(&location);
-    let binary_path = path.with_extension("bin");
     let parent = path.parent().unwrap();
 
     println!("Reading gltf file: {}", path.display());
     let gltf_data =
         std::fs::read(&path).unwrap_or_else(|e| panic!("tried to load gltf file {}: {}", path.display(), e));
-    println!("Reading gltf sidecar file file: {}", binary_path.display());
-    let bin_data = std::fs::read(&binary_path).unwrap_or_else(|e| {
-        panic!(
-            "tried to load gltf binary sidecar file {}: {}",
-            binary_path.display(),
-            e
-        )
-    });
 
-    pollster::block_on(rend3_gltf::load_gltf(
-        renderer,
-        &gltf_data,
-        &bin_data,
-        move |tex_path| {
-            println!("Reading image file: {}", tex_path);
-            let tex_path = tex_path.to_owned();
-            async move {
-                let tex_resolved = parent.join(&tex_path);
-                async_std::fs::read(tex_resolved).await
-            }
-        },
-    ))
+    pollster::block_on(rend3_gltf::load_gltf(renderer, &gltf_data, move |file_path| {
+        println!("Reading file: {}", file_path);
+        let file_path = file_path.to_owned();
+        async move {
+            let file_resolved = parent.join(&file_path);
+            async_std::fs::read(file_resolved).await
+        }
+    }))
     .unwrap()
 }
 
@@ -108,6 +95,14 @@ fn extract_mode(value: &str) -> Result<rend3::RendererMode, &'static str> {
     })
 }
 
+fn extract_transparency(value: &str) -> Result<rend3_list::TransparencyMode, &'static str> {
+    Ok(match value.to_lowercase().as_str() {
+        "sorted" | "s" => rend3_list::TransparencyMode::Sorted,
+        "weighted" | "oit" | "w" => rend3_list::TransparencyMode::WeightedBlended,
+        _ => return Err("transparency mode requested but not found"),
+    })
+}
+
 fn main() {
     wgpu_subscriber::initialize_default_subscriber(None);
 
@@ -118,6 +113,9 @@ fn main() {
         .ok()
         .map(|s: String| s.to_lowercase());
     let desired_mode = args.value_from_fn(["-m", "--mode"], extract_mode).ok();
+    let transparency = args
+        .value_from_fn(["-t", "--transparency"], extract_transparency)
+        .unwrap_or(rend3_list::TransparencyMode::Sorted);
     let file_to_load: Option<String> = args.free_from_str().ok();
 
     rend3::span_transfer!(_ -> main_thread_span, INFO, "Main Thread Setup");
@@ -141,6 +139,8 @@ fn main() {
         vsync: rend3::VSyncMode::Off,
         size: [window_size.width, window_size.height],
         ambient: glam::Vec4::default(),
+        shadow_cascades: rend3::ShadowCascadeOptions::default(),
+        shadow_filter: rend3::ShadowFilter::default(),
     };
 
     let renderer = pollster::block_on(
@@ -158,7 +158,7 @@ fn main() {
 
     rend3::span_transfer!(renderer_span -> loading_span, INFO, "Loading resources");
 
-    load_gltf(
+    let scene = load_gltf(
         &renderer,
         file_to_load.unwrap_or_else(|| concat!(env!("CARGO_MANIFEST_DIR"), "/data/scene.gltf").to_owned()),
     );
@@ -168,14 +168,24 @@ fn main() {
         color: Vec3::ONE,
         intensity: 10.0,
         direction: Vec3::new(-1.0, -1.0, 0.0),
+        shadow_resolution: 2048,
+        depth_bias: 1.0,
+        normal_bias: 1.0,
     });
     rend3::span_transfer!(loading_span -> _);
     rend3::span_transfer!(main_thread_span -> _);
 
     let mut scancode_status = HashMap::with_hasher(FnvBuildHasher::default());
 
-    let mut camera_location = Camera::default();
+    // Start from the first perspective camera of the scene, as the controls only work with perspective cameras.
+    let mut camera_location = scene
+        .cameras
+        .iter()
+        .map(|camera| camera.data)
+        .find(|camera| matches!(camera.projection, CameraProjection::Projection { .. }))
+        .unwrap_or_default();
 
+    let timestamp_start = Instant::now();
     let mut timestamp_last_second = Instant::now();
     let mut timestamp_last_frame = Instant::now();
 
@@ -208,6 +218,12 @@ fn main() {
 
             timestamp_last_frame = now;
 
+            // Loop the first animation of the scene
+            if let Some(animation) = scene.animations.first().filter(|a| a.duration > 0.0) {
+                let time = (now - timestamp_start).as_secs_f32() % animation.duration;
+                animation.apply(&renderer, &scene.nodes, time);
+            }
+
             let forward = {
                 if let CameraProjection::Projection { yaw, pitch, .. } = camera_location.projection {
                     Vec3A::new(yaw.sin() * pitch.cos(), -pitch.sin(), yaw.cos() * pitch.cos())
@@ -309,6 +325,7 @@ fn main() {
                     (options.size[1] as f32 * 1.0) as u32,
                 ],
                 &pipelines,
+                transparency,
             );
             let handle = renderer.render(list, rend3::RendererOutput::InternalSwapchain);
 
@@ -0,0 +1,413 @@
+use crate::{root_transform, GltfLoadError, GltfSceneGraphNodes, Node};
+use fnv::FnvHashMap;
+use glam::{Mat4, Quat, Vec3, Vec4};
+use rend3::{datatypes::AffineTransform, scene::SceneGraph, Renderer};
+use std::ops::{Add, Mul};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    CubicSpline,
+}
+
+/// Keyframe values of a channel. Cubic spline channels store an in-tangent, value and out-tangent per keyframe, in
+/// that order.
+#[derive(Debug, Clone)]
+pub enum ChannelValues {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quat>),
+    Scale(Vec<Vec3>),
+    /// The weight of every morph target of the node's mesh, keyframe after keyframe.
+    MorphWeights(Vec<f32>),
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationChannel {
+    /// Index of the gltf node this channel animates.
+    pub node: usize,
+    pub interpolation: Interpolation,
+    /// Keyframe times in seconds, ascending.
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub name: Option<String>,
+    pub channels: Vec<AnimationChannel>,
+    /// Time of the last keyframe of any channel in seconds.
+    pub duration: f32,
+}
+
+/// Parts of a node's local transform overridden by the channels of an animation.
+#[derive(Debug, Default, Clone)]
+struct NodePose {
+    translation: Option<Vec3>,
+    rotation: Option<Quat>,
+    scale: Option<Vec3>,
+    morph_weights: Option<Vec<f32>>,
+}
+
+impl Animation {
+    /// World transforms of every node in the tree at `time` seconds, indexed by gltf node index.
+    ///
+    /// Each channel holds its first and last keyframe outside of its time range, so wrap time with
+    /// [`Animation::duration`] to loop the clip.
+    pub fn sample_world_transforms(&self, nodes: &[Node], time: f32) -> FnvHashMap<usize, Mat4> {
+        let poses = self.sample_poses(time);
+
+        let mut transforms = FnvHashMap::default();
+        walk_nodes(nodes, &poses, root_transform(), false, &mut |node, transform, _, _| {
+            transforms.insert(node.index, transform);
+        });
+        transforms
+    }
+
+    /// Samples the animation at `time` seconds and moves every object of the animated nodes and their children. Morph
+    /// weights are set on the objects of nodes with animated weights.
+    ///
+    /// Time behaves as in [`Animation::sample_world_transforms`].
+    pub fn apply<TLD>(&self, renderer: &Renderer<TLD>, nodes: &[Node], time: f32)
+    where
+        TLD: 'static,
+    {
+        let poses = self.sample_poses(time);
+
+        walk_nodes(
+            nodes,
+            &poses,
+            root_transform(),
+            false,
+            &mut |node, transform, animated, pose| {
+                if animated {
+                    for &object in &node.objects {
+                        renderer.set_object_transform(object, AffineTransform { transform });
+                    }
+                }
+                if let Some(weights) = pose.and_then(|pose| pose.morph_weights.as_ref()) {
+                    for &object in &node.objects {
+                        renderer.set_object_morph_weights(object, weights.clone());
+                    }
+                }
+            },
+        );
+    }
+
+    /// Samples the animation at `time` seconds and sets the local transforms of the animated nodes in a scene graph
+    /// made by [`add_to_scene_graph`](crate::add_to_scene_graph). [`SceneGraph::update`] then moves their objects.
+    /// Morph weights are set on the objects directly.
+    ///
+    /// Time behaves as in [`Animation::sample_world_transforms`].
+    pub fn apply_to_scene_graph<TLD>(
+        &self,
+        renderer: &Renderer<TLD>,
+        graph: &mut SceneGraph,
+        nodes: &GltfSceneGraphNodes,
+        time: f32,
+    ) where
+        TLD: 'static,
+    {
+        for (node, pose) in self.sample_poses(time) {
+            let handle = match nodes.nodes.get(&node) {
+                Some(&handle) => handle,
+                None => continue,
+            };
+            if pose.has_transform() {
+                let local_transform = pose.apply(graph.local_transform(handle));
+                graph.set_local_transform(handle, local_transform);
+            }
+            if let Some(ref weights) = pose.morph_weights {
+                for &object in graph.objects(handle) {
+                    renderer.set_object_morph_weights(object, weights.clone());
+                }
+            }
+        }
+    }
+
+    fn sample_poses(&self, time: f32) -> FnvHashMap<usize, NodePose> {
+        let mut poses: FnvHashMap<usize, NodePose> = FnvHashMap::default();
+        for channel in &self.channels {
+            let pose = poses.entry(channel.node).or_default();
+            let times = &channel.times;
+            let interpolation = channel.interpolation;
+            match channel.values {
+                ChannelValues::Translation(ref values) => {
+                    pose.translation = Some(sample_keyframes(times, interpolation, time, |i| values[i], Vec3::lerp));
+                }
+                ChannelValues::Rotation(ref values) => {
+                    // Rotations are sampled as vectors so the cubic spline math can be shared, then renormalized.
+                    let rotation = sample_keyframes(
+                        times,
+                        interpolation,
+                        time,
+                        |i| Vec4::from(values[i]),
+                        |a, b, t| Vec4::from(quat_from_vec4(a).slerp(quat_from_vec4(b), t)),
+                    );
+                    pose.rotation = Some(quat_from_vec4(rotation).normalize());
+                }
+                ChannelValues::Scale(ref values) => {
+                    pose.scale = Some(sample_keyframes(times, interpolation, time, |i| values[i], Vec3::lerp));
+                }
+                ChannelValues::MorphWeights(ref values) => {
+                    let values_per_keyframe = match interpolation {
+                        Interpolation::CubicSpline => 3,
+                        Interpolation::Step | Interpolation::Linear => 1,
+                    };
+                    let target_count = values.len() / (times.len() * values_per_keyframe);
+                    // Each weight is sampled on its own, as if it were a channel of its own.
+                    let weights = (0..target_count)
+                        .map(|target| {
+                            sample_keyframes(
+                                times,
+                                interpolation,
+                                time,
+                                |i| values[i * target_count + target],
+                                |a, b, t| a + (b - a) * t,
+                            )
+                        })
+                        .collect();
+                    pose.morph_weights = Some(weights);
+                }
+            }
+        }
+        poses
+    }
+}
+
+impl NodePose {
+    fn has_transform(&self) -> bool {
+        self.translation.is_some() || self.rotation.is_some() || self.scale.is_some()
+    }
+
+    /// Replaces the animated parts of a local transform.
+    fn apply(&self, local_transform: Mat4) -> Mat4 {
+        // Animated nodes are guaranteed by the gltf spec to not have a matrix with shear.
+        let (scale, rotation, translation) = local_transform.to_scale_rotation_translation();
+        Mat4::from_scale_rotation_translation(
+            self.scale.unwrap_or(scale),
+            self.rotation.unwrap_or(rotation),
+            self.translation.unwrap_or(translation),
+        )
+    }
+}
+
+fn quat_from_vec4(v: Vec4) -> Quat {
+    Quat::from_xyzw(v.x, v.y, v.z, v.w)
+}
+
+/// Calls `func` with every node, its world transform, if it or any of its parents are animated and its animated pose.
+fn walk_nodes(
+    nodes: &[Node],
+    poses: &FnvHashMap<usize, NodePose>,
+    parent_transform: Mat4,
+    parent_animated: bool,
+    func: &mut impl FnMut(&Node, Mat4, bool, Option<&NodePose>),
+) {
+    for node in nodes {
+        let pose = poses.get(&node.index);
+        let has_transform = pose.map_or(false, NodePose::has_transform);
+        let local_transform = match pose {
+            Some(pose) if has_transform => pose.apply(node.local_transform),
+            _ => node.local_transform,
+        };
+        let transform = parent_transform * local_transform;
+        let animated = parent_animated || has_transform;
+
+        func(node, transform, animated, pose);
+
+        walk_nodes(&node.children, poses, transform, animated, func);
+    }
+}
+
+/// Samples keyframe values at `time`. `value` returns the value at an index of the output array and `mix` linearly
+/// interpolates between two values.
+fn sample_keyframes<T>(
+    times: &[f32],
+    interpolation: Interpolation,
+    time: f32,
+    value: impl Fn(usize) -> T,
+    mix: impl Fn(T, T, f32) -> T,
+) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    // Cubic splines have the value between the two tangents of each keyframe.
+    let keyframe_value = |keyframe: usize| match interpolation {
+        Interpolation::CubicSpline => value(keyframe * 3 + 1),
+        Interpolation::Step | Interpolation::Linear => value(keyframe),
+    };
+
+    let next = match times.iter().position(|&t| t > time) {
+        Some(0) => return keyframe_value(0),
+        None => return keyframe_value(times.len() - 1),
+        Some(next) => next,
+    };
+    let prev = next - 1;
+
+    let delta = times[next] - times[prev];
+    let factor = (time - times[prev]) / delta;
+
+    match interpolation {
+        Interpolation::Step => keyframe_value(prev),
+        Interpolation::Linear => mix(keyframe_value(prev), keyframe_value(next), factor),
+        Interpolation::CubicSpline => {
+            let t2 = factor * factor;
+            let t3 = t2 * factor;
+
+            let prev_value = value(prev * 3 + 1);
+            let prev_out_tangent = value(prev * 3 + 2) * delta;
+            let next_in_tangent = value(next * 3) * delta;
+            let next_value = value(next * 3 + 1);
+
+            prev_value * (2.0 * t3 - 3.0 * t2 + 1.0)
+                + prev_out_tangent * (t3 - 2.0 * t2 + factor)
+                + next_value * (-2.0 * t3 + 3.0 * t2)
+                + next_in_tangent * (t3 - t2)
+        }
+    }
+}
+
+pub(crate) fn load_animations<'a>(
+    animations: impl Iterator<Item = gltf::Animation<'a>>,
+    buffers: &[Vec<u8>],
+) -> Result<Vec<Animation>, GltfLoadError> {
+    let mut loaded_animations = Vec::new();
+    for animation in animations {
+        let mut channels = Vec::new();
+        let mut duration: f32 = 0.0;
+        for (channel_idx, channel) in animation.channels().enumerate() {
+            let reader = channel.reader(|b| buffers.get(b.index()).map(Vec::as_slice));
+
+            let missing_keyframes = || GltfLoadError::MissingKeyframes(animation.index(), channel_idx);
+
+            let times: Vec<f32> = reader.read_inputs().ok_or_else(missing_keyframes)?.collect();
+            let values = match reader.read_outputs().ok_or_else(missing_keyframes)? {
+                gltf::animation::util::ReadOutputs::Translations(translations) => {
+                    ChannelValues::Translation(translations.map(Vec3::from).collect())
+                }
+                gltf::animation::util::ReadOutputs::Rotations(rotations) => ChannelValues::Rotation(
+                    rotations
+                        .into_f32()
+                        .map(|[x, y, z, w]| Quat::from_xyzw(x, y, z, w))
+                        .collect(),
+                ),
+                gltf::animation::util::ReadOutputs::Scales(scales) => {
+                    ChannelValues::Scale(scales.map(Vec3::from).collect())
+                }
+                gltf::animation::util::ReadOutputs::MorphTargetWeights(weights) => {
+                    ChannelValues::MorphWeights(weights.into_f32().collect())
+                }
+            };
+
+            if times.is_empty() {
+                return Err(missing_keyframes());
+            }
+
+            duration = duration.max(times[times.len() - 1]);
+
+            channels.push(AnimationChannel {
+                node: channel.target().node().index(),
+                interpolation: match channel.sampler().interpolation() {
+                    gltf::animation::Interpolation::Step => Interpolation::Step,
+                    gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                    gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+                },
+                times,
+                values,
+            });
+        }
+
+        loaded_animations.push(Animation {
+            name: animation.name().map(str::to_owned),
+            channels,
+            duration,
+        });
+    }
+
+    Ok(loaded_animations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn sample(times: &[f32], values: &[f32], interpolation: Interpolation, time: f32) -> f32 {
+        sample_keyframes(times, interpolation, time, |i| values[i], |a, b, t| a + (b - a) * t)
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn step_holds_previous_keyframe() {
+        let times = [1.0, 2.0, 4.0];
+        let values = [0.0, 10.0, 20.0];
+
+        assert_close(sample(&times, &values, Interpolation::Step, 1.0), 0.0);
+        assert_close(sample(&times, &values, Interpolation::Step, 1.99), 0.0);
+        assert_close(sample(&times, &values, Interpolation::Step, 2.0), 10.0);
+        assert_close(sample(&times, &values, Interpolation::Step, 3.5), 10.0);
+    }
+
+    #[test]
+    fn linear_interpolates_between_keyframes() {
+        let times = [1.0, 2.0, 4.0];
+        let values = [0.0, 10.0, 20.0];
+
+        assert_close(sample(&times, &values, Interpolation::Linear, 1.5), 5.0);
+        assert_close(sample(&times, &values, Interpolation::Linear, 2.0), 10.0);
+        assert_close(sample(&times, &values, Interpolation::Linear, 3.0), 15.0);
+    }
+
+    #[test]
+    fn linear_rotation_slerps() {
+        let animation = Animation {
+            name: None,
+            channels: vec![AnimationChannel {
+                node: 0,
+                interpolation: Interpolation::Linear,
+                times: vec![0.0, 1.0],
+                values: ChannelValues::Rotation(vec![Quat::IDENTITY, Quat::from_rotation_y(FRAC_PI_2)]),
+            }],
+            duration: 1.0,
+        };
+
+        // A quarter of the way is where slerp and normalized lerp differ the most.
+        let rotation = animation.sample_poses(0.25)[&0].rotation.unwrap();
+        let expected = Quat::from_rotation_y(FRAC_PI_2 * 0.25);
+        assert!(rotation.abs_diff_eq(expected, 1e-4), "{:?} != {:?}", rotation, expected);
+    }
+
+    #[test]
+    fn cubic_spline_uses_tangents() {
+        let times = [0.0, 2.0];
+
+        // In-tangent, value and out-tangent of each keyframe. Zero tangents ease in and out.
+        let eased = [0.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        assert_close(sample(&times, &eased, Interpolation::CubicSpline, 0.5), 0.156_25);
+        assert_close(sample(&times, &eased, Interpolation::CubicSpline, 1.0), 0.5);
+
+        // Tangents are per second, so they are scaled by the keyframe distance. Matching the slope of the values makes
+        // the spline a straight line.
+        let straight = [0.5, 0.0, 0.5, 0.5, 1.0, 0.5];
+        assert_close(sample(&times, &straight, Interpolation::CubicSpline, 0.5), 0.25);
+        assert_close(sample(&times, &straight, Interpolation::CubicSpline, 1.5), 0.75);
+    }
+
+    #[test]
+    fn clamps_outside_of_keyframes() {
+        let times = [1.0, 2.0];
+        let values = [3.0, 7.0];
+        let spline_values = [-1.0, 3.0, -1.0, -1.0, 7.0, -1.0];
+
+        for &interpolation in &[Interpolation::Step, Interpolation::Linear] {
+            assert_close(sample(&times, &values, interpolation, 0.0), 3.0);
+            assert_close(sample(&times, &values, interpolation, 5.0), 7.0);
+        }
+        assert_close(sample(&times, &spline_values, Interpolation::CubicSpline, 0.0), 3.0);
+        assert_close(sample(&times, &spline_values, Interpolation::CubicSpline, 5.0), 7.0);
+    }
+}
@@ -0,0 +1,684 @@
+//! Export of scenes built from the same types given to the renderer.
+//!
+//! The renderer doesn't keep cpu copies of its resources, so a [`GltfExportScene`] mirrors them. Add every mesh,
+//! texture, material and object to it along with adding it to the renderer, then write it out with [`export_gltf`]
+//! or [`export_glb`]. The result loads back through [`load_gltf`](crate::load_gltf) into the same scene.
+
+use crate::root_transform;
+use fnv::FnvHashMap;
+use glam::{Mat3, Vec2, Vec3, Vec4};
+use rend3::datatypes as dt;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum GltfExportError {
+    #[error("Object {0} uses a mesh which wasn't added to the export scene")]
+    MissingMesh(usize),
+    #[error("Object {0} uses a material which wasn't added to the export scene")]
+    MissingMaterial(usize),
+    #[error("Material {0} uses a texture which wasn't added to the export scene")]
+    MissingTexture(usize),
+    #[error("Texture {0} must be Rgba8Srgb or Rgba8Linear to be exported")]
+    UnsupportedTextureFormat(usize),
+    #[error("Texture {0} failed to be encoded as png")]
+    TextureEncode(usize, #[source] image::ImageError),
+    #[error("Gltf json failed to be serialized")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Cpu copies of the resources of a scene, keyed by their renderer handles.
+#[derive(Debug, Default)]
+pub struct GltfExportScene {
+    meshes: FnvHashMap<dt::MeshHandle, dt::Mesh>,
+    textures: FnvHashMap<dt::TextureHandle, dt::Texture>,
+    materials: FnvHashMap<dt::MaterialHandle, dt::Material>,
+    objects: Vec<(dt::ObjectHandle, dt::Object)>,
+}
+
+impl GltfExportScene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_mesh(&mut self, handle: dt::MeshHandle, mesh: dt::Mesh) {
+        self.meshes.insert(handle, mesh);
+    }
+
+    /// Only the largest mip of textures is exported.
+    pub fn add_texture(&mut self, handle: dt::TextureHandle, texture: dt::Texture) {
+        self.textures.insert(handle, texture);
+    }
+
+    pub fn add_material(&mut self, handle: dt::MaterialHandle, material: dt::Material) {
+        self.materials.insert(handle, material);
+    }
+
+    /// Objects are exported as nodes, in the order they were added. Skeletons aren't exported.
+    pub fn add_object(&mut self, handle: dt::ObjectHandle, object: dt::Object) {
+        self.objects.push((handle, object));
+    }
+
+    pub fn set_object_transform(&mut self, handle: dt::ObjectHandle, transform: dt::AffineTransform) {
+        if let Some((_, object)) = self.objects.iter_mut().find(|(h, _)| *h == handle) {
+            object.transform = transform;
+        }
+    }
+
+    pub fn remove_object(&mut self, handle: dt::ObjectHandle) {
+        self.objects.retain(|(h, _)| *h != handle);
+    }
+}
+
+/// Exports the scene as a gltf json file and the binary buffer it references through `bin_uri`. Images are stored
+/// in the binary buffer as pngs.
+pub fn export_gltf(scene: &GltfExportScene, bin_uri: &str) -> Result<(Vec<u8>, Vec<u8>), GltfExportError> {
+    let (mut root, bin) = GltfBuilder::new(scene).build()?;
+    if !bin.is_empty() {
+        root["buffers"] = json!([{ "byteLength": bin.len(), "uri": bin_uri }]);
+    }
+    Ok((serde_json::to_vec_pretty(&root)?, bin))
+}
+
+/// Exports the scene as a single glb file.
+pub fn export_glb(scene: &GltfExportScene) -> Result<Vec<u8>, GltfExportError> {
+    let (mut root, bin) = GltfBuilder::new(scene).build()?;
+    if !bin.is_empty() {
+        root["buffers"] = json!([{ "byteLength": bin.len() }]);
+    }
+    let json = serde_json::to_vec(&root)?;
+
+    const JSON_CHUNK: u32 = 0x4E4F_534A;
+    const BIN_CHUNK: u32 = 0x004E_4942;
+
+    let json_length = padded_length(json.len());
+    let bin_length = padded_length(bin.len());
+    let bin_chunk_length = if bin.is_empty() { 0 } else { 8 + bin_length };
+    let total_length = 12 + 8 + json_length + bin_chunk_length;
+
+    let mut glb = Vec::with_capacity(total_length);
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2_u32.to_le_bytes());
+    glb.extend_from_slice(&(total_length as u32).to_le_bytes());
+
+    glb.extend_from_slice(&(json_length as u32).to_le_bytes());
+    glb.extend_from_slice(&JSON_CHUNK.to_le_bytes());
+    glb.extend_from_slice(&json);
+    // The json chunk is padded with spaces, the binary chunk with zeros.
+    glb.resize(glb.len() + json_length - json.len(), b' ');
+
+    if !bin.is_empty() {
+        glb.extend_from_slice(&(bin_length as u32).to_le_bytes());
+        glb.extend_from_slice(&BIN_CHUNK.to_le_bytes());
+        glb.extend_from_slice(&bin);
+        glb.resize(total_length, 0);
+    }
+
+    Ok(glb)
+}
+
+fn padded_length(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_BYTE: u32 = 5121;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// Accessors of a mesh, shared by every gltf mesh made from it.
+struct MeshAccessors {
+    attributes: Map<String, Value>,
+    colors: Option<usize>,
+    indices: usize,
+    targets: Vec<Value>,
+}
+
+struct GltfBuilder<'a> {
+    scene: &'a GltfExportScene,
+    bin: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    images: Vec<Value>,
+    textures: Vec<Value>,
+    samplers: Vec<Value>,
+    materials: Vec<Value>,
+    meshes: Vec<Value>,
+    nodes: Vec<Value>,
+    extensions_used: BTreeSet<String>,
+
+    /// Samplers are per material in rend3, but per texture in gltf.
+    texture_indices: FnvHashMap<(dt::TextureHandle, dt::SamplerOptions), usize>,
+    sampler_indices: FnvHashMap<dt::SamplerOptions, usize>,
+    image_indices: FnvHashMap<dt::TextureHandle, usize>,
+    material_indices: FnvHashMap<dt::MaterialHandle, usize>,
+    mesh_accessors: FnvHashMap<dt::MeshHandle, MeshAccessors>,
+    /// gltf meshes hold their material, so there is one for every pair of mesh and material used by an object.
+    mesh_indices: FnvHashMap<(dt::MeshHandle, dt::MaterialHandle), usize>,
+}
+
+impl<'a> GltfBuilder<'a> {
+    fn new(scene: &'a GltfExportScene) -> Self {
+        Self {
+            scene,
+            bin: Vec::new(),
+            buffer_views: Vec::new(),
+            accessors: Vec::new(),
+            images: Vec::new(),
+            textures: Vec::new(),
+            samplers: Vec::new(),
+            materials: Vec::new(),
+            meshes: Vec::new(),
+            nodes: Vec::new(),
+            extensions_used: BTreeSet::new(),
+            texture_indices: FnvHashMap::default(),
+            sampler_indices: FnvHashMap::default(),
+            image_indices: FnvHashMap::default(),
+            material_indices: FnvHashMap::default(),
+            mesh_accessors: FnvHashMap::default(),
+            mesh_indices: FnvHashMap::default(),
+        }
+    }
+
+    /// Returns the json root without buffers, and the contents of the binary buffer.
+    fn build(mut self) -> Result<(Value, Vec<u8>), GltfExportError> {
+        // gltf is right handed, so the exported scene is mirrored along z, which load_gltf undoes.
+        let flip = root_transform();
+
+        let scene = self.scene;
+        for (handle, object) in &scene.objects {
+            let mesh = self.mesh(*handle, object)?;
+            let matrix = flip * object.transform.transform * flip;
+
+            let mut node = json!({
+                "mesh": mesh,
+                "matrix": matrix.to_cols_array().to_vec(),
+            });
+            if !object.morph_weights.is_empty() {
+                node["weights"] = json!(object.morph_weights);
+            }
+            self.nodes.push(node);
+        }
+
+        let scene_nodes: Vec<_> = (0..self.nodes.len()).map(|node| json!(node)).collect();
+
+        let mut root = json!({
+            "asset": { "version": "2.0", "generator": "rend3-gltf" },
+            "scene": 0,
+            "scenes": [{}],
+        });
+        // Arrays in gltf must not be empty.
+        insert_array(&mut root["scenes"][0], "nodes", scene_nodes);
+        insert_array(&mut root, "nodes", self.nodes);
+        insert_array(&mut root, "meshes", self.meshes);
+        insert_array(&mut root, "materials", self.materials);
+        insert_array(&mut root, "textures", self.textures);
+        insert_array(&mut root, "images", self.images);
+        insert_array(&mut root, "samplers", self.samplers);
+        insert_array(&mut root, "accessors", self.accessors);
+        insert_array(&mut root, "bufferViews", self.buffer_views);
+        insert_array(
+            &mut root,
+            "extensionsUsed",
+            self.extensions_used.into_iter().map(Value::String).collect(),
+        );
+
+        Ok((root, self.bin))
+    }
+
+    fn mesh(&mut self, object_handle: dt::ObjectHandle, object: &dt::Object) -> Result<usize, GltfExportError> {
+        if let Some(&index) = self.mesh_indices.get(&(object.mesh, object.material)) {
+            return Ok(index);
+        }
+
+        let material_index = self.material(object_handle, object.material)?;
+        let uses_vertex_colors = matches!(
+            self.scene.materials[&object.material].albedo,
+            dt::AlbedoComponent::Vertex { .. }
+                | dt::AlbedoComponent::ValueVertex { .. }
+                | dt::AlbedoComponent::TextureVertex { .. }
+        );
+
+        if !self.mesh_accessors.contains_key(&object.mesh) {
+            let scene = self.scene;
+            let mesh = scene
+                .meshes
+                .get(&object.mesh)
+                .ok_or_else(|| GltfExportError::MissingMesh(object_handle.get()))?;
+            let accessors = self.mesh_accessors(mesh);
+            self.mesh_accessors.insert(object.mesh, accessors);
+        }
+        let accessors = &self.mesh_accessors[&object.mesh];
+
+        let mut attributes = accessors.attributes.clone();
+        if let (true, Some(colors)) = (uses_vertex_colors, accessors.colors) {
+            attributes.insert(String::from("COLOR_0"), json!(colors));
+        }
+        let mut primitive = json!({
+            "attributes": attributes,
+            "indices": accessors.indices,
+            "material": material_index,
+        });
+        if !accessors.targets.is_empty() {
+            primitive["targets"] = json!(accessors.targets);
+        }
+
+        self.meshes.push(json!({ "primitives": [primitive] }));
+        let index = self.meshes.len() - 1;
+        self.mesh_indices.insert((object.mesh, object.material), index);
+        Ok(index)
+    }
+
+    fn mesh_accessors(&mut self, mesh: &dt::Mesh) -> MeshAccessors {
+        let mirror = |v: &Vec3| Vec3::new(v.x, v.y, -v.z);
+        let positions: Vec<_> = mesh.vertex_positions.iter().map(mirror).collect();
+        let normals: Vec<_> = mesh.vertex_normals.iter().map(mirror).collect();
+        let tangents: Vec<_> = mesh.vertex_tangents.iter().map(|t| mirror(t).extend(1.0)).collect();
+
+        let mut attributes = Map::new();
+        attributes.insert(String::from("POSITION"), json!(self.push_positions(&positions)));
+        attributes.insert(String::from("NORMAL"), json!(self.push_floats(&normals, 3, "VEC3")));
+        attributes.insert(String::from("TANGENT"), json!(self.push_floats(&tangents, 4, "VEC4")));
+        attributes.insert(
+            String::from("TEXCOORD_0"),
+            json!(self.push_floats(&mesh.vertex_uvs, 2, "VEC2")),
+        );
+        if mesh.vertex_uv1s.iter().any(|&uv| uv != Vec2::ZERO) {
+            attributes.insert(
+                String::from("TEXCOORD_1"),
+                json!(self.push_floats(&mesh.vertex_uv1s, 2, "VEC2")),
+            );
+        }
+
+        let colors = if mesh.vertex_colors.iter().any(|&color| color != [0; 4]) {
+            let bytes: Vec<u8> = mesh.vertex_colors.iter().flatten().copied().collect();
+            let view = self.push_view(&bytes, Some(ARRAY_BUFFER));
+            Some(self.push_accessor(json!({
+                "bufferView": view,
+                "componentType": UNSIGNED_BYTE,
+                "normalized": true,
+                "count": mesh.vertex_colors.len(),
+                "type": "VEC4",
+            })))
+        } else {
+            None
+        };
+
+        // load_gltf flips the winding order of everything it loads.
+        let indices: Vec<u8> = mesh
+            .indices
+            .chunks_exact(3)
+            .flat_map(|triangle| vec![triangle[2], triangle[1], triangle[0]])
+            .flat_map(|index| index.to_le_bytes().to_vec())
+            .collect();
+        let view = self.push_view(&indices, Some(ELEMENT_ARRAY_BUFFER));
+        let indices = self.push_accessor(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": mesh.indices.len(),
+            "type": "SCALAR",
+        }));
+
+        let targets = mesh
+            .morph_targets
+            .iter()
+            .map(|target| {
+                let positions: Vec<_> = target.position_deltas.iter().map(mirror).collect();
+                let normals: Vec<_> = target.normal_deltas.iter().map(mirror).collect();
+                let tangents: Vec<_> = target.tangent_deltas.iter().map(mirror).collect();
+                json!({
+                    "POSITION": self.push_positions(&positions),
+                    "NORMAL": self.push_floats(&normals, 3, "VEC3"),
+                    "TANGENT": self.push_floats(&tangents, 3, "VEC3"),
+                })
+            })
+            .collect();
+
+        MeshAccessors {
+            attributes,
+            colors,
+            indices,
+            targets,
+        }
+    }
+
+    fn material(
+        &mut self,
+        object_handle: dt::ObjectHandle,
+        handle: dt::MaterialHandle,
+    ) -> Result<usize, GltfExportError> {
+        if let Some(&index) = self.material_indices.get(&handle) {
+            return Ok(index);
+        }
+
+        let scene = self.scene;
+        let material = scene
+            .materials
+            .get(&handle)
+            .ok_or_else(|| GltfExportError::MissingMaterial(object_handle.get()))?;
+        let (base_color, albedo_texture) = match material.albedo {
+            dt::AlbedoComponent::None | dt::AlbedoComponent::Vertex { .. } => (Vec4::ONE, None),
+            dt::AlbedoComponent::Value(value) | dt::AlbedoComponent::ValueVertex { value, .. } => (value, None),
+            dt::AlbedoComponent::Texture(texture) | dt::AlbedoComponent::TextureVertex { handle: texture, .. } => {
+                (Vec4::ONE, Some(texture))
+            }
+            dt::AlbedoComponent::TextureValue { handle, value } => (value, Some(handle)),
+        };
+
+        let mut pbr = json!({
+            "baseColorFactor": base_color.to_array(),
+            "metallicFactor": material.metallic_factor.unwrap_or(0.0),
+            "roughnessFactor": material.roughness_factor.unwrap_or(0.0),
+        });
+        if let Some(texture) = albedo_texture {
+            pbr["baseColorTexture"] = self.texture_info(material, texture, dt::Uv1Textures::ALBEDO)?;
+        }
+
+        let (metallic_roughness_texture, occlusion_texture) = match material.aomr_textures {
+            dt::AoMRTextures::GltfCombined { texture } => (texture, texture),
+            dt::AoMRTextures::GltfSplit { mr_texture, ao_texture } => (mr_texture, ao_texture),
+            // Separate metallic and roughness textures can't be represented in gltf.
+            dt::AoMRTextures::BWSplit { ao_texture, .. } => (None, ao_texture),
+            dt::AoMRTextures::None => (None, None),
+        };
+        if let Some(texture) = metallic_roughness_texture {
+            pbr["metallicRoughnessTexture"] = self.texture_info(material, texture, dt::Uv1Textures::ROUGHNESS)?;
+        }
+
+        let mut gltf_material = json!({
+            "pbrMetallicRoughness": pbr,
+            "alphaMode": match material.transparency {
+                dt::Transparency::Opaque => "OPAQUE",
+                dt::Transparency::Cutout { .. } => "MASK",
+                dt::Transparency::Blend => "BLEND",
+            },
+            "doubleSided": false,
+        });
+        if let dt::Transparency::Cutout { cutout } = material.transparency {
+            gltf_material["alphaCutoff"] = json!(cutout);
+        }
+
+        if let Some(texture) = occlusion_texture {
+            let mut info = self.texture_info(material, texture, dt::Uv1Textures::AMBIENT_OCCLUSION)?;
+            info["strength"] = json!(material.ao_factor.unwrap_or(1.0));
+            gltf_material["occlusionTexture"] = info;
+        }
+
+        // Two component normal maps can't be represented in gltf.
+        if let dt::NormalTexture::Tricomponent(texture) = material.normal {
+            gltf_material["normalTexture"] = self.texture_info(material, texture, dt::Uv1Textures::NORMAL)?;
+        }
+
+        let (emissive, emissive_texture) = match material.emissive {
+            dt::MaterialComponent::None => (Vec3::ZERO, None),
+            dt::MaterialComponent::Value(value) => (value, None),
+            dt::MaterialComponent::Texture(texture) => (Vec3::ONE, Some(texture)),
+            dt::MaterialComponent::TextureValue { handle, value } => (value, Some(handle)),
+        };
+        let mut extensions = Map::new();
+        // Emissive factors above one need KHR_materials_emissive_strength.
+        let emissive_strength = emissive.max_element().max(1.0);
+        gltf_material["emissiveFactor"] = json!((emissive / emissive_strength).to_array());
+        if emissive_strength > 1.0 {
+            extensions.insert(
+                String::from("KHR_materials_emissive_strength"),
+                json!({ "emissiveStrength": emissive_strength }),
+            );
+        }
+        if let Some(texture) = emissive_texture {
+            gltf_material["emissiveTexture"] = self.texture_info(material, texture, dt::Uv1Textures::EMISSIVE)?;
+        }
+
+        let (clearcoat_texture, clearcoat_roughness_texture) = match material.clearcoat_textures {
+            dt::ClearcoatTextures::GltfCombined { texture } => (texture, texture),
+            dt::ClearcoatTextures::GltfSplit {
+                clearcoat_texture,
+                clearcoat_roughness_texture,
+            } => (clearcoat_texture, clearcoat_roughness_texture),
+            // Roughness in the red channel can't be represented in gltf.
+            dt::ClearcoatTextures::BWSplit { clearcoat_texture, .. } => (clearcoat_texture, None),
+            dt::ClearcoatTextures::None => (None, None),
+        };
+        if material.clearcoat_factor.unwrap_or(0.0) != 0.0 {
+            let mut clearcoat = json!({
+                "clearcoatFactor": material.clearcoat_factor.unwrap_or(0.0),
+                "clearcoatRoughnessFactor": material.clearcoat_roughness_factor.unwrap_or(0.0),
+            });
+            if let Some(texture) = clearcoat_texture {
+                clearcoat["clearcoatTexture"] = self.texture_info(material, texture, dt::Uv1Textures::CLEARCOAT)?;
+            }
+            if let Some(texture) = clearcoat_roughness_texture {
+                clearcoat["clearcoatRoughnessTexture"] =
+                    self.texture_info(material, texture, dt::Uv1Textures::CLEARCOAT_ROUGHNESS)?;
+            }
+            extensions.insert(String::from("KHR_materials_clearcoat"), clearcoat);
+        }
+
+        // Reflectance of 0.5 is an ior of 1.5, the gltf default.
+        if let dt::MaterialComponent::Value(reflectance)
+        | dt::MaterialComponent::TextureValue { value: reflectance, .. } = material.reflectance
+        {
+            if (reflectance - 0.5).abs() > f32::EPSILON {
+                // Inverse of the conversion in load_gltf.
+                let f0_sqrt = (0.16 * reflectance * reflectance).sqrt();
+                let ior = (1.0 + f0_sqrt) / (1.0 - f0_sqrt);
+                extensions.insert(String::from("KHR_materials_ior"), json!({ "ior": ior }));
+            }
+        }
+
+        if material.unlit {
+            extensions.insert(String::from("KHR_materials_unlit"), json!({}));
+        }
+
+        self.extensions_used.extend(extensions.keys().cloned());
+        if !extensions.is_empty() {
+            gltf_material["extensions"] = Value::Object(extensions);
+        }
+
+        self.materials.push(gltf_material);
+        let index = self.materials.len() - 1;
+        self.material_indices.insert(handle, index);
+        Ok(index)
+    }
+
+    fn texture_info(
+        &mut self,
+        material: &dt::Material,
+        handle: dt::TextureHandle,
+        slot: dt::Uv1Textures,
+    ) -> Result<Value, GltfExportError> {
+        let index = self.texture(handle, material.sampler)?;
+        let mut info = json!({
+            "index": index,
+            "texCoord": if material.uv1_textures.contains(slot) { 1 } else { 0 },
+        });
+
+        if material.transform != Mat3::IDENTITY {
+            self.extensions_used.insert(String::from("KHR_texture_transform"));
+            info["extensions"] = json!({ "KHR_texture_transform": texture_transform(material.transform) });
+        }
+
+        Ok(info)
+    }
+
+    fn texture(&mut self, handle: dt::TextureHandle, sampler: dt::SamplerOptions) -> Result<usize, GltfExportError> {
+        if let Some(&index) = self.texture_indices.get(&(handle, sampler)) {
+            return Ok(index);
+        }
+
+        let image = match self.image_indices.get(&handle) {
+            Some(&image) => image,
+            None => {
+                let image = self.image(handle)?;
+                self.image_indices.insert(handle, image);
+                image
+            }
+        };
+        let sampler_index = self.sampler(sampler);
+
+        self.textures.push(json!({
+            "source": image,
+            "sampler": sampler_index,
+        }));
+        let index = self.textures.len() - 1;
+        self.texture_indices.insert((handle, sampler), index);
+        Ok(index)
+    }
+
+    fn sampler(&mut self, options: dt::SamplerOptions) -> usize {
+        if let Some(&index) = self.sampler_indices.get(&options) {
+            return index;
+        }
+
+        let wrap = |mode| match mode {
+            dt::SamplerAddressMode::Repeat => 10497,
+            dt::SamplerAddressMode::MirrorRepeat => 33648,
+            dt::SamplerAddressMode::ClampToEdge => 33071,
+        };
+        let mag_filter = match options.mag_filter {
+            dt::SamplerFilter::Nearest => 9728,
+            dt::SamplerFilter::Linear => 9729,
+        };
+        // Always one of the mipmapped filters, as rend3 samples mips.
+        let min_filter = match (options.min_filter, options.mipmap_filter) {
+            (dt::SamplerFilter::Nearest, dt::SamplerFilter::Nearest) => 9984,
+            (dt::SamplerFilter::Linear, dt::SamplerFilter::Nearest) => 9985,
+            (dt::SamplerFilter::Nearest, dt::SamplerFilter::Linear) => 9986,
+            (dt::SamplerFilter::Linear, dt::SamplerFilter::Linear) => 9987,
+        };
+
+        self.samplers.push(json!({
+            "magFilter": mag_filter,
+            "minFilter": min_filter,
+            "wrapS": wrap(options.address_mode_u),
+            "wrapT": wrap(options.address_mode_v),
+        }));
+        let index = self.samplers.len() - 1;
+        self.sampler_indices.insert(options, index);
+        index
+    }
+
+    fn image(&mut self, handle: dt::TextureHandle) -> Result<usize, GltfExportError> {
+        let scene = self.scene;
+        let texture = scene
+            .textures
+            .get(&handle)
+            .ok_or_else(|| GltfExportError::MissingTexture(handle.get()))?;
+
+        match texture.format {
+            dt::RendererTextureFormat::Rgba8Srgb | dt::RendererTextureFormat::Rgba8Linear => {}
+            _ => return Err(GltfExportError::UnsupportedTextureFormat(handle.get())),
+        }
+
+        // The largest mip comes first.
+        let size = (texture.width * texture.height * 4) as usize;
+        let data = texture.data.iter().take(size).copied().collect();
+        let pixels = image::RgbaImage::from_raw(texture.width, texture.height, data)
+            .ok_or(GltfExportError::UnsupportedTextureFormat(handle.get()))?;
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(pixels)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .map_err(|e| GltfExportError::TextureEncode(handle.get(), e))?;
+
+        let view = self.push_view(&png, None);
+        let mut image = json!({ "bufferView": view, "mimeType": "image/png" });
+        if let Some(ref label) = texture.label {
+            image["name"] = json!(label);
+        }
+        self.images.push(image);
+        Ok(self.images.len() - 1)
+    }
+
+    fn push_positions(&mut self, positions: &[Vec3]) -> usize {
+        // Position accessors must have bounds.
+        let min = positions.iter().fold(Vec3::splat(f32::INFINITY), |min, &p| min.min(p));
+        let max = positions
+            .iter()
+            .fold(Vec3::splat(f32::NEG_INFINITY), |max, &p| max.max(p));
+
+        let index = self.push_floats(positions, 3, "VEC3");
+        self.accessors[index]["min"] = json!(min.to_array());
+        self.accessors[index]["max"] = json!(max.to_array());
+        index
+    }
+
+    fn push_floats<T: Floats>(&mut self, values: &[T], components: usize, ty: &str) -> usize {
+        let bytes: Vec<u8> = values
+            .iter()
+            .flat_map(|value| value.floats()[..components].to_vec())
+            .flat_map(|float| float.to_le_bytes().to_vec())
+            .collect();
+        let view = self.push_view(&bytes, Some(ARRAY_BUFFER));
+        self.push_accessor(json!({
+            "bufferView": view,
+            "componentType": FLOAT,
+            "count": values.len(),
+            "type": ty,
+        }))
+    }
+
+    fn push_accessor(&mut self, accessor: Value) -> usize {
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn push_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        // Accessors need their data aligned to the size of their components.
+        self.bin.resize(padded_length(self.bin.len()), 0);
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.bin.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.bin.extend_from_slice(bytes);
+
+        self.buffer_views.push(view);
+        self.buffer_views.len() - 1
+    }
+}
+
+fn insert_array(object: &mut Value, key: &str, array: Vec<Value>) {
+    if !array.is_empty() {
+        object[key] = Value::Array(array);
+    }
+}
+
+/// Vectors written to float accessors.
+trait Floats {
+    fn floats(&self) -> [f32; 4];
+}
+
+impl Floats for Vec2 {
+    fn floats(&self) -> [f32; 4] {
+        [self.x, self.y, 0.0, 0.0]
+    }
+}
+
+impl Floats for Vec3 {
+    fn floats(&self) -> [f32; 4] {
+        [self.x, self.y, self.z, 0.0]
+    }
+}
+
+impl Floats for Vec4 {
+    fn floats(&self) -> [f32; 4] {
+        self.to_array()
+    }
+}
+
+/// Inverse of the uv transform load_gltf builds from KHR_texture_transform. Assumes the matrix is an offset, rotation
+/// and scale.
+fn texture_transform(transform: Mat3) -> Value {
+    let scale = [
+        transform.x_axis.truncate().length(),
+        transform.y_axis.truncate().length(),
+    ];
+    let rotation = -transform.x_axis.y.atan2(transform.x_axis.x);
+    let offset = [transform.z_axis.x, transform.z_axis.y];
+    json!({ "offset": offset, "rotation": rotation, "scale": scale })
+}
@@ -0,0 +1,159 @@
+//! Material extensions the gltf crate doesn't parse, read from the raw json of the file.
+
+use glam::Vec3;
+use serde_json::Value;
+
+/// KHR_texture_transform of a texture the gltf crate doesn't read it for.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct TextureTransform {
+    pub offset: [f32; 2],
+    pub rotation: f32,
+    pub scale: [f32; 2],
+}
+
+/// Texture referenced by an extension.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct ExtensionTexture {
+    pub index: usize,
+    /// Texture coordinates, with the override of KHR_texture_transform applied.
+    pub tex_coord: u32,
+    pub transform: Option<TextureTransform>,
+}
+
+/// KHR_materials_clearcoat
+#[derive(Debug, Clone)]
+pub(crate) struct Clearcoat {
+    pub factor: f32,
+    pub texture: Option<ExtensionTexture>,
+    pub roughness_factor: f32,
+    pub roughness_texture: Option<ExtensionTexture>,
+}
+
+/// KHR_materials_specular
+#[derive(Debug, Clone)]
+pub(crate) struct Specular {
+    pub factor: f32,
+    pub color_factor: Vec3,
+}
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct MaterialExtensions {
+    pub clearcoat: Option<Clearcoat>,
+    /// KHR_materials_emissive_strength
+    pub emissive_strength: Option<f32>,
+    /// KHR_materials_ior
+    pub ior: Option<f32>,
+    pub specular: Option<Specular>,
+    /// KHR_texture_transform of the normal texture, and its texture coordinate override.
+    pub normal_transform: Option<(TextureTransform, Option<u32>)>,
+    /// KHR_texture_transform of the occlusion texture, and its texture coordinate override.
+    pub occlusion_transform: Option<(TextureTransform, Option<u32>)>,
+}
+
+impl MaterialExtensions {
+    /// Reflectance, as used by rend3 materials, of the dielectric part of the material. `None` if neither the ior nor
+    /// specular extensions are used.
+    pub fn reflectance(&self) -> Option<f32> {
+        if self.ior.is_none() && self.specular.is_none() {
+            return None;
+        }
+
+        let ior = self.ior.unwrap_or(1.5);
+        let mut f0 = ((ior - 1.0) / (ior + 1.0)).powi(2);
+        if let Some(ref specular) = self.specular {
+            // rend3 has a scalar reflectance, so use the strongest channel of the specular color.
+            f0 = (f0 * specular.color_factor.max_element()).min(1.0) * specular.factor;
+        }
+
+        // Inverse of compute_dielectric_f0 in pixel.glsl.
+        Some((f0 / 0.16).sqrt())
+    }
+}
+
+/// Extensions of every material, indexed by material index.
+pub(crate) fn load_material_extensions(json: &[u8]) -> Result<Vec<MaterialExtensions>, serde_json::Error> {
+    let root: Value = serde_json::from_slice(json)?;
+
+    Ok(root["materials"]
+        .as_array()
+        .map(|materials| materials.iter().map(parse_material_extensions).collect())
+        .unwrap_or_default())
+}
+
+fn parse_material_extensions(material: &Value) -> MaterialExtensions {
+    let extensions = &material["extensions"];
+    MaterialExtensions {
+        clearcoat: extensions.get("KHR_materials_clearcoat").map(|clearcoat| Clearcoat {
+            factor: float(clearcoat, "clearcoatFactor", 0.0),
+            texture: texture(clearcoat, "clearcoatTexture"),
+            roughness_factor: float(clearcoat, "clearcoatRoughnessFactor", 0.0),
+            roughness_texture: texture(clearcoat, "clearcoatRoughnessTexture"),
+        }),
+        emissive_strength: extensions
+            .get("KHR_materials_emissive_strength")
+            .map(|emissive| float(emissive, "emissiveStrength", 1.0)),
+        ior: extensions.get("KHR_materials_ior").map(|ior| float(ior, "ior", 1.5)),
+        specular: extensions.get("KHR_materials_specular").map(|specular| Specular {
+            factor: float(specular, "specularFactor", 1.0),
+            color_factor: specular["specularColorFactor"]
+                .as_array()
+                .filter(|color| color.len() == 3)
+                .map(|color| Vec3::new(as_f32(&color[0]), as_f32(&color[1]), as_f32(&color[2])))
+                .unwrap_or(Vec3::ONE),
+        }),
+        normal_transform: texture_transform(&material["normalTexture"]),
+        occlusion_transform: texture_transform(&material["occlusionTexture"]),
+    }
+}
+
+fn as_f32(value: &Value) -> f32 {
+    value.as_f64().unwrap_or_default() as f32
+}
+
+fn float(object: &Value, key: &str, default: f32) -> f32 {
+    object
+        .get(key)
+        .and_then(Value::as_f64)
+        .map_or(default, |value| value as f32)
+}
+
+fn texture(object: &Value, key: &str) -> Option<ExtensionTexture> {
+    let texture = object.get(key)?;
+    let transform = texture_transform(texture);
+    Some(ExtensionTexture {
+        index: texture.get("index")?.as_u64()? as usize,
+        tex_coord: transform
+            .and_then(|(_, tex_coord)| tex_coord)
+            .or_else(|| {
+                texture
+                    .get("texCoord")
+                    .and_then(Value::as_u64)
+                    .map(|tex_coord| tex_coord as u32)
+            })
+            .unwrap_or(0),
+        transform: transform.map(|(transform, _)| transform),
+    })
+}
+
+/// KHR_texture_transform of a texture info and its texture coordinate override.
+fn texture_transform(texture: &Value) -> Option<(TextureTransform, Option<u32>)> {
+    let transform = texture.get("extensions")?.get("KHR_texture_transform")?;
+    let vec2 = |key: &str, default: f32| {
+        transform[key]
+            .as_array()
+            .filter(|vec| vec.len() == 2)
+            .map_or([default; 2], |vec| [as_f32(&vec[0]), as_f32(&vec[1])])
+    };
+
+    Some((
+        TextureTransform {
+            offset: vec2("offset", 0.0),
+            rotation: float(transform, "rotation", 0.0),
+            scale: vec2("scale", 1.0),
+        },
+        transform
+            .get("texCoord")
+            .and_then(Value::as_u64)
+            .map(|tex_coord| tex_coord as u32),
+    ))
+}
@@ -1,14 +1,24 @@
 use fnv::FnvHashMap;
 use futures_util::future::OptionFuture;
-use glam::{Mat3, Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use glam::{Mat3, Mat4, Vec2, Vec3, Vec3A, Vec4, Vec4Swizzles};
+use gltf::accessor::Dimensions;
 use rend3::{
     datatypes as dt,
     datatypes::{AffineTransform, MeshBuilder},
+    scene::{NodeHandle, NodeLight, SceneGraph},
     Renderer,
 };
-use std::future::Future;
+use std::{borrow::Cow, future::Future};
 use thiserror::Error;
 
+mod animation;
+mod export;
+mod extensions;
+
+pub use animation::*;
+pub use export::*;
+use extensions::{load_material_extensions, ExtensionTexture, MaterialExtensions, TextureTransform};
+
 #[derive(Debug)]
 pub struct MeshPrimitive {
     pub handle: dt::MeshHandle,
@@ -18,14 +28,63 @@ pub struct MeshPrimitive {
 #[derive(Debug)]
 pub struct Mesh {
     pub primitives: Vec<MeshPrimitive>,
+    /// Default morph target weights of objects using this mesh.
+    pub weights: Vec<f32>,
 }
 
 #[derive(Debug)]
 pub struct Node {
+    /// Index of the node in the gltf file.
+    pub index: usize,
     pub children: Vec<Node>,
     pub local_transform: Mat4,
     pub objects: Vec<dt::ObjectHandle>,
-    pub light: Option<dt::DirectionalLightHandle>,
+    pub light: Option<Light>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Light {
+    Directional(dt::DirectionalLightHandle),
+    Point(dt::PointLightHandle),
+    Spot(dt::SpotLightHandle),
+}
+
+/// Camera of a gltf node, placed at the world transform of the node when the scene was loaded.
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub name: Option<String>,
+    /// Index of the node holding the camera in the gltf file.
+    pub node: usize,
+    /// Roll of the node is lost, as rend3 cameras can't roll. The aspect ratio and far plane of perspective cameras
+    /// are ignored, as rend3 uses the aspect ratio of the output and an infinite far plane.
+    pub data: dt::Camera,
+}
+
+impl Camera {
+    /// Makes this the camera the renderer draws from.
+    pub fn apply<TLD>(&self, renderer: &Renderer<TLD>)
+    where
+        TLD: 'static,
+    {
+        renderer.set_camera_data(self.data);
+    }
+
+    /// This camera placed at a world transform of its node instead of the one it was loaded at. Used to follow
+    /// animated cameras with the transforms from [`Animation::sample_world_transforms`].
+    pub fn at_transform(&self, world_transform: Mat4) -> dt::Camera {
+        place_camera(self.data.projection, world_transform)
+    }
+}
+
+/// Camera of the gltf node with the given index placed at `world_transform`, or None if the node has no camera.
+///
+/// See [`Camera::at_transform`].
+pub fn camera_from_node(scene: &LoadedGltfScene, node: usize, world_transform: Mat4) -> Option<dt::Camera> {
+    scene
+        .cameras
+        .iter()
+        .find(|camera| camera.node == node)
+        .map(|camera| camera.at_transform(world_transform))
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -40,16 +99,50 @@ pub struct LoadedGltfScene {
     pub materials: FnvHashMap<Option<usize>, dt::MaterialHandle>,
     pub images: FnvHashMap<ImageKey, dt::TextureHandle>,
     pub nodes: Vec<Node>,
+    pub animations: Vec<Animation>,
+    /// Every node with a camera, in depth first order of the node tree.
+    pub cameras: Vec<Camera>,
+}
+
+/// Scene graph nodes created by [`add_to_scene_graph`].
+#[derive(Debug)]
+pub struct GltfSceneGraphNodes {
+    /// Parent of all top level nodes, converting from gltf's coords.
+    pub root: NodeHandle,
+    /// Scene graph node of every gltf node, indexed by gltf node index.
+    pub nodes: FnvHashMap<usize, NodeHandle>,
 }
 
 #[derive(Debug, Error)]
 pub enum GltfLoadError {
     #[error("Gltf parsing or validation error")]
     Gltf(#[from] gltf::Error),
+    #[error("Gltf json failed to be parsed for material extensions")]
+    ExtensionJson(#[source] serde_json::Error),
+    #[error("Buffer {0} failed to be loaded from the fs")]
+    BufferIo(String, #[source] async_std::io::Error),
+    #[error("Gltf file references the binary chunk of a glb file, but it doesn't have one")]
+    MissingGlbBinary,
+    #[error("Buffer {0} is shorter than its declared length")]
+    BufferTooShort(usize),
     #[error("Texture {0} failed to be loaded from the fs")]
     TextureIo(String, #[source] async_std::io::Error),
     #[error("Texture {0} failed to be loaded as an image")]
     TextureLoad(String, #[source] image::ImageError),
+    #[error("{0} has a data uri that isn't base64 encoded")]
+    UnsupportedDataUri(String),
+    #[error("{0} has a data uri that failed to be decoded as base64")]
+    DataUriDecode(String, #[source] base64::DecodeError),
+    #[error("Buffer view {0} is not within its buffer")]
+    MissingBufferView(usize),
+    #[error("Accessor {0} reads outside of its buffer view")]
+    AccessorOutOfBounds(usize),
+    #[error("Accessor {0} must contain floats")]
+    UnsupportedAccessorType(usize),
+    #[error("Accessor {0} has {1:?} elements, but {2:?} elements are required")]
+    UnsupportedAccessorDimensions(usize, Dimensions, Dimensions),
+    #[error("Material extension references missing texture {0}")]
+    MissingTexture(usize),
     #[error("Gltf file must have at least one scene")]
     MissingScene,
     #[error("Mesh {0} does not have positions")]
@@ -60,41 +153,97 @@ pub enum GltfLoadError {
     MissingMaterial(usize),
     #[error("Mesh {0} primitive {1} uses unsupported mode {2:?}. Only triangles are supported.")]
     UnsupportedPrimitiveMode(usize, usize, gltf::mesh::Mode),
+    #[error("Animation {0} channel {1} does not have keyframes")]
+    MissingKeyframes(usize, usize),
 }
 
+/// Loads a gltf or glb file. Every external buffer and image is loaded by calling `io_func` with its uri.
 pub async fn load_gltf<TLD, F, Fut>(
     renderer: &Renderer<TLD>,
     data: &[u8],
-    binary: &[u8],
-    mut texture_func: F,
+    mut io_func: F,
 ) -> Result<LoadedGltfScene, GltfLoadError>
 where
     TLD: 'static,
     F: FnMut(&str) -> Fut,
     Fut: Future<Output = Result<Vec<u8>, async_std::io::Error>>,
 {
-    let file = gltf::Gltf::from_slice_without_validation(data)?;
+    let mut file = gltf::Gltf::from_slice_without_validation(data)?;
+
+    let json = if data.starts_with(b"glTF") {
+        gltf::Glb::from_slice(data)?.json
+    } else {
+        Cow::Borrowed(data)
+    };
+    let material_extensions = load_material_extensions(&json).map_err(GltfLoadError::ExtensionJson)?;
+
+    let buffers = load_buffers(file.buffers(), file.blob.take(), &mut io_func).await?;
 
     let mut loaded = LoadedGltfScene::default();
-    load_meshes(renderer, &mut loaded, file.meshes(), binary)?;
+    load_meshes(renderer, &mut loaded, file.meshes(), &buffers)?;
     load_default_material(renderer, &mut loaded);
-    load_materials_and_textures(renderer, &mut loaded, file.materials(), &mut texture_func).await?;
+    load_materials_and_textures(
+        renderer,
+        &mut loaded,
+        &file.document,
+        &material_extensions,
+        &buffers,
+        &mut io_func,
+    )
+    .await?;
 
     let scene = file
         .default_scene()
         .or_else(|| file.scenes().next())
         .ok_or(GltfLoadError::MissingScene)?;
 
-    loaded.nodes = load_gltf_impl(
-        renderer,
-        &mut loaded,
-        scene.nodes(),
-        Mat4::from_scale(Vec3::new(1.0, 1.0, -1.0)),
-    )?;
+    loaded.nodes = load_gltf_impl(renderer, &mut loaded, scene.nodes(), root_transform())?;
+    loaded.animations = load_animations(file.animations(), &buffers)?;
 
     Ok(loaded)
 }
 
+/// Adds the node tree of a loaded scene to a scene graph, attaching each node's objects and light, so moving a node of
+/// the graph moves everything below it.
+pub fn add_to_scene_graph(graph: &mut SceneGraph, nodes: &[Node]) -> GltfSceneGraphNodes {
+    let root = graph.add_node(None, root_transform());
+    let mut handles = FnvHashMap::default();
+    add_to_scene_graph_impl(graph, nodes, root, &mut handles);
+    GltfSceneGraphNodes { root, nodes: handles }
+}
+
+fn add_to_scene_graph_impl(
+    graph: &mut SceneGraph,
+    nodes: &[Node],
+    parent: NodeHandle,
+    handles: &mut FnvHashMap<usize, NodeHandle>,
+) {
+    for node in nodes {
+        let handle = graph.add_node(Some(parent), node.local_transform);
+        for &object in &node.objects {
+            graph.attach_object(handle, object);
+        }
+        if let Some(light) = node.light {
+            graph.attach_light(
+                handle,
+                match light {
+                    Light::Directional(light) => NodeLight::Directional(light),
+                    Light::Point(light) => NodeLight::Point(light),
+                    Light::Spot(light) => NodeLight::Spot(light),
+                },
+            );
+        }
+        handles.insert(node.index, handle);
+
+        add_to_scene_graph_impl(graph, &node.children, handle, handles);
+    }
+}
+
+/// Transform of the root of the node tree. Flips gltf's coords and winding order.
+fn root_transform() -> Mat4 {
+    Mat4::from_scale(Vec3::new(1.0, 1.0, -1.0))
+}
+
 fn load_gltf_impl<'a, TLD>(
     renderer: &Renderer<TLD>,
     loaded: &mut LoadedGltfScene,
@@ -125,6 +274,11 @@ where
                     mesh: prim.handle,
                     material: *mat,
                     transform: AffineTransform { transform },
+                    skeleton: None,
+                    morph_weights: node
+                        .weights()
+                        .map(<[f32]>::to_vec)
+                        .unwrap_or_else(|| mesh_handle.weights.clone()),
                 });
                 objects.push(object_handle);
             }
@@ -134,21 +288,65 @@ where
             match light.kind() {
                 gltf::khr_lights_punctual::Kind::Directional => {
                     let direction = (transform * (-Vec3::Z).extend(1.0)).xyz();
-                    Some(renderer.add_directional_light(dt::DirectionalLight {
+                    Some(Light::Directional(renderer.add_directional_light(
+                        dt::DirectionalLight {
+                            color: Vec3::from(light.color()),
+                            intensity: light.intensity(),
+                            direction,
+                            shadow_resolution: 2048,
+                            depth_bias: 1.0,
+                            normal_bias: 1.0,
+                        },
+                    )))
+                }
+                gltf::khr_lights_punctual::Kind::Point => {
+                    let position = (transform * Vec3::ZERO.extend(1.0)).xyz();
+                    let intensity = light.intensity();
+                    // Lights without a range have infinite reach, cut them off once they drop below 1% brightness.
+                    let radius = light.range().unwrap_or_else(|| (intensity * 100.0).sqrt());
+                    Some(Light::Point(renderer.add_point_light(dt::PointLight {
+                        position,
                         color: Vec3::from(light.color()),
-                        intensity: light.intensity(),
+                        intensity,
+                        radius,
+                    })))
+                }
+                gltf::khr_lights_punctual::Kind::Spot {
+                    inner_cone_angle,
+                    outer_cone_angle,
+                } => {
+                    let position = (transform * Vec3::ZERO.extend(1.0)).xyz();
+                    let direction = transform.transform_vector3(-Vec3::Z);
+                    let intensity = light.intensity();
+                    // Same cutoff as point lights.
+                    let range = light.range().unwrap_or_else(|| (intensity * 100.0).sqrt());
+                    Some(Light::Spot(renderer.add_spot_light(dt::SpotLight {
+                        position,
                         direction,
-                    }))
+                        inner_angle: inner_cone_angle,
+                        outer_angle: outer_cone_angle,
+                        range,
+                        color: Vec3::from(light.color()),
+                        intensity,
+                    })))
                 }
-                _ => None,
             }
         } else {
             None
         };
 
+        if let Some(camera) = node.camera() {
+            loaded.cameras.push(Camera {
+                name: camera.name().map(str::to_owned),
+                node: node.index(),
+                data: load_camera(&camera, transform),
+            });
+        }
+
         let children = load_gltf_impl(renderer, loaded, node.children(), transform)?;
 
         final_nodes.push(Node {
+            index: node.index(),
             children,
             local_transform,
             objects,
@@ -158,11 +356,181 @@ where
     Ok(final_nodes)
 }
 
+fn load_camera(camera: &gltf::Camera<'_>, transform: Mat4) -> dt::Camera {
+    // The direction is filled in by place_camera.
+    let projection = match camera.projection() {
+        gltf::camera::Projection::Perspective(perspective) => dt::CameraProjection::Projection {
+            vfov: perspective.yfov().to_degrees(),
+            near: perspective.znear(),
+            pitch: 0.0,
+            yaw: 0.0,
+        },
+        // rend3 orthographic cameras are centered on their location, so make them deep enough to reach the far plane.
+        gltf::camera::Projection::Orthographic(orthographic) => dt::CameraProjection::Orthographic {
+            size: Vec3A::new(
+                orthographic.xmag() * 2.0,
+                orthographic.ymag() * 2.0,
+                orthographic.zfar() * 2.0,
+            ),
+            direction: Vec3A::ZERO,
+        },
+    };
+
+    place_camera(projection, transform)
+}
+
+/// Moves a camera to the location and look direction of a node's world transform, keeping the rest of its projection.
+fn place_camera(projection: dt::CameraProjection, transform: Mat4) -> dt::Camera {
+    let location = transform.transform_point3(Vec3::ZERO);
+    // Cameras look down their -Z axis.
+    let forward = transform.transform_vector3(-Vec3::Z).normalize();
+
+    let projection = match projection {
+        dt::CameraProjection::Projection { vfov, near, .. } => dt::CameraProjection::Projection {
+            vfov,
+            near,
+            // Inverse of the look direction rend3 computes from pitch and yaw.
+            pitch: (-forward.y).asin(),
+            yaw: forward.x.atan2(forward.z),
+        },
+        dt::CameraProjection::Orthographic { size, .. } => dt::CameraProjection::Orthographic {
+            size,
+            direction: Vec3A::from(forward),
+        },
+    };
+
+    dt::Camera {
+        projection,
+        location: Vec3A::from(location),
+    }
+}
+
+async fn load_buffers<'a, F, Fut>(
+    buffers: impl Iterator<Item = gltf::Buffer<'a>>,
+    mut blob: Option<Vec<u8>>,
+    io_func: &mut F,
+) -> Result<Vec<Vec<u8>>, GltfLoadError>
+where
+    F: FnMut(&str) -> Fut,
+    Fut: Future<Output = Result<Vec<u8>, async_std::io::Error>>,
+{
+    let mut loaded_buffers = Vec::new();
+    for buffer in buffers {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => blob.take().ok_or(GltfLoadError::MissingGlbBinary)?,
+            gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") => {
+                decode_data_uri(&format!("Buffer {}", buffer.index()), uri)?
+            }
+            gltf::buffer::Source::Uri(uri) => io_func(uri)
+                .await
+                .map_err(|e| GltfLoadError::BufferIo(uri.to_string(), e))?,
+        };
+
+        if data.len() < buffer.length() {
+            return Err(GltfLoadError::BufferTooShort(buffer.index()));
+        }
+
+        loaded_buffers.push(data);
+    }
+
+    Ok(loaded_buffers)
+}
+
+/// Data of a buffer view.
+fn view_data<'a>(buffers: &'a [Vec<u8>], view: &gltf::buffer::View<'_>) -> Result<&'a [u8], GltfLoadError> {
+    buffers
+        .get(view.buffer().index())
+        .and_then(|buffer| buffer.get(view.offset()..view.offset() + view.length()))
+        .ok_or_else(|| GltfLoadError::MissingBufferView(view.index()))
+}
+
+/// Reads an accessor of floats, flattening all components. Sparse accessors have their substitutions applied on top
+/// of their buffer view, or on top of zeros if they don't have one.
+fn read_float_accessor(accessor: &gltf::Accessor<'_>, buffers: &[Vec<u8>]) -> Result<Vec<f32>, GltfLoadError> {
+    if accessor.data_type() != gltf::accessor::DataType::F32 {
+        return Err(GltfLoadError::UnsupportedAccessorType(accessor.index()));
+    }
+
+    let out_of_bounds = || GltfLoadError::AccessorOutOfBounds(accessor.index());
+    let components = accessor.dimensions().multiplicity();
+    let element_size = components * 4;
+
+    let mut values = vec![0.0; accessor.count() * components];
+
+    if let Some(view) = accessor.view() {
+        let data = view_data(buffers, &view)?;
+        let stride = view.stride().unwrap_or(element_size);
+        for (idx, element) in values.chunks_exact_mut(components).enumerate() {
+            let start = accessor.offset() + idx * stride;
+            let bytes = data.get(start..start + element_size).ok_or_else(out_of_bounds)?;
+            read_floats(bytes, element);
+        }
+    }
+
+    if let Some(sparse) = accessor.sparse() {
+        let indices = sparse.indices();
+        let index_data = view_data(buffers, &indices.view())?;
+        let index_size = match indices.index_type() {
+            gltf::accessor::sparse::IndexType::U8 => 1,
+            gltf::accessor::sparse::IndexType::U16 => 2,
+            gltf::accessor::sparse::IndexType::U32 => 4,
+        };
+
+        let sparse_values = sparse.values();
+        let value_data = view_data(buffers, &sparse_values.view())?;
+
+        for idx in 0..sparse.count() as usize {
+            let start = indices.offset() as usize + idx * index_size;
+            let index_bytes = index_data.get(start..start + index_size).ok_or_else(out_of_bounds)?;
+            // Little endian
+            let index = index_bytes
+                .iter()
+                .rev()
+                .fold(0_usize, |index, &byte| (index << 8) | byte as usize);
+
+            let start = sparse_values.offset() as usize + idx * element_size;
+            let bytes = value_data.get(start..start + element_size).ok_or_else(out_of_bounds)?;
+            let element = values
+                .get_mut(index * components..(index + 1) * components)
+                .ok_or_else(out_of_bounds)?;
+            read_floats(bytes, element);
+        }
+    }
+
+    Ok(values)
+}
+
+fn read_floats(bytes: &[u8], output: &mut [f32]) {
+    for (value, chunk) in output.iter_mut().zip(bytes.chunks_exact(4)) {
+        *value = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+}
+
+/// Reads the first three components of an accessor of `dimensions` floats, which must be at least three wide.
+fn read_vec3_accessor(
+    accessor: &gltf::Accessor<'_>,
+    buffers: &[Vec<u8>],
+    dimensions: Dimensions,
+) -> Result<Vec<Vec3>, GltfLoadError> {
+    if accessor.dimensions() != dimensions {
+        return Err(GltfLoadError::UnsupportedAccessorDimensions(
+            accessor.index(),
+            accessor.dimensions(),
+            dimensions,
+        ));
+    }
+
+    Ok(read_float_accessor(accessor, buffers)?
+        .chunks_exact(dimensions.multiplicity())
+        .map(|v| Vec3::new(v[0], v[1], v[2]))
+        .collect())
+}
+
 fn load_meshes<'a, TLD>(
     renderer: &Renderer<TLD>,
     loaded: &mut LoadedGltfScene,
     meshes: impl Iterator<Item = gltf::Mesh<'a>>,
-    binary: &[u8],
+    buffers: &[Vec<u8>],
 ) -> Result<(), GltfLoadError>
 where
     TLD: 'static,
@@ -178,35 +546,36 @@ where
                 ));
             }
 
-            let reader = prim.reader(|b| {
-                if b.index() != 0 {
-                    return None;
-                }
-                Some(&binary[..b.length()])
-            });
+            let reader = prim.reader(|b| buffers.get(b.index()).map(Vec::as_slice));
+
+            // Float attributes are read by hand, as they are the ones morph targets and sparse accessors are used with.
+            let positions = prim
+                .get(&gltf::Semantic::Positions)
+                .ok_or_else(|| GltfLoadError::MissingPositions(mesh.index()))?;
+            let vertex_positions = read_vec3_accessor(&positions, buffers, Dimensions::Vec3)?;
 
-            let vertex_positions: Vec<_> = reader
-                .read_positions()
-                .ok_or_else(|| GltfLoadError::MissingPositions(mesh.index()))?
-                .map(Vec3::from)
-                .collect();
+            let vertex_count = vertex_positions.len();
 
             // glTF models are right handed, so we must flip their winding order
             let mut builder = MeshBuilder::new(vertex_positions).with_right_handed();
 
-            if let Some(normals) = reader.read_normals() {
-                builder = builder.with_vertex_normals(normals.map(Vec3::from).collect())
+            if let Some(normals) = prim.get(&gltf::Semantic::Normals) {
+                builder = builder.with_vertex_normals(read_vec3_accessor(&normals, buffers, Dimensions::Vec3)?)
             }
 
-            if let Some(tangents) = reader.read_tangents() {
-                // todo: handedness
-                builder = builder.with_vertex_tangents(tangents.map(|[x, y, z, _]| Vec3::new(x, y, z)).collect())
+            if let Some(tangents) = prim.get(&gltf::Semantic::Tangents) {
+                // todo: handedness, which is the w component
+                builder = builder.with_vertex_tangents(read_vec3_accessor(&tangents, buffers, Dimensions::Vec4)?)
             }
 
             if let Some(uvs) = reader.read_tex_coords(0) {
                 builder = builder.with_vertex_uvs(uvs.into_f32().map(Vec2::from).collect())
             }
 
+            if let Some(uvs) = reader.read_tex_coords(1) {
+                builder = builder.with_vertex_uv1(uvs.into_f32().map(Vec2::from).collect())
+            }
+
             if let Some(colors) = reader.read_colors(0) {
                 builder = builder.with_vertex_colors(colors.into_rgba_u8().collect())
             }
@@ -215,6 +584,26 @@ where
                 builder = builder.with_indices(indices.into_u32().collect())
             }
 
+            let read_deltas = |accessor: Option<gltf::Accessor<'_>>| {
+                accessor
+                    .map(|accessor| read_vec3_accessor(&accessor, buffers, Dimensions::Vec3))
+                    .transpose()
+            };
+            let morph_targets = prim
+                .morph_targets()
+                .map(|target| {
+                    Ok(dt::MorphTarget {
+                        position_deltas: read_deltas(target.positions())?
+                            .unwrap_or_else(|| vec![Vec3::ZERO; vertex_count]),
+                        normal_deltas: read_deltas(target.normals())?.unwrap_or_default(),
+                        tangent_deltas: read_deltas(target.tangents())?.unwrap_or_default(),
+                    })
+                })
+                .collect::<Result<Vec<_>, GltfLoadError>>()?;
+            if !morph_targets.is_empty() {
+                builder = builder.with_morph_targets(morph_targets);
+            }
+
             let mesh = builder.build();
 
             let handle = renderer.add_mesh(mesh);
@@ -224,7 +613,13 @@ where
                 material: prim.material().index(),
             })
         }
-        loaded.meshes.insert(mesh.index(), Mesh { primitives: res_prims });
+        loaded.meshes.insert(
+            mesh.index(),
+            Mesh {
+                primitives: res_prims,
+                weights: mesh.weights().map(<[f32]>::to_vec).unwrap_or_default(),
+            },
+        );
     }
 
     Ok(())
@@ -246,26 +641,46 @@ fn load_default_material<TLD>(renderer: &Renderer<TLD>, loaded: &mut LoadedGltfS
             emissive: dt::MaterialComponent::None,
             reflectance: dt::MaterialComponent::None,
             anisotropy: dt::MaterialComponent::None,
-            alpha_cutout: None,
+            transparency: dt::Transparency::Opaque,
             transform: Mat3::IDENTITY,
+            uv1_textures: dt::Uv1Textures::empty(),
             unlit: false,
-            nearest: false,
+            sampler: dt::SamplerOptions::default(),
         }),
     );
 }
 
-async fn load_materials_and_textures<'a, TLD, F, Fut>(
+async fn load_materials_and_textures<TLD, F, Fut>(
     renderer: &Renderer<TLD>,
     loaded: &mut LoadedGltfScene,
-    materials: impl Iterator<Item = gltf::Material<'a>>,
-    texture_func: &mut F,
+    document: &gltf::Document,
+    material_extensions: &[MaterialExtensions],
+    buffers: &[Vec<u8>],
+    io_func: &mut F,
 ) -> Result<(), GltfLoadError>
 where
     TLD: 'static,
     F: FnMut(&str) -> Fut,
     Fut: Future<Output = Result<Vec<u8>, async_std::io::Error>>,
 {
-    for material in materials {
+    let textures: Vec<_> = document.textures().collect();
+    let extension_texture = |texture: Option<ExtensionTexture>| {
+        texture
+            .map(|texture| {
+                textures
+                    .get(texture.index)
+                    .map(|gltf_texture| (gltf_texture.clone(), texture.tex_coord))
+                    .ok_or(GltfLoadError::MissingTexture(texture.index))
+            })
+            .transpose()
+    };
+
+    for material in document.materials() {
+        let extensions = material
+            .index()
+            .and_then(|index| material_extensions.get(index))
+            .cloned()
+            .unwrap_or_default();
         let pbr = material.pbr_metallic_roughness();
         let albedo = pbr.base_color_texture();
         let albedo_factor = pbr.base_color_factor();
@@ -276,36 +691,143 @@ where
         let roughness_factor = pbr.roughness_factor();
         let metallic_factor = pbr.metallic_factor();
         let metallic_roughness = pbr.metallic_roughness_texture();
+        let clearcoat = extensions.clearcoat.as_ref();
+        let clearcoat_texture = extension_texture(clearcoat.and_then(|c| c.texture))?;
+        let clearcoat_roughness_texture = extension_texture(clearcoat.and_then(|c| c.roughness_texture))?;
+
+        // rend3 has a single uv transform per material, so it can only be used if all textures agree on it. Textures
+        // without a KHR_texture_transform have the identity transform.
+        let info_transform = |info: &gltf::texture::Info<'_>| {
+            info.texture_transform()
+                .map_or(Mat3::IDENTITY, |t| uv_transform(t.offset(), t.rotation(), t.scale()))
+        };
+        let raw_transform = |transform: Option<TextureTransform>| {
+            transform.map_or(Mat3::IDENTITY, |t| uv_transform(t.offset, t.rotation, t.scale))
+        };
+        let texture_transforms: Vec<Mat3> = [
+            albedo.as_ref().map(info_transform),
+            metallic_roughness.as_ref().map(info_transform),
+            emissive.as_ref().map(info_transform),
+            normals
+                .as_ref()
+                .map(|_| raw_transform(extensions.normal_transform.map(|(t, _)| t))),
+            occlusion
+                .as_ref()
+                .map(|_| raw_transform(extensions.occlusion_transform.map(|(t, _)| t))),
+            clearcoat.and_then(|c| c.texture).map(|t| raw_transform(t.transform)),
+            clearcoat
+                .and_then(|c| c.roughness_texture)
+                .map(|t| raw_transform(t.transform)),
+        ]
+        .iter()
+        .flatten()
+        .copied()
+        .collect();
+        let transform = match texture_transforms.split_first() {
+            Some((&first, rest)) if rest.iter().all(|&t| t == first) => first,
+            Some(_) => {
+                tracing::warn!(
+                    "Material {:?} has textures with different KHR_texture_transforms, ignoring them",
+                    material.index()
+                );
+                Mat3::IDENTITY
+            }
+            None => Mat3::IDENTITY,
+        };
 
-        let nearest = albedo
-            .as_ref()
-            .map(|i| i.texture().sampler().mag_filter() == Some(gltf::texture::MagFilter::Nearest))
-            .unwrap_or_default();
+        let mut uv1_textures = dt::Uv1Textures::empty();
+        uv1_textures.set(dt::Uv1Textures::ALBEDO, albedo.as_ref().map_or(false, uses_uv1));
+        uv1_textures.set(
+            dt::Uv1Textures::NORMAL,
+            normals.as_ref().map_or(false, |i| {
+                extensions
+                    .normal_transform
+                    .and_then(|(_, tex_coord)| tex_coord)
+                    .unwrap_or_else(|| i.tex_coord())
+                    == 1
+            }),
+        );
+        uv1_textures.set(
+            dt::Uv1Textures::ROUGHNESS | dt::Uv1Textures::METALLIC,
+            metallic_roughness.as_ref().map_or(false, uses_uv1),
+        );
+        uv1_textures.set(
+            dt::Uv1Textures::AMBIENT_OCCLUSION,
+            occlusion.as_ref().map_or(false, |i| {
+                extensions
+                    .occlusion_transform
+                    .and_then(|(_, tex_coord)| tex_coord)
+                    .unwrap_or_else(|| i.tex_coord())
+                    == 1
+            }),
+        );
+        uv1_textures.set(dt::Uv1Textures::EMISSIVE, emissive.as_ref().map_or(false, uses_uv1));
+        uv1_textures.set(dt::Uv1Textures::CLEARCOAT, matches!(clearcoat_texture, Some((_, 1))));
+        uv1_textures.set(
+            dt::Uv1Textures::CLEARCOAT_ROUGHNESS,
+            matches!(clearcoat_roughness_texture, Some((_, 1))),
+        );
+
+        // rend3 has one sampler per material, so follow the sampler of the base color texture, or of the first other
+        // texture if there isn't one. The samplers of the other textures are not mapped.
+        let samplers: Vec<_> = [
+            albedo.as_ref().map(|i| i.texture()),
+            normals.as_ref().map(|i| i.texture()),
+            metallic_roughness.as_ref().map(|i| i.texture()),
+            occlusion.as_ref().map(|i| i.texture()),
+            emissive.as_ref().map(|i| i.texture()),
+            clearcoat_texture.as_ref().map(|(t, _)| t.clone()),
+            clearcoat_roughness_texture.as_ref().map(|(t, _)| t.clone()),
+        ]
+        .iter()
+        .flatten()
+        .map(|texture| sampler_options(&texture.sampler()))
+        .collect();
+        let sampler = samplers.first().copied().unwrap_or_default();
+        if samplers.iter().any(|&other| other != sampler) {
+            tracing::warn!(
+                "Material {:?} has textures with different samplers, sampling all of them with the first one",
+                material.index()
+            );
+        }
 
-        let albedo_tex =
-            OptionFuture::from(albedo.map(|i| load_image(renderer, loaded, i.texture().source(), true, texture_func)))
-                .await
-                .transpose()?;
+        let albedo_tex = OptionFuture::from(
+            albedo.map(|i| load_image(renderer, loaded, i.texture().source(), true, buffers, io_func)),
+        )
+        .await
+        .transpose()?;
         let occlusion_tex = OptionFuture::from(
-            occlusion.map(|i| load_image(renderer, loaded, i.texture().source(), false, texture_func)),
+            occlusion.map(|i| load_image(renderer, loaded, i.texture().source(), false, buffers, io_func)),
         )
         .await
         .transpose()?;
         let emissive_tex = OptionFuture::from(
-            emissive.map(|i| load_image(renderer, loaded, i.texture().source(), true, texture_func)),
+            emissive.map(|i| load_image(renderer, loaded, i.texture().source(), true, buffers, io_func)),
         )
         .await
         .transpose()?;
         let normals_tex = OptionFuture::from(
-            normals.map(|i| load_image(renderer, loaded, i.texture().source(), false, texture_func)),
+            normals.map(|i| load_image(renderer, loaded, i.texture().source(), false, buffers, io_func)),
         )
         .await
         .transpose()?;
         let metallic_roughness_tex = OptionFuture::from(
-            metallic_roughness.map(|i| load_image(renderer, loaded, i.texture().source(), false, texture_func)),
+            metallic_roughness.map(|i| load_image(renderer, loaded, i.texture().source(), false, buffers, io_func)),
+        )
+        .await
+        .transpose()?;
+        let clearcoat_tex = OptionFuture::from(
+            clearcoat_texture.map(|(t, _)| load_image(renderer, loaded, t.source(), false, buffers, io_func)),
         )
         .await
         .transpose()?;
+        let clearcoat_roughness_tex = OptionFuture::from(
+            clearcoat_roughness_texture.map(|(t, _)| load_image(renderer, loaded, t.source(), false, buffers, io_func)),
+        )
+        .await
+        .transpose()?;
+
+        let emissive_factor = Vec3::from(emissive_factor) * extensions.emissive_strength.unwrap_or(1.0);
 
         let handle = renderer.add_material(dt::Material {
             albedo: match albedo_tex {
@@ -331,12 +853,34 @@ where
             emissive: match emissive_tex {
                 Some(tex) => dt::MaterialComponent::TextureValue {
                     handle: tex,
-                    value: Vec3::from(emissive_factor),
+                    value: emissive_factor,
                 },
-                None => dt::MaterialComponent::Value(Vec3::from(emissive_factor)),
+                None => dt::MaterialComponent::Value(emissive_factor),
             },
+            clearcoat_textures: match (clearcoat_tex, clearcoat_roughness_tex) {
+                (Some(cc), Some(ccr)) if cc == ccr => dt::ClearcoatTextures::GltfCombined { texture: Some(cc) },
+                (cc, ccr) => dt::ClearcoatTextures::GltfSplit {
+                    clearcoat_texture: cc,
+                    clearcoat_roughness_texture: ccr,
+                },
+            },
+            clearcoat_factor: clearcoat.map(|c| c.factor),
+            clearcoat_roughness_factor: clearcoat.map(|c| c.roughness_factor),
+            reflectance: match extensions.reflectance() {
+                Some(reflectance) => dt::MaterialComponent::Value(reflectance),
+                None => dt::MaterialComponent::None,
+            },
+            transparency: match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => dt::Transparency::Opaque,
+                gltf::material::AlphaMode::Mask => dt::Transparency::Cutout {
+                    cutout: material.alpha_cutoff(),
+                },
+                gltf::material::AlphaMode::Blend => dt::Transparency::Blend,
+            },
+            transform,
+            uv1_textures,
             unlit: material.unlit(),
-            nearest,
+            sampler,
             ..dt::Material::default()
         });
 
@@ -348,12 +892,61 @@ where
     Ok(())
 }
 
+fn sampler_options(sampler: &gltf::texture::Sampler<'_>) -> dt::SamplerOptions {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+
+    let address_mode = |mode| match mode {
+        WrappingMode::Repeat => dt::SamplerAddressMode::Repeat,
+        WrappingMode::MirroredRepeat => dt::SamplerAddressMode::MirrorRepeat,
+        WrappingMode::ClampToEdge => dt::SamplerAddressMode::ClampToEdge,
+    };
+
+    // rend3 always samples mips, so filters without mipmaps pick the nearest mip.
+    let (min_filter, mipmap_filter) = match sampler.min_filter() {
+        Some(MinFilter::Nearest) | Some(MinFilter::NearestMipmapNearest) => {
+            (dt::SamplerFilter::Nearest, dt::SamplerFilter::Nearest)
+        }
+        Some(MinFilter::Linear) | Some(MinFilter::LinearMipmapNearest) => {
+            (dt::SamplerFilter::Linear, dt::SamplerFilter::Nearest)
+        }
+        Some(MinFilter::NearestMipmapLinear) => (dt::SamplerFilter::Nearest, dt::SamplerFilter::Linear),
+        Some(MinFilter::LinearMipmapLinear) | None => (dt::SamplerFilter::Linear, dt::SamplerFilter::Linear),
+    };
+
+    dt::SamplerOptions {
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        mag_filter: match sampler.mag_filter() {
+            Some(MagFilter::Nearest) => dt::SamplerFilter::Nearest,
+            Some(MagFilter::Linear) | None => dt::SamplerFilter::Linear,
+        },
+        min_filter,
+        mipmap_filter,
+        ..dt::SamplerOptions::default()
+    }
+}
+
+/// Converts a KHR_texture_transform into a uv transform matrix.
+fn uv_transform(offset: [f32; 2], rotation: f32, scale: [f32; 2]) -> Mat3 {
+    // The extension rotates counter-clockwise in uv space, where v points down.
+    Mat3::from_scale_angle_translation(Vec2::from(scale), -rotation, Vec2::from(offset))
+}
+
+/// If the texture is sampled with TEXCOORD_1. KHR_texture_transform may override the texture coordinates.
+fn uses_uv1(info: &gltf::texture::Info<'_>) -> bool {
+    info.texture_transform()
+        .and_then(|transform| transform.tex_coord())
+        .unwrap_or_else(|| info.tex_coord())
+        == 1
+}
+
 async fn load_image<TLD, F, Fut>(
     renderer: &Renderer<TLD>,
     loaded: &mut LoadedGltfScene,
     image: gltf::Image<'_>,
     srgb: bool,
-    texture_func: &mut F,
+    buffers: &[Vec<u8>],
+    io_func: &mut F,
 ) -> Result<dt::TextureHandle, GltfLoadError>
 where
     TLD: 'static,
@@ -361,35 +954,60 @@ where
     Fut: Future<Output = Result<Vec<u8>, async_std::io::Error>>,
 {
     // TODO: Address format detection for compressed texs
-    // TODO: Allow embedded images
-    if let gltf::image::Source::Uri { uri, .. } = image.source() {
-        let key = ImageKey {
-            index: image.index(),
-            srgb,
-        };
-
-        let data = texture_func(uri)
-            .await
-            .map_err(|e| GltfLoadError::TextureIo(uri.to_string(), e))?;
-        let parsed = image::load_from_memory(&data).map_err(|e| GltfLoadError::TextureLoad(uri.to_string(), e))?;
-        let rgba = parsed.to_rgba8();
-        let handle = renderer.add_texture_2d(dt::Texture {
-            label: image.name().map(str::to_owned),
-            format: match srgb {
-                true => dt::RendererTextureFormat::Rgba8Srgb,
-                false => dt::RendererTextureFormat::Rgba8Linear,
-            },
-            width: rgba.width(),
-            height: rgba.height(),
-            data: rgba.into_raw(),
-            /// TODO: automatic mipmapping (#53)
-            mip_levels: 1,
-        });
-
-        loaded.images.insert(key, handle);
+    let key = ImageKey {
+        index: image.index(),
+        srgb,
+    };
+
+    // Name used in errors
+    let name = match image.source() {
+        gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => uri.to_string(),
+        _ => image
+            .name()
+            .map_or_else(|| format!("#{}", image.index()), str::to_owned),
+    };
+
+    let data = match image.source() {
+        gltf::image::Source::Uri { uri, .. } if uri.starts_with("data:") => {
+            Cow::Owned(decode_data_uri(&format!("Image {}", image.index()), uri)?)
+        }
+        gltf::image::Source::Uri { uri, .. } => Cow::Owned(
+            io_func(uri)
+                .await
+                .map_err(|e| GltfLoadError::TextureIo(uri.to_string(), e))?,
+        ),
+        gltf::image::Source::View { view, .. } => Cow::Borrowed(view_data(buffers, &view)?),
+    };
+
+    let parsed = image::load_from_memory(&data).map_err(|e| GltfLoadError::TextureLoad(name, e))?;
+    let rgba = parsed.to_rgba8();
+    let handle = renderer.add_texture_2d(dt::Texture {
+        label: image.name().map(str::to_owned),
+        format: match srgb {
+            true => dt::RendererTextureFormat::Rgba8Srgb,
+            false => dt::RendererTextureFormat::Rgba8Linear,
+        },
+        width: rgba.width(),
+        height: rgba.height(),
+        data: rgba.into_raw(),
+        mip_levels: 1,
+        mip_mode: dt::MipmapMode::Generate,
+    });
+
+    loaded.images.insert(key, handle);
+
+    Ok(handle)
+}
 
-        Ok(handle)
-    } else {
-        unimplemented!()
+/// Decodes a `data:[<mediatype>];base64,<data>` uri. Only base64 uris are supported, as gltf exporters emit nothing
+/// else. `owner` names what the uri belongs to in errors.
+fn decode_data_uri(owner: &str, uri: &str) -> Result<Vec<u8>, GltfLoadError> {
+    let (header, data) = match uri.find(',') {
+        Some(comma) => (&uri[..comma], &uri[comma + 1..]),
+        None => return Err(GltfLoadError::UnsupportedDataUri(owner.to_string())),
+    };
+    if !header.ends_with(";base64") {
+        return Err(GltfLoadError::UnsupportedDataUri(owner.to_string()));
     }
+    base64::decode(data).map_err(|e| GltfLoadError::DataUriDecode(owner.to_string(), e))
 }
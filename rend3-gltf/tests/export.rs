@@ -0,0 +1,369 @@
+//! Tests of the gltf exporter.
+//!
+//! The structure of the exported files is checked, and they are imported back, through the gltf crate without a
+//! renderer. Loading them back through [`load_gltf`] needs a renderer, so those tests are ignored by default and fail
+//! if there is no adapter. The `software-rendering` CI job runs them on lavapipe.
+
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+use rend3::{
+    datatypes::{
+        AffineTransform, AlbedoComponent, Material, MaterialHandle, Mesh, MeshBuilder, MeshHandle, MipmapMode, Object,
+        ObjectHandle, RendererTextureFormat, SamplerAddressMode, SamplerOptions, Texture, TextureHandle,
+    },
+    Renderer, RendererBuilder, RendererOptions, ShadowCascadeOptions, ShadowFilter, VSyncMode,
+};
+use rend3_gltf::{export_glb, export_gltf, load_gltf, GltfExportScene, LoadedGltfScene};
+use serde_json::Value;
+use std::{convert::TryInto, io, sync::Arc};
+
+const BIN_URI: &str = "scene.bin";
+
+fn create_renderer() -> Arc<Renderer> {
+    let options = RendererOptions {
+        vsync: VSyncMode::Off,
+        size: [64, 64],
+        ambient: Vec4::ZERO,
+        shadow_cascades: ShadowCascadeOptions::default(),
+        shadow_filter: ShadowFilter::default(),
+    };
+
+    pollster::block_on(RendererBuilder::new(options).headless().build())
+        .unwrap_or_else(|e| panic!("Failed to create renderer: {:?}", e))
+}
+
+fn quad_mesh() -> Mesh {
+    MeshBuilder::new(vec![
+        Vec3::new(-1.0, -1.0, 0.5),
+        Vec3::new(1.0, -1.0, 0.5),
+        Vec3::new(1.0, 1.0, 1.5),
+        Vec3::new(-1.0, 1.0, 1.5),
+    ])
+    .with_vertex_normals(vec![Vec3::new(0.0, 0.0, -1.0); 4])
+    .with_vertex_uvs(vec![
+        Vec2::new(0.0, 1.0),
+        Vec2::new(1.0, 1.0),
+        Vec2::new(1.0, 0.0),
+        Vec2::new(0.0, 0.0),
+    ])
+    .with_indices(vec![0, 1, 2, 2, 3, 0])
+    .build()
+}
+
+fn checker_texture() -> Texture {
+    Texture {
+        data: vec![
+            255, 255, 255, 255, 0, 0, 0, 255, //
+            0, 0, 0, 255, 255, 255, 255, 255,
+        ],
+        format: RendererTextureFormat::Rgba8Srgb,
+        width: 2,
+        height: 2,
+        label: Some(String::from("checker")),
+        mip_levels: 1,
+        mip_mode: MipmapMode::Manual,
+    }
+}
+
+fn transforms() -> [Mat4; 2] {
+    [
+        Mat4::from_translation(Vec3::new(1.0, 2.0, 3.0)),
+        Mat4::from_scale_rotation_translation(Vec3::splat(2.0), Quat::from_rotation_y(0.5), Vec3::new(-4.0, 0.0, 1.0)),
+    ]
+}
+
+fn material(texture: TextureHandle) -> Material {
+    Material {
+        albedo: AlbedoComponent::TextureValue {
+            handle: texture,
+            value: Vec4::new(1.0, 0.5, 0.25, 1.0),
+        },
+        metallic_factor: Some(0.25),
+        roughness_factor: Some(0.75),
+        sampler: SamplerOptions {
+            address_mode_u: SamplerAddressMode::ClampToEdge,
+            ..SamplerOptions::NEAREST
+        },
+        ..Material::default()
+    }
+}
+
+fn object(mesh: MeshHandle, material: MaterialHandle, transform: Mat4) -> Object {
+    Object {
+        mesh,
+        material,
+        transform: AffineTransform { transform },
+        skeleton: None,
+        morph_weights: Vec::new(),
+    }
+}
+
+/// Adds a textured quad drawn twice to an export scene, and to the renderer if there is one. Without a renderer,
+/// handles are made up.
+fn build_scene(renderer: Option<&Renderer>) -> GltfExportScene {
+    let mut scene = GltfExportScene::new();
+
+    let mesh = quad_mesh();
+    let mesh_handle = match renderer {
+        Some(renderer) => renderer.add_mesh(mesh.clone()),
+        None => MeshHandle::from_raw(0),
+    };
+    scene.add_mesh(mesh_handle, mesh);
+
+    let texture = checker_texture();
+    let texture_handle = match renderer {
+        Some(renderer) => renderer.add_texture_2d(texture.clone()),
+        None => TextureHandle::from_raw(0),
+    };
+    scene.add_texture(texture_handle, texture);
+
+    let material = material(texture_handle);
+    let material_handle = match renderer {
+        Some(renderer) => renderer.add_material(material),
+        None => MaterialHandle::from_raw(0),
+    };
+    scene.add_material(material_handle, material);
+
+    for (idx, &transform) in transforms().iter().enumerate() {
+        let object = object(mesh_handle, material_handle, transform);
+        let object_handle = match renderer {
+            Some(renderer) => renderer.add_object(object.clone()),
+            None => ObjectHandle::from_raw(idx),
+        };
+        scene.add_object(object_handle, object);
+    }
+
+    scene
+}
+
+fn array_len(json: &Value, key: &str) -> usize {
+    json[key].as_array().map_or(0, Vec::len)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+async fn load(renderer: &Renderer, data: &[u8], bin: Vec<u8>) -> LoadedGltfScene {
+    load_gltf(renderer, data, |uri: &str| {
+        let result = if uri == BIN_URI {
+            Ok(bin.clone())
+        } else {
+            Err(io::Error::new(io::ErrorKind::NotFound, uri.to_owned()))
+        };
+        async move { result }
+    })
+    .await
+    .expect("exported scene failed to load")
+}
+
+/// Checks the loaded scene draws the objects where the exported scene did.
+fn check_loaded(loaded: &LoadedGltfScene) {
+    assert_eq!(loaded.meshes.len(), 1);
+    assert_eq!(loaded.images.len(), 1);
+    // The default material, and the exported one.
+    assert_eq!(loaded.materials.len(), 2);
+    assert_eq!(loaded.nodes.len(), 2);
+
+    let flip = Mat4::from_scale(Vec3::new(1.0, 1.0, -1.0));
+    for (node, &transform) in loaded.nodes.iter().zip(&transforms()) {
+        assert_eq!(node.objects.len(), 1);
+        // Loaded meshes are mirrored back, so the object transforms are mirrored as well.
+        let world = flip * node.local_transform * flip;
+        assert!(world.abs_diff_eq(transform, 1e-5), "{:?} != {:?}", world, transform);
+    }
+}
+
+#[test]
+fn export_gltf_structure() {
+    let scene = build_scene(None);
+
+    let (json, bin) = export_gltf(&scene, BIN_URI).unwrap();
+
+    let root: Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(array_len(&root, "nodes"), 2);
+    assert_eq!(array_len(&root["scenes"][0], "nodes"), 2);
+    // Both objects share the mesh and material, so they share the gltf mesh.
+    assert_eq!(array_len(&root, "meshes"), 1);
+    assert_eq!(array_len(&root, "materials"), 1);
+    assert_eq!(array_len(&root, "textures"), 1);
+    assert_eq!(array_len(&root, "images"), 1);
+    assert_eq!(array_len(&root, "samplers"), 1);
+    assert_eq!(root["buffers"][0]["uri"], BIN_URI);
+    assert_eq!(root["buffers"][0]["byteLength"], bin.len());
+
+    let gltf = gltf::Gltf::from_slice(&json).expect("exported gltf is invalid");
+    let primitive = gltf.meshes().next().unwrap().primitives().next().unwrap();
+    let reader = primitive.reader(|_| Some(bin.as_slice()));
+
+    let mesh = quad_mesh();
+    let positions: Vec<Vec3> = reader.read_positions().unwrap().map(Vec3::from).collect();
+    let expected: Vec<Vec3> = mesh
+        .vertex_positions
+        .iter()
+        .map(|p| Vec3::new(p.x, p.y, -p.z))
+        .collect();
+    assert_eq!(positions, expected);
+
+    let indices: Vec<u32> = reader.read_indices().unwrap().into_u32().collect();
+    assert_eq!(indices, vec![2, 1, 0, 0, 3, 2]);
+
+    let texture = primitive
+        .material()
+        .pbr_metallic_roughness()
+        .base_color_texture()
+        .unwrap();
+    assert_eq!(texture.tex_coord(), 0);
+    let sampler = texture.texture().sampler();
+    assert_eq!(sampler.wrap_s(), gltf::texture::WrappingMode::ClampToEdge);
+    assert_eq!(sampler.wrap_t(), gltf::texture::WrappingMode::Repeat);
+    assert_eq!(sampler.mag_filter(), Some(gltf::texture::MagFilter::Nearest));
+    assert_eq!(
+        sampler.min_filter(),
+        Some(gltf::texture::MinFilter::NearestMipmapNearest)
+    );
+
+    let flip = Mat4::from_scale(Vec3::new(1.0, 1.0, -1.0));
+    for (node, &transform) in gltf.nodes().zip(&transforms()) {
+        let matrix = Mat4::from_cols_array_2d(&node.transform().matrix());
+        assert!((flip * matrix * flip).abs_diff_eq(transform, 1e-5));
+    }
+}
+
+#[test]
+fn export_shares_accessors_between_materials() {
+    let mut scene = build_scene(None);
+
+    // The same mesh with a second material needs a second gltf mesh, but not a second copy of the vertices.
+    let texture = TextureHandle::from_raw(0);
+    let material = MaterialHandle::from_raw(1);
+    scene.add_material(
+        material,
+        Material {
+            unlit: true,
+            ..self::material(texture)
+        },
+    );
+    scene.add_object(
+        ObjectHandle::from_raw(2),
+        object(MeshHandle::from_raw(0), material, Mat4::IDENTITY),
+    );
+
+    let (json, _bin) = export_gltf(&scene, BIN_URI).unwrap();
+
+    let root: Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(array_len(&root, "nodes"), 3);
+    assert_eq!(array_len(&root, "meshes"), 2);
+    assert_eq!(array_len(&root, "materials"), 2);
+    // Both materials sample the texture the same way.
+    assert_eq!(array_len(&root, "textures"), 1);
+    assert_eq!(array_len(&root, "images"), 1);
+
+    let primitive = |mesh: usize| &root["meshes"][mesh]["primitives"][0];
+    assert_eq!(primitive(0)["attributes"], primitive(1)["attributes"]);
+    assert_eq!(primitive(0)["indices"], primitive(1)["indices"]);
+    assert_ne!(primitive(0)["material"], primitive(1)["material"]);
+}
+
+#[test]
+fn export_glb_structure() {
+    let scene = build_scene(None);
+
+    let glb = export_glb(&scene).unwrap();
+
+    // Header
+    assert_eq!(&glb[0..4], b"glTF");
+    assert_eq!(read_u32(&glb, 4), 2);
+    assert_eq!(read_u32(&glb, 8) as usize, glb.len());
+
+    // Json chunk, padded with spaces to four bytes.
+    let json_length = read_u32(&glb, 12) as usize;
+    assert_eq!(&glb[16..20], b"JSON");
+    assert_eq!(json_length % 4, 0);
+    let json = &glb[20..20 + json_length];
+    let root: Value = serde_json::from_slice(json).expect("json chunk is invalid");
+    assert!(root["buffers"][0].get("uri").is_none());
+
+    // Binary chunk, padded with zeros to four bytes, ending the file.
+    let bin_start = 20 + json_length;
+    let bin_length = read_u32(&glb, bin_start) as usize;
+    assert_eq!(&glb[bin_start + 4..bin_start + 8], b"BIN\0");
+    assert_eq!(bin_length % 4, 0);
+    assert_eq!(bin_start + 8 + bin_length, glb.len());
+    let buffer_length = root["buffers"][0]["byteLength"].as_u64().unwrap() as usize;
+    assert!(buffer_length <= bin_length && bin_length < buffer_length + 4);
+
+    let gltf = gltf::Gltf::from_slice(&glb).expect("exported glb is invalid");
+    assert_eq!(gltf.blob.as_ref().map(Vec::len), Some(bin_length));
+    assert_eq!(gltf.nodes().count(), 2);
+    assert_eq!(gltf.meshes().count(), 1);
+    assert_eq!(gltf.materials().count(), 1);
+}
+
+#[test]
+fn export_glb_import_round_trip() {
+    let scene = build_scene(None);
+
+    let glb = export_glb(&scene).unwrap();
+
+    let (document, buffers, images) = gltf::import_slice(&glb).expect("exported glb failed to import");
+
+    let primitive = document.meshes().next().unwrap().primitives().next().unwrap();
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    // Exported meshes are mirrored along z.
+    let mesh = quad_mesh();
+    let mirror = |v: &Vec3| Vec3::new(v.x, v.y, -v.z);
+    let positions: Vec<Vec3> = reader.read_positions().unwrap().map(Vec3::from).collect();
+    assert_eq!(positions, mesh.vertex_positions.iter().map(mirror).collect::<Vec<_>>());
+    let normals: Vec<Vec3> = reader.read_normals().unwrap().map(Vec3::from).collect();
+    assert_eq!(normals, mesh.vertex_normals.iter().map(mirror).collect::<Vec<_>>());
+    let uvs: Vec<Vec2> = reader.read_tex_coords(0).unwrap().into_f32().map(Vec2::from).collect();
+    assert_eq!(uvs, mesh.vertex_uvs);
+    let indices: Vec<u32> = reader.read_indices().unwrap().into_u32().collect();
+    assert_eq!(indices, vec![2, 1, 0, 0, 3, 2]);
+
+    let material = primitive.material();
+    let pbr = material.pbr_metallic_roughness();
+    assert_eq!(pbr.base_color_factor(), [1.0, 0.5, 0.25, 1.0]);
+    assert_eq!(pbr.metallic_factor(), 0.25);
+    assert_eq!(pbr.roughness_factor(), 0.75);
+
+    let image = &images[pbr.base_color_texture().unwrap().texture().source().index()];
+    let texture = checker_texture();
+    assert_eq!(image.format, gltf::image::Format::R8G8B8A8);
+    assert_eq!((image.width, image.height), (texture.width, texture.height));
+    assert_eq!(image.pixels, texture.data);
+
+    let flip = Mat4::from_scale(Vec3::new(1.0, 1.0, -1.0));
+    let nodes: Vec<_> = document.scenes().next().unwrap().nodes().collect();
+    assert_eq!(nodes.len(), 2);
+    for (node, &transform) in nodes.iter().zip(&transforms()) {
+        assert_eq!(node.mesh().map(|mesh| mesh.index()), Some(0));
+        let matrix = Mat4::from_cols_array_2d(&node.transform().matrix());
+        assert!((flip * matrix * flip).abs_diff_eq(transform, 1e-5));
+    }
+}
+
+#[test]
+#[ignore = "needs a gpu adapter"]
+fn export_gltf_load_round_trip() {
+    let renderer = create_renderer();
+    let scene = build_scene(Some(&renderer));
+
+    let (json, bin) = export_gltf(&scene, BIN_URI).unwrap();
+
+    let loaded = pollster::block_on(load(&renderer, &json, bin));
+    check_loaded(&loaded);
+}
+
+#[test]
+#[ignore = "needs a gpu adapter"]
+fn export_glb_load_round_trip() {
+    let renderer = create_renderer();
+    let scene = build_scene(Some(&renderer));
+
+    let glb = export_glb(&scene).unwrap();
+
+    let loaded = pollster::block_on(load(&renderer, &glb, Vec::new()));
+    check_loaded(&loaded);
+}
@@ -1,7 +1,7 @@
 use rend3::{
     datatypes::{
-        DepthCompare, Pipeline, PipelineBindingType, PipelineDepthState, PipelineHandle, PipelineInputType,
-        PipelineOutputAttachment, ShaderHandle,
+        DepthCompare, Pipeline, PipelineBindingType, PipelineBlendMode, PipelineDepthState, PipelineHandle,
+        PipelineInputType, PipelineOutputAttachment, ShaderHandle,
     },
     list::{
         Color, DepthOutput, ImageFormat, ImageInputReference, ImageOutput, ImageOutputReference,
@@ -13,6 +13,17 @@ use rend3::{
 };
 use std::{future::Future, sync::Arc};
 
+/// How the default render list draws objects with blended materials.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransparencyMode {
+    /// Objects are sorted back to front and blended over each other in order. Exact as long as objects don't
+    /// intersect or overlap out of order.
+    Sorted,
+    /// Weighted blended order-independent transparency. Needs no sorting and handles intersecting objects, at the
+    /// cost of approximating the order of overlapping surfaces by their distance.
+    WeightedBlended,
+}
+
 pub struct DefaultShaders {
     pub depth_vert: ShaderHandle,
     pub depth_frag: ShaderHandle,
@@ -20,6 +31,9 @@ pub struct DefaultShaders {
     pub skybox_frag: ShaderHandle,
     pub opaque_vert: ShaderHandle,
     pub opaque_frag: ShaderHandle,
+    pub transparent_frag: ShaderHandle,
+    pub weighted_blended_frag: ShaderHandle,
+    pub weighted_blended_composite_frag: ShaderHandle,
     pub blit_vert: ShaderHandle,
     pub blit_frag: ShaderHandle,
 }
@@ -73,6 +87,28 @@ impl DefaultShaders {
             includes: vec![],
             defines: vec![mode_define.clone()],
         });
+        let transparent_frag = renderer.add_source_shader(SourceShaderDescriptor {
+            source: ShaderSourceType::Builtin("opaque.frag".to_string()),
+            stage: ShaderSourceStage::Fragment,
+            includes: vec![],
+            defines: vec![mode_define.clone(), (String::from("TRANSPARENT"), None)],
+        });
+        let weighted_blended_frag = renderer.add_source_shader(SourceShaderDescriptor {
+            source: ShaderSourceType::Builtin("opaque.frag".to_string()),
+            stage: ShaderSourceStage::Fragment,
+            includes: vec![],
+            defines: vec![
+                mode_define.clone(),
+                (String::from("TRANSPARENT"), None),
+                (String::from("WEIGHTED_BLENDED"), None),
+            ],
+        });
+        let weighted_blended_composite_frag = renderer.add_source_shader(SourceShaderDescriptor {
+            source: ShaderSourceType::Builtin("oit_composite.frag".to_string()),
+            stage: ShaderSourceStage::Fragment,
+            includes: vec![],
+            defines: vec![mode_define.clone()],
+        });
 
         let blit_vert = renderer.add_source_shader(SourceShaderDescriptor {
             source: ShaderSourceType::Builtin("blit.vert".to_string()),
@@ -94,6 +130,9 @@ impl DefaultShaders {
             let skybox_frag = skybox_frag.await;
             let opaque_vert = opaque_vert.await;
             let opaque_frag = opaque_frag.await;
+            let transparent_frag = transparent_frag.await;
+            let weighted_blended_frag = weighted_blended_frag.await;
+            let weighted_blended_composite_frag = weighted_blended_composite_frag.await;
             let blit_vert = blit_vert.await;
             let blit_frag = blit_frag.await;
             Self {
@@ -103,6 +142,9 @@ impl DefaultShaders {
                 skybox_frag,
                 opaque_vert,
                 opaque_frag,
+                transparent_frag,
+                weighted_blended_frag,
+                weighted_blended_composite_frag,
                 blit_vert,
                 blit_frag,
             }
@@ -113,9 +155,13 @@ impl DefaultShaders {
 #[derive(Debug)]
 pub struct DefaultPipelines {
     pub shadow_depth_pipeline: PipelineHandle,
+    pub point_shadow_depth_pipeline: PipelineHandle,
     pub depth_pipeline: PipelineHandle,
     pub skybox_pipeline: PipelineHandle,
     pub opaque_pipeline: PipelineHandle,
+    pub transparent_pipeline: PipelineHandle,
+    pub weighted_blended_pipeline: PipelineHandle,
+    pub weighted_blended_composite_pipeline: PipelineHandle,
     pub blit_pipeline: PipelineHandle,
 }
 
@@ -148,6 +194,7 @@ impl DefaultPipelines {
                 PipelineBindingType::ObjectData,
                 PipelineBindingType::ShadowTexture,
                 PipelineBindingType::CameraData,
+                PipelineBindingType::EnvironmentTextures,
                 PipelineBindingType::CPUMaterial,
             ],
             RendererMode::GPUPowered => vec![
@@ -155,6 +202,7 @@ impl DefaultPipelines {
                 PipelineBindingType::ObjectData,
                 PipelineBindingType::ShadowTexture,
                 PipelineBindingType::CameraData,
+                PipelineBindingType::EnvironmentTextures,
                 PipelineBindingType::GPUMaterial,
                 PipelineBindingType::GPU2DTextures,
             ],
@@ -167,6 +215,22 @@ impl DefaultPipelines {
             depth: Some(PipelineDepthState {
                 format: ImageFormat::Depth32Float,
                 compare: DepthCompare::Closer,
+                write: true,
+            }),
+            vertex: shaders.depth_vert,
+            fragment: Some(shaders.depth_frag),
+            bindings: depth_bindings.clone(),
+            samples: 1,
+        });
+
+        let point_shadow_depth_pipeline = renderer.add_pipeline(Pipeline {
+            run_rate: RenderPassRunRate::PerPointShadow,
+            input: PipelineInputType::Models3d,
+            outputs: vec![],
+            depth: Some(PipelineDepthState {
+                format: ImageFormat::Depth32Float,
+                compare: DepthCompare::Closer,
+                write: true,
             }),
             vertex: shaders.depth_vert,
             fragment: Some(shaders.depth_frag),
@@ -181,15 +245,18 @@ impl DefaultPipelines {
                 PipelineOutputAttachment {
                     format: ImageFormat::Rgba16Float,
                     write: false,
+                    blend: PipelineBlendMode::Replace,
                 },
                 PipelineOutputAttachment {
                     format: ImageFormat::Rgba16Float,
                     write: false,
+                    blend: PipelineBlendMode::Replace,
                 },
             ],
             depth: Some(PipelineDepthState {
                 format: ImageFormat::Depth32Float,
                 compare: DepthCompare::Closer,
+                write: true,
             }),
             vertex: shaders.depth_vert,
             fragment: Some(shaders.depth_frag),
@@ -204,15 +271,18 @@ impl DefaultPipelines {
                 PipelineOutputAttachment {
                     format: ImageFormat::Rgba16Float,
                     write: true,
+                    blend: PipelineBlendMode::Replace,
                 },
                 PipelineOutputAttachment {
                     format: ImageFormat::Rgba16Float,
                     write: false,
+                    blend: PipelineBlendMode::Replace,
                 },
             ],
             depth: Some(PipelineDepthState {
                 format: ImageFormat::Depth32Float,
                 compare: DepthCompare::Equal,
+                write: true,
             }),
             vertex: shaders.skybox_vert,
             fragment: Some(shaders.skybox_frag),
@@ -231,28 +301,103 @@ impl DefaultPipelines {
                 PipelineOutputAttachment {
                     format: ImageFormat::Rgba16Float,
                     write: true,
+                    blend: PipelineBlendMode::Replace,
                 },
                 PipelineOutputAttachment {
                     format: ImageFormat::Rgba16Float,
                     write: true,
+                    blend: PipelineBlendMode::Replace,
                 },
             ],
             depth: Some(PipelineDepthState {
                 format: ImageFormat::Depth32Float,
                 compare: DepthCompare::Equal,
+                write: true,
             }),
             vertex: shaders.opaque_vert,
             fragment: Some(shaders.opaque_frag),
+            bindings: opaque_bindings.clone(),
+            samples: 1,
+        });
+
+        let transparent_pipeline = renderer.add_pipeline(Pipeline {
+            run_rate: RenderPassRunRate::Once,
+            input: PipelineInputType::Models3d,
+            outputs: vec![
+                PipelineOutputAttachment {
+                    format: ImageFormat::Rgba16Float,
+                    write: true,
+                    blend: PipelineBlendMode::AlphaBlend,
+                },
+                PipelineOutputAttachment {
+                    format: ImageFormat::Rgba16Float,
+                    write: false,
+                    blend: PipelineBlendMode::Replace,
+                },
+            ],
+            // Transparent objects aren't in the depth prepass, so they test against it without writing to it.
+            depth: Some(PipelineDepthState {
+                format: ImageFormat::Depth32Float,
+                compare: DepthCompare::Closer,
+                write: false,
+            }),
+            vertex: shaders.opaque_vert,
+            fragment: Some(shaders.transparent_frag),
+            bindings: opaque_bindings.clone(),
+            samples: 1,
+        });
+
+        let weighted_blended_pipeline = renderer.add_pipeline(Pipeline {
+            run_rate: RenderPassRunRate::Once,
+            input: PipelineInputType::Models3d,
+            outputs: vec![
+                PipelineOutputAttachment {
+                    format: ImageFormat::Rgba16Float,
+                    write: true,
+                    blend: PipelineBlendMode::Additive,
+                },
+                PipelineOutputAttachment {
+                    format: ImageFormat::R16Float,
+                    write: true,
+                    blend: PipelineBlendMode::InverseMultiply,
+                },
+            ],
+            depth: Some(PipelineDepthState {
+                format: ImageFormat::Depth32Float,
+                compare: DepthCompare::Closer,
+                write: false,
+            }),
+            vertex: shaders.opaque_vert,
+            fragment: Some(shaders.weighted_blended_frag),
             bindings: opaque_bindings,
             samples: 1,
         });
 
+        let weighted_blended_composite_pipeline = renderer.add_pipeline(Pipeline {
+            run_rate: RenderPassRunRate::Once,
+            input: PipelineInputType::FullscreenTriangle,
+            outputs: vec![PipelineOutputAttachment {
+                format: ImageFormat::Rgba16Float,
+                write: true,
+                blend: PipelineBlendMode::AlphaBlend,
+            }],
+            depth: None,
+            vertex: shaders.blit_vert,
+            fragment: Some(shaders.weighted_blended_composite_frag),
+            bindings: vec![
+                PipelineBindingType::GeneralData,
+                PipelineBindingType::Custom2DTexture { count: 2 },
+            ],
+            samples: 1,
+        });
+
         let blit_pipeline = renderer.add_pipeline(Pipeline {
             run_rate: RenderPassRunRate::Once,
             input: PipelineInputType::FullscreenTriangle,
             outputs: vec![PipelineOutputAttachment {
                 format: SWAPCHAIN_FORMAT,
                 write: true,
+                blend: PipelineBlendMode::Replace,
             }],
             depth: None,
             vertex: shaders.blit_vert,
@@ -266,22 +411,35 @@ impl DefaultPipelines {
 
         async move {
             let shadow_depth_pipeline = shadow_depth_pipeline.await;
+            let point_shadow_depth_pipeline = point_shadow_depth_pipeline.await;
             let depth_pipeline = depth_pipeline.await;
             let skybox_pipeline = skybox_pipeline.await;
             let opaque_pipeline = opaque_pipeline.await;
+            let transparent_pipeline = transparent_pipeline.await;
+            let weighted_blended_pipeline = weighted_blended_pipeline.await;
+            let weighted_blended_composite_pipeline = weighted_blended_composite_pipeline.await;
             let blit_pipeline = blit_pipeline.await;
             Self {
                 shadow_depth_pipeline,
+                point_shadow_depth_pipeline,
                 depth_pipeline,
                 skybox_pipeline,
                 opaque_pipeline,
+                transparent_pipeline,
+                weighted_blended_pipeline,
+                weighted_blended_composite_pipeline,
                 blit_pipeline,
             }
         }
     }
 }
 
-pub fn default_render_list(mode: RendererMode, resolution: [u32; 2], pipelines: &DefaultPipelines) -> RenderList {
+pub fn default_render_list(
+    mode: RendererMode,
+    resolution: [u32; 2],
+    pipelines: &DefaultPipelines,
+    transparency: TransparencyMode,
+) -> RenderList {
     let (depth_bindings, depth_per_obj_bindings) = match mode {
         RendererMode::CPUPowered => (
             vec![
@@ -310,6 +468,7 @@ pub fn default_render_list(mode: RendererMode, resolution: [u32; 2], pipelines:
                 ResourceBinding::ObjectData,
                 ResourceBinding::ShadowTexture,
                 ResourceBinding::CameraData,
+                ResourceBinding::EnvironmentTextures,
             ],
             vec![PerObjectResourceBinding::CPUMaterial],
         ),
@@ -319,6 +478,7 @@ pub fn default_render_list(mode: RendererMode, resolution: [u32; 2], pipelines:
                 ResourceBinding::ObjectData,
                 ResourceBinding::ShadowTexture,
                 ResourceBinding::CameraData,
+                ResourceBinding::EnvironmentTextures,
                 ResourceBinding::GPUMaterial,
                 ResourceBinding::GPU2DTextures,
             ],
@@ -344,6 +504,22 @@ pub fn default_render_list(mode: RendererMode, resolution: [u32; 2], pipelines:
         per_object_bindings: depth_per_obj_bindings.clone(),
     });
 
+    list.add_render_pass(RenderPassDescriptor {
+        run_rate: RenderPassRunRate::PerPointShadow,
+        outputs: vec![],
+        depth: Some(DepthOutput {
+            clear: LoadOp::Clear(1.0),
+            output: ImageOutputReference::OutputImage,
+        }),
+    });
+
+    list.add_render_op(RenderOpDescriptor {
+        pipeline: pipelines.point_shadow_depth_pipeline,
+        input: RenderOpInputType::Models3D,
+        per_op_bindings: depth_bindings.clone(),
+        per_object_bindings: depth_per_obj_bindings.clone(),
+    });
+
     let internal_renderbuffer_name = "color renderbuffer";
 
     list.create_image(
@@ -417,10 +593,92 @@ pub fn default_render_list(mode: RendererMode, resolution: [u32; 2], pipelines:
     list.add_render_op(RenderOpDescriptor {
         pipeline: pipelines.opaque_pipeline,
         input: RenderOpInputType::Models3D,
-        per_op_bindings: opaque_bindings,
-        per_object_bindings: opaque_per_obj_binding,
+        per_op_bindings: opaque_bindings.clone(),
+        per_object_bindings: opaque_per_obj_binding.clone(),
     });
 
+    match transparency {
+        TransparencyMode::Sorted => {
+            list.add_render_op(RenderOpDescriptor {
+                pipeline: pipelines.transparent_pipeline,
+                input: RenderOpInputType::TransparentModels3D,
+                per_op_bindings: opaque_bindings,
+                per_object_bindings: opaque_per_obj_binding,
+            });
+        }
+        TransparencyMode::WeightedBlended => {
+            list.create_image(
+                "accumulation buffer",
+                ImageResourceDescriptor {
+                    resolution,
+                    format: ImageFormat::Rgba16Float,
+                    samples: 1,
+                    usage: ImageUsage::SAMPLED | ImageUsage::RENDER_ATTACHMENT,
+                },
+            );
+
+            list.create_image(
+                "revealage buffer",
+                ImageResourceDescriptor {
+                    resolution,
+                    format: ImageFormat::R16Float,
+                    samples: 1,
+                    usage: ImageUsage::SAMPLED | ImageUsage::RENDER_ATTACHMENT,
+                },
+            );
+
+            list.add_render_pass(RenderPassDescriptor {
+                run_rate: RenderPassRunRate::Once,
+                outputs: vec![
+                    ImageOutput {
+                        output: ImageOutputReference::Custom(String::from("accumulation buffer")),
+                        resolve_target: None,
+                        clear: LoadOp::Clear(Color::TRANSPARENT),
+                    },
+                    ImageOutput {
+                        output: ImageOutputReference::Custom(String::from("revealage buffer")),
+                        resolve_target: None,
+                        clear: LoadOp::Clear(Color::WHITE),
+                    },
+                ],
+                depth: Some(DepthOutput {
+                    clear: LoadOp::Load,
+                    output: ImageOutputReference::Custom(String::from("depth buffer")),
+                }),
+            });
+
+            list.add_render_op(RenderOpDescriptor {
+                pipeline: pipelines.weighted_blended_pipeline,
+                input: RenderOpInputType::TransparentModels3D,
+                per_op_bindings: opaque_bindings,
+                per_object_bindings: opaque_per_obj_binding,
+            });
+
+            list.add_render_pass(RenderPassDescriptor {
+                run_rate: RenderPassRunRate::Once,
+                outputs: vec![ImageOutput {
+                    output: ImageOutputReference::Custom(internal_renderbuffer_name.to_owned()),
+                    resolve_target: None,
+                    clear: LoadOp::Load,
+                }],
+                depth: None,
+            });
+
+            list.add_render_op(RenderOpDescriptor {
+                pipeline: pipelines.weighted_blended_composite_pipeline,
+                input: RenderOpInputType::FullscreenTriangle,
+                per_op_bindings: vec![
+                    ResourceBinding::GeneralData,
+                    ResourceBinding::Custom2DTexture(vec![
+                        ImageInputReference::Custom(String::from("accumulation buffer")),
+                        ImageInputReference::Custom(String::from("revealage buffer")),
+                    ]),
+                ],
+                per_object_bindings: vec![],
+            });
+        }
+    }
+
     list.add_render_pass(RenderPassDescriptor {
         run_rate: RenderPassRunRate::Once,
         outputs: vec![ImageOutput {
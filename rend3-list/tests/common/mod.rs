@@ -0,0 +1,61 @@
+//! Renderer fixture shared by the tests rendering on a vulkan software adapter.
+//!
+//! Set `REND3_TEST_DEVICE` to choose a different adapter than llvmpipe (lavapipe).
+
+use glam::Vec4;
+use rend3::{
+    RenderedImage, Renderer, RendererBuilder, RendererInitializationError, RendererMode, RendererOptions,
+    ShadowCascadeOptions, ShadowFilter, VSyncMode,
+};
+use rend3_list::{default_render_list, DefaultPipelines, DefaultShaders, TransparencyMode};
+use std::sync::Arc;
+
+/// Creates a headless renderer on the test adapter. Panics if there is no such adapter, or if it doesn't support the
+/// requested mode.
+pub fn create_renderer(size: [u32; 2], mode: Option<RendererMode>) -> Arc<Renderer> {
+    let device = std::env::var("REND3_TEST_DEVICE").unwrap_or_else(|_| String::from("llvmpipe"));
+
+    let options = RendererOptions {
+        vsync: VSyncMode::Off,
+        size,
+        ambient: Vec4::new(0.1, 0.1, 0.1, 1.0),
+        shadow_cascades: ShadowCascadeOptions::default(),
+        shadow_filter: ShadowFilter::default(),
+    };
+
+    let renderer = pollster::block_on(
+        RendererBuilder::new(options)
+            .headless()
+            .desired_device(Some(wgpu::Backend::Vulkan), Some(device.to_lowercase()), mode)
+            .build(),
+    );
+
+    match renderer {
+        Ok(renderer) if mode.map_or(true, |mode| renderer.mode() == mode) => renderer,
+        Ok(renderer) => panic!(
+            "Adapter {:?} only supports {:?} mode, but the test needs {:?} mode",
+            device,
+            renderer.mode(),
+            mode
+        ),
+        Err(RendererInitializationError::MissingAdapter) => panic!(
+            "No adapter matching {:?} found, install lavapipe or set REND3_TEST_DEVICE",
+            device
+        ),
+        Err(e) => panic!("Failed to create renderer: {:?}", e),
+    }
+}
+
+pub fn create_pipelines(renderer: &Arc<Renderer>) -> DefaultPipelines {
+    pollster::block_on(async {
+        let shaders = DefaultShaders::new(renderer).await;
+        DefaultPipelines::new(renderer, &shaders).await
+    })
+}
+
+/// Renders a frame of the default render list and reads it back.
+pub fn render(renderer: &Arc<Renderer>, pipelines: &DefaultPipelines, size: [u32; 2]) -> RenderedImage {
+    let list = default_render_list(renderer.mode(), size, pipelines, TransparencyMode::Sorted);
+    let (_stats, image) = pollster::block_on(renderer.render_to_image(list));
+    image
+}
@@ -0,0 +1,323 @@
+//! Golden image tests for the default render list.
+//!
+//! Each canned scene is rendered headless in both renderer modes on a software adapter and compared against the
+//! reference image in `tests/golden/<scene>-<mode>.png`. Set `REND3_TEST_DEVICE` to choose a different adapter than
+//! llvmpipe (lavapipe).
+//!
+//! These need a vulkan software adapter, so they are ignored by default. Run them with `cargo test -p rend3-list --
+//! --ignored`; a missing adapter is then a failure rather than a skip. The `software-rendering` CI job runs them on
+//! lavapipe.
+//!
+//! Set `REND3_BLESS` to write the rendered images as the new references instead of comparing against them. A missing
+//! reference is a failure. On failure, the rendered image and a diff image are written to `target/golden-failures`.
+//! References must come from the same adapter as CI, so generate them with the "Bless golden images" workflow and
+//! commit its artifact to `tests/golden`.
+
+use glam::{Mat4, Vec3, Vec3A, Vec4};
+use rend3::{
+    datatypes::{
+        AffineTransform, AlbedoComponent, Camera, CameraProjection, DirectionalLight, InstancedObject, Material, Mesh,
+        MeshBuilder, Object,
+    },
+    RenderedImage, Renderer, RendererMode,
+};
+use std::path::{Path, PathBuf};
+
+mod common;
+
+const RESOLUTION: [u32; 2] = [256, 256];
+
+/// Maximum perceptual color difference (YIQ, normalized to 0-1) before a pixel counts as different.
+const PIXEL_THRESHOLD: f32 = 0.1;
+/// Fraction of pixels that may differ before the comparison fails.
+const MAX_DIFFERENT_FRACTION: f32 = 0.001;
+
+fn mode_name(mode: RendererMode) -> &'static str {
+    match mode {
+        RendererMode::CPUPowered => "cpu",
+        RendererMode::GPUPowered => "gpu",
+    }
+}
+
+fn cube_mesh() -> Mesh {
+    let positions = vec![
+        // far side (0.0, 0.0, 1.0)
+        Vec3::new(-1.0, -1.0, 1.0),
+        Vec3::new(1.0, -1.0, 1.0),
+        Vec3::new(1.0, 1.0, 1.0),
+        Vec3::new(-1.0, 1.0, 1.0),
+        // near side (0.0, 0.0, -1.0)
+        Vec3::new(-1.0, 1.0, -1.0),
+        Vec3::new(1.0, 1.0, -1.0),
+        Vec3::new(1.0, -1.0, -1.0),
+        Vec3::new(-1.0, -1.0, -1.0),
+        // right side (1.0, 0.0, 0.0)
+        Vec3::new(1.0, -1.0, -1.0),
+        Vec3::new(1.0, 1.0, -1.0),
+        Vec3::new(1.0, 1.0, 1.0),
+        Vec3::new(1.0, -1.0, 1.0),
+        // left side (-1.0, 0.0, 0.0)
+        Vec3::new(-1.0, -1.0, 1.0),
+        Vec3::new(-1.0, 1.0, 1.0),
+        Vec3::new(-1.0, 1.0, -1.0),
+        Vec3::new(-1.0, -1.0, -1.0),
+        // top (0.0, 1.0, 0.0)
+        Vec3::new(1.0, 1.0, -1.0),
+        Vec3::new(-1.0, 1.0, -1.0),
+        Vec3::new(-1.0, 1.0, 1.0),
+        Vec3::new(1.0, 1.0, 1.0),
+        // bottom (0.0, -1.0, 0.0)
+        Vec3::new(1.0, -1.0, 1.0),
+        Vec3::new(-1.0, -1.0, 1.0),
+        Vec3::new(-1.0, -1.0, -1.0),
+        Vec3::new(1.0, -1.0, -1.0),
+    ];
+
+    let indices = vec![
+        0, 1, 2, 2, 3, 0, // far
+        4, 5, 6, 6, 7, 4, // near
+        8, 9, 10, 10, 11, 8, // right
+        12, 13, 14, 14, 15, 12, // left
+        16, 17, 18, 18, 19, 16, // top
+        20, 21, 22, 22, 23, 20, // bottom
+    ];
+
+    MeshBuilder::new(positions).with_indices(indices).build()
+}
+
+fn add_colored_object(renderer: &Renderer, mesh: &Mesh, color: Vec4, transform: Mat4) {
+    let mesh = renderer.add_mesh(mesh.clone());
+    let material = renderer.add_material(Material {
+        albedo: AlbedoComponent::Value(color),
+        ..Material::default()
+    });
+    renderer.add_object(Object {
+        mesh,
+        material,
+        transform: AffineTransform { transform },
+        skeleton: None,
+        morph_weights: Vec::new(),
+    });
+}
+
+fn setup_camera_and_light(renderer: &Renderer) {
+    renderer.set_camera_data(Camera {
+        projection: CameraProjection::Projection {
+            vfov: 60.0,
+            near: 0.1,
+            pitch: 0.5,
+            yaw: -0.55,
+        },
+        location: Vec3A::new(3.0, 3.0, -5.0),
+    });
+
+    renderer.add_directional_light(DirectionalLight {
+        color: Vec3::ONE,
+        intensity: 10.0,
+        direction: Vec3::new(-1.0, -4.0, 2.0),
+        shadow_resolution: 2048,
+        depth_bias: 1.0,
+        normal_bias: 1.0,
+    });
+}
+
+/// A single cube sitting on a ground plane, exercising lighting and the shadow pass.
+fn scene_cube(renderer: &Renderer) {
+    let cube = cube_mesh();
+    add_colored_object(renderer, &cube, Vec4::new(0.0, 0.5, 0.5, 1.0), Mat4::IDENTITY);
+    add_colored_object(
+        renderer,
+        &cube,
+        Vec4::new(0.8, 0.8, 0.8, 1.0),
+        Mat4::from_translation(Vec3::new(0.0, -1.1, 0.0)) * Mat4::from_scale(Vec3::new(10.0, 0.1, 10.0)),
+    );
+    setup_camera_and_light(renderer);
+}
+
+/// A grid of cubes, most of which are outside of the view frustum, exercising culling.
+fn scene_culling(renderer: &Renderer) {
+    let cube = cube_mesh();
+    for x in -10..=10 {
+        for z in -10..=10 {
+            let color = Vec4::new((x + 10) as f32 / 20.0, 0.5, (z + 10) as f32 / 20.0, 1.0);
+            let transform = Mat4::from_translation(Vec3::new(x as f32 * 3.0, 0.0, z as f32 * 3.0))
+                * Mat4::from_scale(Vec3::splat(0.5));
+            add_colored_object(renderer, &cube, color, transform);
+        }
+    }
+    setup_camera_and_light(renderer);
+}
+
+/// The culling grid as a single instanced object, exercising per-instance culling and batched draws.
+fn scene_instanced(renderer: &Renderer) {
+    let mesh = renderer.add_mesh(cube_mesh());
+    let material = renderer.add_material(Material {
+        albedo: AlbedoComponent::Value(Vec4::new(0.8, 0.5, 0.2, 1.0)),
+        ..Material::default()
+    });
+    let transforms = (-10..=10)
+        .flat_map(|x| (-10..=10).map(move |z| (x, z)))
+        .map(|(x, z)| AffineTransform {
+            transform: Mat4::from_translation(Vec3::new(x as f32 * 3.0, 0.0, z as f32 * 3.0))
+                * Mat4::from_scale(Vec3::splat(0.5)),
+        })
+        .collect();
+    renderer.add_instanced_object(InstancedObject {
+        mesh,
+        material,
+        transforms,
+    });
+    setup_camera_and_light(renderer);
+}
+
+fn render_scene(mode: RendererMode, scene: fn(&Renderer)) -> RenderedImage {
+    let renderer = common::create_renderer(RESOLUTION, Some(mode));
+    let pipelines = common::create_pipelines(&renderer);
+
+    scene(&renderer);
+
+    common::render(&renderer, &pipelines, RESOLUTION)
+}
+
+/// Perceptual distance between two rgb colors, using the weighted YIQ difference from
+/// "Measuring perceived color difference using YIQ NTSC transmission color space in mobile applications"
+/// normalized to 0-1.
+fn color_delta(a: &[u8], b: &[u8]) -> f32 {
+    let to_yiq = |c: &[u8]| {
+        let [r, g, b] = [c[0] as f32, c[1] as f32, c[2] as f32];
+        (
+            r * 0.298_895_3 + g * 0.586_622_47 + b * 0.114_482_23,
+            r * 0.595_977_99 - g * 0.274_176_1 - b * 0.321_801_89,
+            r * 0.211_470_17 - g * 0.522_617_2 + b * 0.311_147_03,
+        )
+    };
+
+    let (ya, ia, qa) = to_yiq(a);
+    let (yb, ib, qb) = to_yiq(b);
+    let (y, i, q) = (ya - yb, ia - ib, qa - qb);
+
+    (0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q) / 35215.0
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden"))
+}
+
+fn failure_dir() -> PathBuf {
+    PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../target/golden-failures"))
+}
+
+fn save_png(path: &Path, size: [u32; 2], data: &[u8]) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    image::save_buffer(path, data, size[0], size[1], image::ColorType::Rgba8)
+        .unwrap_or_else(|e| panic!("Failed to write {}: {}", path.display(), e));
+}
+
+fn compare_with_reference(name: &str, image: &RenderedImage) {
+    let reference_path = golden_dir().join(format!("{}.png", name));
+
+    if std::env::var_os("REND3_BLESS").is_some() {
+        eprintln!("Writing reference image {}", reference_path.display());
+        save_png(&reference_path, image.size, &image.data);
+        return;
+    }
+
+    if !reference_path.exists() {
+        let actual_path = failure_dir().join(format!("{}-actual.png", name));
+        save_png(&actual_path, image.size, &image.data);
+        panic!(
+            "{}: missing reference {}, rendered image written to {}. Rerun with REND3_BLESS=1 to accept it",
+            name,
+            reference_path.display(),
+            actual_path.display()
+        );
+    }
+
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", reference_path.display(), e))
+        .to_rgba8();
+
+    let actual_path = failure_dir().join(format!("{}-actual.png", name));
+    let diff_path = failure_dir().join(format!("{}-diff.png", name));
+
+    if reference.dimensions() != (image.size[0], image.size[1]) {
+        save_png(&actual_path, image.size, &image.data);
+        panic!(
+            "{}: reference is {:?} but rendered image is {:?}, rendered image written to {}",
+            name,
+            reference.dimensions(),
+            image.size,
+            actual_path.display()
+        );
+    }
+
+    let mut different = 0_usize;
+    let mut diff = Vec::with_capacity(image.data.len());
+    for (actual, expected) in image.data.chunks_exact(4).zip(reference.as_raw().chunks_exact(4)) {
+        if color_delta(actual, expected) > PIXEL_THRESHOLD * PIXEL_THRESHOLD {
+            different += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            // Faded grayscale version of the reference so the differences stand out.
+            let gray = (expected[0] as u32 + expected[1] as u32 + expected[2] as u32) / 3;
+            let faded = (255 - (255 - gray) / 4) as u8;
+            diff.extend_from_slice(&[faded, faded, faded, 255]);
+        }
+    }
+
+    let pixel_count = (image.size[0] * image.size[1]) as f32;
+    if different as f32 > pixel_count * MAX_DIFFERENT_FRACTION {
+        save_png(&actual_path, image.size, &image.data);
+        save_png(&diff_path, image.size, &diff);
+        panic!(
+            "{}: {} of {} pixels differ from {}, see {} and {}",
+            name,
+            different,
+            pixel_count,
+            reference_path.display(),
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+fn golden_test(scene_name: &str, mode: RendererMode, scene: fn(&Renderer)) {
+    let image = render_scene(mode, scene);
+    compare_with_reference(&format!("{}-{}", scene_name, mode_name(mode)), &image);
+}
+
+#[test]
+#[ignore = "needs a vulkan software adapter"]
+fn cube_cpu() {
+    golden_test("cube", RendererMode::CPUPowered, scene_cube);
+}
+
+#[test]
+#[ignore = "needs a vulkan software adapter"]
+fn cube_gpu() {
+    golden_test("cube", RendererMode::GPUPowered, scene_cube);
+}
+
+#[test]
+#[ignore = "needs a vulkan software adapter"]
+fn culling_cpu() {
+    golden_test("culling", RendererMode::CPUPowered, scene_culling);
+}
+
+#[test]
+#[ignore = "needs a vulkan software adapter"]
+fn culling_gpu() {
+    golden_test("culling", RendererMode::GPUPowered, scene_culling);
+}
+
+#[test]
+#[ignore = "needs a vulkan software adapter"]
+fn instanced_cpu() {
+    golden_test("instanced", RendererMode::CPUPowered, scene_instanced);
+}
+
+#[test]
+#[ignore = "needs a vulkan software adapter"]
+fn instanced_gpu() {
+    golden_test("instanced", RendererMode::GPUPowered, scene_instanced);
+}
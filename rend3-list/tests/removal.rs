@@ -0,0 +1,74 @@
+//! Tests removing resources while objects still reference them.
+//!
+//! Like the golden image tests, these need a vulkan software adapter, so they are ignored by default and only run by
+//! `cargo test -- --ignored` and the `software-rendering` CI job. A plain `cargo test` doesn't cover them.
+
+use glam::{Mat4, Vec3, Vec3A, Vec4};
+use rend3::datatypes::{
+    AffineTransform, AlbedoComponent, Camera, CameraProjection, DirectionalLight, Material, MeshBuilder, Object,
+    Skeleton,
+};
+
+mod common;
+
+const RESOLUTION: [u32; 2] = [64, 64];
+
+#[test]
+#[ignore = "needs a vulkan software adapter"]
+fn skeleton_removed_before_object() {
+    let renderer = common::create_renderer(RESOLUTION, None);
+    let pipelines = common::create_pipelines(&renderer);
+
+    let mesh = MeshBuilder::new(vec![
+        Vec3::new(-1.0, -1.0, 0.0),
+        Vec3::new(1.0, -1.0, 0.0),
+        Vec3::new(1.0, 1.0, 0.0),
+        Vec3::new(-1.0, 1.0, 0.0),
+    ])
+    .with_vertex_joint_indices(vec![[0, 0, 0, 0]; 4])
+    .with_vertex_joint_weights(vec![Vec4::new(1.0, 0.0, 0.0, 0.0); 4])
+    .with_indices(vec![0, 1, 2, 2, 3, 0])
+    .build();
+
+    // An identity joint leaves the mesh in its bind pose, so removing the skeleton mustn't change the frame.
+    let skeleton = renderer.add_skeleton(Skeleton {
+        joint_matrices: vec![Mat4::IDENTITY],
+    });
+    renderer.add_object(Object {
+        mesh: renderer.add_mesh(mesh),
+        material: renderer.add_material(Material {
+            albedo: AlbedoComponent::Value(Vec4::new(1.0, 0.5, 0.0, 1.0)),
+            ..Material::default()
+        }),
+        transform: AffineTransform {
+            transform: Mat4::from_translation(Vec3::new(0.0, 0.0, 3.0)),
+        },
+        skeleton: Some(skeleton),
+        morph_weights: Vec::new(),
+    });
+
+    renderer.set_camera_data(Camera {
+        projection: CameraProjection::Projection {
+            vfov: 60.0,
+            near: 0.1,
+            pitch: 0.0,
+            yaw: 0.0,
+        },
+        location: Vec3A::ZERO,
+    });
+    renderer.add_directional_light(DirectionalLight {
+        color: Vec3::ONE,
+        intensity: 10.0,
+        direction: Vec3::new(0.0, 0.0, 1.0),
+        shadow_resolution: 256,
+        depth_bias: 1.0,
+        normal_bias: 1.0,
+    });
+
+    let skinned = common::render(&renderer, &pipelines, RESOLUTION);
+
+    renderer.remove_skeleton(skeleton);
+    let unskinned = common::render(&renderer, &pipelines, RESOLUTION);
+
+    assert_eq!(skinned.data, unskinned.data);
+}
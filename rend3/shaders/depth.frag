@@ -10,8 +10,8 @@ layout(location = 0) in vec4 i_position;
 layout(location = 1) in vec2 i_coords;
 layout(location = 2) in vec4 i_color;
 layout(location = 3) flat in uint i_material;
+layout(location = 4) in vec2 i_coords1;
 
-layout(set = 0, binding = 0) uniform sampler linear_sampler;
 layout(set = 1, binding = 0, std430) restrict readonly buffer ObjectOutputDataBuffer {
     ObjectOutputData object_output[];
 };
@@ -22,6 +22,8 @@ layout(set = 2, binding = 0) uniform UniformBuffer {
 layout(set = 3, binding = 0, std430) restrict readonly buffer MaterialBuffer {
     GPUMaterialData materials[];
 };
+#define MATERIAL_SAMPLER_SET 3
+#include "material_sampler.glsl"
 layout(set = 4, binding = 0) uniform texture2D textures[];
 #endif
 #ifdef CPU_MODE
@@ -38,10 +40,24 @@ layout(set = 3, binding = 9) uniform texture2D ambient_occlusion_tex;
 layout(set = 3, binding = 10) uniform TextureData {
     CPUMaterialData material;
 };
+layout(set = 3, binding = 11) uniform sampler material_sampler;
 #endif
 
 #include "lighting/texture_access.glsl"
 
+vec4 sample_albedo_sampled(MATERIAL_TYPE material, vec2 coords, vec2 coords1, sampler s) {
+    return texture(sampler2D(ALBEDO_TEXTURE, s), ALBEDO_COORDS);
+}
+
+vec4 sample_albedo(MATERIAL_TYPE material, vec2 coords, vec2 coords1) {
+    #ifdef GPU_MODE
+    #define SAMPLE_ALBEDO(s) sample_albedo_sampled(material, coords, coords1, s)
+    RETURN_WITH_MATERIAL_SAMPLER(material.sampler, SAMPLE_ALBEDO)
+    #else
+    return sample_albedo_sampled(material, coords, coords1, material_sampler);
+    #endif
+}
+
 void main() {
     #ifdef GPU_MODE
     GPUMaterialData material = materials[i_material];
@@ -51,7 +67,8 @@ void main() {
 
     if (has_albedo) {
         vec2 coords = vec2(material.uv_transform * vec3(i_coords, 1.0));
-        vec4 albedo = texture(sampler2D(ALBEDO_TEXTURE, linear_sampler), coords);
+        vec2 coords1 = vec2(material.uv_transform * vec3(i_coords1, 1.0));
+        vec4 albedo = sample_albedo(material, coords, coords1);
 
         if (albedo.a <= 0.5) {
             discard;
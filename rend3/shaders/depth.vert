@@ -1,6 +1,8 @@
 #version 450
 
 #include "structures.glsl"
+#include "skinning.glsl"
+#include "morph.glsl"
 
 layout(location = 0) in vec3 i_position;
 layout(location = 1) in vec3 i_normal;
@@ -8,6 +10,9 @@ layout(location = 2) in vec3 i_tangent;
 layout(location = 3) in vec2 i_coords;
 layout(location = 4) in vec4 i_color;
 layout(location = 5) in uint i_material;
+layout(location = 7) in uvec4 i_joint_indices;
+layout(location = 8) in vec4 i_joint_weights;
+layout(location = 9) in vec2 i_coords1;
 #ifdef GPU_MODE
 layout(location = 6) in uint i_object_idx;
 #endif
@@ -16,6 +21,7 @@ layout(location = 0) out vec4 o_position;
 layout(location = 1) out vec2 o_coords;
 layout(location = 2) out vec4 o_color;
 layout(location = 3) flat out uint o_material;
+layout(location = 4) out vec2 o_coords1;
 
 layout(set = 1, binding = 0, std430) restrict readonly buffer ObjectOutputDataBuffer {
     ObjectOutputData object_output[];
@@ -30,17 +36,32 @@ layout(push_constant) uniform PushConstant {
 #endif
 
 void main() {
+    #ifdef CPU_MODE
+    // The push constant is the first instance of the draw, the rest follow it.
+    uint object_idx = i_object_idx + gl_InstanceIndex;
+    #else
     uint object_idx = i_object_idx;
+    #endif
 
     ObjectOutputData data = object_output[object_idx];
 
-    vec4 position = data.model_view_proj * vec4(i_position, 1.0);
-    o_position = position;
-    gl_Position = position;
+    vec3 model_position = i_position;
+    vec3 normal = i_normal;
+    vec3 tangent = i_tangent;
+    apply_morph_targets(data, gl_VertexIndex, model_position, normal, tangent);
+
+    mat4 skin = skin_matrix(data.joint_offset, i_joint_indices, i_joint_weights);
+    vec4 position = skin * vec4(model_position, 1.0);
+
+    vec4 clip_position = data.model_view_proj * position;
+    o_position = clip_position;
+    gl_Position = clip_position;
 
     o_material = data.material_idx;
 
     o_color = i_color;
 
     o_coords = i_coords;
+
+    o_coords1 = i_coords1;
 }
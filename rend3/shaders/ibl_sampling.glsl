@@ -0,0 +1,58 @@
+#ifndef SHADER_IBL_SAMPLING_GLSL
+#define SHADER_IBL_SAMPLING_GLSL
+
+#include "math.glsl"
+
+// Direction through the center of a texel of a cube face, with the faces laid out as the layers of the cube.
+vec3 cube_texel_direction(uvec3 id, uint size) {
+    vec2 st = (vec2(id.xy) + 0.5) / float(size) * 2.0 - 1.0;
+    vec3 dir;
+    switch (id.z) {
+        case 0u: dir = vec3(1.0, -st.y, -st.x); break;
+        case 1u: dir = vec3(-1.0, -st.y, st.x); break;
+        case 2u: dir = vec3(st.x, 1.0, st.y); break;
+        case 3u: dir = vec3(st.x, -1.0, -st.y); break;
+        case 4u: dir = vec3(st.x, -st.y, 1.0); break;
+        default: dir = vec3(-st.x, -st.y, -1.0); break;
+    }
+    return normalize(dir);
+}
+
+vec2 hammersley(uint i, uint count) {
+    return vec2(float(i) / float(count), float(bitfieldReverse(i)) * 2.3283064365386963e-10);
+}
+
+// Rotates tangent space vectors into the space n is in.
+mat3 tangent_basis(vec3 n) {
+    vec3 up = abs(n.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, n));
+    vec3 bitangent = cross(n, tangent);
+    return mat3(tangent, bitangent, n);
+}
+
+// Tangent space direction with a probability density of cos(theta) / PI.
+vec3 importance_sample_cosine(vec2 xi) {
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt(1.0 - xi.y);
+    float sin_theta = sqrt(xi.y);
+    return vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+// Tangent space half vector distributed according to the GGX normal distribution of the given roughness.
+vec3 importance_sample_ggx(vec2 xi, float a) {
+    float a2 = a * a;
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a2 - 1.0) * xi.y));
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+// Mip of the source to sample so that each sample covers the solid angle it stands in for. Sampling the full
+// resolution source with few samples would alias on bright spots. From "GPU-Based Importance Sampling" in GPU Gems 3.
+float source_lod(float pdf, uint sample_count, float source_size, float max_lod) {
+    float texel_solid_angle = 4.0 * PI / (6.0 * source_size * source_size);
+    float sample_solid_angle = 1.0 / (float(sample_count) * pdf + 0.0001);
+    return clamp(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0, max_lod);
+}
+
+#endif
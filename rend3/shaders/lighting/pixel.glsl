@@ -44,10 +44,11 @@ PixelData get_per_pixel_data_sampled(MATERIAL_TYPE material, sampler s) {
     PixelData pixel;
     
     vec2 coords = vec2(material.uv_transform * vec3(i_coords, 1.0));
+    vec2 coords1 = vec2(material.uv_transform * vec3(i_coords1, 1.0));
 
     if (MATERIAL_FLAG(FLAGS_ALBEDO_ACTIVE)) {
         if (HAS_ALBEDO_TEXTURE) {
-            pixel.albedo = texture(sampler2D(ALBEDO_TEXTURE, s), coords);
+            pixel.albedo = texture(sampler2D(ALBEDO_TEXTURE, s), ALBEDO_COORDS);
         } else {
             pixel.albedo = vec4(1.0);
         }
@@ -68,7 +69,7 @@ PixelData get_per_pixel_data_sampled(MATERIAL_TYPE material, sampler s) {
     }
     else {
         if (HAS_NORMAL_TEXTURE) {
-            vec3 normal = texture(sampler2D(NORMAL_TEXTURE, s), coords).xyz * 2.0 - 1.0;
+            vec3 normal = texture(sampler2D(NORMAL_TEXTURE, s), NORMAL_COORDS).xyz * 2.0 - 1.0;
             vec3 binorm = cross(i_normal, i_tangent);
 
             mat3 tbn = mat3(i_tangent, binorm, i_normal);
@@ -87,7 +88,7 @@ PixelData get_per_pixel_data_sampled(MATERIAL_TYPE material, sampler s) {
         // Blue: Roughness
         if (MATERIAL_FLAG(FLAGS_AOMR_GLTF_COMBINED)) {
             if (HAS_ROUGHNESS_TEXTURE) {
-                vec3 aomr = texture(sampler2D(ROUGHNESS_TEXTURE, s), coords).rgb;
+                vec3 aomr = texture(sampler2D(ROUGHNESS_TEXTURE, s), ROUGHNESS_COORDS).rgb;
                 pixel.ambient_occlusion = material.ambient_occlusion * aomr.r;
                 pixel.metallic = material.metallic * aomr.g;
                 pixel.perceptual_roughness = material.roughness * aomr.b;
@@ -104,7 +105,7 @@ PixelData get_per_pixel_data_sampled(MATERIAL_TYPE material, sampler s) {
         // Blue: Roughness
         else if (MATERIAL_FLAG(FLAGS_AOMR_GLTF_SPLIT)) {
             if (HAS_ROUGHNESS_TEXTURE) {
-                vec2 mr = texture(sampler2D(ROUGHNESS_TEXTURE, s), coords).gb;
+                vec2 mr = texture(sampler2D(ROUGHNESS_TEXTURE, s), ROUGHNESS_COORDS).gb;
                 pixel.metallic = material.metallic * mr[0];
                 pixel.perceptual_roughness = material.roughness * mr[1];
             } else {
@@ -112,7 +113,7 @@ PixelData get_per_pixel_data_sampled(MATERIAL_TYPE material, sampler s) {
                 pixel.perceptual_roughness = material.ambient_occlusion;
             }
             if (HAS_AMBIENT_OCCLUSION_TEXTURE) {
-                pixel.ambient_occlusion = material.ambient_occlusion * texture(sampler2D(AMBIENT_OCCLUSION_TEXTURE, s), coords).r;
+                pixel.ambient_occlusion = material.ambient_occlusion * texture(sampler2D(AMBIENT_OCCLUSION_TEXTURE, s), AMBIENT_OCCLUSION_COORDS).r;
             } else {
                 pixel.ambient_occlusion = material.ambient_occlusion;
             }
@@ -125,26 +126,26 @@ PixelData get_per_pixel_data_sampled(MATERIAL_TYPE material, sampler s) {
         // Red: Roughness
         else if (MATERIAL_FLAG(FLAGS_AOMR_BW_SPLIT)) {
             if (HAS_ROUGHNESS_TEXTURE) {
-                pixel.perceptual_roughness = material.roughness * texture(sampler2D(ROUGHNESS_TEXTURE, s), coords).r;
+                pixel.perceptual_roughness = material.roughness * texture(sampler2D(ROUGHNESS_TEXTURE, s), ROUGHNESS_COORDS).r;
             } else {
                 pixel.perceptual_roughness = material.roughness;
             }
 
             if (HAS_METALLIC_TEXTURE) {
-                pixel.metallic = material.metallic * texture(sampler2D(METALLIC_TEXTURE, s), coords).r;
+                pixel.metallic = material.metallic * texture(sampler2D(METALLIC_TEXTURE, s), METALLIC_COORDS).r;
             } else {
                 pixel.metallic = material.metallic;
             }
 
             if (HAS_AMBIENT_OCCLUSION_TEXTURE) {
-                pixel.ambient_occlusion = material.ambient_occlusion * texture(sampler2D(AMBIENT_OCCLUSION_TEXTURE, s), coords).r;
+                pixel.ambient_occlusion = material.ambient_occlusion * texture(sampler2D(AMBIENT_OCCLUSION_TEXTURE, s), AMBIENT_OCCLUSION_COORDS).r;
             } else {
                 pixel.ambient_occlusion = material.ambient_occlusion;
             }
         }
 
         if (HAS_REFLECTANCE_TEXTURE) {
-            pixel.reflectance = material.reflectance * texture(sampler2D(REFLECTANCE_TEXTURE, s), coords).r;
+            pixel.reflectance = material.reflectance * texture(sampler2D(REFLECTANCE_TEXTURE, s), REFLECTANCE_COORDS).r;
         } else {
             pixel.reflectance = material.reflectance;
         }
@@ -156,7 +157,7 @@ PixelData get_per_pixel_data_sampled(MATERIAL_TYPE material, sampler s) {
 
         if (MATERIAL_FLAG(FLAGS_CC_GLTF_COMBINED)) {
             if (HAS_CLEAR_COAT_TEXTURE) {
-                vec2 cc = texture(sampler2D(CLEAR_COAT_TEXTURE, s), coords).rg;
+                vec2 cc = texture(sampler2D(CLEAR_COAT_TEXTURE, s), CLEAR_COAT_COORDS).rg;
                 pixel.clear_coat = material.clear_coat * cc.r;
                 pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness * cc.g;
             } else {
@@ -165,23 +166,23 @@ PixelData get_per_pixel_data_sampled(MATERIAL_TYPE material, sampler s) {
             }
         } else if (MATERIAL_FLAG(FLAGS_CC_GLTF_SPLIT)) {
             if (HAS_CLEAR_COAT_TEXTURE) {
-                pixel.clear_coat = material.clear_coat * texture(sampler2D(CLEAR_COAT_TEXTURE, s), coords).r;
+                pixel.clear_coat = material.clear_coat * texture(sampler2D(CLEAR_COAT_TEXTURE, s), CLEAR_COAT_COORDS).r;
             } else {
                 pixel.clear_coat = material.clear_coat;
             }
             if (HAS_CLEAR_COAT_ROUGHNESS_TEXTURE) {
-                pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness * texture(sampler2D(CLEAR_COAT_ROUGHNESS_TEXTURE, s), coords).g;
+                pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness * texture(sampler2D(CLEAR_COAT_ROUGHNESS_TEXTURE, s), CLEAR_COAT_ROUGHNESS_COORDS).g;
             } else {
                 pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness;
             }
         } else if (MATERIAL_FLAG(FLAGS_CC_BW_SPLIT)) {
             if (HAS_CLEAR_COAT_TEXTURE) {
-                pixel.clear_coat = material.clear_coat * texture(sampler2D(CLEAR_COAT_TEXTURE, s), coords).r;
+                pixel.clear_coat = material.clear_coat * texture(sampler2D(CLEAR_COAT_TEXTURE, s), CLEAR_COAT_COORDS).r;
             } else {
                 pixel.clear_coat = material.clear_coat;
             }
             if (HAS_CLEAR_COAT_ROUGHNESS_TEXTURE) {
-                pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness * texture(sampler2D(CLEAR_COAT_ROUGHNESS_TEXTURE, s), coords).r;
+                pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness * texture(sampler2D(CLEAR_COAT_ROUGHNESS_TEXTURE, s), CLEAR_COAT_ROUGHNESS_COORDS).r;
             } else {
                 pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness;
             }
@@ -196,14 +197,14 @@ PixelData get_per_pixel_data_sampled(MATERIAL_TYPE material, sampler s) {
         pixel.roughness = perceptual_roughness_to_roughness(pixel.perceptual_roughness);
 
         if (HAS_EMISSIVE_TEXTURE) {
-            pixel.emissive = material.emissive * texture(sampler2D(EMISSIVE_TEXTURE, s), coords).rgb;
+            pixel.emissive = material.emissive * texture(sampler2D(EMISSIVE_TEXTURE, s), EMISSIVE_COORDS).rgb;
         } else {
             pixel.emissive = material.emissive;
         }
 
         // TODO: Aniso info
         if (HAS_ANISOTROPY_TEXTURE) {
-            pixel.anisotropy = material.anisotropy * texture(sampler2D(ANISOTROPY_TEXTURE, s), coords).r;
+            pixel.anisotropy = material.anisotropy * texture(sampler2D(ANISOTROPY_TEXTURE, s), ANISOTROPY_COORDS).r;
         } else {
             pixel.anisotropy = material.anisotropy;
         }
@@ -216,11 +217,31 @@ PixelData get_per_pixel_data_sampled(MATERIAL_TYPE material, sampler s) {
 }
 
 PixelData get_per_pixel_data(MATERIAL_TYPE material) {
-    if (MATERIAL_FLAG(FLAGS_NEAREST)) {
-        return get_per_pixel_data_sampled(material, nearest_sampler);
-    } else {
-        return get_per_pixel_data_sampled(material, linear_sampler);
-    }
+    #ifdef GPU_MODE
+    #define PER_PIXEL_DATA(s) get_per_pixel_data_sampled(material, s)
+    RETURN_WITH_MATERIAL_SAMPLER(material.sampler, PER_PIXEL_DATA)
+    #else
+    return get_per_pixel_data_sampled(material, material_sampler);
+    #endif
+}
+
+// Split sum approximation of the environment's lighting. `n` and `v` are in world space, like the environment maps.
+vec3 image_based_lighting(PixelData pixel, vec3 n, vec3 v, textureCube irradiance, textureCube prefiltered, texture2D lut, sampler s) {
+    float NoV = max(dot(n, v), 1e-4);
+    vec3 r = reflect(-v, n);
+
+    vec3 diffuse = texture(samplerCube(irradiance, s), n).rgb * pixel.diffuse_color;
+
+    float max_lod = float(textureQueryLevels(samplerCube(prefiltered, s)) - 1);
+    vec3 prefiltered_color = textureLod(samplerCube(prefiltered, s), r, pixel.perceptual_roughness * max_lod).rgb;
+
+    // The sampler repeats, so keep lookups from bleeding over the edges of the table.
+    vec2 lut_half_texel = 0.5 / vec2(textureSize(sampler2D(lut, s), 0));
+    vec2 lut_coords = clamp(vec2(NoV, pixel.perceptual_roughness), lut_half_texel, 1.0 - lut_half_texel);
+    vec2 env_brdf = texture(sampler2D(lut, s), lut_coords).rg;
+    vec3 specular = prefiltered_color * (pixel.f0 * env_brdf.x + env_brdf.y);
+
+    return (diffuse + specular) * pixel.ambient_occlusion;
 }
 
 #endif
@@ -0,0 +1,97 @@
+#ifndef SHADER_LIGHTING_SHADOW_GLSL
+#define SHADER_LIGHTING_SHADOW_GLSL
+
+#include "../math.glsl"
+#include "../structures.glsl"
+
+#define SHADOW_POISSON_SAMPLES 32
+#define SHADOW_BLOCKER_SEARCH_SAMPLES 16
+
+const vec2 SHADOW_POISSON_DISK[SHADOW_POISSON_SAMPLES] = vec2[](
+    vec2(0.8050, -0.3060), vec2(-0.0050, 0.0207), vec2(-0.9216, -0.1971), vec2(-0.4734, -0.2281),
+    vec2(0.7158, 0.6262), vec2(0.1805, 0.6762), vec2(-0.9617, 0.1971), vec2(-0.1106, -0.7267),
+    vec2(0.3304, 0.1440), vec2(-0.2088, -0.3924), vec2(0.2844, -0.4934), vec2(0.8980, 0.3455),
+    vec2(-0.6586, 0.4847), vec2(0.2854, -0.8171), vec2(-0.6035, -0.7129), vec2(-0.2063, 0.9686),
+    vec2(-0.3776, 0.3530), vec2(0.5042, 0.4116), vec2(0.6448, -0.7271), vec2(0.9240, -0.0158),
+    vec2(0.0943, 0.3385), vec2(-0.3216, 0.6658), vec2(0.6343, 0.1320), vec2(0.5236, -0.1967),
+    vec2(-0.7004, 0.0302), vec2(0.4891, 0.8247), vec2(-0.7169, -0.4260), vec2(-0.3593, -0.9098),
+    vec2(0.1115, 0.9699), vec2(-0.5964, 0.7939), vec2(-0.3125, 0.0547), vec2(0.2202, -0.1810)
+);
+
+// Rotates the poisson disk per pixel, so the undersampling turns into noise instead of banding.
+mat2 shadow_poisson_rotation() {
+    // Interleaved gradient noise
+    float angle = 2.0 * PI * fract(52.9829189 * fract(dot(gl_FragCoord.xy, vec2(0.06711056, 0.00583715))));
+    float s = sin(angle);
+    float c = cos(angle);
+    return mat2(c, s, -s, c);
+}
+
+// `uv_max` is the edge of the part of the layer which belongs to this shadow map, taps are kept inside of it.
+float shadow_compare(texture2DArray tex, samplerShadow compare_sampler, vec2 uv, float layer, float depth, vec2 uv_max) {
+    return texture(sampler2DArrayShadow(tex, compare_sampler), vec4(clamp(uv, vec2(0.0), uv_max), layer, depth));
+}
+
+float shadow_poisson(texture2DArray tex, samplerShadow compare_sampler, vec2 uv, float layer, float depth, vec2 uv_max, vec2 radius, uint samples) {
+    mat2 rotation = shadow_poisson_rotation();
+
+    float sum = 0.0;
+    for (uint i = 0; i < samples; ++i) {
+        vec2 offset = rotation * SHADOW_POISSON_DISK[i] * radius;
+        sum += shadow_compare(tex, compare_sampler, uv + offset, layer, depth, uv_max);
+    }
+    return sum / float(samples);
+}
+
+// Percentage closer soft shadows, from "Percentage-Closer Soft Shadows" by Randima Fernando.
+float shadow_pcss(texture2DArray tex, samplerShadow compare_sampler, sampler depth_sampler, vec2 uv, float layer, float depth, vec2 uv_max, vec2 texel, float light_size, uint samples) {
+    mat2 rotation = shadow_poisson_rotation();
+
+    // Find the average depth of everything which could be casting a shadow onto this pixel.
+    float blocker_sum = 0.0;
+    uint blocker_count = 0;
+    for (uint i = 0; i < SHADOW_BLOCKER_SEARCH_SAMPLES; ++i) {
+        vec2 offset = rotation * SHADOW_POISSON_DISK[i] * light_size;
+        float blocker_depth = texture(sampler2DArray(tex, depth_sampler), vec3(clamp(uv + offset, vec2(0.0), uv_max), layer)).r;
+        if (blocker_depth < depth) {
+            blocker_sum += blocker_depth;
+            ++blocker_count;
+        }
+    }
+
+    if (blocker_count == 0) {
+        return 1.0;
+    }
+
+    float blocker_depth = max(blocker_sum / float(blocker_count), 0.0001);
+    // The further the receiver is behind the blocker, the wider the penumbra. Anything wider than the search
+    // radius would have missed blockers, so clamp it.
+    float penumbra = clamp((depth - blocker_depth) / blocker_depth * light_size, texel.x, light_size);
+
+    return shadow_poisson(tex, compare_sampler, uv, layer, depth, uv_max, vec2(penumbra), samples);
+}
+
+// Looks up the shadow map according to the renderer's shadow filter. 1.0 is fully lit, 0.0 fully shadowed.
+float filter_shadow(texture2DArray tex, samplerShadow compare_sampler, sampler depth_sampler, ShadowFilterData shadow_filter, vec2 uv, float layer, float depth, vec2 uv_max) {
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DArrayShadow(tex, compare_sampler), 0).xy);
+
+    if (shadow_filter.mode == SHADOW_FILTER_PCF) {
+        float center = (float(shadow_filter.samples) - 1.0) * 0.5;
+        float sum = 0.0;
+        for (uint x = 0; x < shadow_filter.samples; ++x) {
+            for (uint y = 0; y < shadow_filter.samples; ++y) {
+                vec2 offset = (vec2(x, y) - center) * texel;
+                sum += shadow_compare(tex, compare_sampler, uv + offset, layer, depth, uv_max);
+            }
+        }
+        return sum / float(shadow_filter.samples * shadow_filter.samples);
+    } else if (shadow_filter.mode == SHADOW_FILTER_POISSON_PCF) {
+        return shadow_poisson(tex, compare_sampler, uv, layer, depth, uv_max, shadow_filter.radius * texel, shadow_filter.samples);
+    } else if (shadow_filter.mode == SHADOW_FILTER_PCSS) {
+        return shadow_pcss(tex, compare_sampler, depth_sampler, uv, layer, depth, uv_max, texel, shadow_filter.radius, shadow_filter.samples);
+    } else {
+        return shadow_compare(tex, compare_sampler, uv, layer, depth, uv_max);
+    }
+}
+
+#endif
@@ -6,9 +6,7 @@
 #include "brdf.glsl"
 #include "pixel.glsl"
 
-vec3 surface_shading(DirectionalLight light, PixelData pixel, vec3 v, float occlusion) {
-    vec3 l = normalize(mat3(uniforms.view) * -light.direction);
-
+vec3 surface_shading(vec3 l, vec3 light_color, PixelData pixel, vec3 v, float occlusion) {
     vec3 n = pixel.normal;
     vec3 h = normalize(v + l);
 
@@ -34,9 +32,49 @@ vec3 surface_shading(DirectionalLight light, PixelData pixel, vec3 v, float occl
 
     vec3 color = Fd + Fr * energy_compensation;
 
-    float light_attenuation = 1.0;
+    return (color * light_color) * (NoL * occlusion);
+}
+
+vec3 surface_shading(DirectionalLight light, PixelData pixel, vec3 v, float occlusion) {
+    vec3 l = normalize(mat3(uniforms.view) * -light.direction);
+
+    return surface_shading(l, light.color, pixel, v, occlusion);
+}
+
+// Inverse square falloff, smoothly windowed to reach zero at the light's radius.
+float distance_attenuation(float distance_squared, float radius) {
+    float factor = distance_squared / (radius * radius);
+    float window = saturate(1.0 - factor * factor);
+    return (window * window) / max(distance_squared, 1e-4);
+}
+
+vec3 surface_shading(PointLight light, vec3 view_position, PixelData pixel, vec3 v, float occlusion) {
+    vec3 light_view_position = (uniforms.view * vec4(light.position, 1.0)).xyz;
+    vec3 to_light = light_view_position - view_position;
+    vec3 l = normalize(to_light);
+
+    float attenuation = distance_attenuation(dot(to_light, to_light), light.radius);
+
+    return surface_shading(l, light.color, pixel, v, occlusion * attenuation);
+}
+
+// Smooth falloff from the inner cone to the outer cone. Scale and offset are precomputed from the cone angles.
+float spot_attenuation(vec3 l, vec3 spot_direction, float angle_scale, float angle_offset) {
+    float cos_angle = dot(spot_direction, -l);
+    float attenuation = saturate(cos_angle * angle_scale + angle_offset);
+    return attenuation * attenuation;
+}
+
+vec3 surface_shading(SpotLight light, vec3 view_position, PixelData pixel, vec3 v, float occlusion) {
+    vec3 light_view_position = (uniforms.view * vec4(light.position, 1.0)).xyz;
+    vec3 to_light = light_view_position - view_position;
+    vec3 l = normalize(to_light);
+    vec3 spot_direction = normalize(mat3(uniforms.view) * light.direction);
+
+    float attenuation = distance_attenuation(dot(to_light, to_light), light.range)
+        * spot_attenuation(l, spot_direction, light.angle_scale, light.angle_offset);
 
-    return (color * light.color) * (light_attenuation * NoL * occlusion);
+    return surface_shading(l, light.color, pixel, v, occlusion * attenuation);
 }
 
 #endif
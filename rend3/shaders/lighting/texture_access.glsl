@@ -57,4 +57,19 @@ bool has_texture(uint idx) {
 #define AMBIENT_OCCLUSION_TEXTURE ambient_occlusion_tex
 #endif
 
+// Picks the texture coordinates of a texture by its bit in Uv1Textures, which match the bits of texture_enable.
+// Expects the transformed coordinates of both uv sets as coords and coords1.
+#define TEXTURE_COORDS(bit) (bool((material.uv1_textures >> bit) & 0x1) ? coords1 : coords)
+
+#define ALBEDO_COORDS TEXTURE_COORDS(0)
+#define NORMAL_COORDS TEXTURE_COORDS(1)
+#define ROUGHNESS_COORDS TEXTURE_COORDS(2)
+#define METALLIC_COORDS TEXTURE_COORDS(3)
+#define REFLECTANCE_COORDS TEXTURE_COORDS(4)
+#define CLEAR_COAT_COORDS TEXTURE_COORDS(5)
+#define CLEAR_COAT_ROUGHNESS_COORDS TEXTURE_COORDS(6)
+#define EMISSIVE_COORDS TEXTURE_COORDS(7)
+#define ANISOTROPY_COORDS TEXTURE_COORDS(8)
+#define AMBIENT_OCCLUSION_COORDS TEXTURE_COORDS(9)
+
 #endif // SHADER_TEXTURE_ACCESS_GLSL
\ No newline at end of file
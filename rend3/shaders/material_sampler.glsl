@@ -0,0 +1,31 @@
+#ifndef SHADER_MATERIAL_SAMPLER_GLSL
+#define SHADER_MATERIAL_SAMPLER_GLSL
+
+// The sampler table of gpu mode, MAX_GPU_SAMPLERS long. Define MATERIAL_SAMPLER_SET to the set of the material buffer
+// before including this, the samplers follow the buffer from binding 1.
+#ifdef GPU_MODE
+layout(set = MATERIAL_SAMPLER_SET, binding = 1) uniform sampler material_sampler0;
+layout(set = MATERIAL_SAMPLER_SET, binding = 2) uniform sampler material_sampler1;
+layout(set = MATERIAL_SAMPLER_SET, binding = 3) uniform sampler material_sampler2;
+layout(set = MATERIAL_SAMPLER_SET, binding = 4) uniform sampler material_sampler3;
+layout(set = MATERIAL_SAMPLER_SET, binding = 5) uniform sampler material_sampler4;
+layout(set = MATERIAL_SAMPLER_SET, binding = 6) uniform sampler material_sampler5;
+layout(set = MATERIAL_SAMPLER_SET, binding = 7) uniform sampler material_sampler6;
+layout(set = MATERIAL_SAMPLER_SET, binding = 8) uniform sampler material_sampler7;
+
+// Returns `f(s)`, with `s` the material sampler at `idx`. Samplers can't be indexed by a non-uniform value, returned
+// or stored in variables, so this branches to the binding of the sampler.
+#define RETURN_WITH_MATERIAL_SAMPLER(idx, f) \
+    switch (idx) { \
+        case 1: return f(material_sampler1); \
+        case 2: return f(material_sampler2); \
+        case 3: return f(material_sampler3); \
+        case 4: return f(material_sampler4); \
+        case 5: return f(material_sampler5); \
+        case 6: return f(material_sampler6); \
+        case 7: return f(material_sampler7); \
+        default: return f(material_sampler0); \
+    }
+#endif
+
+#endif
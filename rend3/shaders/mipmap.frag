@@ -0,0 +1,22 @@
+#version 450
+
+layout(location = 0) out vec4 color;
+
+layout(set = 0, binding = 0) uniform sampler nearest_sampler;
+layout(set = 0, binding = 1) uniform texture2D source;
+
+// Box filters each 2x2 block of the mip above. Fetched values of sRGB textures are already linear.
+void main() {
+    ivec2 source_max = textureSize(sampler2D(source, nearest_sampler), 0) - 1;
+    ivec2 base = ivec2(gl_FragCoord.xy) * 2;
+
+    vec4 sum = vec4(0.0);
+    for (int y = 0; y < 2; ++y) {
+        for (int x = 0; x < 2; ++x) {
+            ivec2 coord = min(base + ivec2(x, y), source_max);
+            sum += texelFetch(sampler2D(source, nearest_sampler), coord, 0);
+        }
+    }
+
+    color = sum * 0.25;
+}
@@ -0,0 +1,35 @@
+#ifndef SHADER_MORPH_GLSL
+#define SHADER_MORPH_GLSL
+
+#include "structures.glsl"
+
+// If you change this, change the MorphDelta struct in mesh.rs
+#define MORPH_DELTA_STRIDE 9
+
+// Position, normal and tangent deltas, with the targets of each vertex next to each other.
+layout(set = 1, binding = 2, std430) restrict readonly buffer MorphDeltaBuffer {
+    float morph_deltas[];
+};
+layout(set = 1, binding = 3, std430) restrict readonly buffer MorphWeightBuffer {
+    float morph_weights[];
+};
+
+vec3 morph_delta(uint delta_idx, uint component) {
+    uint base = delta_idx * MORPH_DELTA_STRIDE + component * 3;
+    return vec3(morph_deltas[base], morph_deltas[base + 1], morph_deltas[base + 2]);
+}
+
+// Adds the weighted deltas of every morph target of the object's mesh to the vertex.
+void apply_morph_targets(ObjectOutputData data, uint vertex_idx, inout vec3 position, inout vec3 normal, inout vec3 tangent) {
+    uint local_vertex = vertex_idx - uint(data.vertex_offset);
+    uint first_delta = data.morph_delta_offset + local_vertex * data.morph_target_count;
+    for (uint target_idx = 0; target_idx < data.morph_target_count; ++target_idx) {
+        float weight = morph_weights[data.morph_weight_offset + target_idx];
+        uint delta_idx = first_delta + target_idx;
+        position += weight * morph_delta(delta_idx, 0);
+        normal += weight * morph_delta(delta_idx, 1);
+        tangent += weight * morph_delta(delta_idx, 2);
+    }
+}
+
+#endif
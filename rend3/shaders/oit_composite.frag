@@ -0,0 +1,23 @@
+#version 450
+
+layout(location = 0) in vec2 tex_coords;
+
+layout(location = 0) out vec4 color;
+
+layout(set = 0, binding = 1) uniform sampler nearest_sampler;
+layout(set = 1, binding = 0) uniform texture2D accumulation;
+layout(set = 1, binding = 1) uniform texture2D revealage;
+
+void main() {
+    ivec2 coords = ivec2(gl_FragCoord.xy);
+    vec4 accum = texelFetch(sampler2D(accumulation, nearest_sampler), coords, 0);
+    float reveal = texelFetch(sampler2D(revealage, nearest_sampler), coords, 0).r;
+
+    // Nothing transparent covers this pixel.
+    if (reveal >= 1.0) {
+        discard;
+    }
+
+    // Alpha blended onto the opaque color, so the coverage is one minus how much of the background shows through.
+    color = vec4(accum.rgb / max(accum.a, 1e-5), 1.0 - reveal);
+}
@@ -14,6 +14,7 @@ layout(location = 2) in vec3 i_tangent;
 layout(location = 3) in vec2 i_coords;
 layout(location = 4) in vec4 i_color;
 layout(location = 5) flat in uint i_material;
+layout(location = 6) in vec2 i_coords1;
 
 layout(location = 0) out vec4 o_color;
 layout(location = 1) out vec4 o_normal;
@@ -29,33 +30,115 @@ layout(set = 2, binding = 0) restrict readonly buffer DirectionalLightBuffer {
     DirectionalLight directional_lights[];
 };
 layout(set = 2, binding = 1) uniform texture2DArray shadow;
+layout(set = 2, binding = 2) restrict readonly buffer PointLightBuffer {
+    PointLightBufferHeader point_light_header;
+    PointLight point_lights[];
+};
+layout(set = 2, binding = 3) uniform textureCubeArray point_shadow;
+layout(set = 2, binding = 4) restrict readonly buffer SpotLightBuffer {
+    SpotLightBufferHeader spot_light_header;
+    SpotLight spot_lights[];
+};
+layout(set = 2, binding = 5) uniform texture2DArray spot_shadow;
 layout(set = 3, binding = 0) uniform UniformBuffer {
     UniformData uniforms;
 };
+layout(set = 4, binding = 0) uniform textureCube irradiance_map;
+layout(set = 4, binding = 1) uniform textureCube prefiltered_map;
+layout(set = 4, binding = 2) uniform texture2D brdf_lut;
 #ifdef GPU_MODE
-layout(set = 4, binding = 0, std430) restrict readonly buffer MaterialBuffer {
+layout(set = 5, binding = 0, std430) restrict readonly buffer MaterialBuffer {
     GPUMaterialData materials[];
 };
-layout(set = 5, binding = 0) uniform texture2D textures[];
+#define MATERIAL_SAMPLER_SET 5
+#include "material_sampler.glsl"
+layout(set = 6, binding = 0) uniform texture2D textures[];
 #endif
 #ifdef CPU_MODE
-layout(set = 4, binding = 0) uniform texture2D albedo_tex;
-layout(set = 4, binding = 1) uniform texture2D normal_tex;
-layout(set = 4, binding = 2) uniform texture2D roughness_tex;
-layout(set = 4, binding = 3) uniform texture2D metallic_tex;
-layout(set = 4, binding = 4) uniform texture2D reflectance_tex;
-layout(set = 4, binding = 5) uniform texture2D clear_coat_tex;
-layout(set = 4, binding = 6) uniform texture2D clear_coat_roughness_tex;
-layout(set = 4, binding = 7) uniform texture2D emissive_tex;
-layout(set = 4, binding = 8) uniform texture2D anisotropy_tex;
-layout(set = 4, binding = 9) uniform texture2D ambient_occlusion_tex;
-layout(set = 4, binding = 10) uniform TextureData {
+layout(set = 5, binding = 0) uniform texture2D albedo_tex;
+layout(set = 5, binding = 1) uniform texture2D normal_tex;
+layout(set = 5, binding = 2) uniform texture2D roughness_tex;
+layout(set = 5, binding = 3) uniform texture2D metallic_tex;
+layout(set = 5, binding = 4) uniform texture2D reflectance_tex;
+layout(set = 5, binding = 5) uniform texture2D clear_coat_tex;
+layout(set = 5, binding = 6) uniform texture2D clear_coat_roughness_tex;
+layout(set = 5, binding = 7) uniform texture2D emissive_tex;
+layout(set = 5, binding = 8) uniform texture2D anisotropy_tex;
+layout(set = 5, binding = 9) uniform texture2D ambient_occlusion_tex;
+layout(set = 5, binding = 10) uniform TextureData {
     CPUMaterialData material;
 };
+layout(set = 5, binding = 11) uniform sampler material_sampler;
 #endif
 
+#include "lighting/shadow.glsl"
 #include "lighting/surface.glsl"
 
+// Fraction of each cascade, at its far end, over which it fades into the next one.
+#define CASCADE_BLEND_FRACTION 0.1
+
+float directional_cascade_shadow(DirectionalLight light, uint cascade, vec3 world_position, vec3 world_normal) {
+    vec3 offset_position = world_position + world_normal * light.normal_offsets[cascade];
+    vec3 shadow_ndc = (light.view_proj[cascade] * vec4(offset_position, 1.0)).xyz;
+    vec2 shadow_flipped = (shadow_ndc.xy * 0.5) + 0.5;
+    shadow_flipped.y = 1 - shadow_flipped.y;
+
+    if (shadow_flipped.x < 0 || shadow_flipped.x > 1 || shadow_flipped.y < 0 || shadow_flipped.y > 1) {
+        return 1.0;
+    }
+
+    // Lights with a lower resolution only render into the top left corner of the texture.
+    return filter_shadow(
+        shadow,
+        shadow_sampler,
+        nearest_sampler,
+        uniforms.shadow_filter,
+        shadow_flipped * light.uv_scale,
+        light.shadow_tex + cascade,
+        shadow_ndc.z - light.depth_biases[cascade],
+        vec2(light.uv_scale)
+    );
+}
+
+float directional_shadow(DirectionalLight light, vec3 world_position, vec3 world_normal, float view_depth) {
+    uint cascade = 0;
+    while (cascade < light.cascade_count && view_depth > light.cascade_splits[cascade]) {
+        ++cascade;
+    }
+    // Past the last cascade nothing is shadowed.
+    if (cascade == light.cascade_count) {
+        return 1.0;
+    }
+
+    float shadow_value = directional_cascade_shadow(light, cascade, world_position, world_normal);
+
+    // Blend into the next cascade so the switch isn't visible. The last cascade fades out entirely.
+    float cascade_end = light.cascade_splits[cascade];
+    float cascade_start = cascade == 0 ? 0.0 : light.cascade_splits[cascade - 1];
+    float blend_start = cascade_end - (cascade_end - cascade_start) * CASCADE_BLEND_FRACTION;
+    if (view_depth > blend_start) {
+        float next_value = cascade + 1 < light.cascade_count ? directional_cascade_shadow(light, cascade + 1, world_position, world_normal) : 1.0;
+        shadow_value = mix(shadow_value, next_value, (view_depth - blend_start) / (cascade_end - blend_start));
+    }
+
+    return shadow_value;
+}
+
+// With WEIGHTED_BLENDED the two outputs are the accumulation and revealage targets of weighted blended
+// order-independent transparency instead of color and normal.
+void write_output(vec4 color, vec3 normal) {
+    #ifdef WEIGHTED_BLENDED
+    // Equation 9 from "Weighted Blended Order-Independent Transparency" (McGuire and Bavoil), favoring close surfaces.
+    float depth = abs(i_view_position.z);
+    float weight = color.a * clamp(10.0 / (1e-5 + pow(depth / 5.0, 2.0) + pow(depth / 200.0, 6.0)), 1e-2, 3e3);
+    o_color = vec4(color.rgb * color.a, color.a) * weight;
+    o_normal = vec4(color.a);
+    #else
+    o_color = color;
+    o_normal = vec4(normal, 0.0);
+    #endif
+}
+
 void main() {
     #ifdef GPU_MODE
     GPUMaterialData material = materials[i_material];
@@ -64,30 +147,73 @@ void main() {
     PixelData pixel = get_per_pixel_data(material);
 
     if (MATERIAL_FLAG(FLAGS_UNLIT)) {
-        o_color = pixel.albedo;
-        o_normal = vec4(i_normal, 0.0);
+        write_output(pixel.albedo, i_normal);
     } else {
         vec3 v = -normalize(i_view_position.xyz);
 
         vec3 color = vec3(pixel.emissive);
+        vec3 world_position = (uniforms.inv_view * i_view_position).xyz;
+        // The normal offset uses the geometric normal, normal maps would make it jitter across the surface.
+        vec3 world_normal = normalize(mat3(uniforms.inv_view) * i_normal);
         for (uint i = 0; i < directional_light_header.total_lights; ++i) {
             DirectionalLight light = directional_lights[i];
 
-            vec3 shadow_ndc = (directional_lights[i].view_proj * uniforms.inv_view * i_view_position).xyz;
+            float shadow_value = directional_shadow(light, world_position, world_normal, i_view_position.z);
+
+            color += surface_shading(light, pixel, v, shadow_value * pixel.ambient_occlusion);
+        }
+
+        for (uint i = 0; i < spot_light_header.total_lights; ++i) {
+            SpotLight light = spot_lights[i];
+
+            vec4 shadow_clip = light.view_proj * vec4(world_position, 1.0);
+            vec3 shadow_ndc = shadow_clip.xyz / shadow_clip.w;
             vec2 shadow_flipped = (shadow_ndc.xy * 0.5) + 0.5;
-            vec4 shadow_shadow_coords = vec4(shadow_flipped.x, 1 - shadow_flipped.y, light.shadow_tex, shadow_ndc.z);
+            shadow_flipped.y = 1 - shadow_flipped.y;
 
             float shadow_value;
-            if (shadow_shadow_coords.x < 0 || shadow_shadow_coords.x > 1 || shadow_shadow_coords.y < 0 || shadow_shadow_coords.y > 1) {
+            if (shadow_clip.w <= 0 || shadow_flipped.x < 0 || shadow_flipped.x > 1 || shadow_flipped.y < 0 || shadow_flipped.y > 1) {
                 shadow_value = 1.0;
             } else {
-                shadow_value = texture(sampler2DArrayShadow(shadow, shadow_sampler), shadow_shadow_coords);
+                shadow_value = filter_shadow(spot_shadow, shadow_sampler, nearest_sampler, uniforms.shadow_filter, shadow_flipped, light.shadow_tex, shadow_ndc.z, vec2(1.0));
             }
 
-            color += surface_shading(directional_lights[i], pixel, v, shadow_value * pixel.ambient_occlusion);
+            color += surface_shading(light, i_view_position.xyz, pixel, v, shadow_value * pixel.ambient_occlusion);
         }
 
-        o_color = max(vec4(color, 1.0), uniforms.ambient * pixel.albedo);
-        o_normal = vec4(pixel.normal, 0.0);
+        for (uint i = 0; i < point_light_header.total_lights; ++i) {
+            PointLight light = point_lights[i];
+
+            // The cube face is picked by the major axis, so that axis is the view space depth inside that face.
+            vec3 light_to_pixel = world_position - light.position;
+            vec3 abs_light_to_pixel = abs(light_to_pixel);
+            float face_depth = max(abs_light_to_pixel.x, max(abs_light_to_pixel.y, abs_light_to_pixel.z));
+
+            float far = light.radius;
+            float near = POINT_SHADOW_NEAR;
+            float shadow_depth = (far / (far - near)) - (far * near) / ((far - near) * face_depth);
+
+            float shadow_value = texture(samplerCubeArrayShadow(point_shadow, shadow_sampler), vec4(light_to_pixel, light.shadow_tex), shadow_depth);
+
+            color += surface_shading(light, i_view_position.xyz, pixel, v, shadow_value * pixel.ambient_occlusion);
+        }
+
+        mat3 inv_view_rotation = mat3(uniforms.inv_view);
+        color += image_based_lighting(
+            pixel,
+            inv_view_rotation * pixel.normal,
+            inv_view_rotation * v,
+            irradiance_map,
+            prefiltered_map,
+            brdf_lut,
+            linear_sampler
+        );
+
+        #ifdef TRANSPARENT
+        // Blended over what is already drawn, so coverage comes from the albedo alpha.
+        write_output(vec4(max(color, uniforms.ambient.rgb * pixel.albedo.rgb), pixel.albedo.a), pixel.normal);
+        #else
+        write_output(max(vec4(color, 1.0), uniforms.ambient * pixel.albedo), pixel.normal);
+        #endif
     }
 }
@@ -3,6 +3,8 @@
 #extension GL_GOOGLE_include_directive : require
 
 #include "structures.glsl"
+#include "skinning.glsl"
+#include "morph.glsl"
 
 layout(location = 0) in vec3 i_position;
 layout(location = 1) in vec3 i_normal;
@@ -10,6 +12,9 @@ layout(location = 2) in vec3 i_tangent;
 layout(location = 3) in vec2 i_coords;
 layout(location = 4) in vec4 i_color;
 layout(location = 5) in uint i_material;
+layout(location = 7) in uvec4 i_joint_indices;
+layout(location = 8) in vec4 i_joint_weights;
+layout(location = 9) in vec2 i_coords1;
 #ifdef GPU_MODE
 layout(location = 6) in uint i_object_idx;
 #endif
@@ -20,6 +25,7 @@ layout(location = 2) out vec3 o_tangent;
 layout(location = 3) out vec2 o_coords;
 layout(location = 4) out vec4 o_color;
 layout(location = 5) flat out uint o_material;
+layout(location = 6) out vec2 o_coords1;
 
 layout(set = 1, binding = 0, std430) restrict readonly buffer ObjectOutputDataBuffer {
     ObjectOutputData object_output[];
@@ -34,21 +40,36 @@ layout(push_constant) uniform PushConstant {
 #endif
 
 void main() {
+    #ifdef CPU_MODE
+    // The push constant is the first instance of the draw, the rest follow it.
+    uint object_idx = i_object_idx + gl_InstanceIndex;
+    #else
     uint object_idx = i_object_idx;
+    #endif
 
     ObjectOutputData data = object_output[object_idx];
 
-    gl_Position = data.model_view_proj * vec4(i_position, 1.0);
+    vec3 model_position = i_position;
+    vec3 normal = i_normal;
+    vec3 tangent = i_tangent;
+    apply_morph_targets(data, gl_VertexIndex, model_position, normal, tangent);
+
+    mat4 skin = skin_matrix(data.joint_offset, i_joint_indices, i_joint_weights);
+    vec4 position = skin * vec4(model_position, 1.0);
+
+    gl_Position = data.model_view_proj * position;
 
     o_material = data.material_idx;
 
-    o_view_position = data.model_view * vec4(i_position, 1.0);
+    o_view_position = data.model_view * position;
 
-    o_normal = data.inv_trans_model_view * i_normal;
+    o_normal = data.inv_trans_model_view * (mat3(skin) * normal);
 
-    o_tangent = data.inv_trans_model_view * i_tangent;
+    o_tangent = data.inv_trans_model_view * (mat3(skin) * tangent);
 
     o_color = i_color;
 
     o_coords = i_coords;
+
+    o_coords1 = i_coords1;
 }
@@ -0,0 +1,23 @@
+#ifndef SHADER_SKINNING_GLSL
+#define SHADER_SKINNING_GLSL
+
+// If you change this, change NO_SKELETON in skeleton.rs
+#define NO_SKELETON 0xFFFFFFFF
+
+layout(set = 1, binding = 1, std430) restrict readonly buffer JointMatrixBuffer {
+    mat4 joint_matrices[];
+};
+
+// Model space transform of a vertex from its bind pose to the current pose of the skeleton.
+mat4 skin_matrix(uint joint_offset, uvec4 joint_indices, vec4 joint_weights) {
+    if (joint_offset == NO_SKELETON) {
+        return mat4(1.0);
+    }
+
+    return joint_weights.x * joint_matrices[joint_offset + joint_indices.x] +
+           joint_weights.y * joint_matrices[joint_offset + joint_indices.y] +
+           joint_weights.z * joint_matrices[joint_offset + joint_indices.z] +
+           joint_weights.w * joint_matrices[joint_offset + joint_indices.w];
+}
+
+#endif
@@ -22,6 +22,15 @@ struct ObjectInputData {
     mat4 transform;
     // xyz position; w radius
     vec4 bounding_sphere;
+    bool transparent;
+    // Range of the instances of the same object, which are drawn together.
+    uint batch_start;
+    uint batch_count;
+    // Index of the first joint matrix of the skeleton, or NO_SKELETON.
+    uint joint_offset;
+    uint morph_delta_offset;
+    uint morph_target_count;
+    uint morph_weight_offset;
 };
 
 /// If you change this struct, change the object output size in culling.rs
@@ -31,6 +40,19 @@ struct ObjectOutputData {
     mat3 inv_trans_model_view;
     uint material_idx;
     bool activ;
+    uint joint_offset;
+    int vertex_offset;
+    uint morph_delta_offset;
+    uint morph_target_count;
+    uint morph_weight_offset;
+};
+
+/// If you change this struct, change the transparent sort entry size in culling.rs
+struct TransparentSortEntry {
+    // Squared view space distance, as uint bits to keep the comparisons cheap. Only valid for positive floats.
+    uint distance;
+    uint input_idx;
+    uint output_idx;
 };
 
 struct IndirectCall {
@@ -54,7 +76,6 @@ struct IndirectCall {
 #define FLAGS_CC_GLTF_SPLIT      0x0400
 #define FLAGS_CC_BW_SPLIT        0x0800
 #define FLAGS_UNLIT              0x1000
-#define FLAGS_NEAREST            0x2000
 
 #define MATERIAL_FLAG(name) bool(material.material_flags & name)
 
@@ -83,6 +104,9 @@ struct GPUMaterialData {
     uint anisotropy_tex;
     uint ambient_occlusion_tex;
     uint material_flags;
+    uint uv1_textures;
+    // Index into the material_sampler bindings of material_sampler.glsl.
+    uint sampler;
 };
 
 struct CPUMaterialData {
@@ -101,6 +125,21 @@ struct CPUMaterialData {
 
     uint texture_enable;
     uint material_flags;
+    uint uv1_textures;
+};
+
+// If you change these, change ShaderShadowFilter in uniforms.rs
+#define SHADOW_FILTER_HARD 0
+#define SHADOW_FILTER_PCF 1
+#define SHADOW_FILTER_POISSON_PCF 2
+#define SHADOW_FILTER_PCSS 3
+
+struct ShadowFilterData {
+    uint mode;
+    // Kernel size for pcf, sample count for poisson pcf and pcss.
+    uint samples;
+    // Radius in texels for poisson pcf, light size in uv space for pcss.
+    float radius;
 };
 
 struct UniformData {
@@ -110,17 +149,55 @@ struct UniformData {
     mat4 inv_origin_view_proj;
     Frustum frustum;
     vec4 ambient;
+    ShadowFilterData shadow_filter;
 };
 
 struct DirectionalLightBufferHeader {
     uint total_lights;
 };
 
+// If you change this, change MAX_SHADOW_CASCADES in directional.rs
+#define MAX_SHADOW_CASCADES 4
+
 struct DirectionalLight {
-    mat4 view_proj;
+    mat4 view_proj[MAX_SHADOW_CASCADES];
+    vec4 cascade_splits;
+    vec4 depth_biases;
+    vec4 normal_offsets;
+    vec3 color;
+    uint shadow_tex;
+    vec3 direction;
+    uint cascade_count;
+    float uv_scale;
+};
+
+// If you change this, change POINT_SHADOW_NEAR in point.rs
+#define POINT_SHADOW_NEAR 0.1
+
+struct PointLightBufferHeader {
+    uint total_lights;
+};
+
+struct PointLight {
+    vec3 position;
+    float radius;
     vec3 color;
     uint shadow_tex;
+};
+
+struct SpotLightBufferHeader {
+    uint total_lights;
+};
+
+struct SpotLight {
+    mat4 view_proj;
+    vec3 position;
+    float range;
     vec3 direction;
+    float angle_scale;
+    vec3 color;
+    float angle_offset;
+    uint shadow_tex;
 };
 
 #endif
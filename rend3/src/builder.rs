@@ -35,6 +35,7 @@ where
     pub(crate) desired_backend: Option<Backend>,
     pub(crate) desired_device_name: Option<String>,
     pub(crate) desired_mode: Option<RendererMode>,
+    pub(crate) headless: bool,
 }
 impl<'a> RendererBuilder<'a, DummyWindow, ()> {
     pub fn new(options: RendererOptions) -> Self {
@@ -47,6 +48,7 @@ impl<'a> RendererBuilder<'a, DummyWindow, ()> {
             desired_backend: None,
             desired_device_name: None,
             desired_mode: None,
+            headless: false,
         }
     }
 }
@@ -73,6 +75,14 @@ where
         self
     }
 
+    /// Create an offscreen target of [`RendererOptions::size`] that can be rendered to with
+    /// [`RendererOutput::Headless`](crate::RendererOutput::Headless). Does not require a window, so works
+    /// on machines without a display, including software adapters.
+    pub fn headless(mut self) -> Self {
+        self.headless = true;
+        self
+    }
+
     pub fn window<W2: HasRawWindowHandle>(self, window: &'a W2) -> RendererBuilder<'a, W2, TLD> {
         RendererBuilder {
             window: Some(window),
@@ -83,6 +93,7 @@ where
             desired_backend: self.desired_backend,
             desired_device_name: self.desired_device_name,
             desired_mode: self.desired_mode,
+            headless: self.headless,
         }
     }
 
@@ -100,6 +111,7 @@ where
             desired_backend: self.desired_backend,
             desired_device_name: self.desired_device_name,
             desired_mode: self.desired_mode,
+            headless: self.headless,
         }
     }
 
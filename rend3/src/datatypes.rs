@@ -13,6 +13,13 @@ macro_rules! declare_handle {
         pub struct $name(pub(crate) usize);
 
         impl $name {
+            /// Makes a handle from the value [`get`](Self::get) returns, for tests that need handles without a
+            /// renderer. Not part of the public api: only a renderer that allocated the same value knows about it.
+            #[doc(hidden)]
+            pub fn from_raw(raw: usize) -> Self {
+                Self(raw)
+            }
+
             pub fn get(&self) -> usize {
                 self.0
             }
@@ -26,15 +33,19 @@ declare_handle!(
     MaterialHandle,
     ObjectHandle,
     DirectionalLightHandle,
+    PointLightHandle,
+    SpotLightHandle,
+    SkeletonHandle,
     ShaderHandle,
     PipelineHandle
 );
 
 macro_rules! changeable_struct {
-    ($(#[$outer:meta])* pub struct $name:ident <- nodefault $name_change:ident { $($field_vis:vis $field_name:ident : $field_type:ty),* $(,)? } ) => {
+    ($(#[$outer:meta])* pub struct $name:ident <- nodefault $name_change:ident { $($(#[$inner:meta])* $field_vis:vis $field_name:ident : $field_type:ty),* $(,)? } ) => {
         $(#[$outer])*
         pub struct $name {
             $(
+                $(#[$inner])*
                 $field_vis $field_name : $field_type
             ),*
         }
@@ -50,14 +61,16 @@ macro_rules! changeable_struct {
         $(#[$outer])*
         pub struct $name_change {
             $(
+                $(#[$inner])*
                 $field_vis $field_name : Option<$field_type>
             ),*
         }
     };
-    ($(#[$outer:meta])* pub struct $name:ident <- $name_change:ident { $($field_vis:vis $field_name:ident : $field_type:ty),* $(,)? } ) => {
+    ($(#[$outer:meta])* pub struct $name:ident <- $name_change:ident { $($(#[$inner:meta])* $field_vis:vis $field_name:ident : $field_type:ty),* $(,)? } ) => {
         $(#[$outer])*
         pub struct $name {
             $(
+                $(#[$inner])*
                 $field_vis $field_name : $field_type
             ),*
         }
@@ -74,6 +87,7 @@ macro_rules! changeable_struct {
         #[derive(Default)]
         pub struct $name_change {
             $(
+                $(#[$inner])*
                 $field_vis $field_name : Option<$field_type>
             ),*
         }
@@ -103,12 +117,19 @@ pub enum RendererTextureFormat {
     Bc6Unsigned,
     Bc7Linear,
     Bc7Srgb,
+    Rgba16Float,
+    /// Not filterable, so it can't be sampled by materials. Useful as a high precision source for
+    /// [`Renderer::add_texture_cube_from_equirectangular`](crate::Renderer::add_texture_cube_from_equirectangular).
+    Rgba32Float,
 }
 
 impl RendererTextureFormat {
     pub fn pixels_per_block(&self) -> u32 {
         match self {
-            RendererTextureFormat::Rgba8Srgb | RendererTextureFormat::Rgba8Linear => 1,
+            RendererTextureFormat::Rgba8Srgb
+            | RendererTextureFormat::Rgba8Linear
+            | RendererTextureFormat::Rgba16Float
+            | RendererTextureFormat::Rgba32Float => 1,
             RendererTextureFormat::Bc1Linear
             | RendererTextureFormat::Bc1Srgb
             | RendererTextureFormat::Bc3Linear
@@ -125,14 +146,18 @@ impl RendererTextureFormat {
     pub fn bytes_per_block(&self) -> u32 {
         match self {
             RendererTextureFormat::Rgba8Srgb | RendererTextureFormat::Rgba8Linear => 4,
-            RendererTextureFormat::Bc1Linear | RendererTextureFormat::Bc1Srgb | RendererTextureFormat::Bc4Linear => 8,
+            RendererTextureFormat::Bc1Linear
+            | RendererTextureFormat::Bc1Srgb
+            | RendererTextureFormat::Bc4Linear
+            | RendererTextureFormat::Rgba16Float => 8,
             RendererTextureFormat::Bc3Linear
             | RendererTextureFormat::Bc3Srgb
             | RendererTextureFormat::Bc5Normal
             | RendererTextureFormat::Bc6Signed
             | RendererTextureFormat::Bc6Unsigned
             | RendererTextureFormat::Bc7Linear
-            | RendererTextureFormat::Bc7Srgb => 16,
+            | RendererTextureFormat::Bc7Srgb
+            | RendererTextureFormat::Rgba32Float => 16,
         }
     }
 }
@@ -152,6 +177,8 @@ impl From<RendererTextureFormat> for wgpu::TextureFormat {
             RendererTextureFormat::Bc6Unsigned => TextureFormat::Bc6hRgbUfloat,
             RendererTextureFormat::Bc7Linear => TextureFormat::Bc7RgbaUnorm,
             RendererTextureFormat::Bc7Srgb => TextureFormat::Bc7RgbaUnormSrgb,
+            RendererTextureFormat::Rgba16Float => TextureFormat::Rgba16Float,
+            RendererTextureFormat::Rgba32Float => TextureFormat::Rgba32Float,
         }
     }
 }
@@ -183,8 +210,12 @@ pub struct MeshBuilder {
     vertex_normals: Option<Vec<Vec3>>,
     vertex_tangents: Option<Vec<Vec3>>,
     vertex_uvs: Option<Vec<Vec2>>,
+    vertex_uv1s: Option<Vec<Vec2>>,
     vertex_colors: Option<Vec<[u8; 4]>>,
     vertex_material_indices: Option<Vec<u32>>,
+    vertex_joint_indices: Option<Vec<[u16; 4]>>,
+    vertex_joint_weights: Option<Vec<Vec4>>,
+    morph_targets: Vec<MorphTarget>,
     vertex_count: usize,
 
     indices: Option<Vec<u32>>,
@@ -246,6 +277,18 @@ impl MeshBuilder {
         self
     }
 
+    /// Add a second set of texture coordinates to the given mesh, used by the textures in a material's
+    /// [`Material::uv1_textures`]. Commonly used for lightmaps and ambient occlusion.
+    ///
+    /// # Panic
+    ///
+    /// Will panic if the length is different from the position buffer length.
+    pub fn with_vertex_uv1(mut self, uvs: Vec<Vec2>) -> Self {
+        self.validate_len(uvs.len());
+        self.vertex_uv1s = Some(uvs);
+        self
+    }
+
     /// Add vertex colors to the given mesh.
     ///
     /// # Panic
@@ -268,6 +311,49 @@ impl MeshBuilder {
         self
     }
 
+    /// Add the indices of the four joints influencing each vertex, for use with a [`Skeleton`].
+    ///
+    /// # Panic
+    ///
+    /// Will panic if the length is different from the position buffer length.
+    pub fn with_vertex_joint_indices(mut self, joint_indices: Vec<[u16; 4]>) -> Self {
+        self.validate_len(joint_indices.len());
+        self.vertex_joint_indices = Some(joint_indices);
+        self
+    }
+
+    /// Add the weights of the four joints influencing each vertex, for use with a [`Skeleton`]. The weights of a
+    /// vertex should add up to one.
+    ///
+    /// # Panic
+    ///
+    /// Will panic if the length is different from the position buffer length.
+    pub fn with_vertex_joint_weights(mut self, joint_weights: Vec<Vec4>) -> Self {
+        self.validate_len(joint_weights.len());
+        self.vertex_joint_weights = Some(joint_weights);
+        self
+    }
+
+    /// Add morph targets, blended onto the mesh by the [`Object::morph_weights`] of each object. Normal and tangent
+    /// deltas may be left empty.
+    ///
+    /// # Panic
+    ///
+    /// Will panic if the length of any non-empty delta buffer is different from the position buffer length.
+    pub fn with_morph_targets(mut self, mut morph_targets: Vec<MorphTarget>) -> Self {
+        for target in &mut morph_targets {
+            self.validate_len(target.position_deltas.len());
+            for deltas in [&mut target.normal_deltas, &mut target.tangent_deltas].iter_mut() {
+                if deltas.is_empty() {
+                    **deltas = vec![Vec3::ZERO; self.vertex_count];
+                }
+                self.validate_len(deltas.len());
+            }
+        }
+        self.morph_targets = morph_targets;
+        self
+    }
+
     /// Add indices to the given mesh.
     ///
     /// # Panic
@@ -308,8 +394,12 @@ impl MeshBuilder {
             vertex_normals: self.vertex_normals.unwrap_or_else(|| vec![Vec3::ZERO; length]),
             vertex_tangents: self.vertex_tangents.unwrap_or_else(|| vec![Vec3::ZERO; length]),
             vertex_uvs: self.vertex_uvs.unwrap_or_else(|| vec![Vec2::ZERO; length]),
+            vertex_uv1s: self.vertex_uv1s.unwrap_or_else(|| vec![Vec2::ZERO; length]),
             vertex_colors: self.vertex_colors.unwrap_or_else(|| vec![[0; 4]; length]),
             vertex_material_indices: self.vertex_material_indices.unwrap_or_else(|| vec![0; length]),
+            vertex_joint_indices: self.vertex_joint_indices.unwrap_or_else(|| vec![[0; 4]; length]),
+            vertex_joint_weights: self.vertex_joint_weights.unwrap_or_else(|| vec![Vec4::ZERO; length]),
+            morph_targets: self.morph_targets,
             indices: self.indices.unwrap_or_else(|| (0..length as u32).collect()),
         };
 
@@ -330,6 +420,14 @@ impl MeshBuilder {
     }
 }
 
+/// Per vertex offsets added to a mesh, scaled by the weight of the target on each object.
+#[derive(Debug, Default, Clone)]
+pub struct MorphTarget {
+    pub position_deltas: Vec<Vec3>,
+    pub normal_deltas: Vec<Vec3>,
+    pub tangent_deltas: Vec<Vec3>,
+}
+
 /// Represents a mesh that may be used by many objects.
 ///
 /// Meshes are in Structure of Array format and must have all the vertex_* arrays be the same length.
@@ -342,25 +440,43 @@ pub struct Mesh {
     pub vertex_normals: Vec<Vec3>,
     pub vertex_tangents: Vec<Vec3>,
     pub vertex_uvs: Vec<Vec2>,
+    /// Second set of texture coordinates, see [`MeshBuilder::with_vertex_uv1`].
+    pub vertex_uv1s: Vec<Vec2>,
     pub vertex_colors: Vec<[u8; 4]>,
     pub vertex_material_indices: Vec<u32>,
+    /// Only used when drawn with a [`Skeleton`].
+    pub vertex_joint_indices: Vec<[u16; 4]>,
+    /// Only used when drawn with a [`Skeleton`].
+    pub vertex_joint_weights: Vec<Vec4>,
+    pub morph_targets: Vec<MorphTarget>,
 
     pub indices: Vec<u32>,
 }
 
 impl Mesh {
-    /// Validates that all vertex attributes have the same length.
+    /// Validates that all vertex attributes and morph target deltas have the same length.
     pub fn validate(&self) -> bool {
-        [
-            self.vertex_positions.len(),
-            self.vertex_normals.len(),
-            self.vertex_tangents.len(),
-            self.vertex_uvs.len(),
-            self.vertex_colors.len(),
-            self.vertex_material_indices.len(),
-        ]
-        .iter()
-        .all_equal()
+        let vertex_count = self.vertex_positions.len();
+        let targets_valid = self.morph_targets.iter().all(|target| {
+            target.position_deltas.len() == vertex_count
+                && target.normal_deltas.len() == vertex_count
+                && target.tangent_deltas.len() == vertex_count
+        });
+
+        targets_valid
+            && [
+                self.vertex_positions.len(),
+                self.vertex_normals.len(),
+                self.vertex_tangents.len(),
+                self.vertex_uvs.len(),
+                self.vertex_uv1s.len(),
+                self.vertex_colors.len(),
+                self.vertex_material_indices.len(),
+                self.vertex_joint_indices.len(),
+                self.vertex_joint_weights.len(),
+            ]
+            .iter()
+            .all_equal()
     }
 
     /// Calculate normals for the given mesh, assuming smooth shading and per-vertex normals.
@@ -511,6 +627,19 @@ pub struct Texture {
     pub height: u32,
     pub label: Option<String>,
     pub mip_levels: u32,
+    pub mip_mode: MipmapMode,
+}
+
+/// Where the mips of a [`Texture`] come from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MipmapMode {
+    /// `data` holds `mip_levels` mips, largest first.
+    Manual,
+    /// `data` only holds the largest mip, the rest of the chain is generated on the gpu and `mip_levels` is ignored.
+    /// Filtering of sRGB formats happens in linear space.
+    ///
+    /// Only supported for uncompressed formats.
+    Generate,
 }
 
 bitflags::bitflags! {
@@ -528,7 +657,24 @@ bitflags::bitflags! {
         const CC_GLTF_SPLIT =      0b0000_0100_0000_0000;
         const CC_BW_SPLIT =        0b0000_1000_0000_0000;
         const UNLIT =              0b0001_0000_0000_0000;
-        const NEAREST =            0b0010_0000_0000_0000;
+    }
+}
+
+bitflags::bitflags! {
+    /// Textures of a [`Material`] which are sampled with the second set of texture coordinates of the mesh, set with
+    /// [`MeshBuilder::with_vertex_uv1`].
+    #[derive(Default)]
+    pub struct Uv1Textures : u32 {
+        const ALBEDO =               0b00_0000_0001;
+        const NORMAL =               0b00_0000_0010;
+        const ROUGHNESS =            0b00_0000_0100;
+        const METALLIC =             0b00_0000_1000;
+        const REFLECTANCE =          0b00_0001_0000;
+        const CLEARCOAT =            0b00_0010_0000;
+        const CLEARCOAT_ROUGHNESS =  0b00_0100_0000;
+        const EMISSIVE =             0b00_1000_0000;
+        const ANISOTROPY =           0b01_0000_0000;
+        const AMBIENT_OCCLUSION =    0b10_0000_0000;
     }
 }
 
@@ -857,6 +1003,89 @@ impl Default for ClearcoatTextures {
     }
 }
 
+/// How the alpha of a material's albedo is used.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Transparency {
+    /// Alpha is ignored.
+    Opaque,
+    /// Pixels with an alpha below `cutout` are discarded.
+    Cutout { cutout: f32 },
+    /// Blended over what is behind it. Blended objects are drawn by
+    /// [`RenderOpInputType::TransparentModels3D`](crate::list::RenderOpInputType::TransparentModels3D) ops, sorted back
+    /// to front, and don't cast shadows.
+    Blend,
+}
+impl Transparency {
+    pub(crate) fn cutout(&self) -> f32 {
+        match *self {
+            Self::Cutout { cutout } => cutout,
+            Self::Opaque | Self::Blend => 0.0,
+        }
+    }
+}
+impl Default for Transparency {
+    fn default() -> Self {
+        Self::Opaque
+    }
+}
+
+/// How texture coordinates outside of 0 to 1 are sampled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SamplerAddressMode {
+    Repeat,
+    MirrorRepeat,
+    ClampToEdge,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SamplerFilter {
+    Nearest,
+    Linear,
+}
+
+/// How every texture of a [`Material`] is sampled.
+///
+/// Materials with the same options share a sampler, which is freed once no material uses it. In gpu mode at most 8
+/// different options, including the default, can be in use at once. Materials with further options are sampled with
+/// [`SamplerOptions::LINEAR`] until a slot frees up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SamplerOptions {
+    pub address_mode_u: SamplerAddressMode,
+    pub address_mode_v: SamplerAddressMode,
+    pub mag_filter: SamplerFilter,
+    pub min_filter: SamplerFilter,
+    pub mipmap_filter: SamplerFilter,
+    /// Maximum anisotropy of anisotropic filtering, rounded down to 1, 2, 4, 8 or 16. Only used if every filter is
+    /// linear.
+    pub anisotropy: u8,
+}
+impl SamplerOptions {
+    /// Repeating trilinear filtering with 16x anisotropic filtering.
+    pub const LINEAR: Self = Self {
+        address_mode_u: SamplerAddressMode::Repeat,
+        address_mode_v: SamplerAddressMode::Repeat,
+        mag_filter: SamplerFilter::Linear,
+        min_filter: SamplerFilter::Linear,
+        mipmap_filter: SamplerFilter::Linear,
+        anisotropy: 16,
+    };
+
+    /// Repeating nearest filtering, within and between mips.
+    pub const NEAREST: Self = Self {
+        address_mode_u: SamplerAddressMode::Repeat,
+        address_mode_v: SamplerAddressMode::Repeat,
+        mag_filter: SamplerFilter::Nearest,
+        min_filter: SamplerFilter::Nearest,
+        mipmap_filter: SamplerFilter::Nearest,
+        anisotropy: 1,
+    };
+}
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        Self::LINEAR
+    }
+}
+
 // Consider:
 //
 // - Green screen value
@@ -875,11 +1104,14 @@ changeable_struct! {
         pub emissive: MaterialComponent<Vec3>,
         pub reflectance: MaterialComponent<f32>,
         pub anisotropy: MaterialComponent<f32>,
-        pub alpha_cutout: Option<f32>,
+        pub transparency: Transparency,
+        /// Transform applied to the texture coordinates of every texture.
         pub transform: Mat3,
+        /// Textures sampled with the second set of texture coordinates instead of the first.
+        pub uv1_textures: Uv1Textures,
         // TODO: Determine how to make this a clearer part of the type system, esp. with the changable_struct macro.
         pub unlit: bool,
-        pub nearest: bool,
+        pub sampler: SamplerOptions,
     }
 }
 
@@ -888,6 +1120,28 @@ pub struct Object {
     pub mesh: MeshHandle,
     pub material: MaterialHandle,
     pub transform: AffineTransform,
+    /// Skeleton deforming the mesh. The mesh needs joint indices and weights.
+    pub skeleton: Option<SkeletonHandle>,
+    /// Weight of each morph target of the mesh. Missing weights are zero. Culling assumes weights between -1 and 1.
+    pub morph_weights: Vec<f32>,
+}
+
+/// Joint matrices deforming any objects drawn with it.
+///
+/// Each matrix takes a vertex from the bind pose of the mesh to its animated position, so is the global transform of
+/// the joint multiplied by its inverse bind matrix. Both are in the model space of the object.
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    pub joint_matrices: Vec<Mat4>,
+}
+
+/// Many copies of the same mesh and material. Every instance is culled on its own, and the survivors are drawn in a
+/// single draw call.
+#[derive(Debug, Clone)]
+pub struct InstancedObject {
+    pub mesh: MeshHandle,
+    pub material: MaterialHandle,
+    pub transforms: Vec<AffineTransform>,
 }
 
 #[derive(Debug, Default, Copy, Clone)]
@@ -941,6 +1195,41 @@ changeable_struct! {
         pub color: Vec3,
         pub intensity: f32,
         pub direction: Vec3,
+        /// Width and height of every shadow cascade of this light, in texels.
+        pub shadow_resolution: u32,
+        /// Distance, in shadow texels, that surfaces are moved towards the light before their shadow is looked up.
+        pub depth_bias: f32,
+        /// Distance, in shadow texels, that surfaces are moved along their normal before their shadow is looked up.
+        /// Fixes shadow acne on curved surfaces and surfaces facing away from the light.
+        pub normal_bias: f32,
+    }
+}
+
+changeable_struct! {
+    #[derive(Debug, Copy, Clone)]
+    pub struct PointLight <- PointLightChange {
+        pub position: Vec3,
+        pub color: Vec3,
+        pub intensity: f32,
+        /// Distance at which the light no longer has any effect. Also the far plane of the light's shadow map, so it is
+        /// clamped to at least 0.2.
+        pub radius: f32,
+    }
+}
+
+changeable_struct! {
+    #[derive(Debug, Copy, Clone)]
+    pub struct SpotLight <- SpotLightChange {
+        pub position: Vec3,
+        pub direction: Vec3,
+        /// Angle in radians between the direction and the edge of the fully lit inner cone.
+        pub inner_angle: f32,
+        /// Angle in radians between the direction and the edge of the cone, past which there is no light.
+        pub outer_angle: f32,
+        /// Distance at which the light no longer has any effect. Also the far plane of the light's shadow map.
+        pub range: f32,
+        pub color: Vec3,
+        pub intensity: f32,
     }
 }
 
@@ -961,6 +1250,7 @@ pub enum PipelineBindingType {
     GPUCubeTextures,
     ShadowTexture,
     SkyboxTexture,
+    EnvironmentTextures,
     Custom2DTexture { count: usize },
     CustomCubeTexture { count: usize },
 }
@@ -974,21 +1264,38 @@ pub enum DepthCompare {
     FurtherEqual,
 }
 
+/// How the output of a pipeline is combined with what is already in the attachment.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PipelineBlendMode {
+    /// The output overwrites the attachment.
+    Replace,
+    /// The output is blended over the attachment by its alpha: `src * src_alpha + dst * (1 - src_alpha)`.
+    AlphaBlend,
+    /// The output is added to the attachment: `src + dst`.
+    Additive,
+    /// The attachment is scaled by the inverse of the output: `dst * (1 - src)`. Used for the revealage of weighted
+    /// blended transparency.
+    InverseMultiply,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct PipelineOutputAttachment {
     pub format: ImageFormat,
     pub write: bool,
+    pub blend: PipelineBlendMode,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct PipelineDepthState {
     pub format: ImageFormat,
     pub compare: DepthCompare,
+    /// Write the depth of passing fragments. Blended geometry generally shouldn't, so it doesn't hide what is behind
+    /// it.
+    pub write: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Pipeline {
-    // TODO: Alpha
     pub run_rate: RenderPassRunRate,
     pub input: PipelineInputType,
     pub outputs: Vec<PipelineOutputAttachment>,
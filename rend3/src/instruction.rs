@@ -1,11 +1,13 @@
 use crate::{
     datatypes::{
-        AffineTransform, Camera, DirectionalLight, DirectionalLightChange, DirectionalLightHandle, Material,
-        MaterialChange, MaterialHandle, Mesh, MeshHandle, Object, ObjectHandle, PipelineHandle, ShaderHandle, Texture,
-        TextureHandle,
+        AffineTransform, Camera, DirectionalLight, DirectionalLightChange, DirectionalLightHandle, InstancedObject,
+        Material, MaterialChange, MaterialHandle, Mesh, MeshHandle, Object, ObjectHandle, PipelineHandle, PointLight,
+        PointLightChange, PointLightHandle, ShaderHandle, Skeleton, SkeletonHandle, SpotLight, SpotLightChange,
+        SpotLightHandle, Texture, TextureHandle,
     },
     RendererOptions,
 };
+use glam::Mat4;
 use parking_lot::Mutex;
 use std::mem;
 
@@ -28,6 +30,11 @@ pub enum Instruction {
         handle: TextureHandle,
         texture: Texture,
     },
+    AddTextureCubeFromEquirectangular {
+        handle: TextureHandle,
+        texture: Texture,
+        resolution: u32,
+    },
     RemoveTextureCube {
         handle: TextureHandle,
     },
@@ -46,13 +53,36 @@ pub enum Instruction {
         handle: ObjectHandle,
         object: Object,
     },
+    AddInstancedObject {
+        handle: ObjectHandle,
+        object: InstancedObject,
+    },
     SetObjectTransform {
         handle: ObjectHandle,
         transform: AffineTransform,
     },
+    SetInstanceTransforms {
+        handle: ObjectHandle,
+        transforms: Vec<AffineTransform>,
+    },
+    SetObjectMorphWeights {
+        handle: ObjectHandle,
+        weights: Vec<f32>,
+    },
     RemoveObject {
         handle: ObjectHandle,
     },
+    AddSkeleton {
+        handle: SkeletonHandle,
+        skeleton: Skeleton,
+    },
+    SetSkeletonJointMatrices {
+        handle: SkeletonHandle,
+        joint_matrices: Vec<Mat4>,
+    },
+    RemoveSkeleton {
+        handle: SkeletonHandle,
+    },
     AddDirectionalLight {
         handle: DirectionalLightHandle,
         light: DirectionalLight,
@@ -64,6 +94,28 @@ pub enum Instruction {
     RemoveDirectionalLight {
         handle: DirectionalLightHandle,
     },
+    AddPointLight {
+        handle: PointLightHandle,
+        light: PointLight,
+    },
+    ChangePointLight {
+        handle: PointLightHandle,
+        change: PointLightChange,
+    },
+    RemovePointLight {
+        handle: PointLightHandle,
+    },
+    AddSpotLight {
+        handle: SpotLightHandle,
+        light: SpotLight,
+    },
+    ChangeSpotLight {
+        handle: SpotLightHandle,
+        change: SpotLightChange,
+    },
+    RemoveSpotLight {
+        handle: SpotLightHandle,
+    },
     AddBinaryShader {
         handle: ShaderHandle,
         shader: Vec<u32>,
@@ -84,6 +136,10 @@ pub enum Instruction {
         handle: TextureHandle,
     },
     ClearBackgroundTexture,
+    SetEnvironmentTexture {
+        handle: TextureHandle,
+    },
+    ClearEnvironmentTexture,
 }
 
 pub struct InstructionStreamPair {
@@ -88,6 +88,7 @@ mod options;
 mod output;
 mod registry;
 mod renderer;
+pub mod scene;
 mod statistics;
 
 pub use builder::*;
@@ -7,6 +7,9 @@ use crate::{
 pub enum RenderPassRunRate {
     /// Run this RenderPassSet once for every shadow, the output texture being the shadow map.
     PerShadow,
+    /// Run this RenderPassSet once for every face of every point light's shadow cube, the output texture being the
+    /// cube face.
+    PerPointShadow,
     /// Run this RenderPassSet once. Output texture is the swapchain frame.
     Once,
 }
@@ -30,9 +33,11 @@ pub struct RenderOpDescriptor {
 pub enum RenderOpInputType {
     /// No bound vertex inputs, just a simple `draw(0..3)`
     FullscreenTriangle,
-    /// Render all 3D models.
+    /// Render all 3D models without a blended material, grouped by material.
     // TODO: Filtering
     Models3D,
+    /// Render all 3D models with a blended material, sorted back to front.
+    TransparentModels3D,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
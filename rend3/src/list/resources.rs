@@ -41,6 +41,11 @@ pub enum ResourceBinding {
     /// Binding in All Modes:
     /// 0: Current skybox texture
     SkyboxTexture,
+    /// Bindings in All Modes:
+    /// 0: Diffuse irradiance cubemap
+    /// 1: GGX prefiltered specular cubemap, roughness increasing with the mip level
+    /// 2: BRDF integration lookup table
+    EnvironmentTextures,
     /// Usable in all modes.
     ///
     /// Each given texture will be it's own binding
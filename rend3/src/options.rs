@@ -6,12 +6,55 @@ pub enum VSyncMode {
     Off,
 }
 
+/// How directional light shadows are split up along the view of the camera.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShadowCascadeOptions {
+    /// Amount of shadow maps every directional light gets. Clamped to between 1 and 4.
+    pub count: u32,
+    /// Where the splits between cascades go, from 0.0 for evenly spaced splits to 1.0 for logarithmic splits which
+    /// favor detail close to the camera.
+    pub split_lambda: f32,
+    /// Distance from the camera past which there are no directional shadows.
+    pub max_distance: f32,
+}
+impl Default for ShadowCascadeOptions {
+    fn default() -> Self {
+        Self {
+            count: 4,
+            split_lambda: 0.75,
+            max_distance: 150.0,
+        }
+    }
+}
+
+/// How shadow maps are filtered when directional and spot lights are shaded.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ShadowFilter {
+    /// A single unfiltered comparison, giving hard, aliased edges.
+    Hard,
+    /// Percentage closer filtering over a square of `kernel_size * kernel_size` texels. Clamped to between 1 and 9.
+    Pcf { kernel_size: u32 },
+    /// Percentage closer filtering over `samples` points of a poisson disk `radius` texels wide, rotated per pixel to
+    /// trade banding for noise. Clamped to between 1 and 32 samples.
+    PoissonPcf { samples: u32, radius: f32 },
+    /// Percentage closer soft shadows, which widen the penumbra as the distance between occluder and receiver grows.
+    /// `light_size` is the size of the light in shadow map uv space, values around 0.01 to 0.05 look natural.
+    Pcss { light_size: f32 },
+}
+impl Default for ShadowFilter {
+    fn default() -> Self {
+        Self::Pcf { kernel_size: 3 }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct RendererOptions {
     pub vsync: VSyncMode,
     pub size: [u32; 2],
-    /// A temporary minimum linear color value used to compensate for the lack of IBL currently. The result of lighting is combined like so `max(lighting, ambient * albedo)`. Set to zero to ignore ambient.
+    /// A minimum linear color value, for scenes without an environment texture to light them. The result of lighting is combined like so `max(lighting, ambient * albedo)`. Set to zero to ignore ambient.
     pub ambient: Vec4,
+    pub shadow_cascades: ShadowCascadeOptions,
+    pub shadow_filter: ShadowFilter,
 }
 impl RendererOptions {
     pub fn aspect_ratio(&self) -> f32 {
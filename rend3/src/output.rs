@@ -1,3 +1,4 @@
+use crate::renderer::headless::HeadlessTarget;
 use std::sync::Arc;
 use wgpu::{SwapChain, SwapChainError, SwapChainFrame, TextureFormat, TextureView};
 
@@ -30,9 +31,16 @@ pub enum RendererOutput {
     ExternalSwapchain(Arc<SwapChainFrame>),
     /// Use an arbitrary texture view. Format must be [`SWAPCHAIN_FORMAT`].
     Image(Arc<TextureView>),
+    /// Use the internally managed offscreen target. Must setup renderer using [`RendererBuilder::headless`]
+    /// before this can be used. Use [`Renderer::render_to_image`] to also read the frame back to the cpu.
+    ///
+    /// # Panics
+    ///
+    /// Rendering will panic if the renderer wasn't built headless.
+    Headless,
 }
 impl RendererOutput {
-    pub(crate) fn acquire(self, internal: &mut Option<SwapChain>) -> OutputFrame {
+    pub(crate) fn acquire(self, internal: &mut Option<SwapChain>, headless: Option<&HeadlessTarget>) -> OutputFrame {
         match self {
             RendererOutput::InternalSwapchain => {
                 let sc = internal
@@ -55,6 +63,19 @@ impl RendererOutput {
             }
             RendererOutput::ExternalSwapchain(frame) => OutputFrame::Swapchain(frame),
             RendererOutput::Image(view) => OutputFrame::View(view),
+            RendererOutput::Headless => OutputFrame::View(Arc::clone(
+                &headless
+                    .expect("Must setup renderer as headless in order to use headless output")
+                    .view,
+            )),
         }
     }
 }
+
+/// A frame read back from the headless target.
+#[derive(Debug, Clone)]
+pub struct RenderedImage {
+    pub size: [u32; 2],
+    /// Tightly packed rgba8 pixels, row by row starting at the top left.
+    pub data: Vec<u8>,
+}
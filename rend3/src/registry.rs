@@ -44,10 +44,19 @@ impl<T> ResourceRegistry<T> {
         self.mapping.get(&handle).unwrap()
     }
 
+    pub fn try_get(&self, handle: usize) -> Option<&T> {
+        self.mapping.get(&handle)
+    }
+
     pub fn get_mut(&mut self, handle: usize) -> &mut T {
         self.mapping.get_mut(&handle).unwrap()
     }
 
+    /// Value at the given position of [`values`](Self::values).
+    pub fn get_by_index(&self, index: usize) -> &T {
+        self.mapping.get_index(index).unwrap().1
+    }
+
     pub fn get_index_of(&self, handle: usize) -> usize {
         self.mapping.get_index_of(&handle).unwrap()
     }
@@ -1,5 +1,5 @@
 use crate::datatypes::{Camera, CameraProjection};
-use glam::{EulerRot, Mat3, Mat4, Vec3, Vec3A};
+use glam::{EulerRot, Mat3, Mat4, Vec2, Vec3, Vec3A};
 
 #[derive(Copy, Clone)]
 pub struct CameraManager {
@@ -24,6 +24,20 @@ impl CameraManager {
         }
     }
 
+    /// Builds a camera from explicit matrices, for views which can't be described by [`Camera`] such as shadow map
+    /// faces. Changing the aspect ratio will throw away the matrices, so this is only for internal cameras.
+    pub fn from_matrices(location: Vec3, view: Mat4, proj: Mat4) -> Self {
+        Self {
+            orig_view: view * Mat4::from_translation(location),
+            view,
+            proj,
+            data: Camera {
+                location: location.into(),
+                ..Camera::default()
+            },
+        }
+    }
+
     /// Sets the camera data, rebuilding the using the given aspect ratio. If no aspect ratio is given
     /// it is assumed that no aspect ratio scaling should be done.
     pub fn set_data(&mut self, data: Camera, aspect_ratio: Option<f32>) {
@@ -52,6 +66,35 @@ impl CameraManager {
     pub fn proj(&self) -> Mat4 {
         self.proj
     }
+
+    /// Distance to the near plane. Orthographic cameras see everything in front of them.
+    pub fn near(&self) -> f32 {
+        match self.data.projection {
+            CameraProjection::Projection { near, .. } => near,
+            CameraProjection::Orthographic { .. } => 0.0,
+        }
+    }
+
+    /// World space corners of the slice of the view frustum between the given distances from the camera.
+    pub fn frustum_corners(&self, near: f32, far: f32) -> [Vec3; 8] {
+        // Half of the width and height of the frustum, at one unit from the camera for perspective cameras.
+        let half_extent = Vec2::new(1.0 / self.proj.x_axis.x, 1.0 / self.proj.y_axis.y);
+        let slice_extent = |distance: f32| match self.data.projection {
+            CameraProjection::Projection { .. } => half_extent * distance,
+            CameraProjection::Orthographic { .. } => half_extent,
+        };
+
+        let inv_view = self.view.inverse();
+        let mut corners = [Vec3::ZERO; 8];
+        for (idx, &distance) in [near, far].iter().enumerate() {
+            let extent = slice_extent(distance);
+            corners[idx * 4] = inv_view.transform_point3(Vec3::new(-extent.x, -extent.y, distance));
+            corners[idx * 4 + 1] = inv_view.transform_point3(Vec3::new(extent.x, -extent.y, distance));
+            corners[idx * 4 + 2] = inv_view.transform_point3(Vec3::new(extent.x, extent.y, distance));
+            corners[idx * 4 + 3] = inv_view.transform_point3(Vec3::new(-extent.x, extent.y, distance));
+        }
+        corners
+    }
 }
 
 fn compute_look_offset(data: Camera) -> Vec3A {
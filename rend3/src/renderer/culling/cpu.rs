@@ -1,20 +1,14 @@
 use crate::{
     datatypes::MaterialHandle,
     renderer::{
-        camera::CameraManager,
-        culling::CullingPassData,
-        frustum::ShaderFrustum,
-        object::{InternalObject, ObjectManager},
-        OrdEqFloat,
+        camera::CameraManager, culling::CullingPassData, frustum::ShaderFrustum, object::ObjectManager, OrdEqFloat,
+        Renderer,
     },
-    JobPriorities,
 };
 use futures::{stream::FuturesUnordered, StreamExt};
 use glam::{Mat4, Vec4, Vec4Swizzles};
-use itertools::Itertools;
-use smallvec::SmallVec;
-use switchyard::Switchyard;
-use wgpu::Queue;
+use smallvec::{smallvec, SmallVec};
+use std::{ops::Range, sync::Arc};
 
 #[derive(Debug, Copy, Clone)]
 pub struct CPUDrawCall {
@@ -22,12 +16,16 @@ pub struct CPUDrawCall {
     pub count: u32,
     pub vertex_offset: i32,
     pub handle: MaterialHandle,
+    pub transparent: bool,
+    /// Index of the output data of the first instance. The rest of the instances follow it.
+    pub object_idx: u32,
+    pub instance_count: u32,
 }
 
 #[derive(Debug, Clone)]
 pub struct CullingOutputData {
     call: CPUDrawCall,
-    output: ShaderOutputObject,
+    outputs: SmallVec<[ShaderOutputObject; 1]>,
     distance: f32,
 }
 
@@ -44,23 +42,69 @@ struct ShaderOutputObject {
     _material_idx: u32,
     // Unused in shader
     _active: u32,
+    joint_offset: u32,
+    vertex_offset: i32,
+    morph_delta_offset: u32,
+    morph_target_count: u32,
+    morph_weight_offset: u32,
 }
 
 unsafe impl bytemuck::Zeroable for ShaderOutputObject {}
 unsafe impl bytemuck::Pod for ShaderOutputObject {}
 
+/// A range of the instances of a single object, by index into the object manager.
+#[derive(Debug, Clone)]
+struct InstanceRange {
+    object_idx: usize,
+    instances: Range<usize>,
+}
+
+/// Splits all instances into `threads` chunks of about the same amount of instances. Objects with more instances than
+/// fit in a chunk are split across chunks, so a single large instanced object doesn't end up culled on one thread.
+fn split_instances(object_manager: &ObjectManager, threads: usize) -> Vec<Vec<InstanceRange>> {
+    let instance_count = object_manager.instance_count();
+    // Want chunks of no smaller than 1 so every chunk makes progress.
+    let chunk_size = ((instance_count + threads - 1) / threads).max(1);
+
+    let mut chunks = Vec::with_capacity(threads);
+    let mut chunk = Vec::new();
+    let mut chunk_len = 0;
+    for (object_idx, object) in object_manager.values().enumerate() {
+        let mut start = 0;
+        while start < object.transforms.len() {
+            let end = object.transforms.len().min(start + chunk_size - chunk_len);
+            chunk.push(InstanceRange {
+                object_idx,
+                instances: start..end,
+            });
+            chunk_len += end - start;
+            start = end;
+
+            if chunk_len == chunk_size {
+                chunks.push(std::mem::take(&mut chunk));
+                chunk_len = 0;
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
+
+/// Culls every instance on the renderer's thread pool.
+///
+/// The jobs read the objects straight out of the renderer's object manager, so the caller must be holding a read lock
+/// on it for the duration, which keeps it from changing underneath them.
 pub(crate) async fn run<TD>(
-    yard: &Switchyard<TD>,
-    yard_priorities: JobPriorities,
-    queue: &Queue,
+    renderer: &Arc<Renderer<TD>>,
     object_manager: &ObjectManager,
     data: &mut CullingPassData,
     camera: CameraManager,
 ) where
     TD: 'static,
 {
-    let object_count = data.object_count;
-
     let proj = camera.proj();
     let frustum = ShaderFrustum::from_matrix(proj);
     let view = camera.view();
@@ -68,55 +112,85 @@ pub(crate) async fn run<TD>(
 
     // TODO: real thread count
     let threads = 8;
-    // Want chunks of no smaller than 1 to not trigger assert in chunks.
-    let chunk_size = ((object_count + threads - 1) / threads).max(1);
-
-    let chunks = object_manager.values().cloned().chunks(chunk_size as usize);
 
     let mut res_futures = FuturesUnordered::new();
-    for object_chunk in (&chunks).into_iter().map(|v| v.collect_vec()) {
-        let object_chunk: Vec<InternalObject> = object_chunk;
+    for instance_chunk in split_instances(object_manager, threads) {
+        let renderer_clone = Arc::clone(renderer);
 
-        res_futures.push(yard.spawn(
-            yard_priorities.compute_pool,
-            yard_priorities.culling_priority,
+        res_futures.push(renderer.yard.spawn(
+            renderer.yard_priorites.compute_pool,
+            renderer.yard_priorites.culling_priority,
             async move {
-                let mut chunk_results = Vec::with_capacity(object_chunk.len());
-
-                for object in object_chunk {
-                    let model = object.transform.transform;
-                    let model_view = view * model;
+                // Recursive, as the render loop already holds a read lock and a queued writer mustn't block this one.
+                let object_manager = renderer_clone.object_manager.read_recursive();
 
-                    let transformed = object.sphere.apply_transform(model_view);
-                    if !frustum.contains_sphere(transformed) {
-                        continue;
-                    }
-
-                    let view_position = (model_view * object.sphere.center.extend(1.0)).xyz();
-                    let distance = view_position.length_squared();
-
-                    let model_view_proj = view_proj * model;
-
-                    let inv_trans_model_view = model_view.inverse().transpose();
-
-                    let output = ShaderOutputObject {
-                        model_view,
-                        model_view_proj,
-                        inv_trans_model_view_0: inv_trans_model_view.x_axis,
-                        inv_trans_model_view_1: inv_trans_model_view.y_axis,
-                        inv_trans_model_view_2: inv_trans_model_view.z_axis,
-                        _material_idx: 0,
-                        _active: 0,
-                    };
+                let mut chunk_results = Vec::with_capacity(instance_chunk.len());
 
+                for range in instance_chunk {
+                    let object = object_manager.get_by_index(range.object_idx);
                     let call = CPUDrawCall {
                         start_idx: object.start_idx,
                         count: object.count,
                         vertex_offset: object.vertex_offset,
                         handle: object.material,
+                        transparent: object.transparent,
+                        object_idx: 0,
+                        instance_count: 0,
                     };
 
-                    chunk_results.push(CullingOutputData { call, output, distance })
+                    let mut batch = CullingOutputData {
+                        call,
+                        outputs: SmallVec::new(),
+                        distance: f32::INFINITY,
+                    };
+
+                    for transform in &object.transforms[range.instances] {
+                        let model = transform.transform;
+                        let model_view = view * model;
+
+                        let transformed = object.sphere.apply_transform(model_view);
+                        if !frustum.contains_sphere(transformed) {
+                            continue;
+                        }
+
+                        let view_position = (model_view * object.sphere.center.extend(1.0)).xyz();
+                        let distance = view_position.length_squared();
+
+                        let model_view_proj = view_proj * model;
+
+                        let inv_trans_model_view = model_view.inverse().transpose();
+
+                        let output = ShaderOutputObject {
+                            model_view,
+                            model_view_proj,
+                            inv_trans_model_view_0: inv_trans_model_view.x_axis,
+                            inv_trans_model_view_1: inv_trans_model_view.y_axis,
+                            inv_trans_model_view_2: inv_trans_model_view.z_axis,
+                            _material_idx: 0,
+                            _active: 0,
+                            joint_offset: object.joint_offset,
+                            vertex_offset: object.vertex_offset,
+                            morph_delta_offset: object.morph_delta_offset,
+                            morph_target_count: object.morph_target_count,
+                            morph_weight_offset: object.morph_weight_offset,
+                        };
+
+                        // Transparent instances need to be sorted against everything else, so each is drawn on its own.
+                        if object.transparent {
+                            chunk_results.push(CullingOutputData {
+                                call,
+                                outputs: smallvec![output],
+                                distance,
+                            });
+                        } else {
+                            batch.outputs.push(output);
+                            batch.distance = batch.distance.min(distance);
+                        }
+                    }
+
+                    if !batch.outputs.is_empty() {
+                        chunk_results.push(batch);
+                    }
                 }
 
                 chunk_results
@@ -124,29 +198,39 @@ pub(crate) async fn run<TD>(
         ))
     }
 
-    let mut total_post_cull_objects = 0_usize;
+    let mut total_post_cull_batches = 0_usize;
     let mut res_vectors: SmallVec<[_; 32]> = SmallVec::new();
     while let Some(vec) = res_futures.next().await {
-        total_post_cull_objects += vec.len();
+        total_post_cull_batches += vec.len();
         res_vectors.push(vec)
     }
 
-    let mut res = Vec::with_capacity(total_post_cull_objects);
+    let mut res = Vec::with_capacity(total_post_cull_batches);
     for vec in res_vectors {
         res.extend_from_slice(&vec);
     }
 
-    res.sort_unstable_by_key(|v| (v.call.handle.0, OrdEqFloat(v.distance)));
+    // Opaque objects first, grouped by material and front to back within each, then transparent objects back to front.
+    res.sort_unstable_by_key(|v| match v.call.transparent {
+        false => (false, v.call.handle.0, OrdEqFloat(v.distance)),
+        true => (true, 0, OrdEqFloat(-v.distance)),
+    });
 
-    let mut output_data = Vec::with_capacity(res.len());
+    let mut output_data = Vec::with_capacity(data.object_count as usize);
     let mut calls = Vec::with_capacity(res.len());
 
     for data in res {
-        output_data.push(data.output);
-        calls.push(data.call);
+        calls.push(CPUDrawCall {
+            object_idx: output_data.len() as u32,
+            instance_count: data.outputs.len() as u32,
+            ..data.call
+        });
+        output_data.extend_from_slice(&data.outputs);
     }
 
-    queue.write_buffer(&data.output_buffer, 0, bytemuck::cast_slice(&output_data));
+    renderer
+        .queue
+        .write_buffer(&data.output_buffer, 0, bytemuck::cast_slice(&output_data));
 
     *data.inner.as_cpu_mut() = calls;
 }
@@ -2,34 +2,44 @@ pub use crate::renderer::culling::cpu::CPUDrawCall;
 use crate::{
     list::{ShaderSourceStage, ShaderSourceType, SourceShaderDescriptor},
     mode::ModeData,
-    renderer::{camera::CameraManager, object::ObjectManager, shaders::ShaderManager},
-    JobPriorities, RendererMode,
+    renderer::{camera::CameraManager, object::ObjectManager, shaders::ShaderManager, Renderer},
+    RendererMode,
 };
 use futures::future::Either;
-use std::future::Future;
-use switchyard::Switchyard;
+use std::{future::Future, sync::Arc};
 use tracing_futures::Instrument;
 use wgpu::{
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, Buffer, BufferAddress, BufferDescriptor,
     BufferUsage, ComputePass, ComputePipeline, ComputePipelineDescriptor, Device, PipelineLayoutDescriptor,
-    PushConstantRange, Queue, ShaderStage,
+    PushConstantRange, ShaderStage,
 };
 
 mod cpu;
 
 const SIZE_OF_STATUS: BufferAddress = 4;
 const SIZE_OF_INDEX: BufferAddress = 4;
-const SIZE_OF_OUTPUT_DATA: BufferAddress = 12 * 16;
+const SIZE_OF_OUTPUT_DATA: BufferAddress = 13 * 16;
 const SIZE_OF_INDIRECT_CALL: BufferAddress = 5 * 4;
 const SIZE_OF_INDIRECT_COUNT: BufferAddress = 4;
+const SIZE_OF_TRANSPARENT_SORT_ENTRY: BufferAddress = 3 * 4;
 
 pub(crate) struct GPUCullingPassData {
     pub pre_cull_bg: BindGroup,
     pub prefix_sum_bg1: BindGroup,
     pub prefix_sum_bg2: BindGroup,
     pub output_bg: BindGroup,
+    pub transparent_sort_bg: BindGroup,
+    /// One indirect call per batch of visible instances of the same object, in no particular order.
     pub indirect_buffer: Buffer,
     pub count_buffer: Buffer,
+    /// Index of the object output data of every visible instance, bound as a per-instance vertex buffer.
+    pub object_index_buffer: Buffer,
+    /// Indirect calls of the visible transparent objects, back to front.
+    pub transparent_indirect_buffer: Buffer,
+    pub transparent_count_buffer: Buffer,
+    /// Amount of entries the bitonic sort runs over, the object count rounded up to a power of two.
+    pub sort_size: u32,
+    pub sort_transparent: bool,
 }
 
 pub(crate) struct CullingPassData {
@@ -43,6 +53,8 @@ pub struct GPUCullingPass {
     pre_cull_pipeline: ComputePipeline,
     prefix_sum_pipeline: ComputePipeline,
     post_cull_pipeline: ComputePipeline,
+    transparent_sort_pipeline: ComputePipeline,
+    transparent_resolve_pipeline: ComputePipeline,
     subgroup_size: u32,
 }
 
@@ -53,6 +65,7 @@ pub struct CullingPassCreationArgs<'a> {
     pub pre_cull_bgl: &'a BindGroupLayout,
     pub object_input_bgl: &'a BindGroupLayout,
     pub output_bgl: &'a BindGroupLayout,
+    pub transparent_sort_bgl: &'a BindGroupLayout,
     pub uniform_bgl: &'a BindGroupLayout,
     pub subgroup_size: u32,
 }
@@ -63,8 +76,11 @@ pub struct CullingPassPrepareArgs<'a> {
     pub prefix_sum_bgl: &'a BindGroupLayout,
     pub pre_cull_bgl: &'a BindGroupLayout,
     pub output_bgl: &'a BindGroupLayout,
+    pub transparent_sort_bgl: &'a BindGroupLayout,
     pub object_count: u32,
     pub name: String,
+    /// Sort the transparent objects into their own list. Only needed if the list is drawn from.
+    pub sort_transparent: bool,
 }
 
 pub struct CullingPass {
@@ -97,6 +113,20 @@ impl CullingPass {
                 stage: ShaderSourceStage::Compute,
             });
 
+            let transparent_sort_shader = args.shader_manager.compile_shader(SourceShaderDescriptor {
+                source: ShaderSourceType::Builtin(String::from("transparent_sort.comp")),
+                defines: vec![(String::from("WARP_SIZE"), Some(args.subgroup_size.to_string()))],
+                includes: vec![],
+                stage: ShaderSourceStage::Compute,
+            });
+
+            let transparent_resolve_shader = args.shader_manager.compile_shader(SourceShaderDescriptor {
+                source: ShaderSourceType::Builtin(String::from("transparent_resolve.comp")),
+                defines: vec![(String::from("WARP_SIZE"), Some(args.subgroup_size.to_string()))],
+                includes: vec![],
+                stage: ShaderSourceStage::Compute,
+            });
+
             let pre_cull_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
                 label: Some("pre-cull pipeline layout"),
                 bind_group_layouts: &[args.object_input_bgl, args.pre_cull_bgl, args.uniform_bgl],
@@ -124,6 +154,21 @@ impl CullingPass {
                 }],
             });
 
+            let transparent_sort_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("transparent sort pipeline layout"),
+                bind_group_layouts: &[args.transparent_sort_bgl],
+                push_constant_ranges: &[PushConstantRange {
+                    range: 0..12,
+                    stages: ShaderStage::COMPUTE,
+                }],
+            });
+
+            let transparent_resolve_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("transparent resolve pipeline layout"),
+                bind_group_layouts: &[args.object_input_bgl, args.transparent_sort_bgl],
+                push_constant_ranges: &[],
+            });
+
             drop(new_span_guard);
 
             // Need to not keep arguments alive
@@ -134,6 +179,8 @@ impl CullingPass {
                     let pre_cull_shader = pre_cull_shader.await.unwrap();
                     let prefix_sum = prefix_sum.await.unwrap();
                     let post_cull_shader = post_cull_shader.await.unwrap();
+                    let transparent_sort_shader = transparent_sort_shader.await.unwrap();
+                    let transparent_resolve_shader = transparent_resolve_shader.await.unwrap();
 
                     let pre_cull_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
                         label: Some("culling pipeline"),
@@ -156,11 +203,27 @@ impl CullingPass {
                         entry_point: "main",
                     });
 
+                    let transparent_sort_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+                        label: Some("transparent sort pipeline"),
+                        layout: Some(&transparent_sort_pipeline_layout),
+                        module: &transparent_sort_shader,
+                        entry_point: "main",
+                    });
+
+                    let transparent_resolve_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+                        label: Some("transparent resolve pipeline"),
+                        layout: Some(&transparent_resolve_pipeline_layout),
+                        module: &transparent_resolve_shader,
+                        entry_point: "main",
+                    });
+
                     Self {
                         inner: ModeData::GPU(GPUCullingPass {
                             pre_cull_pipeline,
                             prefix_sum_pipeline,
                             post_cull_pipeline,
+                            transparent_sort_pipeline,
+                            transparent_resolve_pipeline,
                             subgroup_size,
                         }),
                     }
@@ -214,10 +277,11 @@ impl CullingPass {
             let indirect_buffer = args.device.create_buffer(&BufferDescriptor {
                 label: Some(&*format!("indirect buffer for {}", &args.name)),
                 size: SIZE_OF_INDIRECT_CALL * args.object_count as BufferAddress,
-                usage: BufferUsage::STORAGE | BufferUsage::INDIRECT | BufferUsage::VERTEX,
+                usage: BufferUsage::STORAGE | BufferUsage::INDIRECT,
                 mapped_at_creation: false,
             });
 
+            // Counted up by the post-cull shader as it appends calls.
             let count_buffer = args.device.create_buffer(&BufferDescriptor {
                 label: Some(&*format!("count buffer for {}", &args.name)),
                 size: SIZE_OF_INDIRECT_COUNT,
@@ -231,6 +295,43 @@ impl CullingPass {
                 .copy_from_slice(bytemuck::bytes_of(&0));
             count_buffer.unmap();
 
+            let object_index_buffer = args.device.create_buffer(&BufferDescriptor {
+                label: Some(&*format!("object index buffer for {}", &args.name)),
+                size: SIZE_OF_INDEX * args.object_count as BufferAddress,
+                usage: BufferUsage::STORAGE | BufferUsage::VERTEX,
+                mapped_at_creation: false,
+            });
+
+            let sort_size = args.object_count.next_power_of_two();
+
+            let transparent_sort_buffer = args.device.create_buffer(&BufferDescriptor {
+                label: Some(&*format!("transparent sort buffer for {}", &args.name)),
+                size: SIZE_OF_TRANSPARENT_SORT_ENTRY * sort_size as BufferAddress,
+                usage: BufferUsage::STORAGE,
+                mapped_at_creation: false,
+            });
+
+            let transparent_indirect_buffer = args.device.create_buffer(&BufferDescriptor {
+                label: Some(&*format!("transparent indirect buffer for {}", &args.name)),
+                size: SIZE_OF_INDIRECT_CALL * args.object_count as BufferAddress,
+                usage: BufferUsage::STORAGE | BufferUsage::INDIRECT,
+                mapped_at_creation: false,
+            });
+
+            // Counted up by the post-cull shader as it appends transparent objects.
+            let transparent_count_buffer = args.device.create_buffer(&BufferDescriptor {
+                label: Some(&*format!("transparent count buffer for {}", &args.name)),
+                size: SIZE_OF_INDIRECT_COUNT,
+                usage: BufferUsage::STORAGE | BufferUsage::INDIRECT,
+                mapped_at_creation: true,
+            });
+
+            transparent_count_buffer
+                .slice(..)
+                .get_mapped_range_mut()
+                .copy_from_slice(bytemuck::bytes_of(&0));
+            transparent_count_buffer.unmap();
+
             let count = (args.object_count as f32).log2().ceil() as u32;
 
             let pre_cull_bg = args.device.create_bind_group(&BindGroupDescriptor {
@@ -304,6 +405,37 @@ impl CullingPass {
                         binding: 4,
                         resource: count_buffer.as_entire_binding(),
                     },
+                    BindGroupEntry {
+                        binding: 5,
+                        resource: transparent_sort_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 6,
+                        resource: transparent_count_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 7,
+                        resource: object_index_buffer.as_entire_binding(),
+                    },
+                ],
+            });
+
+            let transparent_sort_bg = args.device.create_bind_group(&BindGroupDescriptor {
+                label: Some(&*format!("transparent sort bind group for {}", &args.name)),
+                layout: args.transparent_sort_bgl,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: transparent_sort_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: transparent_count_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: transparent_indirect_buffer.as_entire_binding(),
+                    },
                 ],
             });
 
@@ -312,8 +444,14 @@ impl CullingPass {
                 prefix_sum_bg1,
                 prefix_sum_bg2,
                 output_bg,
+                transparent_sort_bg,
                 indirect_buffer,
                 count_buffer,
+                object_index_buffer,
+                transparent_indirect_buffer,
+                transparent_count_buffer,
+                sort_size,
+                sort_transparent: args.sort_transparent,
             }
         });
 
@@ -327,9 +465,7 @@ impl CullingPass {
 
     pub(crate) fn cpu_run<'a, TD>(
         &self,
-        yard: &'a Switchyard<TD>,
-        yard_priorities: JobPriorities,
-        queue: &'a Queue,
+        renderer: &'a Arc<Renderer<TD>>,
         object_manager: &'a ObjectManager,
         data: &'a mut CullingPassData,
         camera: CameraManager,
@@ -337,7 +473,7 @@ impl CullingPass {
     where
         TD: 'static,
     {
-        cpu::run(yard, yard_priorities, queue, object_manager, data, camera)
+        cpu::run(renderer, object_manager, data, camera)
     }
 
     pub(crate) fn gpu_run<'a>(
@@ -380,5 +516,33 @@ impl CullingPass {
         cpass.set_bind_group(1, &data.inner.as_gpu().output_bg, &[]);
         cpass.set_bind_group(2, uniform_bg, &[]);
         cpass.dispatch(dispatch_count, 1, 1);
+
+        let gpu_data = data.inner.as_gpu();
+        if !gpu_data.sort_transparent {
+            return;
+        }
+
+        // Bitonic sort over the transparent objects. The first dispatch, with k = 0, marks the unused entries so they
+        // sort to the end.
+        let sort_dispatch_count = (gpu_data.sort_size + cull_pass.subgroup_size - 1) / cull_pass.subgroup_size;
+        cpass.set_pipeline(&cull_pass.transparent_sort_pipeline);
+        cpass.set_bind_group(0, &gpu_data.transparent_sort_bg, &[]);
+        cpass.set_push_constants(0, bytemuck::cast_slice(&[gpu_data.sort_size, 0, 0]));
+        cpass.dispatch(sort_dispatch_count, 1, 1);
+        let mut k = 2_u32;
+        while k <= gpu_data.sort_size {
+            let mut j = k / 2;
+            while j > 0 {
+                cpass.set_push_constants(0, bytemuck::cast_slice(&[gpu_data.sort_size, j, k]));
+                cpass.dispatch(sort_dispatch_count, 1, 1);
+                j /= 2;
+            }
+            k <<= 1;
+        }
+
+        cpass.set_pipeline(&cull_pass.transparent_resolve_pipeline);
+        cpass.set_bind_group(0, object_input_bg, &[]);
+        cpass.set_bind_group(1, &gpu_data.transparent_sort_bg, &[]);
+        cpass.dispatch(dispatch_count, 1, 1);
     }
 }
@@ -0,0 +1,271 @@
+use crate::{
+    datatypes::Texture,
+    list::{ShaderSourceStage, ShaderSourceType, SourceShaderDescriptor},
+    renderer::{shaders::ShaderManager, util::round_to_multiple},
+};
+use std::{future::Future, num::NonZeroU32};
+use wgpu::{
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+    BindingResource, BindingType, BufferAddress, CommandEncoder, ComputePassDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, Device, Extent3d, Origin3d, PipelineLayoutDescriptor, Queue, Sampler, ShaderStage,
+    StorageTextureAccess, TextureAspect, TextureCopyView, TextureDataLayout, TextureDescriptor, TextureDimension,
+    TextureFormat, TextureSampleType, TextureUsage, TextureView, TextureViewDescriptor, TextureViewDimension,
+};
+
+/// Format of the generated cube textures. Needs to be both filterable and usable as a storage texture.
+pub const CUBE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// If you change this, change local_size in equirect_to_cube.comp and cube_downsample.comp.
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Turns equirectangular panoramas into cube textures on the gpu.
+pub struct EquirectConverter {
+    convert_bgl: BindGroupLayout,
+    downsample_bgl: BindGroupLayout,
+    convert_pipeline: ComputePipeline,
+    downsample_pipeline: ComputePipeline,
+}
+impl EquirectConverter {
+    pub fn new<'a>(device: &'a Device, shader_manager: &ShaderManager) -> impl Future<Output = Self> + 'a {
+        let compile = |name: &str| {
+            shader_manager.compile_shader(SourceShaderDescriptor {
+                source: ShaderSourceType::Builtin(String::from(name)),
+                defines: vec![],
+                includes: vec![],
+                stage: ShaderSourceStage::Compute,
+            })
+        };
+        let convert_shader = compile("equirect_to_cube.comp");
+        let downsample_shader = compile("cube_downsample.comp");
+
+        let convert_bgl = create_bind_group_layout(device, "equirect to cube bgl", TextureViewDimension::D2);
+        let downsample_bgl = create_bind_group_layout(device, "cube downsample bgl", TextureViewDimension::D2Array);
+
+        async move {
+            let convert_shader = convert_shader.await.unwrap();
+            let downsample_shader = downsample_shader.await.unwrap();
+
+            let convert_pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("equirect to cube pll"),
+                bind_group_layouts: &[&convert_bgl],
+                push_constant_ranges: &[],
+            });
+
+            let downsample_pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("cube downsample pll"),
+                bind_group_layouts: &[&downsample_bgl],
+                push_constant_ranges: &[],
+            });
+
+            let convert_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("equirect to cube pipeline"),
+                layout: Some(&convert_pll),
+                module: &convert_shader,
+                entry_point: "main",
+            });
+
+            let downsample_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("cube downsample pipeline"),
+                layout: Some(&downsample_pll),
+                module: &downsample_shader,
+                entry_point: "main",
+            });
+
+            Self {
+                convert_bgl,
+                downsample_bgl,
+                convert_pipeline,
+                downsample_pipeline,
+            }
+        }
+    }
+
+    /// Uploads the top mip of `texture` and records the conversion into a `resolution` sized cube with a full mip
+    /// chain. Returns a cube view of the result along with the amount of bytes uploaded.
+    pub fn convert(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        sampler: &Sampler,
+        texture: &Texture,
+        resolution: u32,
+    ) -> (TextureView, BufferAddress) {
+        span_transfer!(_ -> convert_span, INFO, "Equirect to cube");
+
+        assert_ne!(resolution, 0, "Cube resolution must be greater than 0");
+
+        let size = Extent3d {
+            width: texture.width,
+            height: texture.height,
+            depth: 1,
+        };
+
+        let source = device.create_texture(&TextureDescriptor {
+            label: Some("equirect source"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: texture.format.into(),
+            usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+        });
+
+        let block_width = texture.format.pixels_per_block();
+        let bytes_per_row = round_to_multiple(size.width, block_width) / block_width * texture.format.bytes_per_block();
+        let bytes = bytes_per_row * round_to_multiple(size.height, block_width) / block_width;
+
+        queue.write_texture(
+            TextureCopyView {
+                texture: &source,
+                origin: Origin3d::ZERO,
+                mip_level: 0,
+            },
+            &texture.data[..bytes as usize],
+            TextureDataLayout {
+                offset: 0,
+                bytes_per_row,
+                rows_per_image: 0,
+            },
+            Extent3d {
+                width: round_to_multiple(size.width, block_width),
+                height: round_to_multiple(size.height, block_width),
+                depth: 1,
+            },
+        );
+
+        let source_view = source.create_view(&TextureViewDescriptor::default());
+
+        let mip_levels = 32 - resolution.leading_zeros();
+
+        let cube = device.create_texture(&TextureDescriptor {
+            label: texture.label.as_deref(),
+            size: Extent3d {
+                width: resolution,
+                height: resolution,
+                depth: 6,
+            },
+            mip_level_count: mip_levels,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: CUBE_FORMAT,
+            usage: TextureUsage::STORAGE | TextureUsage::SAMPLED,
+        });
+
+        // All six faces of a single mip, written as storage and read back by the next mip down.
+        let mip_views: Vec<_> = (0..mip_levels)
+            .map(|mip| {
+                cube.create_view(&TextureViewDescriptor {
+                    label: Some("cube mip view"),
+                    format: None,
+                    dimension: Some(TextureViewDimension::D2Array),
+                    aspect: TextureAspect::All,
+                    base_mip_level: mip,
+                    level_count: NonZeroU32::new(1),
+                    base_array_layer: 0,
+                    array_layer_count: NonZeroU32::new(6),
+                })
+            })
+            .collect();
+
+        let bind_group = |layout: &BindGroupLayout, input: &TextureView, output: &TextureView| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: Some("equirect to cube bg"),
+                layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::Sampler(sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(input),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(output),
+                    },
+                ],
+            })
+        };
+
+        let convert_bg = bind_group(&self.convert_bgl, &source_view, &mip_views[0]);
+        let downsample_bgs: Vec<_> = mip_views
+            .windows(2)
+            .map(|pair| bind_group(&self.downsample_bgl, &pair[0], &pair[1]))
+            .collect();
+
+        let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("equirect to cube"),
+        });
+
+        cpass.set_pipeline(&self.convert_pipeline);
+        cpass.set_bind_group(0, &convert_bg, &[]);
+        let groups = dispatch_count(resolution);
+        cpass.dispatch(groups, groups, 6);
+
+        cpass.set_pipeline(&self.downsample_pipeline);
+        for (idx, bg) in downsample_bgs.iter().enumerate() {
+            cpass.set_bind_group(0, bg, &[]);
+            let groups = dispatch_count((resolution >> (idx + 1)).max(1));
+            cpass.dispatch(groups, groups, 6);
+        }
+
+        drop(cpass);
+
+        let view = cube.create_view(&TextureViewDescriptor {
+            label: None,
+            format: None,
+            dimension: Some(TextureViewDimension::Cube),
+            aspect: TextureAspect::All,
+            base_mip_level: 0,
+            level_count: None,
+            base_array_layer: 0,
+            array_layer_count: None,
+        });
+
+        (view, bytes as BufferAddress)
+    }
+}
+
+fn dispatch_count(size: u32) -> u32 {
+    (size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE
+}
+
+fn create_bind_group_layout(device: &Device, label: &str, source_dimension: TextureViewDimension) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some(label),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStage::COMPUTE,
+                ty: BindingType::Sampler {
+                    filtering: false,
+                    comparison: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStage::COMPUTE,
+                // Float textures aren't guaranteed to be filterable, so the shaders filter by hand.
+                ty: BindingType::Texture {
+                    view_dimension: source_dimension,
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStage::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: CUBE_FORMAT,
+                    view_dimension: TextureViewDimension::D2Array,
+                },
+                count: None,
+            },
+        ],
+    })
+}
@@ -17,6 +17,24 @@ impl BoundingSphere {
         }
     }
 
+    /// Sphere around the middle of the given spheres that contains all of them. None if there are no spheres.
+    pub fn enclosing(spheres: &[Self]) -> Option<Self> {
+        let centers: Vec<_> = spheres.iter().map(|sphere| sphere.center).collect();
+        if centers.is_empty() {
+            return None;
+        }
+
+        let center = find_mesh_center(&centers);
+        let radius = spheres.iter().fold(0.0_f32, |radius, sphere| {
+            radius.max((Vec3A::from(sphere.center) - center).length() + sphere.radius)
+        });
+
+        Some(Self {
+            center: Vec3::from(center),
+            radius,
+        })
+    }
+
     pub fn apply_transform(self, model_view: Mat4) -> Self {
         let max_scale = model_view
             .x_axis
@@ -0,0 +1,113 @@
+use crate::{output::SWAPCHAIN_FORMAT, renderer::util::round_to_multiple, RenderedImage};
+use std::sync::Arc;
+use wgpu::{
+    Buffer, BufferCopyView, BufferDescriptor, BufferUsage, CommandEncoder, Device, Extent3d, Maintain, MapMode,
+    Origin3d, Texture, TextureCopyView, TextureDataLayout, TextureDescriptor, TextureDimension, TextureUsage,
+    TextureView, TextureViewDescriptor, COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+/// Bytes per pixel of [`SWAPCHAIN_FORMAT`].
+const BYTES_PER_PIXEL: u32 = 4;
+
+/// Offscreen render target used in place of a swapchain when the renderer is built headless.
+pub struct HeadlessTarget {
+    texture: Texture,
+    pub view: Arc<TextureView>,
+    size: [u32; 2],
+}
+impl HeadlessTarget {
+    pub fn new(device: &Device, size: [u32; 2]) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("headless target"),
+            size: Extent3d {
+                width: size[0],
+                height: size[1],
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: SWAPCHAIN_FORMAT,
+            usage: TextureUsage::RENDER_ATTACHMENT | TextureUsage::COPY_SRC | TextureUsage::SAMPLED,
+        });
+
+        let view = Arc::new(texture.create_view(&TextureViewDescriptor::default()));
+
+        Self { texture, view, size }
+    }
+
+    /// Records a copy of the current contents of the target into a fresh mappable buffer.
+    pub fn readback(&self, device: &Device, encoder: &mut CommandEncoder) -> ImageReadback {
+        // Rows in a texture -> buffer copy must be aligned to 256 bytes, we strip the padding when reading back.
+        let unpadded_bytes_per_row = self.size[0] * BYTES_PER_PIXEL;
+        let padded_bytes_per_row = round_to_multiple(unpadded_bytes_per_row, COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("headless readback buffer"),
+            size: (padded_bytes_per_row * self.size[1]) as u64,
+            usage: BufferUsage::COPY_DST | BufferUsage::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            TextureCopyView {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+            },
+            BufferCopyView {
+                buffer: &buffer,
+                layout: TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: padded_bytes_per_row,
+                    rows_per_image: 0,
+                },
+            },
+            Extent3d {
+                width: self.size[0],
+                height: self.size[1],
+                depth: 1,
+            },
+        );
+
+        ImageReadback {
+            buffer,
+            size: self.size,
+            padded_bytes_per_row,
+        }
+    }
+}
+
+/// A pending copy of a headless frame. Only valid to read once the command buffer containing the copy is submitted.
+pub struct ImageReadback {
+    buffer: Buffer,
+    size: [u32; 2],
+    padded_bytes_per_row: u32,
+}
+impl ImageReadback {
+    pub async fn read(self, device: &Device) -> RenderedImage {
+        let slice = self.buffer.slice(..);
+        let mapping = slice.map_async(MapMode::Read);
+
+        // Nothing else is guaranteed to be polling the device, so make sure the map resolves.
+        device.poll(Maintain::Wait);
+        mapping.await.expect("Failed to map headless readback buffer");
+
+        let unpadded_bytes_per_row = (self.size[0] * BYTES_PER_PIXEL) as usize;
+        let mut data = Vec::with_capacity(unpadded_bytes_per_row * self.size[1] as usize);
+
+        {
+            let mapped = slice.get_mapped_range();
+            for row in mapped.chunks(self.padded_bytes_per_row as usize) {
+                // SWAPCHAIN_FORMAT is bgra, swizzle to rgba.
+                for pixel in row[..unpadded_bytes_per_row].chunks_exact(BYTES_PER_PIXEL as usize) {
+                    data.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                }
+            }
+        }
+
+        self.buffer.unmap();
+
+        RenderedImage { size: self.size, data }
+    }
+}
@@ -0,0 +1,380 @@
+use crate::{
+    bind_merge::BindGroupBuilder,
+    datatypes::TextureHandle,
+    list::{ShaderSourceStage, ShaderSourceType, SourceShaderDescriptor},
+    renderer::{shaders::ShaderManager, texture::TextureManager},
+};
+use std::{future::Future, mem::size_of, num::NonZeroU32};
+use wgpu::{
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+    BindingResource, BindingType, CommandEncoder, CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, Device, Extent3d, Origin3d, PipelineLayoutDescriptor, PushConstantRange, Queue, Sampler,
+    ShaderStage, StorageTextureAccess, Texture, TextureAspect, TextureCopyView, TextureDataLayout, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureSampleType, TextureUsage, TextureView, TextureViewDescriptor,
+    TextureViewDimension,
+};
+
+const IBL_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// Diffuse lighting varies slowly over the sphere, so this can be tiny.
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+/// Mip `n` of the prefiltered map is filtered for a perceptual roughness of `n / (PREFILTERED_MIPS - 1)`.
+const PREFILTERED_MIPS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 256;
+
+/// If you change this, change local_size in the ibl compute shaders.
+const WORKGROUP_SIZE: u32 = 8;
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct ShaderPrefilterInput {
+    roughness: f32,
+}
+
+unsafe impl bytemuck::Zeroable for ShaderPrefilterInput {}
+unsafe impl bytemuck::Pod for ShaderPrefilterInput {}
+
+/// Generates and holds the maps used for image based lighting: diffuse irradiance, GGX-prefiltered specular and the
+/// BRDF integration lookup table.
+pub struct IblManager {
+    generation_bgl: BindGroupLayout,
+    irradiance_pipeline: ComputePipeline,
+    prefilter_pipeline: ComputePipeline,
+
+    irradiance_view: TextureView,
+    irradiance_storage_view: TextureView,
+    prefiltered_view: TextureView,
+    prefiltered_storage_views: Vec<TextureView>,
+    brdf_lut_view: TextureView,
+    black_cube_view: TextureView,
+
+    /// The environment the maps were last generated from.
+    generated_from: Option<TextureHandle>,
+}
+impl IblManager {
+    pub fn new<'a>(
+        device: &'a Device,
+        queue: &'a Queue,
+        shader_manager: &ShaderManager,
+    ) -> impl Future<Output = Self> + 'a {
+        let compile = |name: &str| {
+            shader_manager.compile_shader(SourceShaderDescriptor {
+                source: ShaderSourceType::Builtin(String::from(name)),
+                defines: vec![],
+                includes: vec![],
+                stage: ShaderSourceStage::Compute,
+            })
+        };
+        let irradiance_shader = compile("ibl_irradiance.comp");
+        let prefilter_shader = compile("ibl_prefilter.comp");
+        let brdf_shader = compile("ibl_brdf.comp");
+
+        let generation_bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("ibl generation bgl"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStage::COMPUTE,
+                    ty: BindingType::Sampler {
+                        filtering: true,
+                        comparison: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStage::COMPUTE,
+                    ty: BindingType::Texture {
+                        view_dimension: TextureViewDimension::Cube,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                storage_texture_entry(2, TextureViewDimension::D2Array),
+            ],
+        });
+
+        let brdf_bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("brdf lut bgl"),
+            entries: &[storage_texture_entry(0, TextureViewDimension::D2)],
+        });
+
+        let irradiance_pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("irradiance pll"),
+            bind_group_layouts: &[&generation_bgl],
+            push_constant_ranges: &[],
+        });
+
+        let prefilter_pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("prefilter pll"),
+            bind_group_layouts: &[&generation_bgl],
+            push_constant_ranges: &[PushConstantRange {
+                stages: ShaderStage::COMPUTE,
+                range: 0..size_of::<ShaderPrefilterInput>() as _,
+            }],
+        });
+
+        let brdf_pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("brdf lut pll"),
+            bind_group_layouts: &[&brdf_bgl],
+            push_constant_ranges: &[],
+        });
+
+        let irradiance = create_cube_texture(device, "irradiance map", IRRADIANCE_SIZE, 1);
+        let prefiltered = create_cube_texture(
+            device,
+            "prefiltered environment map",
+            PREFILTERED_SIZE,
+            PREFILTERED_MIPS,
+        );
+        let black_cube = create_cube_texture(device, "black cube", 1, 1);
+        // Used while there is no environment, so image based lighting adds nothing.
+        queue.write_texture(
+            TextureCopyView {
+                texture: &black_cube,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+            },
+            &[0; 6 * 8],
+            TextureDataLayout {
+                offset: 0,
+                bytes_per_row: 8,
+                rows_per_image: 1,
+            },
+            Extent3d {
+                width: 1,
+                height: 1,
+                depth: 6,
+            },
+        );
+
+        let brdf_lut = device.create_texture(&TextureDescriptor {
+            label: Some("brdf lut"),
+            size: Extent3d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: IBL_FORMAT,
+            usage: TextureUsage::STORAGE | TextureUsage::SAMPLED,
+        });
+        let brdf_lut_view = brdf_lut.create_view(&TextureViewDescriptor::default());
+
+        let irradiance_view = create_cube_view(&irradiance, "irradiance map view");
+        let irradiance_storage_view = create_storage_view(&irradiance, "irradiance map storage view", 0);
+        let prefiltered_view = create_cube_view(&prefiltered, "prefiltered environment map view");
+        let prefiltered_storage_views = (0..PREFILTERED_MIPS)
+            .map(|mip| create_storage_view(&prefiltered, "prefiltered environment map storage view", mip))
+            .collect();
+        let black_cube_view = create_cube_view(&black_cube, "black cube view");
+
+        async move {
+            let irradiance_shader = irradiance_shader.await.unwrap();
+            let prefilter_shader = prefilter_shader.await.unwrap();
+            let brdf_shader = brdf_shader.await.unwrap();
+
+            let irradiance_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("irradiance pipeline"),
+                layout: Some(&irradiance_pll),
+                module: &irradiance_shader,
+                entry_point: "main",
+            });
+
+            let prefilter_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("prefilter pipeline"),
+                layout: Some(&prefilter_pll),
+                module: &prefilter_shader,
+                entry_point: "main",
+            });
+
+            let brdf_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("brdf lut pipeline"),
+                layout: Some(&brdf_pll),
+                module: &brdf_shader,
+                entry_point: "main",
+            });
+
+            // The lookup table doesn't depend on the environment, so it only needs to be generated once.
+            let brdf_bg = device.create_bind_group(&BindGroupDescriptor {
+                label: Some("brdf lut bg"),
+                layout: &brdf_bgl,
+                entries: &[BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&brdf_lut_view),
+                }],
+            });
+
+            let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("brdf lut encoder"),
+            });
+            let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("brdf lut"),
+            });
+            cpass.set_pipeline(&brdf_pipeline);
+            cpass.set_bind_group(0, &brdf_bg, &[]);
+            let groups = dispatch_count(BRDF_LUT_SIZE);
+            cpass.dispatch(groups, groups, 1);
+            drop(cpass);
+            queue.submit(Some(encoder.finish()));
+
+            Self {
+                generation_bgl,
+                irradiance_pipeline,
+                prefilter_pipeline,
+                irradiance_view,
+                irradiance_storage_view,
+                prefiltered_view,
+                prefiltered_storage_views,
+                brdf_lut_view,
+                black_cube_view,
+                generated_from: None,
+            }
+        }
+    }
+
+    /// Regenerates the maps if the environment changed since the last call.
+    pub fn ready(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        texture_manager_cube: &TextureManager,
+        environment: Option<TextureHandle>,
+        sampler: &Sampler,
+    ) {
+        if environment == self.generated_from {
+            return;
+        }
+        self.generated_from = environment;
+
+        let handle = match environment {
+            Some(handle) => handle,
+            None => return,
+        };
+
+        span_transfer!(_ -> generate_span, INFO, "Generating IBL maps");
+
+        let source = texture_manager_cube.get_view(handle);
+        let bind_group = |label: &str, output: &TextureView| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: Some(label),
+                layout: &self.generation_bgl,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::Sampler(sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(source),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(output),
+                    },
+                ],
+            })
+        };
+
+        let irradiance_bg = bind_group("irradiance bg", &self.irradiance_storage_view);
+        let prefilter_bgs: Vec<_> = self
+            .prefiltered_storage_views
+            .iter()
+            .map(|view| bind_group("prefilter bg", view))
+            .collect();
+
+        let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("ibl generation"),
+        });
+
+        cpass.set_pipeline(&self.irradiance_pipeline);
+        cpass.set_bind_group(0, &irradiance_bg, &[]);
+        let groups = dispatch_count(IRRADIANCE_SIZE);
+        cpass.dispatch(groups, groups, 6);
+
+        cpass.set_pipeline(&self.prefilter_pipeline);
+        for (mip, bg) in prefilter_bgs.iter().enumerate() {
+            let input = ShaderPrefilterInput {
+                roughness: mip as f32 / (PREFILTERED_MIPS - 1) as f32,
+            };
+            cpass.set_push_constants(0, bytemuck::bytes_of(&input));
+            cpass.set_bind_group(0, bg, &[]);
+            let groups = dispatch_count((PREFILTERED_SIZE >> mip).max(1));
+            cpass.dispatch(groups, groups, 6);
+        }
+    }
+
+    pub fn append_to_bgb<'a>(&'a self, builder: &mut BindGroupBuilder<'a>) {
+        let (irradiance, prefiltered) = match self.generated_from {
+            Some(_) => (&self.irradiance_view, &self.prefiltered_view),
+            None => (&self.black_cube_view, &self.black_cube_view),
+        };
+        builder.append(BindingResource::TextureView(irradiance));
+        builder.append(BindingResource::TextureView(prefiltered));
+        builder.append(BindingResource::TextureView(&self.brdf_lut_view));
+    }
+}
+
+fn dispatch_count(size: u32) -> u32 {
+    (size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE
+}
+
+fn storage_texture_entry(binding: u32, view_dimension: TextureViewDimension) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStage::COMPUTE,
+        ty: BindingType::StorageTexture {
+            access: StorageTextureAccess::WriteOnly,
+            format: IBL_FORMAT,
+            view_dimension,
+        },
+        count: None,
+    }
+}
+
+fn create_cube_texture(device: &Device, label: &str, size: u32, mips: u32) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some(label),
+        size: Extent3d {
+            width: size,
+            height: size,
+            depth: 6,
+        },
+        mip_level_count: mips,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: IBL_FORMAT,
+        usage: TextureUsage::STORAGE | TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+    })
+}
+
+fn create_cube_view(texture: &Texture, label: &str) -> TextureView {
+    texture.create_view(&TextureViewDescriptor {
+        label: Some(label),
+        format: None,
+        dimension: Some(TextureViewDimension::Cube),
+        aspect: TextureAspect::All,
+        base_mip_level: 0,
+        level_count: None,
+        base_array_layer: 0,
+        array_layer_count: None,
+    })
+}
+
+/// All six faces of a single mip, for compute shaders to write into.
+fn create_storage_view(texture: &Texture, label: &str, mip: u32) -> TextureView {
+    texture.create_view(&TextureViewDescriptor {
+        label: Some(label),
+        format: None,
+        dimension: Some(TextureViewDimension::D2Array),
+        aspect: TextureAspect::All,
+        base_mip_level: mip,
+        level_count: NonZeroU32::new(1),
+        base_array_layer: 0,
+        array_layer_count: NonZeroU32::new(6),
+    })
+}
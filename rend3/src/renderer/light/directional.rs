@@ -1,10 +1,11 @@
 use crate::{
     bind_merge::BindGroupBuilder,
-    datatypes::{Camera, CameraProjection, DirectionalLight, DirectionalLightHandle},
+    datatypes::{DirectionalLight, DirectionalLightChange, DirectionalLightHandle},
     registry::ResourceRegistry,
-    renderer::{camera::CameraManager, INTERNAL_SHADOW_DEPTH_FORMAT, SHADOW_DIMENSIONS},
+    renderer::{camera::CameraManager, INTERNAL_SHADOW_DEPTH_FORMAT},
+    ShadowCascadeOptions,
 };
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec3, Vec4};
 use std::{mem::size_of, num::NonZeroU32, sync::Arc};
 use wgpu::{
     BindingResource, BufferAddress, BufferUsage, CommandEncoder, Device, Extent3d, TextureAspect, TextureDescriptor,
@@ -12,9 +13,14 @@ use wgpu::{
 };
 use wgpu_conveyor::{write_to_buffer1, AutomatedBuffer, AutomatedBufferManager, IdBuffer};
 
+/// If you change this, change MAX_SHADOW_CASCADES in structures.glsl.
+pub const MAX_SHADOW_CASCADES: usize = 4;
+
 pub struct InternalDirectionalLight {
     pub inner: DirectionalLight,
-    pub camera: CameraManager,
+    /// One camera for every cascade, refitted to the main camera every frame.
+    pub cascades: Vec<CameraManager>,
+    /// Layer of the first cascade, the rest follow it.
     pub shadow_tex: u32,
 }
 
@@ -30,10 +36,19 @@ unsafe impl bytemuck::Pod for ShaderDirectionalLightBufferHeader {}
 #[derive(Debug, Copy, Clone)]
 #[repr(C, align(16))]
 struct ShaderDirectionalLight {
-    pub view_proj: Mat4,
+    pub view_proj: [Mat4; MAX_SHADOW_CASCADES],
+    /// Distance from the camera at which each cascade ends.
+    pub cascade_splits: Vec4,
+    /// Depth bias of each cascade, in shadow depth units.
+    pub depth_biases: Vec4,
+    /// Normal bias of each cascade, in world units.
+    pub normal_offsets: Vec4,
     pub color: Vec3,
     pub shadow_tex: u32,
     pub direction: Vec3,
+    pub cascade_count: u32,
+    /// Fraction of the shadow texture this light's cascades cover.
+    pub uv_scale: f32,
 }
 
 unsafe impl bytemuck::Zeroable for ShaderDirectionalLight {}
@@ -45,6 +60,7 @@ pub struct DirectionalLightManager {
 
     view: TextureView,
     layer_views: Vec<Arc<TextureView>>,
+    texture_size: u32,
 
     registry: ResourceRegistry<InternalDirectionalLight>,
 }
@@ -54,13 +70,14 @@ impl DirectionalLightManager {
 
         let buffer = buffer_manager.create_new_buffer(device, 0, BufferUsage::STORAGE, Some("directional lights"));
 
-        let (view, layer_views) = create_shadow_texture(device, 1);
+        let (view, layer_views) = create_shadow_texture(device, "shadow texture", 1, 1);
 
         Self {
             buffer_storage: None,
             buffer,
             view,
             layer_views,
+            texture_size: 1,
             registry,
         }
     }
@@ -74,20 +91,14 @@ impl DirectionalLightManager {
             handle.0,
             InternalDirectionalLight {
                 inner: light,
-                camera: CameraManager::new(
-                    Camera {
-                        projection: CameraProjection::from_orthographic_direction(light.direction.into()),
-                        ..Camera::default()
-                    },
-                    None,
-                ),
-                shadow_tex: self.registry.count() as u32,
+                cascades: Vec::new(),
+                shadow_tex: 0,
             },
         );
     }
 
-    pub fn get_mut(&mut self, handle: DirectionalLightHandle) -> &mut InternalDirectionalLight {
-        self.registry.get_mut(handle.0)
+    pub fn update_from_changes(&mut self, handle: DirectionalLightHandle, change: DirectionalLightChange) {
+        self.registry.get_mut(handle.0).inner.update_from_changes(change);
     }
 
     pub fn get_layer_view_arc(&self, layer: u32) -> Arc<TextureView> {
@@ -98,15 +109,42 @@ impl DirectionalLightManager {
         self.registry.remove(handle.0);
     }
 
-    pub fn ready(&mut self, device: &Device, encoder: &mut CommandEncoder) {
+    /// Fits the cascades of every light to the given camera. Returns the amount of bytes uploaded.
+    pub fn ready(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        camera: &CameraManager,
+        cascade_options: &ShadowCascadeOptions,
+    ) -> BufferAddress {
+        let cascade_count = (cascade_options.count as usize).clamp(1, MAX_SHADOW_CASCADES);
+        let splits = compute_cascade_splits(camera, cascade_options, cascade_count);
+
+        // Every light gets a whole layer per cascade, lights with a lower resolution only use part of it.
         let registered_count = self.registry.count();
-        if registered_count != self.layer_views.len() && registered_count != 0 {
-            let (view, layer_views) = create_shadow_texture(device, registered_count as u32);
+        let layer_count = registered_count * cascade_count;
+        let texture_size = self
+            .registry
+            .values()
+            .map(|light| light.inner.shadow_resolution)
+            .max()
+            .unwrap_or(1)
+            .max(1);
+        if (layer_count != self.layer_views.len() || texture_size != self.texture_size) && registered_count != 0 {
+            let (view, layer_views) = create_shadow_texture(device, "shadow texture", layer_count as u32, texture_size);
             self.view = view;
             self.layer_views = layer_views;
+            self.texture_size = texture_size;
+        }
+
+        // Removals shuffle lights around, so reassign the layers every frame.
+        for (idx, light) in self.registry.values_mut().enumerate() {
+            light.shadow_tex = (idx * cascade_count) as u32;
+            light.cascades = fit_cascades(camera, &light.inner, &splits, cascade_options.max_distance);
         }
 
         let registry = &self.registry;
+        let texture_size = self.texture_size;
 
         let size = self.registry.count() * size_of::<ShaderDirectionalLight>()
             + size_of::<ShaderDirectionalLightBufferHeader>();
@@ -124,18 +162,43 @@ impl DirectionalLightManager {
 
                 buffer_header.total_lights = registry.count() as u32;
 
+                let mut cascade_splits = [0.0; MAX_SHADOW_CASCADES];
+                cascade_splits[..cascade_count].copy_from_slice(&splits[1..]);
+
                 for (idx, light) in registry.values().enumerate() {
+                    let resolution = light.inner.shadow_resolution.max(1) as f32;
+
+                    let mut view_proj = [Mat4::IDENTITY; MAX_SHADOW_CASCADES];
+                    let mut depth_biases = [0.0; MAX_SHADOW_CASCADES];
+                    let mut normal_offsets = [0.0; MAX_SHADOW_CASCADES];
+                    for (cascade_idx, cascade) in light.cascades.iter().enumerate() {
+                        view_proj[cascade_idx] = cascade.view_proj();
+
+                        // The biases are given in texels, which are a different size in every cascade.
+                        let proj = cascade.proj();
+                        let texel_size = 2.0 / (proj.x_axis.x * resolution);
+                        depth_biases[cascade_idx] = light.inner.depth_bias * texel_size * proj.z_axis.z;
+                        normal_offsets[cascade_idx] = light.inner.normal_bias * texel_size;
+                    }
+
                     buffer_body[idx] = ShaderDirectionalLight {
-                        view_proj: light.camera.view_proj(),
+                        view_proj,
+                        cascade_splits: Vec4::from(cascade_splits),
+                        depth_biases: Vec4::from(depth_biases),
+                        normal_offsets: Vec4::from(normal_offsets),
                         color: light.inner.color * light.inner.intensity,
+                        shadow_tex: light.shadow_tex,
                         direction: light.inner.direction,
-                        shadow_tex: light.shadow_tex as u32,
+                        cascade_count: cascade_count as u32,
+                        uv_scale: light.inner.shadow_resolution.max(1) as f32 / texture_size as f32,
                     }
                 }
             },
         );
 
         self.buffer_storage = Some(self.buffer.get_current_inner());
+
+        size as BufferAddress
     }
 
     pub fn append_to_bgb<'a>(&'a self, builder: &mut BindGroupBuilder<'a>) {
@@ -146,14 +209,90 @@ impl DirectionalLightManager {
     pub fn values(&self) -> impl Iterator<Item = &InternalDirectionalLight> {
         self.registry.values()
     }
+
+    pub fn count(&self) -> usize {
+        self.registry.count()
+    }
+}
+
+/// Distances from the camera at which the cascades start and end, `cascade_count + 1` values starting at the near
+/// plane. Blends between uniform and logarithmic splits using the split lambda.
+fn compute_cascade_splits(camera: &CameraManager, options: &ShadowCascadeOptions, cascade_count: usize) -> Vec<f32> {
+    // Logarithmic splits need a near plane above zero.
+    let near = camera.near().max(0.01);
+    let far = options.max_distance.max(near * 2.0);
+
+    (0..=cascade_count)
+        .map(|idx| {
+            let fraction = idx as f32 / cascade_count as f32;
+            let logarithmic = near * (far / near).powf(fraction);
+            let uniform = near + (far - near) * fraction;
+            options.split_lambda * logarithmic + (1.0 - options.split_lambda) * uniform
+        })
+        .collect()
+}
+
+/// Builds a camera for every cascade which tightly encloses its slice of the main camera's frustum.
+fn fit_cascades(
+    camera: &CameraManager,
+    light: &DirectionalLight,
+    splits: &[f32],
+    max_distance: f32,
+) -> Vec<CameraManager> {
+    let direction = light.direction.normalize();
+    // look_at falls apart if the up vector is parallel to the direction.
+    let up = if direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+    // Only depends on the light's direction, so shadow texels stay put in world space as the camera moves.
+    let view = Mat4::look_at_lh(Vec3::ZERO, direction, up);
+
+    let resolution = light.shadow_resolution.max(1) as f32;
+
+    splits
+        .windows(2)
+        .map(|slice| {
+            let corners = camera.frustum_corners(slice[0], slice[1]);
+
+            // Bounding sphere of the slice. Its size doesn't change when the camera rotates, unlike a bounding box.
+            let center = corners.iter().fold(Vec3::ZERO, |sum, &corner| sum + corner) / corners.len() as f32;
+            let radius = corners
+                .iter()
+                .fold(0.0_f32, |radius, &corner| radius.max((corner - center).length()));
+            // Round up to avoid floating point noise changing the size of texels.
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            // Snap the center to whole texels so the shadow doesn't shimmer when the camera moves.
+            let texel_size = (radius * 2.0) / resolution;
+            let light_center = view.transform_point3(center);
+            let snapped_x = (light_center.x / texel_size).floor() * texel_size;
+            let snapped_y = (light_center.y / texel_size).floor() * texel_size;
+
+            // Objects between the light and the slice still cast shadows into it, so extend the near plane back.
+            let proj = Mat4::orthographic_lh(
+                snapped_x - radius,
+                snapped_x + radius,
+                snapped_y - radius,
+                snapped_y + radius,
+                light_center.z - radius - max_distance,
+                light_center.z + radius,
+            );
+
+            CameraManager::from_matrices(Vec3::ZERO, view, proj)
+        })
+        .collect()
 }
 
-fn create_shadow_texture(device: &Device, count: u32) -> (TextureView, Vec<Arc<TextureView>>) {
+/// Creates an array of `count` shadow maps, returning a view of the whole array and a view of every layer.
+pub(super) fn create_shadow_texture(
+    device: &Device,
+    label: &str,
+    count: u32,
+    size: u32,
+) -> (TextureView, Vec<Arc<TextureView>>) {
     let texture = device.create_texture(&TextureDescriptor {
-        label: Some("shadow texture"),
+        label: Some(label),
         size: Extent3d {
-            width: SHADOW_DIMENSIONS,
-            height: SHADOW_DIMENSIONS,
+            width: size,
+            height: size,
             depth: count,
         },
         mip_level_count: 1,
@@ -164,7 +303,7 @@ fn create_shadow_texture(device: &Device, count: u32) -> (TextureView, Vec<Arc<T
     });
 
     let primary_view = texture.create_view(&TextureViewDescriptor {
-        label: Some("shadow texture view"),
+        label: Some(&format!("{} view", label)),
         format: None,
         dimension: Some(TextureViewDimension::D2Array),
         aspect: TextureAspect::All,
@@ -177,7 +316,7 @@ fn create_shadow_texture(device: &Device, count: u32) -> (TextureView, Vec<Arc<T
     let layer_views: Vec<_> = (0..count)
         .map(|idx| {
             Arc::new(texture.create_view(&TextureViewDescriptor {
-                label: Some(&format!("shadow texture layer {}", count)),
+                label: Some(&format!("{} layer {}", label, idx)),
                 format: None,
                 dimension: Some(TextureViewDimension::D2),
                 aspect: TextureAspect::All,
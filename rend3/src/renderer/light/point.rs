@@ -0,0 +1,232 @@
+use crate::{
+    bind_merge::BindGroupBuilder,
+    datatypes::{PointLight, PointLightChange, PointLightHandle},
+    registry::ResourceRegistry,
+    renderer::{camera::CameraManager, INTERNAL_SHADOW_DEPTH_FORMAT, POINT_SHADOW_DIMENSIONS},
+};
+use glam::{Mat4, Vec3};
+use std::{f32::consts::FRAC_PI_2, mem::size_of, num::NonZeroU32, sync::Arc};
+use wgpu::{
+    BindingResource, BufferAddress, BufferUsage, CommandEncoder, Device, Extent3d, TextureAspect, TextureDescriptor,
+    TextureDimension, TextureUsage, TextureView, TextureViewDescriptor, TextureViewDimension,
+};
+use wgpu_conveyor::{write_to_buffer1, AutomatedBuffer, AutomatedBufferManager, IdBuffer};
+
+/// Near plane of every cube face. If you change this, change POINT_SHADOW_NEAR in structures.glsl.
+const POINT_SHADOW_NEAR: f32 = 0.1;
+
+/// Radius the light is uploaded and rendered with, kept past the near plane so the shadow cube projection stays valid.
+/// Both the face cameras and the shader use this as the far plane, so they must agree.
+fn clamped_radius(light: &PointLight) -> f32 {
+    light.radius.max(POINT_SHADOW_NEAR * 2.0)
+}
+
+pub struct InternalPointLight {
+    pub inner: PointLight,
+    /// One camera for every face of the shadow cube.
+    pub cameras: [CameraManager; 6],
+    pub shadow_tex: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+struct ShaderPointLightBufferHeader {
+    total_lights: u32,
+}
+
+unsafe impl bytemuck::Zeroable for ShaderPointLightBufferHeader {}
+unsafe impl bytemuck::Pod for ShaderPointLightBufferHeader {}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+struct ShaderPointLight {
+    pub position: Vec3,
+    pub radius: f32,
+    pub color: Vec3,
+    pub shadow_tex: u32,
+}
+
+unsafe impl bytemuck::Zeroable for ShaderPointLight {}
+unsafe impl bytemuck::Pod for ShaderPointLight {}
+
+pub struct PointLightManager {
+    buffer_storage: Option<Arc<IdBuffer>>,
+    buffer: AutomatedBuffer,
+
+    view: TextureView,
+    face_views: Vec<Arc<TextureView>>,
+
+    registry: ResourceRegistry<InternalPointLight>,
+}
+impl PointLightManager {
+    pub fn new(device: &Device, buffer_manager: &mut AutomatedBufferManager) -> Self {
+        let registry = ResourceRegistry::new();
+
+        let buffer = buffer_manager.create_new_buffer(device, 0, BufferUsage::STORAGE, Some("point lights"));
+
+        let (view, face_views) = create_shadow_texture(device, 1);
+
+        Self {
+            buffer_storage: None,
+            buffer,
+            view,
+            face_views,
+            registry,
+        }
+    }
+
+    pub fn allocate(&self) -> PointLightHandle {
+        PointLightHandle(self.registry.allocate())
+    }
+
+    pub fn fill(&mut self, handle: PointLightHandle, light: PointLight) {
+        self.registry.insert(
+            handle.0,
+            InternalPointLight {
+                inner: light,
+                cameras: compute_face_cameras(&light),
+                shadow_tex: 0,
+            },
+        );
+    }
+
+    pub fn update_from_changes(&mut self, handle: PointLightHandle, change: PointLightChange) {
+        let value = self.registry.get_mut(handle.0);
+        value.inner.update_from_changes(change);
+        if change.position.is_some() || change.radius.is_some() {
+            value.cameras = compute_face_cameras(&value.inner);
+        }
+    }
+
+    /// View of a single face of a light's shadow cube.
+    pub fn get_face_view_arc(&self, shadow_tex: u32, face: usize) -> Arc<TextureView> {
+        Arc::clone(&self.face_views[shadow_tex as usize * 6 + face])
+    }
+
+    pub fn remove(&mut self, handle: PointLightHandle) {
+        self.registry.remove(handle.0);
+    }
+
+    /// Returns the amount of bytes uploaded.
+    pub fn ready(&mut self, device: &Device, encoder: &mut CommandEncoder) -> BufferAddress {
+        let registered_count = self.registry.count();
+        if registered_count * 6 != self.face_views.len() && registered_count != 0 {
+            let (view, face_views) = create_shadow_texture(device, registered_count as u32);
+            self.view = view;
+            self.face_views = face_views;
+        }
+
+        // Removals shuffle lights around, so reassign the cubes every frame.
+        for (idx, light) in self.registry.values_mut().enumerate() {
+            light.shadow_tex = idx as u32;
+        }
+
+        let registry = &self.registry;
+
+        let size = self.registry.count() * size_of::<ShaderPointLight>() + size_of::<ShaderPointLightBufferHeader>();
+        write_to_buffer1(
+            device,
+            encoder,
+            &mut self.buffer,
+            size as BufferAddress,
+            |_, raw_buffer| {
+                let (raw_buffer_header, raw_buffer_body) =
+                    raw_buffer.split_at_mut(size_of::<ShaderPointLightBufferHeader>());
+                let buffer_header: &mut ShaderPointLightBufferHeader = bytemuck::from_bytes_mut(raw_buffer_header);
+                let buffer_body: &mut [ShaderPointLight] = bytemuck::cast_slice_mut(raw_buffer_body);
+
+                buffer_header.total_lights = registry.count() as u32;
+
+                for (idx, light) in registry.values().enumerate() {
+                    buffer_body[idx] = ShaderPointLight {
+                        position: light.inner.position,
+                        radius: clamped_radius(&light.inner),
+                        color: light.inner.color * light.inner.intensity,
+                        shadow_tex: light.shadow_tex,
+                    }
+                }
+            },
+        );
+
+        self.buffer_storage = Some(self.buffer.get_current_inner());
+
+        size as BufferAddress
+    }
+
+    pub fn append_to_bgb<'a>(&'a self, builder: &mut BindGroupBuilder<'a>) {
+        builder.append(self.buffer_storage.as_ref().unwrap().inner.as_entire_binding());
+        builder.append(BindingResource::TextureView(&self.view));
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &InternalPointLight> {
+        self.registry.values()
+    }
+
+    pub fn count(&self) -> usize {
+        self.registry.count()
+    }
+}
+
+fn compute_face_cameras(light: &PointLight) -> [CameraManager; 6] {
+    // Unlike the main camera, the faces use forward depth so the shader can reconstruct depth from distance.
+    let proj = Mat4::perspective_lh(FRAC_PI_2, 1.0, POINT_SHADOW_NEAR, clamped_radius(light));
+
+    let camera = |direction: Vec3, up: Vec3| {
+        let view = Mat4::look_at_lh(light.position, light.position + direction, up);
+        CameraManager::from_matrices(light.position, view, proj)
+    };
+
+    // Faces in the order they are laid out in a cube texture, oriented to match how the cube is sampled.
+    [
+        camera(Vec3::X, Vec3::Y),
+        camera(-Vec3::X, Vec3::Y),
+        camera(Vec3::Y, -Vec3::Z),
+        camera(-Vec3::Y, Vec3::Z),
+        camera(Vec3::Z, Vec3::Y),
+        camera(-Vec3::Z, Vec3::Y),
+    ]
+}
+
+fn create_shadow_texture(device: &Device, count: u32) -> (TextureView, Vec<Arc<TextureView>>) {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some("point shadow texture"),
+        size: Extent3d {
+            width: POINT_SHADOW_DIMENSIONS,
+            height: POINT_SHADOW_DIMENSIONS,
+            depth: count * 6,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: INTERNAL_SHADOW_DEPTH_FORMAT,
+        usage: TextureUsage::RENDER_ATTACHMENT | TextureUsage::SAMPLED,
+    });
+
+    let primary_view = texture.create_view(&TextureViewDescriptor {
+        label: Some("point shadow texture view"),
+        format: None,
+        dimension: Some(TextureViewDimension::CubeArray),
+        aspect: TextureAspect::All,
+        base_mip_level: 0,
+        level_count: None,
+        base_array_layer: 0,
+        array_layer_count: None,
+    });
+
+    let face_views: Vec<_> = (0..count * 6)
+        .map(|idx| {
+            Arc::new(texture.create_view(&TextureViewDescriptor {
+                label: Some(&format!("point shadow texture cube {} face {}", idx / 6, idx % 6)),
+                format: None,
+                dimension: Some(TextureViewDimension::D2),
+                aspect: TextureAspect::All,
+                base_mip_level: 0,
+                level_count: None,
+                base_array_layer: idx,
+                array_layer_count: NonZeroU32::new(1),
+            }))
+        })
+        .collect();
+
+    (primary_view, face_views)
+}
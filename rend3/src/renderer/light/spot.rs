@@ -0,0 +1,190 @@
+use crate::{
+    bind_merge::BindGroupBuilder,
+    datatypes::{SpotLight, SpotLightChange, SpotLightHandle},
+    registry::ResourceRegistry,
+    renderer::{camera::CameraManager, light::directional::create_shadow_texture, SHADOW_DIMENSIONS},
+};
+use glam::{Mat4, Vec3};
+use std::{mem::size_of, sync::Arc};
+use wgpu::{BindingResource, BufferAddress, BufferUsage, CommandEncoder, Device, TextureView};
+use wgpu_conveyor::{write_to_buffer1, AutomatedBuffer, AutomatedBufferManager, IdBuffer};
+
+const SPOT_SHADOW_NEAR: f32 = 0.1;
+
+/// The shadow map can't cover a full hemisphere, wider cones get their edges cut off.
+const MAX_SHADOW_FOV: f32 = 170.0;
+
+pub struct InternalSpotLight {
+    pub inner: SpotLight,
+    pub camera: CameraManager,
+    pub shadow_tex: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+struct ShaderSpotLightBufferHeader {
+    total_lights: u32,
+}
+
+unsafe impl bytemuck::Zeroable for ShaderSpotLightBufferHeader {}
+unsafe impl bytemuck::Pod for ShaderSpotLightBufferHeader {}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+struct ShaderSpotLight {
+    pub view_proj: Mat4,
+    pub position: Vec3,
+    pub range: f32,
+    pub direction: Vec3,
+    pub angle_scale: f32,
+    pub color: Vec3,
+    pub angle_offset: f32,
+    pub shadow_tex: u32,
+}
+
+unsafe impl bytemuck::Zeroable for ShaderSpotLight {}
+unsafe impl bytemuck::Pod for ShaderSpotLight {}
+
+/// Spot shadows get their own array of fixed size layers instead of sharing the directional light shadow array, which is
+/// recreated whenever the largest directional shadow resolution or the cascade count changes.
+pub struct SpotLightManager {
+    buffer_storage: Option<Arc<IdBuffer>>,
+    buffer: AutomatedBuffer,
+
+    view: TextureView,
+    layer_views: Vec<Arc<TextureView>>,
+
+    registry: ResourceRegistry<InternalSpotLight>,
+}
+impl SpotLightManager {
+    pub fn new(device: &Device, buffer_manager: &mut AutomatedBufferManager) -> Self {
+        let registry = ResourceRegistry::new();
+
+        let buffer = buffer_manager.create_new_buffer(device, 0, BufferUsage::STORAGE, Some("spot lights"));
+
+        let (view, layer_views) = create_shadow_texture(device, "spot shadow texture", 1, SHADOW_DIMENSIONS);
+
+        Self {
+            buffer_storage: None,
+            buffer,
+            view,
+            layer_views,
+            registry,
+        }
+    }
+
+    pub fn allocate(&self) -> SpotLightHandle {
+        SpotLightHandle(self.registry.allocate())
+    }
+
+    pub fn fill(&mut self, handle: SpotLightHandle, light: SpotLight) {
+        self.registry.insert(
+            handle.0,
+            InternalSpotLight {
+                inner: light,
+                camera: compute_camera(&light),
+                shadow_tex: 0,
+            },
+        );
+    }
+
+    pub fn update_from_changes(&mut self, handle: SpotLightHandle, change: SpotLightChange) {
+        let value = self.registry.get_mut(handle.0);
+        value.inner.update_from_changes(change);
+        value.camera = compute_camera(&value.inner);
+    }
+
+    pub fn get_layer_view_arc(&self, layer: u32) -> Arc<TextureView> {
+        Arc::clone(&self.layer_views[layer as usize])
+    }
+
+    pub fn remove(&mut self, handle: SpotLightHandle) {
+        self.registry.remove(handle.0);
+    }
+
+    /// Returns the amount of bytes uploaded.
+    pub fn ready(&mut self, device: &Device, encoder: &mut CommandEncoder) -> BufferAddress {
+        let registered_count = self.registry.count();
+        if registered_count != self.layer_views.len() && registered_count != 0 {
+            let (view, layer_views) = create_shadow_texture(
+                device,
+                "spot shadow texture",
+                registered_count as u32,
+                SHADOW_DIMENSIONS,
+            );
+            self.view = view;
+            self.layer_views = layer_views;
+        }
+
+        // Removals shuffle lights around, so reassign the layers every frame.
+        for (idx, light) in self.registry.values_mut().enumerate() {
+            light.shadow_tex = idx as u32;
+        }
+
+        let registry = &self.registry;
+
+        let size = self.registry.count() * size_of::<ShaderSpotLight>() + size_of::<ShaderSpotLightBufferHeader>();
+        write_to_buffer1(
+            device,
+            encoder,
+            &mut self.buffer,
+            size as BufferAddress,
+            |_, raw_buffer| {
+                let (raw_buffer_header, raw_buffer_body) =
+                    raw_buffer.split_at_mut(size_of::<ShaderSpotLightBufferHeader>());
+                let buffer_header: &mut ShaderSpotLightBufferHeader = bytemuck::from_bytes_mut(raw_buffer_header);
+                let buffer_body: &mut [ShaderSpotLight] = bytemuck::cast_slice_mut(raw_buffer_body);
+
+                buffer_header.total_lights = registry.count() as u32;
+
+                for (idx, light) in registry.values().enumerate() {
+                    // Attenuation is `saturate(cos(angle) * scale + offset)`, going from 1 at the inner cone to 0 at
+                    // the outer cone.
+                    let cos_inner = light.inner.inner_angle.cos();
+                    let cos_outer = light.inner.outer_angle.cos();
+                    let angle_scale = 1.0 / (cos_inner - cos_outer).max(1e-4);
+
+                    buffer_body[idx] = ShaderSpotLight {
+                        view_proj: light.camera.view_proj(),
+                        position: light.inner.position,
+                        range: light.inner.range,
+                        direction: light.inner.direction.normalize(),
+                        angle_scale,
+                        color: light.inner.color * light.inner.intensity,
+                        angle_offset: -cos_outer * angle_scale,
+                        shadow_tex: light.shadow_tex,
+                    }
+                }
+            },
+        );
+
+        self.buffer_storage = Some(self.buffer.get_current_inner());
+
+        size as BufferAddress
+    }
+
+    pub fn append_to_bgb<'a>(&'a self, builder: &mut BindGroupBuilder<'a>) {
+        builder.append(self.buffer_storage.as_ref().unwrap().inner.as_entire_binding());
+        builder.append(BindingResource::TextureView(&self.view));
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &InternalSpotLight> {
+        self.registry.values()
+    }
+
+    pub fn count(&self) -> usize {
+        self.registry.count()
+    }
+}
+
+fn compute_camera(light: &SpotLight) -> CameraManager {
+    let direction = light.direction.normalize();
+    // look_at falls apart if the up vector is parallel to the direction.
+    let up = if direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+    let view = Mat4::look_at_lh(light.position, light.position + direction, up);
+
+    let fov = (light.outer_angle * 2.0).min(MAX_SHADOW_FOV.to_radians());
+    let proj = Mat4::perspective_lh(fov, 1.0, SPOT_SHADOW_NEAR, light.range.max(SPOT_SHADOW_NEAR * 2.0));
+
+    CameraManager::from_matrices(light.position, view, proj)
+}
@@ -1,4 +1,4 @@
-use crate::{LimitType, RendererInitializationError, RendererMode};
+use crate::{renderer::sampler::MAX_GPU_SAMPLERS, LimitType, RendererInitializationError, RendererMode};
 use wgpu::{BufferAddress, Features, Limits};
 
 pub const MAX_UNIFORM_BUFFER_BINDING_SIZE: BufferAddress = 1024;
@@ -21,7 +21,7 @@ pub fn cpu_required_features() -> Features {
 }
 
 pub fn optional_features() -> Features {
-    wgpu::Features::TEXTURE_COMPRESSION_BC
+    wgpu::Features::TEXTURE_COMPRESSION_BC | wgpu::Features::TIMESTAMP_QUERY
 }
 
 pub fn check_features(mode: RendererMode, device: Features) -> Result<Features, RendererInitializationError> {
@@ -43,9 +43,10 @@ pub const GPU_REQUIRED_LIMITS: Limits = Limits {
     max_dynamic_uniform_buffers_per_pipeline_layout: 0,
     max_dynamic_storage_buffers_per_pipeline_layout: 0,
     max_sampled_textures_per_shader_stage: 256,
-    max_samplers_per_shader_stage: 2,
-    max_storage_buffers_per_shader_stage: 5,
-    max_storage_textures_per_shader_stage: 0,
+    // 3 general samplers and the material sampler table.
+    max_samplers_per_shader_stage: 3 + MAX_GPU_SAMPLERS as u32,
+    max_storage_buffers_per_shader_stage: 8,
+    max_storage_textures_per_shader_stage: 1,
     max_uniform_buffers_per_shader_stage: 2,
     max_uniform_buffer_binding_size: MAX_UNIFORM_BUFFER_BINDING_SIZE as u32,
     max_push_constant_size: 128,
@@ -55,10 +56,11 @@ pub const CPU_REQUIRED_LIMITS: Limits = Limits {
     max_bind_groups: 8,
     max_dynamic_uniform_buffers_per_pipeline_layout: 0,
     max_dynamic_storage_buffers_per_pipeline_layout: 0,
-    max_sampled_textures_per_shader_stage: 10,
-    max_samplers_per_shader_stage: 2,
-    max_storage_buffers_per_shader_stage: 2,
-    max_storage_textures_per_shader_stage: 0,
+    max_sampled_textures_per_shader_stage: 16,
+    // 3 general samplers and the material sampler.
+    max_samplers_per_shader_stage: 4,
+    max_storage_buffers_per_shader_stage: 4,
+    max_storage_textures_per_shader_stage: 1,
     max_uniform_buffers_per_shader_stage: 2,
     max_uniform_buffer_binding_size: MAX_UNIFORM_BUFFER_BINDING_SIZE as u32,
     max_push_constant_size: 128,
@@ -11,7 +11,7 @@ use crate::{
 use std::sync::Arc;
 use wgpu::{
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, CommandBuffer, CommandEncoderDescriptor,
-    IndexFormat, Operations, RenderPassColorAttachmentDescriptor, RenderPassDepthStencilAttachmentDescriptor,
+    IndexFormat, Operations, QuerySet, RenderPassColorAttachmentDescriptor, RenderPassDepthStencilAttachmentDescriptor,
     RenderPassDescriptor, ShaderStage, TextureViewDimension,
 };
 
@@ -24,6 +24,7 @@ pub(crate) struct BindingData {
     pub gpu_cube_textures_bg: ModeData<(), Arc<BindGroup>>,
     pub shadow_texture_bg: Arc<BindGroup>,
     pub skybox_texture_bg: Arc<BindGroup>,
+    pub environment_bg: Arc<BindGroup>,
     pub wrapped_uniform: Arc<WrappedUniform>,
 }
 
@@ -31,9 +32,12 @@ pub(crate) async fn render_single_render_pass<TD>(
     renderer: Arc<Renderer<TD>>,
     pass: RenderPass,
     output: OutputFrame,
+    // Restricts rendering to a square in the top left corner of the output, of this size.
+    viewport: Option<u32>,
     culling_data: Arc<CullingPassData>,
     binding_data: BindingData,
-) -> CommandBuffer
+    timestamps: Option<(Arc<QuerySet>, u32)>,
+) -> (CommandBuffer, usize)
 where
     TD: 'static,
 {
@@ -157,6 +161,7 @@ where
                     ResourceBinding::GPUCubeTextures => &*binding_data.gpu_cube_textures_bg.as_gpu(),
                     ResourceBinding::ShadowTexture => &*binding_data.shadow_texture_bg,
                     ResourceBinding::SkyboxTexture => &*binding_data.skybox_texture_bg,
+                    ResourceBinding::EnvironmentTextures => &*binding_data.environment_bg,
                     ResourceBinding::Custom2DTexture(..) | ResourceBinding::CustomCubeTexture(..) => {
                         owned_bg_iter.next().unwrap()
                     }
@@ -183,7 +188,16 @@ where
         depth_stencil_attachment: depth,
     });
 
-    for (op, bindings, pipeline) in &ops {
+    if let Some(size) = viewport {
+        rpass.set_viewport(0.0, 0.0, size as f32, size as f32, 0.0, 1.0);
+    }
+
+    let mut draw_calls = 0;
+
+    for (op_idx, (op, bindings, pipeline)) in ops.iter().enumerate() {
+        if let Some((ref query_set, base)) = timestamps {
+            rpass.write_timestamp(query_set, base + op_idx as u32 * 2);
+        }
         rpass.set_pipeline(&pipeline);
         for (idx, binding) in bindings.iter().enumerate() {
             rpass.set_bind_group(idx as u32, binding, &[]);
@@ -192,18 +206,22 @@ where
         match op.input {
             RenderOpInputType::FullscreenTriangle => {
                 rpass.draw(0..3, 0..1);
+                draw_calls += 1;
             }
-            RenderOpInputType::Models3D => match culling_data.inner {
+            RenderOpInputType::Models3D | RenderOpInputType::TransparentModels3D => match culling_data.inner {
                 ModeData::CPU(ref c) => {
+                    let transparent = matches!(op.input, RenderOpInputType::TransparentModels3D);
                     rpass.set_vertex_buffer(0, buffers.vertex_position.slice(..));
                     rpass.set_vertex_buffer(1, buffers.vertex_normal.slice(..));
                     rpass.set_vertex_buffer(2, buffers.vertex_tangent.slice(..));
                     rpass.set_vertex_buffer(3, buffers.vertex_uv.slice(..));
                     rpass.set_vertex_buffer(4, buffers.vertex_color.slice(..));
                     rpass.set_vertex_buffer(5, buffers.vertex_mat_index.slice(..));
+                    rpass.set_vertex_buffer(6, buffers.vertex_joints.slice(..));
                     rpass.set_index_buffer(buffers.index.slice(..), IndexFormat::Uint32);
                     let mut last_material = None;
-                    for (draw_call_idx, object) in c.iter().enumerate() {
+                    let objects = c.iter().filter(|object| object.transparent == transparent);
+                    for object in objects {
                         for (idx, binding) in op.per_object_bindings.iter().enumerate() {
                             match binding {
                                 PerObjectResourceBinding::CPUMaterial => {
@@ -218,11 +236,12 @@ where
                         rpass.set_push_constants(
                             ShaderStage::VERTEX | ShaderStage::FRAGMENT,
                             0,
-                            bytemuck::bytes_of(&(draw_call_idx as u32)),
+                            bytemuck::bytes_of(&object.object_idx),
                         );
                         let start = object.start_idx;
                         let end = start + object.count;
-                        rpass.draw_indexed(start..end, object.vertex_offset, 0..1);
+                        rpass.draw_indexed(start..end, object.vertex_offset, 0..object.instance_count);
+                        draw_calls += 1;
                     }
                 }
                 ModeData::GPU(ref g) => {
@@ -232,22 +251,35 @@ where
                     rpass.set_vertex_buffer(3, buffers.vertex_uv.slice(..));
                     rpass.set_vertex_buffer(4, buffers.vertex_color.slice(..));
                     rpass.set_vertex_buffer(5, buffers.vertex_mat_index.slice(..));
+                    rpass.set_vertex_buffer(6, buffers.vertex_joints.slice(..));
                     rpass.set_index_buffer(buffers.index.slice(..), IndexFormat::Uint32);
 
-                    rpass.set_vertex_buffer(6, g.indirect_buffer.slice(..));
+                    let (indirect_buffer, count_buffer) = match op.input {
+                        RenderOpInputType::TransparentModels3D => {
+                            (&g.transparent_indirect_buffer, &g.transparent_count_buffer)
+                        }
+                        _ => (&g.indirect_buffer, &g.count_buffer),
+                    };
+
+                    // The object index is read per instance, starting from the base instance of each call.
+                    rpass.set_vertex_buffer(7, g.object_index_buffer.slice(..));
                     rpass.multi_draw_indexed_indirect_count(
-                        &g.indirect_buffer,
+                        indirect_buffer,
                         0,
-                        &g.count_buffer,
+                        count_buffer,
                         0,
                         culling_data.object_count,
                     );
+                    draw_calls += 1;
                 }
             },
         }
+        if let Some((ref query_set, base)) = timestamps {
+            rpass.write_timestamp(query_set, base + op_idx as u32 * 2 + 1);
+        }
     }
 
     drop(rpass);
 
-    encoder.finish()
+    (encoder.finish(), draw_calls)
 }
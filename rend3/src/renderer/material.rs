@@ -1,16 +1,24 @@
 use crate::{
     bind_merge::BindGroupBuilder,
-    datatypes::{Material, MaterialChange, MaterialFlags, MaterialHandle, TextureHandle},
+    datatypes::{Material, MaterialChange, MaterialFlags, MaterialHandle, SamplerOptions, TextureHandle, Transparency},
     mode::ModeData,
     registry::ResourceRegistry,
-    renderer::texture::TextureManager,
+    renderer::{
+        sampler::{SamplerManager, MAX_GPU_SAMPLERS},
+        texture::TextureManager,
+    },
     RendererMode,
 };
 use glam::{Vec3, Vec4};
-use std::{mem::size_of, num::NonZeroU32, sync::Arc};
+use std::{
+    mem::{self, size_of},
+    num::NonZeroU32,
+    sync::Arc,
+};
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     BindGroup, BindGroupLayout, BindingResource, Buffer, BufferAddress, BufferUsage, CommandEncoder, Device, Queue,
+    Sampler,
 };
 use wgpu_conveyor::{AutomatedBuffer, AutomatedBufferManager, IdBuffer};
 
@@ -33,6 +41,7 @@ pub struct CPUShaderMaterial {
 
     texture_enable: u32,
     material_flags: MaterialFlags,
+    uv1_textures: u32,
 }
 
 unsafe impl bytemuck::Zeroable for CPUShaderMaterial {}
@@ -53,7 +62,7 @@ impl CPUShaderMaterial {
             emissive: material.emissive.to_value(Vec3::ZERO),
             anisotropy: material.anisotropy.to_value(0.0),
             ambient_occlusion: material.ao_factor.unwrap_or(1.0),
-            alpha_cutout: material.alpha_cutout.unwrap_or(0.0),
+            alpha_cutout: material.transparency.cutout(),
             texture_enable: material.albedo.is_texture() as u32
                 | (material.normal.to_texture(|_| ()).is_some() as u32) << 1
                 | (material.aomr_textures.to_roughness_texture(|_| ()).is_some() as u32) << 2
@@ -73,11 +82,14 @@ impl CPUShaderMaterial {
                 flags |= material.normal.to_flags();
                 flags |= material.aomr_textures.to_flags();
                 flags |= material.clearcoat_textures.to_flags();
-                flags.set(MaterialFlags::ALPHA_CUTOUT, material.alpha_cutout.is_some());
+                flags.set(
+                    MaterialFlags::ALPHA_CUTOUT,
+                    matches!(material.transparency, Transparency::Cutout { .. }),
+                );
                 flags.set(MaterialFlags::UNLIT, material.unlit);
-                flags.set(MaterialFlags::NEAREST, material.nearest);
                 flags
             },
+            uv1_textures: material.uv1_textures.bits(),
         }
     }
 }
@@ -111,6 +123,8 @@ struct GPUShaderMaterial {
     anisotropy_tex: Option<NonZeroU32>,
     ambient_occlusion_tex: Option<NonZeroU32>,
     material_flags: MaterialFlags,
+    uv1_textures: u32,
+    sampler: u32,
 }
 
 unsafe impl bytemuck::Zeroable for GPUShaderMaterial {}
@@ -120,11 +134,21 @@ struct InternalMaterial {
     mat: Material,
     bind_group: ModeData<Arc<BindGroup>, ()>,
     material_buffer: ModeData<Buffer, ()>,
+    /// Index of the material's sampler in the sampler manager. None while the material waits for a free slot of the
+    /// gpu sampler table.
+    sampler: Option<usize>,
+}
+impl InternalMaterial {
+    /// Materials waiting for their own sampler are sampled with the default one.
+    fn sampler_index(&self) -> usize {
+        self.sampler.unwrap_or(0)
+    }
 }
 
 pub struct MaterialManager {
     buffer: ModeData<(), AutomatedBuffer>,
     buffer_storage: ModeData<(), Option<Arc<IdBuffer>>>,
+    samplers: SamplerManager,
 
     registry: ResourceRegistry<InternalMaterial>,
 }
@@ -137,11 +161,13 @@ impl MaterialManager {
             || (),
             || manager.create_new_buffer(device, 0, BufferUsage::STORAGE, Some("material buffer")),
         );
+        let samplers = SamplerManager::new(device, mode);
         let registry = ResourceRegistry::new();
 
         Self {
             buffer,
             buffer_storage: mode.into_data(|| (), || None),
+            samplers,
             registry,
         }
     }
@@ -162,7 +188,6 @@ impl MaterialManager {
         span_transfer!(_ -> fill_span, INFO, "Material Manager Fill");
 
         texture_manager_2d.ensure_null_view();
-        let null_tex = texture_manager_2d.get_null_view();
 
         let material_buffer = mode.into_data(
             || {
@@ -177,78 +202,93 @@ impl MaterialManager {
             || (),
         );
 
-        let lookup_fn = |handle: TextureHandle| texture_manager_2d.get_view(handle);
+        let sampler = self.samplers.acquire(device, material.sampler);
+        if sampler.is_none() {
+            warn_sampler_table_full(&material.sampler);
+        }
+
+        let bind_group = mode.into_data(
+            || {
+                cpu_create_bind_group(
+                    device,
+                    texture_manager_2d,
+                    material_bgl,
+                    &material,
+                    material_buffer.as_cpu(),
+                    // Only gpu mode has a limit on samplers.
+                    self.samplers.get(sampler.unwrap()),
+                )
+            },
+            || (),
+        );
 
         self.registry.insert(
             handle.0,
             InternalMaterial {
-                bind_group: mode.into_data(
-                    || {
-                        let mut bgb = BindGroupBuilder::new(None);
-                        bgb.append(BindingResource::TextureView(
-                            material.albedo.to_texture(lookup_fn).unwrap_or(null_tex),
-                        ));
-                        bgb.append(BindingResource::TextureView(
-                            material.normal.to_texture(lookup_fn).unwrap_or(null_tex),
-                        ));
-                        bgb.append(BindingResource::TextureView(
-                            material
-                                .aomr_textures
-                                .to_roughness_texture(lookup_fn)
-                                .unwrap_or(null_tex),
-                        ));
-                        bgb.append(BindingResource::TextureView(
-                            material
-                                .aomr_textures
-                                .to_metallic_texture(lookup_fn)
-                                .unwrap_or(null_tex),
-                        ));
-                        bgb.append(BindingResource::TextureView(
-                            material.reflectance.to_texture(lookup_fn).unwrap_or(null_tex),
-                        ));
-                        bgb.append(BindingResource::TextureView(
-                            material
-                                .clearcoat_textures
-                                .to_clearcoat_texture(lookup_fn)
-                                .unwrap_or(null_tex),
-                        ));
-                        bgb.append(BindingResource::TextureView(
-                            material
-                                .clearcoat_textures
-                                .to_clearcoat_roughness_texture(lookup_fn)
-                                .unwrap_or(null_tex),
-                        ));
-                        bgb.append(BindingResource::TextureView(
-                            material.emissive.to_texture(lookup_fn).unwrap_or(null_tex),
-                        ));
-                        bgb.append(BindingResource::TextureView(
-                            material.anisotropy.to_texture(lookup_fn).unwrap_or(null_tex),
-                        ));
-                        bgb.append(BindingResource::TextureView(
-                            material.aomr_textures.to_ao_texture(lookup_fn).unwrap_or(null_tex),
-                        ));
-                        bgb.append(material_buffer.as_cpu().as_entire_binding());
-                        bgb.build(device, material_bgl)
-                    },
-                    || (),
-                ),
+                bind_group,
                 mat: material,
                 material_buffer,
+                sampler,
             },
         );
     }
 
-    pub fn remove(&mut self, handle: MaterialHandle) {
-        self.registry.remove(handle.0);
+    pub fn remove(&mut self, device: &Device, handle: MaterialHandle) {
+        let (_, material) = self.registry.remove(handle.0);
+        if let Some(sampler) = material.sampler {
+            if self.samplers.release(sampler) {
+                self.acquire_waiting_samplers(device);
+            }
+        }
     }
 
-    pub fn update_from_changes(&mut self, queue: &Queue, handle: MaterialHandle, change: MaterialChange) {
+    /// Gives materials waiting for a slot of the gpu sampler table their own sampler, after a slot has been freed.
+    /// Their index is picked up by the material buffer the next time it is written in [`ready`](Self::ready).
+    fn acquire_waiting_samplers(&mut self, device: &Device) {
+        let samplers = &mut self.samplers;
+        for material in self.registry.values_mut().filter(|material| material.sampler.is_none()) {
+            material.sampler = samplers.acquire(device, material.mat.sampler);
+        }
+    }
+
+    pub fn update_from_changes(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        texture_manager_2d: &mut TextureManager,
+        material_bgl: &BindGroupLayout,
+        handle: MaterialHandle,
+        change: MaterialChange,
+    ) {
+        texture_manager_2d.ensure_null_view();
+
         let material = self.registry.get_mut(handle.0);
         material.mat.update_from_changes(change);
+        // Acquired before releasing the old one, so an unchanged sampler isn't freed and recreated.
+        let sampler = self.samplers.acquire(device, material.mat.sampler);
+        let old_sampler = mem::replace(&mut material.sampler, sampler);
+        // Releasing the old sampler may free the slot this material is waiting for.
+        if old_sampler.map_or(false, |old_sampler| self.samplers.release(old_sampler)) {
+            self.acquire_waiting_samplers(device);
+        }
 
+        let material = self.registry.get_mut(handle.0);
+        if material.sampler.is_none() {
+            warn_sampler_table_full(&material.mat.sampler);
+        }
         if let ModeData::CPU(ref mut mat_buffer) = material.material_buffer {
             let cpu = CPUShaderMaterial::from_material(&material.mat);
             queue.write_buffer(mat_buffer, 0, bytemuck::bytes_of(&cpu));
+
+            // The textures and sampler are part of the bind group, so it has to be rebuilt.
+            material.bind_group = ModeData::CPU(cpu_create_bind_group(
+                device,
+                texture_manager_2d,
+                material_bgl,
+                &material.mat,
+                mat_buffer,
+                self.samplers.get(material.sampler_index()),
+            ));
         }
     }
 
@@ -256,11 +296,25 @@ impl MaterialManager {
         self.registry.get(handle.0).bind_group.as_cpu()
     }
 
+    pub fn is_transparent(&self, handle: MaterialHandle) -> bool {
+        self.registry.get(handle.0).mat.transparency == Transparency::Blend
+    }
+
     pub fn internal_index(&self, handle: MaterialHandle) -> usize {
         self.registry.get_index_of(handle.0)
     }
 
-    pub fn ready(&mut self, device: &Device, encoder: &mut CommandEncoder, texture_manager: &TextureManager) {
+    pub fn count(&self) -> usize {
+        self.registry.count()
+    }
+
+    /// Returns the amount of bytes uploaded.
+    pub fn ready(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        texture_manager: &TextureManager,
+    ) -> BufferAddress {
         span_transfer!(_ -> ready_span, INFO, "Material Manager Ready");
 
         if let ModeData::GPU(ref mut buffer) = self.buffer {
@@ -284,7 +338,7 @@ impl MaterialManager {
                         clear_coat_roughness: material.clearcoat_roughness_factor.unwrap_or(0.0),
                         anisotropy: material.anisotropy.to_value(0.0),
                         ambient_occlusion: material.ao_factor.unwrap_or(1.0),
-                        alpha_cutout: material.alpha_cutout.unwrap_or(0.0),
+                        alpha_cutout: material.transparency.cutout(),
 
                         uv_transform_row0: material.transform.x_axis.extend(0.0),
                         uv_transform_row1: material.transform.y_axis.extend(0.0),
@@ -307,19 +361,96 @@ impl MaterialManager {
                             flags |= material.normal.to_flags();
                             flags |= material.aomr_textures.to_flags();
                             flags |= material.clearcoat_textures.to_flags();
-                            flags.set(MaterialFlags::ALPHA_CUTOUT, material.alpha_cutout.is_some());
+                            flags.set(
+                                MaterialFlags::ALPHA_CUTOUT,
+                                matches!(material.transparency, Transparency::Cutout { .. }),
+                            );
                             flags.set(MaterialFlags::UNLIT, material.unlit);
-                            flags.set(MaterialFlags::NEAREST, material.nearest);
                             flags
                         },
+                        uv1_textures: material.uv1_textures.bits(),
+                        sampler: internal.sampler_index() as u32,
                     }
                 }
             });
             *self.buffer_storage.as_gpu_mut() = Some(self.buffer.as_gpu().get_current_inner());
+
+            size as BufferAddress
+        } else {
+            0
         }
     }
 
     pub fn gpu_append_to_bgb<'a>(&'a self, general_bgb: &mut BindGroupBuilder<'a>) {
         general_bgb.append(self.buffer_storage.as_gpu().as_ref().unwrap().inner.as_entire_binding());
+        self.samplers.gpu_append_to_bgb(general_bgb);
     }
 }
+
+fn warn_sampler_table_full(options: &SamplerOptions) {
+    tracing::warn!(
+        "More than {} different material samplers in use, sampling with the default sampler instead of {:?} until a \
+         slot is freed",
+        MAX_GPU_SAMPLERS,
+        options
+    );
+}
+
+fn cpu_create_bind_group(
+    device: &Device,
+    texture_manager_2d: &TextureManager,
+    material_bgl: &BindGroupLayout,
+    material: &Material,
+    material_buffer: &Buffer,
+    sampler: &Sampler,
+) -> Arc<BindGroup> {
+    let null_tex = texture_manager_2d.get_null_view();
+    let lookup_fn = |handle: TextureHandle| texture_manager_2d.get_view(handle);
+
+    let mut bgb = BindGroupBuilder::new(None);
+    bgb.append(BindingResource::TextureView(
+        material.albedo.to_texture(lookup_fn).unwrap_or(null_tex),
+    ));
+    bgb.append(BindingResource::TextureView(
+        material.normal.to_texture(lookup_fn).unwrap_or(null_tex),
+    ));
+    bgb.append(BindingResource::TextureView(
+        material
+            .aomr_textures
+            .to_roughness_texture(lookup_fn)
+            .unwrap_or(null_tex),
+    ));
+    bgb.append(BindingResource::TextureView(
+        material
+            .aomr_textures
+            .to_metallic_texture(lookup_fn)
+            .unwrap_or(null_tex),
+    ));
+    bgb.append(BindingResource::TextureView(
+        material.reflectance.to_texture(lookup_fn).unwrap_or(null_tex),
+    ));
+    bgb.append(BindingResource::TextureView(
+        material
+            .clearcoat_textures
+            .to_clearcoat_texture(lookup_fn)
+            .unwrap_or(null_tex),
+    ));
+    bgb.append(BindingResource::TextureView(
+        material
+            .clearcoat_textures
+            .to_clearcoat_roughness_texture(lookup_fn)
+            .unwrap_or(null_tex),
+    ));
+    bgb.append(BindingResource::TextureView(
+        material.emissive.to_texture(lookup_fn).unwrap_or(null_tex),
+    ));
+    bgb.append(BindingResource::TextureView(
+        material.anisotropy.to_texture(lookup_fn).unwrap_or(null_tex),
+    ));
+    bgb.append(BindingResource::TextureView(
+        material.aomr_textures.to_ao_texture(lookup_fn).unwrap_or(null_tex),
+    ));
+    bgb.append(material_buffer.as_entire_binding());
+    bgb.append(BindingResource::Sampler(sampler));
+    bgb.build(device, material_bgl)
+}
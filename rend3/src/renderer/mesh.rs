@@ -1,4 +1,5 @@
 use crate::{
+    bind_merge::BindGroupBuilder,
     datatypes::{Mesh, MeshHandle},
     registry::ResourceRegistry,
     renderer::{copy::GpuCopy, frustum::BoundingSphere},
@@ -13,17 +14,57 @@ use wgpu::{
 pub const VERTEX_POSITION_SIZE: usize = size_of::<Vec3>();
 pub const VERTEX_NORMAL_SIZE: usize = size_of::<Vec3>();
 pub const VERTEX_TANGENT_SIZE: usize = size_of::<Vec3>();
-pub const VERTEX_UV_SIZE: usize = size_of::<Vec2>();
+pub const VERTEX_UV_SIZE: usize = size_of::<VertexUvs>();
 pub const VERTEX_COLOR_SIZE: usize = size_of::<[u8; 4]>();
 pub const VERTEX_MATERIAL_INDEX_SIZE: usize = size_of::<u32>();
+pub const VERTEX_JOINT_SIZE: usize = size_of::<VertexJoints>();
 const INDEX_SIZE: usize = size_of::<u32>();
+const MORPH_DELTA_SIZE: usize = size_of::<MorphDelta>();
 
 const STARTING_VERTICES: usize = 1 << 16;
 const STARTING_INDICES: usize = 1 << 16;
+const STARTING_MORPH_DELTAS: usize = 1 << 14;
+
+/// Both sets of texture coordinates are interleaved to keep the amount of vertex buffers within limits.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct VertexUvs {
+    uv0: Vec2,
+    uv1: Vec2,
+}
+
+unsafe impl bytemuck::Zeroable for VertexUvs {}
+unsafe impl bytemuck::Pod for VertexUvs {}
+
+/// Joint indices and weights are interleaved to keep the amount of vertex buffers within limits.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct VertexJoints {
+    indices: [u16; 4],
+    weights: [f32; 4],
+}
+
+unsafe impl bytemuck::Zeroable for VertexJoints {}
+unsafe impl bytemuck::Pod for VertexJoints {}
+
+/// If you change this struct, change the morph delta stride in morph.glsl.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct MorphDelta {
+    position: [f32; 3],
+    normal: [f32; 3],
+    tangent: [f32; 3],
+}
+
+unsafe impl bytemuck::Zeroable for MorphDelta {}
+unsafe impl bytemuck::Pod for MorphDelta {}
 
 pub struct InternalMesh {
     pub vertex_range: Range<usize>,
     pub index_range: Range<usize>,
+    /// Deltas of all morph targets, with the targets of each vertex next to each other. Empty without morph targets.
+    pub morph_range: Range<usize>,
+    pub morph_target_count: u32,
     pub bounding_sphere: BoundingSphere,
 }
 
@@ -34,6 +75,7 @@ pub struct MeshBuffers {
     pub vertex_uv: Buffer,
     pub vertex_color: Buffer,
     pub vertex_mat_index: Buffer,
+    pub vertex_joints: Buffer,
 
     pub index: Buffer,
 }
@@ -47,7 +89,13 @@ pub struct MeshManager {
     index_count: usize,
     index_alloc: RangeAllocator<usize>,
 
+    morph_deltas: Buffer,
+    morph_delta_count: usize,
+    morph_alloc: RangeAllocator<usize>,
+
     registry: ResourceRegistry<InternalMesh>,
+
+    reallocations: usize,
 }
 
 impl MeshManager {
@@ -62,6 +110,10 @@ impl MeshManager {
         let vertex_alloc = RangeAllocator::new(0..vertex_count);
         let index_alloc = RangeAllocator::new(0..index_count);
 
+        let morph_delta_count = STARTING_MORPH_DELTAS;
+        let morph_deltas = create_morph_delta_buffer(device, morph_delta_count);
+        let morph_alloc = RangeAllocator::new(0..morph_delta_count);
+
         let registry = ResourceRegistry::new();
 
         Self {
@@ -70,7 +122,11 @@ impl MeshManager {
             vertex_alloc,
             index_count,
             index_alloc,
+            morph_deltas,
+            morph_delta_count,
+            morph_alloc,
             registry,
+            reallocations: 0,
         }
     }
 
@@ -86,7 +142,7 @@ impl MeshManager {
         encoder: &mut CommandEncoder,
         handle: MeshHandle,
         mesh: Mesh,
-    ) {
+    ) -> u64 {
         span_transfer!(_ -> fill_span, INFO, "Mesh Manager Fill");
 
         assert!(mesh.validate());
@@ -128,10 +184,16 @@ impl MeshManager {
             (vertex_range.start * VERTEX_NORMAL_SIZE) as BufferAddress,
             bytemuck::cast_slice(&mesh.vertex_normals),
         );
+        let vertex_uvs: Vec<_> = mesh
+            .vertex_uvs
+            .iter()
+            .zip(&mesh.vertex_uv1s)
+            .map(|(&uv0, &uv1)| VertexUvs { uv0, uv1 })
+            .collect();
         queue.write_buffer(
             &self.buffers.vertex_uv,
             (vertex_range.start * VERTEX_UV_SIZE) as BufferAddress,
-            bytemuck::cast_slice(&mesh.vertex_uvs),
+            bytemuck::cast_slice(&vertex_uvs),
         );
         queue.write_buffer(
             &self.buffers.vertex_color,
@@ -143,21 +205,92 @@ impl MeshManager {
             (vertex_range.start * VERTEX_MATERIAL_INDEX_SIZE) as BufferAddress,
             bytemuck::cast_slice(&mesh.vertex_material_indices),
         );
+        let vertex_joints: Vec<_> = mesh
+            .vertex_joint_indices
+            .iter()
+            .zip(&mesh.vertex_joint_weights)
+            .map(|(&indices, weights)| VertexJoints {
+                indices,
+                weights: weights.to_array(),
+            })
+            .collect();
+        queue.write_buffer(
+            &self.buffers.vertex_joints,
+            (vertex_range.start * VERTEX_JOINT_SIZE) as BufferAddress,
+            bytemuck::cast_slice(&vertex_joints),
+        );
         queue.write_buffer(
             &self.buffers.index,
             (index_range.start * INDEX_SIZE) as BufferAddress,
             bytemuck::cast_slice(&mesh.indices),
         );
 
-        let bounding_sphere = BoundingSphere::from_mesh(&mesh.vertex_positions);
+        let morph_target_count = mesh.morph_targets.len();
+        let morph_delta_count = vertex_count * morph_target_count;
+        let morph_range = if morph_delta_count == 0 {
+            0..0
+        } else {
+            let morph_range = match self.morph_alloc.allocate_range(morph_delta_count) {
+                Ok(range) => range,
+                Err(_) => {
+                    self.reallocate_morph_buffer(device, encoder, morph_delta_count);
+                    self.morph_alloc.allocate_range(morph_delta_count).unwrap()
+                }
+            };
+
+            let targets = &mesh.morph_targets;
+            let morph_deltas: Vec<_> = (0..vertex_count)
+                .flat_map(|vertex| {
+                    targets.iter().map(move |target| MorphDelta {
+                        position: target.position_deltas[vertex].to_array(),
+                        normal: target.normal_deltas[vertex].to_array(),
+                        tangent: target.tangent_deltas[vertex].to_array(),
+                    })
+                })
+                .collect();
+            queue.write_buffer(
+                &self.morph_deltas,
+                (morph_range.start * MORPH_DELTA_SIZE) as BufferAddress,
+                bytemuck::cast_slice(&morph_deltas),
+            );
+
+            morph_range
+        };
+
+        let bytes_uploaded = (vertex_count
+            * (VERTEX_POSITION_SIZE
+                + VERTEX_NORMAL_SIZE
+                + VERTEX_TANGENT_SIZE
+                + VERTEX_UV_SIZE
+                + VERTEX_COLOR_SIZE
+                + VERTEX_MATERIAL_INDEX_SIZE
+                + VERTEX_JOINT_SIZE)
+            + index_count * INDEX_SIZE
+            + morph_delta_count * MORPH_DELTA_SIZE) as u64;
+
+        let mut bounding_sphere = BoundingSphere::from_mesh(&mesh.vertex_positions);
+        // With every weight at one, the targets can move a vertex by at most the sum of their deltas.
+        let max_morph_distance = (0..vertex_count)
+            .map(|vertex| {
+                mesh.morph_targets
+                    .iter()
+                    .map(|target| target.position_deltas[vertex].length())
+                    .sum::<f32>()
+            })
+            .fold(0.0, f32::max);
+        bounding_sphere.radius += max_morph_distance;
 
         let mesh = InternalMesh {
             vertex_range,
             index_range,
+            morph_range,
+            morph_target_count: morph_target_count as u32,
             bounding_sphere,
         };
 
         self.registry.insert(handle.0, mesh);
+
+        bytes_uploaded
     }
 
     pub fn remove(&mut self, handle: MeshHandle) {
@@ -165,6 +298,9 @@ impl MeshManager {
 
         self.vertex_alloc.free_range(mesh.vertex_range);
         self.index_alloc.free_range(mesh.index_range);
+        if !mesh.morph_range.is_empty() {
+            self.morph_alloc.free_range(mesh.morph_range);
+        }
     }
 
     pub fn buffers(&self) -> &MeshBuffers {
@@ -175,6 +311,19 @@ impl MeshManager {
         self.registry.get(handle.0)
     }
 
+    pub fn append_to_bgb<'a>(&'a self, builder: &mut BindGroupBuilder<'a>) {
+        builder.append(self.morph_deltas.as_entire_binding());
+    }
+
+    pub fn count(&self) -> usize {
+        self.registry.count()
+    }
+
+    /// Returns the amount of buffer reallocations since the last call.
+    pub fn take_reallocation_count(&mut self) -> usize {
+        std::mem::take(&mut self.reallocations)
+    }
+
     pub fn reallocate_buffers(
         &mut self,
         device: &Device,
@@ -243,6 +392,13 @@ impl MeshManager {
             &new_buffers.vertex_mat_index,
             "vertex material index copy",
         );
+
+        let vertex_joints_copy_data = gpu_copy.prepare(
+            device,
+            &self.buffers.vertex_joints,
+            &new_buffers.vertex_joints,
+            "vertex joints copy",
+        );
         let index_copy_data = gpu_copy.prepare(device, &self.buffers.index, &new_buffers.index, "index copy");
 
         let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
@@ -275,6 +431,7 @@ impl MeshManager {
             copy_vert_fn!(&vertex_uv_copy_data, VERTEX_UV_SIZE);
             copy_vert_fn!(&vertex_color_copy_data, VERTEX_COLOR_SIZE);
             copy_vert_fn!(&vertex_mat_index_copy_data, VERTEX_MATERIAL_INDEX_SIZE);
+            copy_vert_fn!(&vertex_joints_copy_data, VERTEX_JOINT_SIZE);
 
             // Copy indices over to new buffer, adjusting their value by the difference
             let index_copy_start = (mesh.index_range.start * INDEX_SIZE) / 4;
@@ -299,9 +456,45 @@ impl MeshManager {
         self.index_count = new_index_count;
         self.vertex_alloc = new_vert_alloc;
         self.index_alloc = new_index_alloc;
+        self.reallocations += 1;
+    }
+
+    fn reallocate_morph_buffer(&mut self, device: &Device, encoder: &mut CommandEncoder, needed_deltas: usize) {
+        let new_delta_count = (self.morph_delta_count + needed_deltas).next_power_of_two();
+
+        tracing::debug!(
+            "Recreating morph delta buffer from {} to {}",
+            self.morph_delta_count,
+            new_delta_count
+        );
+
+        let new_morph_deltas = create_morph_delta_buffer(device, new_delta_count);
+
+        // Deltas keep their offsets, so objects referencing them stay valid.
+        for mesh in self.registry.values() {
+            if !mesh.morph_range.is_empty() {
+                let offset = (mesh.morph_range.start * MORPH_DELTA_SIZE) as BufferAddress;
+                let size = (mesh.morph_range.len() * MORPH_DELTA_SIZE) as BufferAddress;
+                encoder.copy_buffer_to_buffer(&self.morph_deltas, offset, &new_morph_deltas, offset, size);
+            }
+        }
+
+        self.morph_alloc.grow_to(new_delta_count);
+        self.morph_deltas = new_morph_deltas;
+        self.morph_delta_count = new_delta_count;
+        self.reallocations += 1;
     }
 }
 
+fn create_morph_delta_buffer(device: &Device, delta_count: usize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("morph delta buffer"),
+        size: (delta_count * MORPH_DELTA_SIZE) as BufferAddress,
+        usage: BufferUsage::COPY_SRC | BufferUsage::COPY_DST | BufferUsage::STORAGE,
+        mapped_at_creation: false,
+    })
+}
+
 fn create_buffers(device: &Device, vertex_count: usize, index_count: usize) -> MeshBuffers {
     let position_bytes = vertex_count * VERTEX_POSITION_SIZE;
     let normal_bytes = vertex_count * VERTEX_NORMAL_SIZE;
@@ -309,6 +502,7 @@ fn create_buffers(device: &Device, vertex_count: usize, index_count: usize) -> M
     let uv_bytes = vertex_count * VERTEX_UV_SIZE;
     let color_bytes = vertex_count * VERTEX_COLOR_SIZE;
     let mat_index_bytes = vertex_count * VERTEX_MATERIAL_INDEX_SIZE;
+    let joints_bytes = vertex_count * VERTEX_JOINT_SIZE;
     let index_bytes = index_count * INDEX_SIZE;
 
     let vertex_position = device.create_buffer(&BufferDescriptor {
@@ -353,6 +547,13 @@ fn create_buffers(device: &Device, vertex_count: usize, index_count: usize) -> M
         mapped_at_creation: false,
     });
 
+    let vertex_joints = device.create_buffer(&BufferDescriptor {
+        label: Some("joints vertex buffer"),
+        size: joints_bytes as BufferAddress,
+        usage: BufferUsage::COPY_DST | BufferUsage::VERTEX | BufferUsage::STORAGE,
+        mapped_at_creation: false,
+    });
+
     let index = device.create_buffer(&BufferDescriptor {
         label: Some("index buffer"),
         size: index_bytes as BufferAddress,
@@ -367,6 +568,7 @@ fn create_buffers(device: &Device, vertex_count: usize, index_count: usize) -> M
         vertex_uv,
         vertex_color,
         vertex_mat_index,
+        vertex_joints,
         index,
     }
 }
@@ -0,0 +1,198 @@
+use crate::{
+    list::{ShaderSourceStage, ShaderSourceType, SourceShaderDescriptor},
+    renderer::shaders::ShaderManager,
+};
+use fnv::FnvHashMap;
+use std::{future::Future, num::NonZeroU32};
+use wgpu::{
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+    BindingResource, BindingType, BlendState, Color, ColorTargetState, ColorWrite, CommandEncoder, CullMode, Device,
+    FragmentState, FrontFace, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor, PrimitiveState,
+    PrimitiveTopology, RenderPassColorAttachmentDescriptor, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, Sampler, ShaderStage, Texture, TextureAspect, TextureFormat, TextureSampleType,
+    TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+/// Formats mipmaps can be generated for. Compressed formats can't be rendered to.
+const SUPPORTED_FORMATS: [TextureFormat; 4] = [
+    TextureFormat::Rgba8Unorm,
+    TextureFormat::Rgba8UnormSrgb,
+    TextureFormat::Rgba16Float,
+    TextureFormat::Rgba32Float,
+];
+
+/// Generates mip chains by rendering each mip from the one above it.
+///
+/// Sampling an sRGB view decodes to linear and rendering to one encodes back, so sRGB textures are filtered in linear
+/// space without any special casing.
+pub struct MipmapGenerator {
+    bgl: BindGroupLayout,
+    pipelines: FnvHashMap<TextureFormat, RenderPipeline>,
+}
+impl MipmapGenerator {
+    pub fn new<'a>(device: &'a Device, shader_manager: &ShaderManager) -> impl Future<Output = Self> + 'a {
+        let vertex_shader = shader_manager.compile_shader(SourceShaderDescriptor {
+            source: ShaderSourceType::Builtin(String::from("blit.vert")),
+            defines: vec![],
+            includes: vec![],
+            stage: ShaderSourceStage::Vertex,
+        });
+        let fragment_shader = shader_manager.compile_shader(SourceShaderDescriptor {
+            source: ShaderSourceType::Builtin(String::from("mipmap.frag")),
+            defines: vec![],
+            includes: vec![],
+            stage: ShaderSourceStage::Fragment,
+        });
+
+        let bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("mipmap bgl"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Sampler {
+                        filtering: false,
+                        comparison: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStage::FRAGMENT,
+                    // Rgba32Float can't be filtered, so the shader filters by hand.
+                    ty: BindingType::Texture {
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        async move {
+            let vertex_shader = vertex_shader.await.unwrap();
+            let fragment_shader = fragment_shader.await.unwrap();
+
+            let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("mipmap pll"),
+                bind_group_layouts: &[&bgl],
+                push_constant_ranges: &[],
+            });
+
+            let pipelines = SUPPORTED_FORMATS
+                .iter()
+                .map(|&format| {
+                    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+                        label: Some(&*format!("{:?} mipmap pipeline", format)),
+                        layout: Some(&pll),
+                        vertex: VertexState {
+                            module: &vertex_shader,
+                            entry_point: "main",
+                            buffers: &[],
+                        },
+                        primitive: PrimitiveState {
+                            topology: PrimitiveTopology::TriangleList,
+                            strip_index_format: None,
+                            front_face: FrontFace::Cw,
+                            cull_mode: CullMode::None,
+                            polygon_mode: Default::default(),
+                        },
+                        depth_stencil: None,
+                        multisample: MultisampleState::default(),
+                        fragment: Some(FragmentState {
+                            module: &fragment_shader,
+                            entry_point: "main",
+                            targets: &[ColorTargetState {
+                                format,
+                                alpha_blend: BlendState::REPLACE,
+                                color_blend: BlendState::REPLACE,
+                                write_mask: ColorWrite::ALL,
+                            }],
+                        }),
+                    });
+                    (format, pipeline)
+                })
+                .collect();
+
+            Self { bgl, pipelines }
+        }
+    }
+
+    pub fn supports(format: TextureFormat) -> bool {
+        SUPPORTED_FORMATS.contains(&format)
+    }
+
+    /// Fills mips `1..mip_levels` of every layer of the texture from mip 0.
+    ///
+    /// The texture needs to be created with `RENDER_ATTACHMENT` and `SAMPLED` usage.
+    #[allow(clippy::too_many_arguments)]
+    pub fn generate(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        sampler: &Sampler,
+        texture: &Texture,
+        format: TextureFormat,
+        mip_levels: u32,
+        layers: u32,
+    ) {
+        span_transfer!(_ -> generate_span, INFO, "Generating mipmaps");
+
+        let pipeline = self
+            .pipelines
+            .get(&format)
+            .unwrap_or_else(|| panic!("Mipmap generation is not supported for {:?}", format));
+
+        for layer in 0..layers {
+            let views: Vec<TextureView> = (0..mip_levels)
+                .map(|mip| {
+                    texture.create_view(&TextureViewDescriptor {
+                        label: Some("mipmap view"),
+                        format: None,
+                        dimension: Some(TextureViewDimension::D2),
+                        aspect: TextureAspect::All,
+                        base_mip_level: mip,
+                        level_count: NonZeroU32::new(1),
+                        base_array_layer: layer,
+                        array_layer_count: NonZeroU32::new(1),
+                    })
+                })
+                .collect();
+
+            for pair in views.windows(2) {
+                let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("mipmap bg"),
+                    layout: &self.bgl,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::Sampler(sampler),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::TextureView(&pair[0]),
+                        },
+                    ],
+                });
+
+                let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+                    label: Some("mipmap generation"),
+                    color_attachments: &[RenderPassColorAttachmentDescriptor {
+                        attachment: &pair[1],
+                        resolve_target: None,
+                        ops: Operations {
+                            load: LoadOp::Clear(Color::TRANSPARENT),
+                            store: true,
+                        },
+                    }],
+                    depth_stencil_attachment: None,
+                });
+
+                rpass.set_pipeline(pipeline);
+                rpass.set_bind_group(0, &bind_group, &[]);
+                rpass.draw(0..3, 0..1);
+            }
+        }
+    }
+}
@@ -1,19 +1,24 @@
 use crate::{
     datatypes::{
-        AffineTransform, Camera, DirectionalLight, DirectionalLightChange, DirectionalLightHandle, Material,
-        MaterialChange, MaterialHandle, Mesh, MeshHandle, Object, ObjectHandle, Pipeline, PipelineHandle, ShaderHandle,
-        Texture, TextureHandle,
+        AffineTransform, Camera, DirectionalLight, DirectionalLightChange, DirectionalLightHandle, InstancedObject,
+        Material, MaterialChange, MaterialHandle, Mesh, MeshHandle, Object, ObjectHandle, Pipeline, PipelineHandle,
+        PointLight, PointLightChange, PointLightHandle, ShaderHandle, Skeleton, SkeletonHandle, SpotLight,
+        SpotLightChange, SpotLightHandle, Texture, TextureHandle,
     },
     instruction::{Instruction, InstructionStreamPair},
     list::{RenderList, SourceShaderDescriptor},
     renderer::{
         info::ExtendedAdapterInfo, material::MaterialManager, mesh::MeshManager, object::ObjectManager,
-        pipeline::PipelineManager, resources::RendererGlobalResources, shaders::ShaderManager, texture::TextureManager,
+        pipeline::PipelineManager, resources::RendererGlobalResources, shaders::ShaderManager,
+        skeleton::SkeletonManager, texture::TextureManager,
     },
     statistics::RendererStatistics,
-    JobPriorities, RendererBuilder, RendererInitializationError, RendererMode, RendererOptions, RendererOutput,
+    JobPriorities, RenderedImage, RendererBuilder, RendererInitializationError, RendererMode, RendererOptions,
+    RendererOutput,
 };
 use bitflags::_core::cmp::Ordering;
+use futures::FutureExt;
+use glam::Mat4;
 use parking_lot::{Mutex, RwLock};
 use raw_window_handle::HasRawWindowHandle;
 use std::{future::Future, sync::Arc};
@@ -27,13 +32,20 @@ mod util;
 mod camera;
 mod copy;
 mod culling;
+mod equirect;
 pub mod error;
 mod frustum;
+pub(crate) mod headless;
+mod ibl;
 mod info;
 mod light {
     pub mod directional;
+    pub mod point;
+    pub mod spot;
 
     pub use directional::*;
+    pub use point::*;
+    pub use spot::*;
 }
 pub mod limits;
 mod list {
@@ -47,12 +59,16 @@ mod list {
 }
 mod material;
 mod mesh;
+mod mipmap;
 mod object;
 mod pipeline;
+mod profiler;
 mod render;
 mod resources;
+mod sampler;
 mod setup;
 mod shaders;
+mod skeleton;
 mod texture;
 mod uniforms;
 
@@ -70,6 +86,8 @@ const INTERNAL_SHADOW_DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
 
 const SHADOW_DIMENSIONS: u32 = 2048;
 
+const POINT_SHADOW_DIMENSIONS: u32 = 1024;
+
 pub struct Renderer<TLD = ()>
 where
     TLD: 'static,
@@ -94,11 +112,18 @@ where
     texture_manager_cube: RwLock<TextureManager>,
     material_manager: RwLock<MaterialManager>,
     object_manager: RwLock<ObjectManager>,
+    skeleton_manager: RwLock<SkeletonManager>,
     directional_light_manager: RwLock<light::DirectionalLightManager>,
+    point_light_manager: RwLock<light::PointLightManager>,
+    spot_light_manager: RwLock<light::SpotLightManager>,
+    ibl_manager: RwLock<ibl::IblManager>,
     render_list_cache: RwLock<list::RenderListCache>,
 
     gpu_copy: copy::GpuCopy,
+    equirect_converter: equirect::EquirectConverter,
+    mipmap_generator: mipmap::MipmapGenerator,
     culling_pass: culling::CullingPass,
+    profiler: profiler::GpuProfiler,
 
     // _imgui_renderer: imgui_wgpu::Renderer,
     options: RwLock<RendererOptions>,
@@ -174,6 +199,23 @@ impl<TLD: 'static> Renderer<TLD> {
         handle
     }
 
+    /// Converts an equirectangular panorama into a cube texture with faces `resolution` pixels wide on the gpu. The
+    /// result is an `Rgba16Float` texture with a full mip chain, usable as a background or environment texture.
+    ///
+    /// Only the top mip of the panorama is used. The center of the panorama faces +Z.
+    pub fn add_texture_cube_from_equirectangular(&self, texture: Texture, resolution: u32) -> TextureHandle {
+        let handle = self.texture_manager_cube.read().allocate();
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::AddTextureCubeFromEquirectangular {
+                handle,
+                texture,
+                resolution,
+            });
+        handle
+    }
+
     pub fn remove_texture_cube(&self, handle: TextureHandle) {
         self.instructions
             .producer
@@ -181,6 +223,10 @@ impl<TLD: 'static> Renderer<TLD> {
             .push(Instruction::RemoveTextureCube { handle })
     }
 
+    /// In gpu mode, materials can use at most 8 different [`SamplerOptions`](crate::datatypes::SamplerOptions) at once,
+    /// including the default. Materials added or changed to use options past that log a warning and are sampled with
+    /// the default sampler until removing or changing the last material using some other options frees a slot, at
+    /// which point they get their own sampler.
     pub fn add_material(&self, material: Material) -> MaterialHandle {
         let handle = self.material_manager.read().allocate();
         self.instructions
@@ -213,6 +259,16 @@ impl<TLD: 'static> Renderer<TLD> {
         handle
     }
 
+    pub fn add_instanced_object(&self, object: InstancedObject) -> ObjectHandle {
+        let handle = self.object_manager.read().allocate();
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::AddInstancedObject { handle, object });
+        handle
+    }
+
+    /// Sets the transform of the object. On an instanced object, this replaces all instances with a single one.
     pub fn set_object_transform(&self, handle: ObjectHandle, transform: AffineTransform) {
         self.instructions
             .producer
@@ -220,6 +276,30 @@ impl<TLD: 'static> Renderer<TLD> {
             .push(Instruction::SetObjectTransform { handle, transform });
     }
 
+    /// Sets the transforms of many objects at once, queueing them all under a single lock.
+    pub fn set_object_transforms(&self, transforms: impl IntoIterator<Item = (ObjectHandle, AffineTransform)>) {
+        let mut producer = self.instructions.producer.lock();
+        for (handle, transform) in transforms {
+            producer.push(Instruction::SetObjectTransform { handle, transform });
+        }
+    }
+
+    /// Replaces all instances of the object, changing the instance count to the length of `transforms`.
+    pub fn set_instance_transforms(&self, handle: ObjectHandle, transforms: Vec<AffineTransform>) {
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::SetInstanceTransforms { handle, transforms });
+    }
+
+    /// Sets the weight of each morph target of the object's mesh. All instances of an instanced object share them.
+    pub fn set_object_morph_weights(&self, handle: ObjectHandle, weights: Vec<f32>) {
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::SetObjectMorphWeights { handle, weights });
+    }
+
     pub fn remove_object(&self, handle: ObjectHandle) {
         self.instructions
             .producer
@@ -227,6 +307,31 @@ impl<TLD: 'static> Renderer<TLD> {
             .push(Instruction::RemoveObject { handle })
     }
 
+    pub fn add_skeleton(&self, skeleton: Skeleton) -> SkeletonHandle {
+        let handle = self.skeleton_manager.read().allocate();
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::AddSkeleton { handle, skeleton });
+        handle
+    }
+
+    /// Replaces the joint matrices of the skeleton. The joint count must stay the same.
+    pub fn set_skeleton_joint_matrices(&self, handle: SkeletonHandle, joint_matrices: Vec<Mat4>) {
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::SetSkeletonJointMatrices { handle, joint_matrices });
+    }
+
+    /// Objects still using the skeleton are drawn in the bind pose of their mesh afterwards.
+    pub fn remove_skeleton(&self, handle: SkeletonHandle) {
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::RemoveSkeleton { handle })
+    }
+
     pub fn add_directional_light(&self, light: DirectionalLight) -> DirectionalLightHandle {
         let handle = self.directional_light_manager.read().allocate();
 
@@ -252,6 +357,56 @@ impl<TLD: 'static> Renderer<TLD> {
             .push(Instruction::RemoveDirectionalLight { handle })
     }
 
+    pub fn add_point_light(&self, light: PointLight) -> PointLightHandle {
+        let handle = self.point_light_manager.read().allocate();
+
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::AddPointLight { handle, light });
+
+        handle
+    }
+
+    pub fn update_point_light(&self, handle: PointLightHandle, change: PointLightChange) {
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::ChangePointLight { handle, change })
+    }
+
+    pub fn remove_point_light(&self, handle: PointLightHandle) {
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::RemovePointLight { handle })
+    }
+
+    pub fn add_spot_light(&self, light: SpotLight) -> SpotLightHandle {
+        let handle = self.spot_light_manager.read().allocate();
+
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::AddSpotLight { handle, light });
+
+        handle
+    }
+
+    pub fn update_spot_light(&self, handle: SpotLightHandle, change: SpotLightChange) {
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::ChangeSpotLight { handle, change })
+    }
+
+    pub fn remove_spot_light(&self, handle: SpotLightHandle) {
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::RemoveSpotLight { handle })
+    }
+
     pub fn add_binary_shader(&self, shader: Vec<u32>) -> ShaderHandle {
         let handle = self.shader_manager.allocate();
 
@@ -313,12 +468,48 @@ impl<TLD: 'static> Renderer<TLD> {
             .push(Instruction::ClearBackgroundTexture)
     }
 
+    /// Sets the cube texture image based lighting is generated from. Without one, the background texture is used.
+    pub fn set_environment_texture(&self, handle: TextureHandle) {
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::SetEnvironmentTexture { handle })
+    }
+
+    pub fn clear_environment_texture(&self) {
+        self.instructions
+            .producer
+            .lock()
+            .push(Instruction::ClearEnvironmentTexture)
+    }
+
     pub fn render(self: &Arc<Self>, list: RenderList, output: RendererOutput) -> JoinHandle<RendererStatistics> {
         let this = Arc::clone(self);
         self.yard.spawn_local(
             self.yard_priorites.compute_pool,
             self.yard_priorites.main_task_priority,
-            move |_| render::render_loop(this, list, output),
+            move |_| render::render_loop(this, list, output).map(|(stats, _)| stats),
+        )
+    }
+
+    /// Renders to the headless target and reads the resulting frame back to the cpu.
+    ///
+    /// # Panics
+    ///
+    /// Rendering will panic if the renderer wasn't built using [`RendererBuilder::headless`].
+    pub fn render_to_image(self: &Arc<Self>, list: RenderList) -> JoinHandle<(RendererStatistics, RenderedImage)> {
+        let this = Arc::clone(self);
+        self.yard.spawn_local(
+            self.yard_priorites.compute_pool,
+            self.yard_priorites.main_task_priority,
+            move |_| async move {
+                let (stats, readback) = render::render_loop(Arc::clone(&this), list, RendererOutput::Headless).await;
+                let image = readback
+                    .expect("Headless output must produce a readback")
+                    .read(&this.device)
+                    .await;
+                (stats, image)
+            },
         )
     }
 }
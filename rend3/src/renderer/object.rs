@@ -1,11 +1,17 @@
 use crate::{
     bind_merge::BindGroupBuilder,
-    datatypes::{AffineTransform, MaterialHandle, Object, ObjectHandle},
+    datatypes::{AffineTransform, InstancedObject, MaterialHandle, MeshHandle, Object, ObjectHandle, SkeletonHandle},
     mode::ModeData,
     registry::ResourceRegistry,
-    renderer::{frustum::BoundingSphere, material::MaterialManager, mesh::MeshManager},
+    renderer::{
+        frustum::BoundingSphere,
+        material::MaterialManager,
+        mesh::MeshManager,
+        skeleton::{SkeletonManager, NO_SKELETON},
+    },
     RendererMode,
 };
+use smallvec::{smallvec, SmallVec};
 use std::{mem::size_of, sync::Arc};
 use wgpu::{BufferAddress, BufferUsage, CommandEncoder, Device};
 use wgpu_conveyor::{write_to_buffer1, AutomatedBuffer, AutomatedBufferManager, IdBuffer};
@@ -13,11 +19,25 @@ use wgpu_conveyor::{write_to_buffer1, AutomatedBuffer, AutomatedBufferManager, I
 #[derive(Debug, Clone)]
 pub struct InternalObject {
     pub material: MaterialHandle,
-    pub transform: AffineTransform,
+    /// One transform per instance. Regular objects are a single instance.
+    pub transforms: SmallVec<[AffineTransform; 1]>,
+    pub skeleton: Option<SkeletonHandle>,
+    /// Bounding sphere of the mesh in its bind pose.
+    pub mesh_sphere: BoundingSphere,
+    /// Bounding sphere used for culling. Covers every pose of the skeleton, if there is one. Refreshed every frame.
     pub sphere: BoundingSphere,
     pub start_idx: u32,
     pub count: u32,
     pub vertex_offset: i32,
+    /// If the material blends. Refreshed every frame, as the material may change.
+    pub transparent: bool,
+    /// Index of the first joint matrix of the skeleton, or NO_SKELETON. Refreshed every frame.
+    pub joint_offset: u32,
+    pub morph_weights: Vec<f32>,
+    pub morph_delta_offset: u32,
+    pub morph_target_count: u32,
+    /// Index of the first morph weight of this object in the weight buffer. Refreshed every frame.
+    pub morph_weight_offset: u32,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -29,6 +49,15 @@ struct ShaderInputObject {
     material_idx: u32,
     transform: AffineTransform,
     sphere: BoundingSphere,
+    transparent: u32,
+    /// Index of the first instance of this object in the object buffer.
+    batch_start: u32,
+    /// Amount of instances of this object.
+    batch_count: u32,
+    joint_offset: u32,
+    morph_delta_offset: u32,
+    morph_target_count: u32,
+    morph_weight_offset: u32,
 }
 
 unsafe impl bytemuck::Zeroable for ShaderInputObject {}
@@ -36,10 +65,20 @@ unsafe impl bytemuck::Pod for ShaderInputObject {}
 
 const SHADER_OBJECT_SIZE: usize = size_of::<ShaderInputObject>();
 
+pub struct ObjectManagerReadyOutput {
+    pub object_count: usize,
+    /// Sum of the instance counts of all objects. This is the amount of entries culling runs over.
+    pub instance_count: usize,
+    pub bytes_uploaded: BufferAddress,
+}
+
 pub struct ObjectManager {
     object_info_buffer: ModeData<(), AutomatedBuffer>,
     object_info_buffer_storage: ModeData<(), Option<Arc<IdBuffer>>>,
 
+    morph_weight_buffer: AutomatedBuffer,
+    morph_weight_buffer_storage: Option<Arc<IdBuffer>>,
+
     registry: ResourceRegistry<InternalObject>,
 }
 impl ObjectManager {
@@ -51,11 +90,16 @@ impl ObjectManager {
             || buffer_manager.create_new_buffer(device, 0, BufferUsage::STORAGE, Some("object info buffer")),
         );
 
+        let morph_weight_buffer =
+            buffer_manager.create_new_buffer(device, 0, BufferUsage::STORAGE, Some("morph weight buffer"));
+
         let registry = ResourceRegistry::new();
 
         Self {
             object_info_buffer,
             object_info_buffer_storage: mode.into_data(|| (), || None),
+            morph_weight_buffer,
+            morph_weight_buffer_storage: None,
             registry,
         }
     }
@@ -67,15 +111,58 @@ impl ObjectManager {
     pub fn fill(&mut self, handle: ObjectHandle, object: Object, mesh_manager: &MeshManager) {
         span_transfer!(_ -> fill_span, INFO, "Object Manager Fill");
 
-        let mesh = mesh_manager.internal_data(object.mesh);
+        self.fill_inner(
+            handle,
+            object.mesh,
+            object.material,
+            smallvec![object.transform],
+            object.skeleton,
+            object.morph_weights,
+            mesh_manager,
+        );
+    }
+
+    pub fn fill_instanced(&mut self, handle: ObjectHandle, object: InstancedObject, mesh_manager: &MeshManager) {
+        span_transfer!(_ -> fill_span, INFO, "Object Manager Fill Instanced");
+
+        self.fill_inner(
+            handle,
+            object.mesh,
+            object.material,
+            SmallVec::from_vec(object.transforms),
+            None,
+            Vec::new(),
+            mesh_manager,
+        );
+    }
+
+    fn fill_inner(
+        &mut self,
+        handle: ObjectHandle,
+        mesh: MeshHandle,
+        material: MaterialHandle,
+        transforms: SmallVec<[AffineTransform; 1]>,
+        skeleton: Option<SkeletonHandle>,
+        morph_weights: Vec<f32>,
+        mesh_manager: &MeshManager,
+    ) {
+        let mesh = mesh_manager.internal_data(mesh);
 
         let shader_object = InternalObject {
-            material: object.material,
-            transform: object.transform,
+            material,
+            transforms,
+            skeleton,
+            mesh_sphere: mesh.bounding_sphere,
             sphere: mesh.bounding_sphere,
             start_idx: mesh.index_range.start as u32,
             count: (mesh.index_range.end - mesh.index_range.start) as u32,
             vertex_offset: mesh.vertex_range.start as i32,
+            transparent: false,
+            joint_offset: NO_SKELETON,
+            morph_weights,
+            morph_delta_offset: mesh.morph_range.start as u32,
+            morph_target_count: mesh.morph_target_count,
+            morph_weight_offset: 0,
         };
 
         self.registry.insert(handle.0, shader_object);
@@ -90,46 +177,140 @@ impl ObjectManager {
         device: &Device,
         encoder: &mut CommandEncoder,
         material_manager: &MaterialManager,
-    ) -> usize {
+        skeleton_manager: &SkeletonManager,
+    ) -> ObjectManagerReadyOutput {
         span_transfer!(_ -> ready_span, INFO, "Object Manager Ready");
 
         let object_count = self.registry.count();
+        let instance_count = self.instance_count();
 
-        if object_count == 0 {
-            return object_count;
+        // The weight buffer is always bound, so it is written even without objects.
+        let mut bytes_uploaded = self.upload_morph_weights(device, encoder);
+
+        if instance_count == 0 {
+            return ObjectManagerReadyOutput {
+                object_count,
+                instance_count,
+                bytes_uploaded,
+            };
+        }
+
+        for object in self.registry.values_mut() {
+            object.transparent = material_manager.is_transparent(object.material);
+            // Objects outlive a removed skeleton, they are drawn in the bind pose of their mesh from then on.
+            match object.skeleton.and_then(|skeleton| skeleton_manager.try_get(skeleton)) {
+                Some(skeleton) => {
+                    object.sphere = skeleton.bounding_sphere(object.mesh_sphere);
+                    object.joint_offset = skeleton.joint_offset;
+                }
+                None => {
+                    object.sphere = object.mesh_sphere;
+                    object.joint_offset = NO_SKELETON;
+                }
+            }
         }
 
         if let ModeData::GPU(ref mut obj_buffer) = self.object_info_buffer {
             let registry = &self.registry;
 
-            let obj_buffer_size = (object_count * SHADER_OBJECT_SIZE) as BufferAddress;
+            let obj_buffer_size = (instance_count * SHADER_OBJECT_SIZE) as BufferAddress;
             write_to_buffer1(device, encoder, obj_buffer, obj_buffer_size, |_, obj_slice| {
                 let obj_slice: &mut [ShaderInputObject] = bytemuck::cast_slice_mut(obj_slice);
 
-                for (object_idx, object) in registry.values().enumerate() {
+                let mut batch_start = 0;
+                for object in registry.values() {
                     // Object Update
 
-                    obj_slice[object_idx] = ShaderInputObject {
-                        start_idx: object.start_idx,
-                        count: object.count,
-                        vertex_offset: object.vertex_offset,
-                        material_idx: material_manager.internal_index(object.material) as u32,
-                        transform: object.transform,
-                        sphere: object.sphere,
-                    };
+                    let material_idx = material_manager.internal_index(object.material) as u32;
+                    let batch_count = object.transforms.len();
+                    let batch = &mut obj_slice[batch_start..batch_start + batch_count];
+                    for (shader_object, &transform) in batch.iter_mut().zip(&object.transforms) {
+                        *shader_object = ShaderInputObject {
+                            start_idx: object.start_idx,
+                            count: object.count,
+                            vertex_offset: object.vertex_offset,
+                            material_idx,
+                            transform,
+                            sphere: object.sphere,
+                            transparent: object.transparent as u32,
+                            batch_start: batch_start as u32,
+                            batch_count: batch_count as u32,
+                            joint_offset: object.joint_offset,
+                            morph_delta_offset: object.morph_delta_offset,
+                            morph_target_count: object.morph_target_count,
+                            morph_weight_offset: object.morph_weight_offset,
+                        };
+                    }
+
+                    batch_start += batch_count;
                 }
             });
 
             *self.object_info_buffer_storage.as_gpu_mut() = Some(obj_buffer.get_current_inner());
+
+            bytes_uploaded += obj_buffer_size;
         }
 
-        object_count
+        ObjectManagerReadyOutput {
+            object_count,
+            instance_count,
+            bytes_uploaded,
+        }
+    }
+
+    /// Returns the amount of bytes uploaded.
+    fn upload_morph_weights(&mut self, device: &Device, encoder: &mut CommandEncoder) -> BufferAddress {
+        let mut weight_count = 0;
+        for object in self.registry.values_mut() {
+            object.morph_weight_offset = weight_count as u32;
+            weight_count += object.morph_target_count as usize;
+        }
+
+        let registry = &self.registry;
+
+        // Never empty, as the buffer is always bound.
+        let size = weight_count.max(1) * size_of::<f32>();
+        write_to_buffer1(
+            device,
+            encoder,
+            &mut self.morph_weight_buffer,
+            size as BufferAddress,
+            |_, raw_buffer| {
+                let buffer_body: &mut [f32] = bytemuck::cast_slice_mut(raw_buffer);
+
+                for object in registry.values() {
+                    let start = object.morph_weight_offset as usize;
+                    let weights = &mut buffer_body[start..start + object.morph_target_count as usize];
+                    // Missing weights are zero, extra weights are ignored.
+                    for (idx, weight) in weights.iter_mut().enumerate() {
+                        *weight = object.morph_weights.get(idx).copied().unwrap_or(0.0);
+                    }
+                }
+            },
+        );
+
+        self.morph_weight_buffer_storage = Some(self.morph_weight_buffer.get_current_inner());
+
+        size as BufferAddress
     }
 
     pub fn values(&self) -> impl Iterator<Item = &InternalObject> {
         self.registry.values()
     }
 
+    /// Object at the given position of [`values`](Self::values).
+    pub fn get_by_index(&self, index: usize) -> &InternalObject {
+        self.registry.get_by_index(index)
+    }
+
+    pub fn count(&self) -> usize {
+        self.registry.count()
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.registry.values().map(|object| object.transforms.len()).sum()
+    }
+
     pub fn gpu_append_to_bgb<'a>(&'a self, general_bgb: &mut BindGroupBuilder<'a>) {
         general_bgb.append(
             self.object_info_buffer_storage
@@ -141,7 +322,25 @@ impl ObjectManager {
         );
     }
 
+    pub fn append_morph_weights_to_bgb<'a>(&'a self, builder: &mut BindGroupBuilder<'a>) {
+        builder.append(
+            self.morph_weight_buffer_storage
+                .as_ref()
+                .unwrap()
+                .inner
+                .as_entire_binding(),
+        );
+    }
+
     pub fn set_object_transform(&mut self, handle: ObjectHandle, transform: AffineTransform) {
-        self.registry.get_mut(handle.0).transform = transform;
+        self.registry.get_mut(handle.0).transforms = smallvec![transform];
+    }
+
+    pub fn set_instance_transforms(&mut self, handle: ObjectHandle, transforms: Vec<AffineTransform>) {
+        self.registry.get_mut(handle.0).transforms = SmallVec::from_vec(transforms);
+    }
+
+    pub fn set_morph_weights(&mut self, handle: ObjectHandle, weights: Vec<f32>) {
+        self.registry.get_mut(handle.0).morph_weights = weights;
     }
 }
@@ -1,10 +1,10 @@
 use crate::{
-    datatypes::{DepthCompare, Pipeline, PipelineBindingType, PipelineHandle, PipelineInputType},
+    datatypes::{DepthCompare, Pipeline, PipelineBindingType, PipelineBlendMode, PipelineHandle, PipelineInputType},
     list::RenderPassRunRate,
     registry::ResourceRegistry,
     renderer::mesh::{
-        VERTEX_COLOR_SIZE, VERTEX_MATERIAL_INDEX_SIZE, VERTEX_NORMAL_SIZE, VERTEX_POSITION_SIZE, VERTEX_TANGENT_SIZE,
-        VERTEX_UV_SIZE,
+        VERTEX_COLOR_SIZE, VERTEX_JOINT_SIZE, VERTEX_MATERIAL_INDEX_SIZE, VERTEX_NORMAL_SIZE, VERTEX_POSITION_SIZE,
+        VERTEX_TANGENT_SIZE, VERTEX_UV_SIZE,
     },
     Renderer, RendererMode,
 };
@@ -12,10 +12,23 @@ use futures::{stream::FuturesUnordered, StreamExt};
 use parking_lot::RwLock;
 use std::{future::Future, sync::Arc};
 use wgpu::{
-    BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendState, ColorTargetState,
-    ColorWrite, CompareFunction, CullMode, DepthBiasState, DepthStencilState, Device, FragmentState, FrontFace,
-    MultisampleState, PipelineLayoutDescriptor, PrimitiveState, PrimitiveTopology, PushConstantRange, RenderPipeline,
-    RenderPipelineDescriptor, ShaderStage, StencilState, TextureSampleType, TextureViewDimension, VertexState,
+    BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendFactor, BlendOperation,
+    BlendState, ColorTargetState, ColorWrite, CompareFunction, CullMode, DepthBiasState, DepthStencilState, Device,
+    FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor, PrimitiveState, PrimitiveTopology,
+    PushConstantRange, RenderPipeline, RenderPipelineDescriptor, ShaderStage, StencilState, TextureSampleType,
+    TextureViewDimension, VertexState,
+};
+
+const ADDITIVE_BLEND: BlendState = BlendState {
+    src_factor: BlendFactor::One,
+    dst_factor: BlendFactor::One,
+    operation: BlendOperation::Add,
+};
+
+const INVERSE_MULTIPLY_BLEND: BlendState = BlendState {
+    src_factor: BlendFactor::Zero,
+    dst_factor: BlendFactor::OneMinusSrcColor,
+    operation: BlendOperation::Add,
 };
 
 #[derive(Debug)]
@@ -114,6 +127,7 @@ impl PipelineManager {
                         }
                         PipelineBindingType::ShadowTexture => &global_data.shadow_texture_bgl,
                         PipelineBindingType::SkyboxTexture => &global_data.skybox_bgl,
+                        PipelineBindingType::EnvironmentTextures => &global_data.environment_bgl,
                         PipelineBindingType::Custom2DTexture { .. } => custom_layout_iter.next().unwrap(),
                         PipelineBindingType::CustomCubeTexture { .. } => custom_layout_iter.next().unwrap(),
                     })
@@ -141,8 +155,26 @@ impl PipelineManager {
                     .outputs
                     .iter()
                     .map(|&attachment| ColorTargetState {
-                        alpha_blend: BlendState::REPLACE,
-                        color_blend: BlendState::REPLACE,
+                        alpha_blend: match attachment.blend {
+                            PipelineBlendMode::Replace => BlendState::REPLACE,
+                            PipelineBlendMode::AlphaBlend => BlendState {
+                                src_factor: BlendFactor::One,
+                                dst_factor: BlendFactor::OneMinusSrcAlpha,
+                                operation: BlendOperation::Add,
+                            },
+                            PipelineBlendMode::Additive => ADDITIVE_BLEND,
+                            PipelineBlendMode::InverseMultiply => INVERSE_MULTIPLY_BLEND,
+                        },
+                        color_blend: match attachment.blend {
+                            PipelineBlendMode::Replace => BlendState::REPLACE,
+                            PipelineBlendMode::AlphaBlend => BlendState {
+                                src_factor: BlendFactor::SrcAlpha,
+                                dst_factor: BlendFactor::OneMinusSrcAlpha,
+                                operation: BlendOperation::Add,
+                            },
+                            PipelineBlendMode::Additive => ADDITIVE_BLEND,
+                            PipelineBlendMode::InverseMultiply => INVERSE_MULTIPLY_BLEND,
+                        },
                         write_mask: match attachment.write {
                             true => ColorWrite::ALL,
                             false => ColorWrite::empty(),
@@ -153,25 +185,29 @@ impl PipelineManager {
 
                 let depth_state = pipeline_desc.depth.map(|state| DepthStencilState {
                     format: state.format,
-                    depth_write_enabled: true,
-                    depth_compare: match (state.compare, pipeline_desc.run_rate) {
+                    depth_write_enabled: state.write,
+                    depth_compare: match pipeline_desc.run_rate {
                         // Shadow modes
-                        (DepthCompare::Closer, RenderPassRunRate::PerShadow) => CompareFunction::Less,
-                        (DepthCompare::CloserEqual, RenderPassRunRate::PerShadow) => CompareFunction::LessEqual,
-                        (DepthCompare::Equal, RenderPassRunRate::PerShadow) => CompareFunction::Equal,
-                        (DepthCompare::Further, RenderPassRunRate::PerShadow) => CompareFunction::Greater,
-                        (DepthCompare::FurtherEqual, RenderPassRunRate::PerShadow) => CompareFunction::GreaterEqual,
+                        RenderPassRunRate::PerShadow | RenderPassRunRate::PerPointShadow => match state.compare {
+                            DepthCompare::Closer => CompareFunction::Less,
+                            DepthCompare::CloserEqual => CompareFunction::LessEqual,
+                            DepthCompare::Equal => CompareFunction::Equal,
+                            DepthCompare::Further => CompareFunction::Greater,
+                            DepthCompare::FurtherEqual => CompareFunction::GreaterEqual,
+                        },
 
                         // Forward modes
-                        (DepthCompare::Closer, RenderPassRunRate::Once) => CompareFunction::Greater,
-                        (DepthCompare::CloserEqual, RenderPassRunRate::Once) => CompareFunction::GreaterEqual,
-                        (DepthCompare::Equal, RenderPassRunRate::Once) => CompareFunction::Equal,
-                        (DepthCompare::Further, RenderPassRunRate::Once) => CompareFunction::Less,
-                        (DepthCompare::FurtherEqual, RenderPassRunRate::Once) => CompareFunction::LessEqual,
+                        RenderPassRunRate::Once => match state.compare {
+                            DepthCompare::Closer => CompareFunction::Greater,
+                            DepthCompare::CloserEqual => CompareFunction::GreaterEqual,
+                            DepthCompare::Equal => CompareFunction::Equal,
+                            DepthCompare::Further => CompareFunction::Less,
+                            DepthCompare::FurtherEqual => CompareFunction::LessEqual,
+                        },
                     },
                     stencil: StencilState::default(),
                     bias: match pipeline_desc.run_rate {
-                        RenderPassRunRate::PerShadow => DepthBiasState {
+                        RenderPassRunRate::PerShadow | RenderPassRunRate::PerPointShadow => DepthBiasState {
                             constant: 2,
                             slope_scale: 2.0,
                             clamp: 0.0,
@@ -200,7 +236,7 @@ impl PipelineManager {
                     wgpu::VertexBufferLayout {
                         array_stride: VERTEX_UV_SIZE as u64,
                         step_mode: wgpu::InputStepMode::Vertex,
-                        attributes: &wgpu::vertex_attr_array![3 => Float2],
+                        attributes: &wgpu::vertex_attr_array![3 => Float2, 9 => Float2],
                     },
                     wgpu::VertexBufferLayout {
                         array_stride: VERTEX_COLOR_SIZE as u64,
@@ -213,13 +249,14 @@ impl PipelineManager {
                         attributes: &wgpu::vertex_attr_array![5 => Uint],
                     },
                     wgpu::VertexBufferLayout {
-                        array_stride: 20,
+                        array_stride: VERTEX_JOINT_SIZE as u64,
+                        step_mode: wgpu::InputStepMode::Vertex,
+                        attributes: &wgpu::vertex_attr_array![7 => Ushort4, 8 => Float4],
+                    },
+                    wgpu::VertexBufferLayout {
+                        array_stride: 4,
                         step_mode: wgpu::InputStepMode::Instance,
-                        attributes: &[wgpu::VertexAttribute {
-                            format: wgpu::VertexFormat::Uint,
-                            offset: 16,
-                            shader_location: 6,
-                        }],
+                        attributes: &wgpu::vertex_attr_array![6 => Uint],
                     },
                 ];
 
@@ -234,7 +271,7 @@ impl PipelineManager {
                         buffers: match pipeline_desc.input {
                             PipelineInputType::FullscreenTriangle => &[],
                             PipelineInputType::Models3d => match renderer.mode {
-                                RendererMode::CPUPowered => &vertex_states[0..6],
+                                RendererMode::CPUPowered => &vertex_states[0..7],
                                 RendererMode::GPUPowered => &vertex_states,
                             },
                         },
@@ -246,7 +283,10 @@ impl PipelineManager {
                         cull_mode: match (pipeline_desc.input, pipeline_desc.run_rate) {
                             (PipelineInputType::FullscreenTriangle, _) => CullMode::None,
                             (PipelineInputType::Models3d, RenderPassRunRate::Once) => CullMode::Back,
-                            (PipelineInputType::Models3d, RenderPassRunRate::PerShadow) => CullMode::Front,
+                            (
+                                PipelineInputType::Models3d,
+                                RenderPassRunRate::PerShadow | RenderPassRunRate::PerPointShadow,
+                            ) => CullMode::Front,
                         },
                         polygon_mode: Default::default(),
                     },
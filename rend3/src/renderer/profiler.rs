@@ -0,0 +1,171 @@
+use crate::{
+    list::RenderPass,
+    statistics::{GpuFrameTimings, GpuTiming, GpuTimingSource},
+};
+use parking_lot::Mutex;
+use std::{
+    mem::size_of,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use wgpu::{
+    Buffer, BufferAddress, BufferDescriptor, BufferUsage, CommandEncoder, Device, Features, MapMode, QuerySet,
+    QuerySetDescriptor, QueryType, Queue,
+};
+
+/// Keeps track of frames and gpu timings which have finished, but haven't been handed to the user yet.
+pub struct GpuProfiler {
+    enabled: bool,
+    frame: AtomicU64,
+    finished: Arc<Mutex<Vec<GpuFrameTimings>>>,
+}
+impl GpuProfiler {
+    pub fn new(features: Features) -> Self {
+        Self {
+            enabled: features.contains(Features::TIMESTAMP_QUERY),
+            frame: AtomicU64::new(0),
+            finished: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Returns the index of the frame which is starting.
+    pub fn next_frame(&self) -> u64 {
+        self.frame.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Returns timings of all frames finished since the last call.
+    pub fn take_finished(&self) -> Vec<GpuFrameTimings> {
+        std::mem::take(&mut *self.finished.lock())
+    }
+
+    pub fn finished_handle(&self) -> Arc<Mutex<Vec<GpuFrameTimings>>> {
+        Arc::clone(&self.finished)
+    }
+
+    /// Returns `None` if timestamp queries aren't supported or there is nothing to time.
+    pub fn begin_frame(&self, device: &Device, query_count: u32) -> Option<FrameQueries> {
+        if !self.enabled || query_count == 0 {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            ty: QueryType::Timestamp,
+            count: query_count,
+        });
+
+        Some(FrameQueries {
+            query_set: Arc::new(query_set),
+            query_count,
+            labels: Vec::with_capacity(query_count as usize / 2),
+        })
+    }
+}
+
+/// Timestamp queries for a single frame. Every timing takes up two consecutive queries, begin and end.
+pub struct FrameQueries {
+    query_set: Arc<QuerySet>,
+    query_count: u32,
+    labels: Vec<(usize, GpuTimingSource)>,
+}
+impl FrameQueries {
+    pub fn query_set(&self) -> &Arc<QuerySet> {
+        &self.query_set
+    }
+
+    /// Returns the index of the begin query, the end query directly follows it.
+    pub fn allocate(&mut self, pass: usize, source: GpuTimingSource) -> u32 {
+        let index = self.labels.len() as u32 * 2;
+        assert!(
+            index + 2 <= self.query_count,
+            "Allocated more timestamp queries than reserved"
+        );
+        self.labels.push((pass, source));
+        index
+    }
+
+    /// Allocates timings for every op of a render pass. Returns the index of the first op's begin query.
+    pub fn allocate_render_pass(&mut self, pass: usize, render_pass_idx: usize, render_pass: &RenderPass) -> u32 {
+        let base = self.labels.len() as u32 * 2;
+        for (op_idx, op) in render_pass.ops.iter().enumerate() {
+            self.allocate(
+                pass,
+                GpuTimingSource::RenderOp {
+                    render_pass: render_pass_idx,
+                    op: op_idx,
+                    pipeline: op.pipeline,
+                },
+            );
+        }
+        base
+    }
+
+    /// Records the resolve of all queries. Must be recorded after all command buffers which write timestamps.
+    pub fn resolve(self, device: &Device, queue: &Queue, encoder: &mut CommandEncoder, frame: u64) -> PendingTimings {
+        let used_queries = self.labels.len() as u32 * 2;
+
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("timestamp resolve buffer"),
+            size: (used_queries as usize * size_of::<u64>()).max(size_of::<u64>()) as BufferAddress,
+            usage: BufferUsage::COPY_DST | BufferUsage::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        if used_queries != 0 {
+            encoder.resolve_query_set(&self.query_set, 0..used_queries, &buffer, 0);
+        }
+
+        PendingTimings {
+            buffer,
+            labels: self.labels,
+            frame,
+            period: queue.get_timestamp_period(),
+        }
+    }
+}
+
+/// Resolved timestamps waiting for the gpu to finish the frame.
+pub struct PendingTimings {
+    buffer: Buffer,
+    labels: Vec<(usize, GpuTimingSource)>,
+    frame: u64,
+    period: f32,
+}
+impl PendingTimings {
+    /// Waits on the readback and hands the timings to the profiler. Resolves once the device has been polled after
+    /// the frame was submitted, which generally happens during the next frame.
+    pub async fn read(self, profiler_finished: Arc<Mutex<Vec<GpuFrameTimings>>>) {
+        let slice = self.buffer.slice(..);
+        if slice.map_async(MapMode::Read).await.is_err() {
+            return;
+        }
+
+        let timings = {
+            let mapped = slice.get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&mapped);
+            let period = self.period;
+
+            self.labels
+                .into_iter()
+                .enumerate()
+                .map(|(idx, (pass, source))| {
+                    let ticks = timestamps[idx * 2 + 1].saturating_sub(timestamps[idx * 2]);
+                    GpuTiming {
+                        pass,
+                        source,
+                        duration: Duration::from_nanos((ticks as f64 * period as f64) as u64),
+                    }
+                })
+                .collect()
+        };
+
+        self.buffer.unmap();
+
+        profiler_finished.lock().push(GpuFrameTimings {
+            frame: self.frame,
+            timings,
+        });
+    }
+}
@@ -1,10 +1,13 @@
 use crate::{
     bind_merge::BindGroupBuilder,
-    datatypes::{Camera, CameraProjection},
+    datatypes::{MipmapMode, RendererTextureFormat},
     instruction::Instruction,
     list::{RenderList, RenderPassRunRate},
-    renderer::{culling, list, uniforms::WrappedUniform, util::round_to_multiple},
-    statistics::RendererStatistics,
+    renderer::{
+        culling, headless::ImageReadback, list, mipmap::MipmapGenerator, uniforms::WrappedUniform,
+        util::round_to_multiple,
+    },
+    statistics::{GpuTimingSource, PassStatistics, RendererStatistics, SpanTimer},
     OutputFrame, Renderer, RendererMode, RendererOutput,
 };
 use futures::{stream::FuturesOrdered, StreamExt};
@@ -20,7 +23,7 @@ pub fn render_loop<TLD: 'static>(
     renderer: Arc<Renderer<TLD>>,
     render_list: RenderList,
     output: RendererOutput,
-) -> impl Future<Output = RendererStatistics> {
+) -> impl Future<Output = (RendererStatistics, Option<ImageReadback>)> {
     span_transfer!(_ -> render_create_span, INFO, "Render Loop Creation");
 
     // blocks, do it before we async
@@ -31,6 +34,15 @@ pub fn render_loop<TLD: 'static>(
         let mut instructions = renderer.instructions.consumer.lock();
 
         span_transfer!(_ -> event_span, INFO, "Process events");
+        let mut timer = SpanTimer::new();
+        timer.transfer(Some("Process events"));
+
+        let mut stats = RendererStatistics {
+            frame: renderer.profiler.next_frame(),
+            instructions_processed: instructions.len(),
+            gpu_timings: renderer.profiler.take_finished(),
+            ..RendererStatistics::default()
+        };
 
         let mut encoder = renderer.device.create_command_encoder(&CommandEncoderDescriptor {
             label: Some("primary encoder"),
@@ -43,14 +55,18 @@ pub fn render_loop<TLD: 'static>(
         let mut texture_manager_cube = renderer.texture_manager_cube.write();
         let mut material_manager = renderer.material_manager.write();
         let mut object_manager = renderer.object_manager.write();
+        let mut skeleton_manager = renderer.skeleton_manager.write();
         let mut directional_light_manager = renderer.directional_light_manager.write();
+        let mut point_light_manager = renderer.point_light_manager.write();
+        let mut spot_light_manager = renderer.spot_light_manager.write();
+        let mut ibl_manager = renderer.ibl_manager.write();
         let mut global_resources = renderer.global_resources.write();
         let options = renderer.options.read();
 
         for cmd in instructions.drain(..) {
             match cmd {
                 Instruction::AddMesh { handle, mesh } => {
-                    mesh_manager.fill(
+                    stats.bytes_uploaded += mesh_manager.fill(
                         &renderer.device,
                         &renderer.queue,
                         &renderer.gpu_copy,
@@ -69,7 +85,15 @@ pub fn render_loop<TLD: 'static>(
                         depth: 1,
                     };
 
-                    assert!(texture.mip_levels > 0, "Mipmap levels must be greater than 0");
+                    let generate_mips = texture.mip_mode == MipmapMode::Generate;
+                    if generate_mips {
+                        assert!(
+                            MipmapGenerator::supports(texture.format.into()),
+                            "Mipmap generation is only supported for uncompressed formats"
+                        );
+                    } else {
+                        assert!(texture.mip_levels > 0, "Mipmap levels must be greater than 0");
+                    }
 
                     let block_width = texture.format.pixels_per_block();
                     let block_bytes = texture.format.bytes_per_block();
@@ -83,7 +107,15 @@ pub fn render_loop<TLD: 'static>(
                     let block_mips = (std::mem::size_of_val(&block_width) * 8) as u32 - block_width.leading_zeros() - 1;
                     let max_mip_levels = tex_mips - block_mips + 1;
 
-                    let mip_levels = texture.mip_levels.min(max_mip_levels);
+                    let mip_levels = match texture.mip_mode {
+                        MipmapMode::Manual => texture.mip_levels.min(max_mip_levels),
+                        MipmapMode::Generate => 32 - size.width.max(size.height).leading_zeros(),
+                    };
+                    // How many mips of each layer are in the data.
+                    let data_mips = match texture.mip_mode {
+                        MipmapMode::Manual => texture.mip_levels,
+                        MipmapMode::Generate => 1,
+                    };
 
                     let uploaded_tex = renderer.device.create_texture(&TextureDescriptor {
                         label: None,
@@ -92,12 +124,15 @@ pub fn render_loop<TLD: 'static>(
                         sample_count: 1,
                         dimension: TextureDimension::D2,
                         format: texture.format.into(),
-                        usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+                        usage: match generate_mips {
+                            true => TextureUsage::SAMPLED | TextureUsage::COPY_DST | TextureUsage::RENDER_ATTACHMENT,
+                            false => TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+                        },
                     });
 
                     let mut offset = 0;
 
-                    for mip in 0..mip_levels {
+                    for mip in 0..mip_levels.min(data_mips) {
                         // Each mip is 1/4th the size of the previous level, so our divisors are the powers of two
                         let div = 1 << mip;
 
@@ -132,10 +167,23 @@ pub fn render_loop<TLD: 'static>(
                             },
                             mip_size,
                         );
+                        stats.bytes_uploaded += bytes as u64;
 
                         offset = offset_end;
                     }
 
+                    if generate_mips {
+                        renderer.mipmap_generator.generate(
+                            &renderer.device,
+                            &mut encoder,
+                            &global_resources.nearest_sampler,
+                            &uploaded_tex,
+                            texture.format.into(),
+                            mip_levels,
+                            1,
+                        );
+                    }
+
                     texture_manager_2d.fill(
                         handle,
                         uploaded_tex.create_view(&TextureViewDescriptor::default()),
@@ -152,7 +200,15 @@ pub fn render_loop<TLD: 'static>(
                         depth: 6,
                     };
 
-                    assert!(texture.mip_levels > 0, "Mipmap levels must be greater than 0");
+                    let generate_mips = texture.mip_mode == MipmapMode::Generate;
+                    if generate_mips {
+                        assert!(
+                            MipmapGenerator::supports(texture.format.into()),
+                            "Mipmap generation is only supported for uncompressed formats"
+                        );
+                    } else {
+                        assert!(texture.mip_levels > 0, "Mipmap levels must be greater than 0");
+                    }
 
                     let block_width = texture.format.pixels_per_block();
                     let block_bytes = texture.format.bytes_per_block();
@@ -166,7 +222,15 @@ pub fn render_loop<TLD: 'static>(
                     let block_mips = (std::mem::size_of_val(&block_width) * 8) as u32 - block_width.leading_zeros() - 1;
                     let max_mip_levels = tex_mips - block_mips + 1;
 
-                    let mip_levels = texture.mip_levels.min(max_mip_levels);
+                    let mip_levels = match texture.mip_mode {
+                        MipmapMode::Manual => texture.mip_levels.min(max_mip_levels),
+                        MipmapMode::Generate => 32 - size.width.max(size.height).leading_zeros(),
+                    };
+                    // How many mips of each layer are in the data.
+                    let data_mips = match texture.mip_mode {
+                        MipmapMode::Manual => texture.mip_levels,
+                        MipmapMode::Generate => 1,
+                    };
 
                     let uploaded_tex = renderer.device.create_texture(&TextureDescriptor {
                         label: None,
@@ -175,14 +239,17 @@ pub fn render_loop<TLD: 'static>(
                         sample_count: 1,
                         dimension: TextureDimension::D2,
                         format: texture.format.into(),
-                        usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+                        usage: match generate_mips {
+                            true => TextureUsage::SAMPLED | TextureUsage::COPY_DST | TextureUsage::RENDER_ATTACHMENT,
+                            false => TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+                        },
                     });
 
                     let mut offset = 0;
 
                     for layer in 0..6 {
                         // Not 0..mip_levels, because we do want to skip over the unused mip levels
-                        for mip in 0..texture.mip_levels {
+                        for mip in 0..data_mips {
                             // Each mip is 1/4th the size of the previous level, so our divisors are the powers of two
                             let div = 1 << mip;
 
@@ -219,12 +286,25 @@ pub fn render_loop<TLD: 'static>(
                                     },
                                     mip_size,
                                 );
+                                stats.bytes_uploaded += bytes as u64;
                             }
 
                             offset = offset_end;
                         }
                     }
 
+                    if generate_mips {
+                        renderer.mipmap_generator.generate(
+                            &renderer.device,
+                            &mut encoder,
+                            &global_resources.nearest_sampler,
+                            &uploaded_tex,
+                            texture.format.into(),
+                            mip_levels,
+                            6,
+                        );
+                    }
+
                     texture_manager_cube.fill(
                         handle,
                         uploaded_tex.create_view(&TextureViewDescriptor {
@@ -240,6 +320,23 @@ pub fn render_loop<TLD: 'static>(
                         Some(texture.format),
                     );
                 }
+                Instruction::AddTextureCubeFromEquirectangular {
+                    handle,
+                    texture,
+                    resolution,
+                } => {
+                    let (view, bytes) = renderer.equirect_converter.convert(
+                        &renderer.device,
+                        &renderer.queue,
+                        &mut encoder,
+                        &global_resources.nearest_sampler,
+                        &texture,
+                        resolution,
+                    );
+                    stats.bytes_uploaded += bytes;
+
+                    texture_manager_cube.fill(handle, view, Some(RendererTextureFormat::Rgba16Float));
+                }
                 Instruction::RemoveTextureCube { handle } => {
                     texture_manager_cube.remove(handle);
                 }
@@ -254,41 +351,69 @@ pub fn render_loop<TLD: 'static>(
                     );
                 }
                 Instruction::ChangeMaterial { handle, change } => {
-                    material_manager.update_from_changes(&renderer.queue, handle, change);
+                    material_manager.update_from_changes(
+                        &renderer.device,
+                        &renderer.queue,
+                        &mut texture_manager_2d,
+                        &global_resources.material_bgl,
+                        handle,
+                        change,
+                    );
                 }
                 Instruction::RemoveMaterial { handle } => {
-                    material_manager.remove(handle);
+                    material_manager.remove(&renderer.device, handle);
                 }
                 Instruction::AddObject { handle, object } => {
                     object_manager.fill(handle, object, &mesh_manager);
                 }
+                Instruction::AddInstancedObject { handle, object } => {
+                    object_manager.fill_instanced(handle, object, &mesh_manager);
+                }
                 Instruction::SetObjectTransform {
                     handle: object,
                     transform,
                 } => {
                     object_manager.set_object_transform(object, transform);
                 }
+                Instruction::SetInstanceTransforms { handle, transforms } => {
+                    object_manager.set_instance_transforms(handle, transforms);
+                }
+                Instruction::SetObjectMorphWeights { handle, weights } => {
+                    object_manager.set_morph_weights(handle, weights);
+                }
                 Instruction::RemoveObject { handle } => {
                     object_manager.remove(handle);
                 }
+                Instruction::AddSkeleton { handle, skeleton } => {
+                    skeleton_manager.fill(handle, skeleton);
+                }
+                Instruction::SetSkeletonJointMatrices { handle, joint_matrices } => {
+                    skeleton_manager.set_joint_matrices(handle, joint_matrices);
+                }
+                Instruction::RemoveSkeleton { handle } => {
+                    skeleton_manager.remove(handle);
+                }
                 Instruction::AddDirectionalLight { handle, light } => {
                     directional_light_manager.fill(handle, light);
                 }
                 Instruction::ChangeDirectionalLight { handle, change } => {
-                    // TODO: Move these inside the managers
-                    let value = directional_light_manager.get_mut(handle);
-                    value.inner.update_from_changes(change);
-                    if let Some(direction) = change.direction {
-                        value.camera.set_data(
-                            Camera {
-                                projection: CameraProjection::from_orthographic_direction(direction.into()),
-                                ..Camera::default()
-                            },
-                            None,
-                        );
-                    }
+                    directional_light_manager.update_from_changes(handle, change);
                 }
                 Instruction::RemoveDirectionalLight { handle } => directional_light_manager.remove(handle),
+                Instruction::AddPointLight { handle, light } => {
+                    point_light_manager.fill(handle, light);
+                }
+                Instruction::ChangePointLight { handle, change } => {
+                    point_light_manager.update_from_changes(handle, change);
+                }
+                Instruction::RemovePointLight { handle } => point_light_manager.remove(handle),
+                Instruction::AddSpotLight { handle, light } => {
+                    spot_light_manager.fill(handle, light);
+                }
+                Instruction::ChangeSpotLight { handle, change } => {
+                    spot_light_manager.update_from_changes(handle, change);
+                }
+                Instruction::RemoveSpotLight { handle } => spot_light_manager.remove(handle),
                 Instruction::AddBinaryShader { handle, shader } => {
                     let module = renderer.device.create_shader_module(&ShaderModuleDescriptor {
                         label: None,
@@ -313,6 +438,12 @@ pub fn render_loop<TLD: 'static>(
                 Instruction::ClearBackgroundTexture => {
                     global_resources.background_texture = None;
                 }
+                Instruction::SetEnvironmentTexture { handle } => {
+                    global_resources.environment_texture = Some(handle);
+                }
+                Instruction::ClearEnvironmentTexture => {
+                    global_resources.environment_texture = None;
+                }
             }
         }
 
@@ -324,6 +455,16 @@ pub fn render_loop<TLD: 'static>(
         let texture_2d_ready = texture_manager_2d.ready(&renderer.device);
         let texture_cube_ready = texture_manager_cube.ready(&renderer.device);
 
+        ibl_manager.ready(
+            &renderer.device,
+            &mut encoder,
+            &texture_manager_cube,
+            global_resources
+                .environment_texture
+                .or(global_resources.background_texture),
+            &global_resources.linear_sampler,
+        );
+
         let recompile_future = if renderer.mode == RendererMode::GPUPowered {
             Some(renderer.pipeline_manager.recompile_pipelines(
                 &renderer,
@@ -334,9 +475,31 @@ pub fn render_loop<TLD: 'static>(
             None
         };
 
-        material_manager.ready(&renderer.device, &mut encoder, &texture_manager_2d);
-        let object_count = object_manager.ready(&renderer.device, &mut encoder, &material_manager);
-        directional_light_manager.ready(&renderer.device, &mut encoder);
+        stats.bytes_uploaded += material_manager.ready(&renderer.device, &mut encoder, &texture_manager_2d);
+        stats.bytes_uploaded += skeleton_manager.ready(&renderer.device, &mut encoder);
+        let object_ready = object_manager.ready(&renderer.device, &mut encoder, &material_manager, &skeleton_manager);
+        let instance_count = object_ready.instance_count;
+        stats.bytes_uploaded += object_ready.bytes_uploaded;
+        stats.bytes_uploaded += directional_light_manager.ready(
+            &renderer.device,
+            &mut encoder,
+            &global_resources.camera,
+            &options.shadow_cascades,
+        );
+        stats.bytes_uploaded += point_light_manager.ready(&renderer.device, &mut encoder);
+        stats.bytes_uploaded += spot_light_manager.ready(&renderer.device, &mut encoder);
+
+        stats.mesh_count = mesh_manager.count();
+        stats.texture_2d_count = texture_manager_2d.count();
+        stats.texture_cube_count = texture_manager_cube.count();
+        stats.material_count = material_manager.count();
+        stats.object_count = object_ready.object_count;
+        stats.instance_count = instance_count;
+        stats.directional_light_count = directional_light_manager.count();
+        stats.point_light_count = point_light_manager.count();
+        stats.spot_light_count = spot_light_manager.count();
+        stats.skeleton_count = skeleton_manager.count();
+        stats.buffer_reallocations = mesh_manager.take_reallocation_count();
 
         let object_input_bg = renderer.mode.into_data(
             || (),
@@ -362,6 +525,8 @@ pub fn render_loop<TLD: 'static>(
 
         let mut shadow_bgb = BindGroupBuilder::new(Some(String::from("shadow bg")));
         directional_light_manager.append_to_bgb(&mut shadow_bgb);
+        point_light_manager.append_to_bgb(&mut shadow_bgb);
+        spot_light_manager.append_to_bgb(&mut shadow_bgb);
         let shadow_bg = shadow_bgb.build(&renderer.device, &global_resources.shadow_texture_bgl);
 
         let skybox_texture_view = if let Some(ref sky) = global_resources.background_texture {
@@ -374,6 +539,10 @@ pub fn render_loop<TLD: 'static>(
         skybox_bgb.append(BindingResource::TextureView(skybox_texture_view));
         let skybox_bg = skybox_bgb.build(&renderer.device, &global_resources.skybox_bgl);
 
+        let mut environment_bgb = BindGroupBuilder::new(Some(String::from("environment bg")));
+        ibl_manager.append_to_bgb(&mut environment_bgb);
+        let environment_bg = environment_bgb.build(&renderer.device, &global_resources.environment_bgl);
+
         drop((
             options,
             mesh_manager,
@@ -381,10 +550,15 @@ pub fn render_loop<TLD: 'static>(
             texture_manager_cube,
             material_manager,
             object_manager,
+            skeleton_manager,
             directional_light_manager,
+            point_light_manager,
+            spot_light_manager,
+            ibl_manager,
         ));
 
         span_transfer!(event_span -> resource_update_span, INFO, "Update resources");
+        timer.transfer(Some("Update resources"));
 
         let options = if let Some(new_opt) = new_options {
             let mut option_guard = renderer.options.write();
@@ -401,46 +575,141 @@ pub fn render_loop<TLD: 'static>(
         }
 
         let global_resources = renderer.global_resources.read();
+        let mesh_manager = renderer.mesh_manager.read();
         let object_manager = renderer.object_manager.read();
+        let skeleton_manager = renderer.skeleton_manager.read();
         let directional_light_manager = renderer.directional_light_manager.read();
+        let point_light_manager = renderer.point_light_manager.read();
+        let spot_light_manager = renderer.spot_light_manager.read();
+
+        // Two timestamps for every culling pass and every render op
+        let culling_timings = match renderer.mode {
+            RendererMode::CPUPowered => 0,
+            RendererMode::GPUPowered => 1,
+        };
+        let op_count = |run_rate| {
+            render_list
+                .passes
+                .iter()
+                .filter(|p| p.desc.run_rate == run_rate)
+                .map(|p| p.ops.len())
+                .sum::<usize>()
+        };
+        let cascade_count: usize = directional_light_manager
+            .values()
+            .map(|light| light.cascades.len())
+            .sum();
+        let timing_count = (cascade_count + spot_light_manager.count())
+            * (culling_timings + op_count(RenderPassRunRate::PerShadow))
+            + point_light_manager.count() * 6 * (culling_timings + op_count(RenderPassRunRate::PerPointShadow))
+            + culling_timings
+            + op_count(RenderPassRunRate::Once);
+        let mut frame_queries = renderer.profiler.begin_frame(&renderer.device, timing_count as u32 * 2);
 
         let mut command_buffer_futures = FuturesOrdered::new();
+        // Index into stats.passes for every command buffer future, in the same order
+        let mut command_buffer_passes = Vec::new();
+
+        // Every shadow map that needs rendering: pass name, camera, which passes render it, the output view and the
+        // part of it which is used.
+        let directional_light_manager_ref = &*directional_light_manager;
+        let point_light_manager_ref = &*point_light_manager;
+        let shadow_views: Vec<_> = directional_light_manager
+            .values()
+            .enumerate()
+            .flat_map(|(light_idx, light)| {
+                light.cascades.iter().enumerate().map(move |(cascade, camera)| {
+                    (
+                        format!("shadow pass {} cascade {}", light_idx, cascade),
+                        *camera,
+                        RenderPassRunRate::PerShadow,
+                        directional_light_manager_ref.get_layer_view_arc(light.shadow_tex + cascade as u32),
+                        Some(light.inner.shadow_resolution),
+                    )
+                })
+            })
+            .chain(spot_light_manager.values().enumerate().map(|(light_idx, light)| {
+                (
+                    format!("spot shadow pass {}", light_idx),
+                    light.camera,
+                    RenderPassRunRate::PerShadow,
+                    spot_light_manager.get_layer_view_arc(light.shadow_tex),
+                    None,
+                )
+            }))
+            .chain(point_light_manager.values().enumerate().flat_map(|(light_idx, light)| {
+                light.cameras.iter().enumerate().map(move |(face, camera)| {
+                    (
+                        format!("point shadow pass {} face {}", light_idx, face),
+                        *camera,
+                        RenderPassRunRate::PerPointShadow,
+                        point_light_manager_ref.get_face_view_arc(light.shadow_tex, face),
+                        None,
+                    )
+                })
+            }))
+            .collect();
+
+        for (name, camera, run_rate, output, viewport) in shadow_views {
+            let pass_idx = stats.passes.len();
+            stats.passes.push(PassStatistics {
+                name,
+                objects_submitted: instance_count,
+                ..PassStatistics::default()
+            });
 
-        for light in directional_light_manager.values() {
             let mut cull_data = renderer.culling_pass.prepare(culling::CullingPassPrepareArgs {
                 device: &renderer.device,
                 mode: renderer.mode,
                 prefix_sum_bgl: &global_resources.prefix_sum_bgl,
                 pre_cull_bgl: &global_resources.pre_cull_bgl,
                 output_bgl: &global_resources.object_output_bgl,
-                object_count: object_count as _,
+                transparent_sort_bgl: &global_resources.transparent_sort_bgl,
+                object_count: instance_count as _,
                 name: String::from("shadow pass"),
+                sort_transparent: false,
             });
 
             let mut object_bgb = BindGroupBuilder::new(Some(String::from("object bg")));
             object_bgb.append(cull_data.output_buffer.as_entire_binding());
+            skeleton_manager.append_to_bgb(&mut object_bgb);
+            mesh_manager.append_to_bgb(&mut object_bgb);
+            object_manager.append_morph_weights_to_bgb(&mut object_bgb);
             let object_bg = object_bgb.build(&renderer.device, &global_resources.object_data_bgl);
 
             let uniform = WrappedUniform::new(&renderer.device, &global_resources.camera_data_bgl);
-            uniform.upload(&renderer.queue, &light.camera, options.ambient);
+            uniform.upload(&renderer.queue, &camera, options.ambient, options.shadow_filter);
 
             match renderer.mode {
                 RendererMode::CPUPowered => {
                     renderer
                         .culling_pass
-                        .cpu_run(
-                            &renderer.yard,
-                            renderer.yard_priorites,
-                            &renderer.queue,
-                            &object_manager,
-                            &mut cull_data,
-                            light.camera,
-                        )
+                        .cpu_run(&renderer, &object_manager, &mut cull_data, camera)
                         .await;
+                    stats.passes[pass_idx].objects_culled = Some(
+                        instance_count
+                            - cull_data
+                                .inner
+                                .as_cpu()
+                                .iter()
+                                .map(|call| call.instance_count as usize)
+                                .sum::<usize>(),
+                    );
                 }
                 RendererMode::GPUPowered => {
+                    let timestamp = frame_queries.as_mut().map(|queries| {
+                        (
+                            Arc::clone(queries.query_set()),
+                            queries.allocate(pass_idx, GpuTimingSource::Culling),
+                        )
+                    });
+
                     let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
 
+                    if let Some((ref query_set, idx)) = timestamp {
+                        cpass.write_timestamp(query_set, idx);
+                    }
+
                     renderer.culling_pass.gpu_run(
                         &mut cpass,
                         object_input_bg.as_gpu(),
@@ -448,6 +717,10 @@ pub fn render_loop<TLD: 'static>(
                         &cull_data,
                     );
 
+                    if let Some((ref query_set, idx)) = timestamp {
+                        cpass.write_timestamp(query_set, idx + 1);
+                    }
+
                     drop(cpass);
                 }
             }
@@ -460,76 +733,121 @@ pub fn render_loop<TLD: 'static>(
                 gpu_cube_textures_bg: texture_cube_ready.bg.as_ref().map(|_| (), Arc::clone),
                 shadow_texture_bg: Arc::clone(&shadow_bg),
                 skybox_texture_bg: Arc::clone(&skybox_bg),
+                environment_bg: Arc::clone(&environment_bg),
                 wrapped_uniform: Arc::new(uniform),
             };
 
             let cull_data_arc = Arc::new(cull_data);
 
-            for render_pass in &render_list.passes {
-                if render_pass.desc.run_rate != RenderPassRunRate::PerShadow {
+            for (render_pass_idx, render_pass) in render_list.passes.iter().enumerate() {
+                if render_pass.desc.run_rate != run_rate {
                     continue;
                 }
 
-                let output = directional_light_manager.get_layer_view_arc(light.shadow_tex);
+                let timestamps = frame_queries.as_mut().map(|queries| {
+                    (
+                        Arc::clone(queries.query_set()),
+                        queries.allocate_render_pass(pass_idx, render_pass_idx, render_pass),
+                    )
+                });
 
+                command_buffer_passes.push(pass_idx);
                 command_buffer_futures.push(renderer.yard.spawn(
                     renderer.yard_priorites.compute_pool,
                     renderer.yard_priorites.render_record_priority,
                     list::render_single_render_pass(
                         Arc::clone(&renderer),
                         render_pass.clone(),
-                        OutputFrame::View(output),
+                        OutputFrame::View(Arc::clone(&output)),
+                        viewport,
                         Arc::clone(&cull_data_arc),
                         binding_data.clone(),
+                        timestamps,
                     ),
                 ));
             }
         }
 
-        drop(directional_light_manager);
+        drop((directional_light_manager, point_light_manager, spot_light_manager));
 
         // In wgpu 0.6, get_current_frame erroneously requires &mut
         drop(global_resources);
 
-        let frame = output.acquire(&mut renderer.global_resources.write().swapchain);
+        let headless = matches!(output, RendererOutput::Headless);
+
+        let frame = {
+            let mut global_resources = renderer.global_resources.write();
+            let global_resources = &mut *global_resources;
+            output.acquire(&mut global_resources.swapchain, global_resources.headless.as_ref())
+        };
 
         let global_resources = renderer.global_resources.read();
 
         {
+            let pass_idx = stats.passes.len();
+            stats.passes.push(PassStatistics {
+                name: String::from("camera pass"),
+                objects_submitted: instance_count,
+                ..PassStatistics::default()
+            });
+
             let mut cull_data = renderer.culling_pass.prepare(culling::CullingPassPrepareArgs {
                 device: &renderer.device,
                 mode: renderer.mode,
                 prefix_sum_bgl: &global_resources.prefix_sum_bgl,
                 pre_cull_bgl: &global_resources.pre_cull_bgl,
                 output_bgl: &global_resources.object_output_bgl,
-                object_count: object_count as _,
+                transparent_sort_bgl: &global_resources.transparent_sort_bgl,
+                object_count: instance_count as _,
                 name: String::from("camera pass"),
+                sort_transparent: true,
             });
 
             let mut object_bgb = BindGroupBuilder::new(Some(String::from("object bg")));
             object_bgb.append(cull_data.output_buffer.as_entire_binding());
+            skeleton_manager.append_to_bgb(&mut object_bgb);
+            mesh_manager.append_to_bgb(&mut object_bgb);
+            object_manager.append_morph_weights_to_bgb(&mut object_bgb);
             let object_bg = object_bgb.build(&renderer.device, &global_resources.object_data_bgl);
 
             let uniform = WrappedUniform::new(&renderer.device, &global_resources.camera_data_bgl);
-            uniform.upload(&renderer.queue, &global_resources.camera, options.ambient);
+            uniform.upload(
+                &renderer.queue,
+                &global_resources.camera,
+                options.ambient,
+                options.shadow_filter,
+            );
 
             match renderer.mode {
                 RendererMode::CPUPowered => {
                     renderer
                         .culling_pass
-                        .cpu_run(
-                            &renderer.yard,
-                            renderer.yard_priorites,
-                            &renderer.queue,
-                            &object_manager,
-                            &mut cull_data,
-                            global_resources.camera,
-                        )
+                        .cpu_run(&renderer, &object_manager, &mut cull_data, global_resources.camera)
                         .await;
+                    stats.passes[pass_idx].objects_culled = Some(
+                        instance_count
+                            - cull_data
+                                .inner
+                                .as_cpu()
+                                .iter()
+                                .map(|call| call.instance_count as usize)
+                                .sum::<usize>(),
+                    );
                 }
                 RendererMode::GPUPowered => {
+                    let timestamp = frame_queries.as_mut().map(|queries| {
+                        (
+                            Arc::clone(queries.query_set()),
+                            queries.allocate(pass_idx, GpuTimingSource::Culling),
+                        )
+                    });
+
                     let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
 
+                    if let Some((ref query_set, idx)) = timestamp {
+                        cpass.write_timestamp(query_set, idx);
+                    }
+
                     renderer.culling_pass.gpu_run(
                         &mut cpass,
                         object_input_bg.as_gpu(),
@@ -537,6 +855,10 @@ pub fn render_loop<TLD: 'static>(
                         &cull_data,
                     );
 
+                    if let Some((ref query_set, idx)) = timestamp {
+                        cpass.write_timestamp(query_set, idx + 1);
+                    }
+
                     drop(cpass);
                 }
             }
@@ -549,16 +871,25 @@ pub fn render_loop<TLD: 'static>(
                 gpu_cube_textures_bg: texture_cube_ready.bg.as_ref().map(|_| (), Arc::clone),
                 shadow_texture_bg: Arc::clone(&shadow_bg),
                 skybox_texture_bg: Arc::clone(&skybox_bg),
+                environment_bg: Arc::clone(&environment_bg),
                 wrapped_uniform: Arc::new(uniform),
             };
 
             let cull_data_arc = Arc::new(cull_data);
 
-            for render_pass in &render_list.passes {
+            for (render_pass_idx, render_pass) in render_list.passes.iter().enumerate() {
                 if render_pass.desc.run_rate != RenderPassRunRate::Once {
                     continue;
                 }
 
+                let timestamps = frame_queries.as_mut().map(|queries| {
+                    (
+                        Arc::clone(queries.query_set()),
+                        queries.allocate_render_pass(pass_idx, render_pass_idx, render_pass),
+                    )
+                });
+
+                command_buffer_passes.push(pass_idx);
                 command_buffer_futures.push(renderer.yard.spawn(
                     renderer.yard_priorites.compute_pool,
                     renderer.yard_priorites.render_record_priority,
@@ -566,29 +897,71 @@ pub fn render_loop<TLD: 'static>(
                         Arc::clone(&renderer),
                         render_pass.clone(),
                         frame.clone(),
+                        None,
                         Arc::clone(&cull_data_arc),
                         binding_data.clone(),
+                        timestamps,
                     ),
                 ));
             }
         }
 
-        drop((object_manager, global_resources));
+        drop((mesh_manager, object_manager, skeleton_manager, global_resources));
 
         span_transfer!(resource_update_span -> _);
+        timer.transfer(None);
 
         let mut command_buffers = vec![encoder.finish()];
 
-        while let Some(buffer) = command_buffer_futures.next().await {
+        let mut command_buffer_pass_iter = command_buffer_passes.into_iter();
+        while let Some((buffer, draw_calls)) = command_buffer_futures.next().await {
+            let pass_idx = command_buffer_pass_iter.next().unwrap();
+            stats.passes[pass_idx].draw_calls += draw_calls;
             command_buffers.push(buffer);
         }
 
+        let readback = if headless {
+            let mut readback_encoder = renderer.device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("headless readback encoder"),
+            });
+            let readback = renderer
+                .global_resources
+                .read()
+                .headless
+                .as_ref()
+                .expect("Must setup renderer as headless in order to use headless output")
+                .readback(&renderer.device, &mut readback_encoder);
+            command_buffers.push(readback_encoder.finish());
+            Some(readback)
+        } else {
+            None
+        };
+
+        let pending_timings = frame_queries.map(|queries| {
+            let mut resolve_encoder = renderer.device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("timestamp resolve encoder"),
+            });
+            let pending = queries.resolve(&renderer.device, &renderer.queue, &mut resolve_encoder, stats.frame);
+            command_buffers.push(resolve_encoder.finish());
+            pending
+        });
+
         span_transfer!(_ -> queue_submit_span, INFO, "Submitting to Queue");
+        timer.transfer(Some("Submitting to Queue"));
 
         renderer.device.poll(Maintain::Wait);
         renderer.queue.submit(command_buffers);
 
         span_transfer!(queue_submit_span -> buffer_pump_span, INFO, "Pumping Buffers");
+        timer.transfer(Some("Pumping Buffers"));
+
+        if let Some(pending) = pending_timings {
+            renderer.yard.spawn(
+                renderer.yard_priorites.compute_pool,
+                renderer.yard_priorites.buffer_recall_priority,
+                pending.read(renderer.profiler.finished_handle()),
+            );
+        }
 
         let futures = renderer.buffer_manager.lock().pump();
         for future in futures {
@@ -601,11 +974,14 @@ pub fn render_loop<TLD: 'static>(
         }
 
         span_transfer!(buffer_pump_span -> present_span, INFO, "Presenting");
+        timer.transfer(Some("Presenting"));
         drop(frame); //
 
         span_transfer!(present_span -> drop_span, INFO, "Dropping loop data");
 
-        RendererStatistics {}
+        stats.timings = timer.finish();
+
+        (stats, readback)
     }
     .instrument(render_loop_span)
 }
@@ -1,21 +1,24 @@
 use crate::{
     bind_merge::BindGroupBuilder,
     datatypes::{Camera, TextureHandle},
-    renderer::{camera::CameraManager, util, util::SamplerType},
+    renderer::{camera::CameraManager, headless::HeadlessTarget, util, util::SamplerType},
     RendererMode, RendererOptions,
 };
 use wgpu::{BindGroupLayout, BindingResource, Device, Sampler, Surface, SwapChain};
 
 pub struct RendererGlobalResources {
     pub swapchain: Option<SwapChain>,
+    pub headless: Option<HeadlessTarget>,
 
     pub camera: CameraManager,
     pub background_texture: Option<TextureHandle>,
+    pub environment_texture: Option<TextureHandle>,
 
     pub prefix_sum_bgl: BindGroupLayout,
     pub object_input_bgl: BindGroupLayout,
     pub object_output_bgl: BindGroupLayout,
     pub pre_cull_bgl: BindGroupLayout,
+    pub transparent_sort_bgl: BindGroupLayout,
 
     pub general_bgl: BindGroupLayout,
     pub object_data_bgl: BindGroupLayout,
@@ -23,14 +26,26 @@ pub struct RendererGlobalResources {
     pub camera_data_bgl: BindGroupLayout,
     pub shadow_texture_bgl: BindGroupLayout,
     pub skybox_bgl: BindGroupLayout,
+    pub environment_bgl: BindGroupLayout,
 
     pub linear_sampler: Sampler,
     pub nearest_sampler: Sampler,
     pub shadow_sampler: Sampler,
 }
 impl RendererGlobalResources {
-    pub fn new(device: &Device, surface: Option<&Surface>, mode: RendererMode, options: &RendererOptions) -> Self {
+    pub fn new(
+        device: &Device,
+        surface: Option<&Surface>,
+        headless: bool,
+        mode: RendererMode,
+        options: &RendererOptions,
+    ) -> Self {
         let swapchain = surface.map(|surface| util::create_swapchain(device, surface, options.size, options.vsync));
+        let headless = if headless {
+            Some(HeadlessTarget::new(device, options.size))
+        } else {
+            None
+        };
 
         let camera = CameraManager::new(Camera::default(), Some(options.aspect_ratio()));
 
@@ -38,6 +53,7 @@ impl RendererGlobalResources {
         let pre_cull_bgl = util::create_pre_cull_bgl(device);
         let object_input_bgl = util::create_object_input_bgl(device);
         let object_output_bgl = util::create_object_output_bgl(device);
+        let transparent_sort_bgl = util::create_transparent_sort_bgl(device);
 
         let general_bgl = util::create_general_bind_group_layout(device);
         let object_data_bgl = util::create_object_data_bgl(device);
@@ -45,17 +61,21 @@ impl RendererGlobalResources {
         let camera_data_bgl = util::create_camera_data_bgl(device);
         let shadow_texture_bgl = util::create_shadow_texture_bgl(device);
         let skybox_bgl = util::create_skybox_bgl(device);
+        let environment_bgl = util::create_environment_bgl(device);
 
         let linear_sampler = util::create_sampler(device, SamplerType::Linear);
         let nearest_sampler = util::create_sampler(device, SamplerType::Nearest);
-        let shadow_sampler = util::create_sampler(device, SamplerType::Shadow);
+        let shadow_sampler = util::create_sampler(device, SamplerType::Shadow(options.shadow_filter));
 
         Self {
             swapchain,
+            headless,
             camera,
             background_texture: None,
+            environment_texture: None,
             prefix_sum_bgl,
             pre_cull_bgl,
+            transparent_sort_bgl,
             general_bgl,
             object_input_bgl,
             object_output_bgl,
@@ -64,6 +84,7 @@ impl RendererGlobalResources {
             camera_data_bgl,
             shadow_texture_bgl,
             skybox_bgl,
+            environment_bgl,
             linear_sampler,
             nearest_sampler,
             shadow_sampler,
@@ -82,10 +103,16 @@ impl RendererGlobalResources {
         if dirty.contains(DirtyResources::SWAPCHAIN) {
             self.swapchain =
                 surface.map(|surface| util::create_swapchain(device, surface, new_options.size, new_options.vsync));
+            if self.headless.is_some() {
+                self.headless = Some(HeadlessTarget::new(device, new_options.size));
+            }
         }
         if dirty.contains(DirtyResources::CAMERA) {
             self.camera.set_aspect_ratio(Some(new_options.aspect_ratio()));
         }
+        if dirty.contains(DirtyResources::SHADOW_SAMPLER) {
+            self.shadow_sampler = util::create_sampler(device, SamplerType::Shadow(new_options.shadow_filter));
+        }
 
         *old_options = new_options
     }
@@ -101,6 +128,7 @@ bitflags::bitflags! {
     struct DirtyResources: u8 {
         const SWAPCHAIN = 0x01;
         const CAMERA = 0x02;
+        const SHADOW_SAMPLER = 0x04;
     }
 }
 
@@ -116,5 +144,9 @@ fn determine_dirty(current: &RendererOptions, new: &RendererOptions) -> DirtyRes
         dirty |= DirtyResources::SWAPCHAIN;
     }
 
+    if current.shadow_filter != new.shadow_filter {
+        dirty |= DirtyResources::SHADOW_SAMPLER;
+    }
+
     dirty
 }
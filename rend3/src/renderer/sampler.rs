@@ -0,0 +1,103 @@
+use crate::{bind_merge::BindGroupBuilder, datatypes::SamplerOptions, renderer::util, RendererMode};
+use fnv::FnvHashMap;
+use wgpu::{BindingResource, Device, Sampler};
+
+/// Size of the sampler table of gpu mode. If you change this, change the bindings and the switch over them in
+/// material_sampler.glsl, and the docs of SamplerOptions and Renderer::add_material.
+pub const MAX_GPU_SAMPLERS: usize = 8;
+
+struct SamplerSlot {
+    options: SamplerOptions,
+    sampler: Sampler,
+    /// Amount of materials using the sampler.
+    refs: usize,
+}
+
+/// Samplers of materials, shared by every material with the same options. Index 0 is always the default sampler, which
+/// the manager holds a reference to so it is never freed. Other samplers are freed when the last material using them
+/// is removed or changed, making their index available to new options.
+pub struct SamplerManager {
+    mode: RendererMode,
+    slots: Vec<Option<SamplerSlot>>,
+    indices: FnvHashMap<SamplerOptions, usize>,
+}
+
+impl SamplerManager {
+    pub fn new(device: &Device, mode: RendererMode) -> Self {
+        span_transfer!(_ -> new_span, INFO, "Creating Sampler Manager");
+
+        let mut manager = Self {
+            mode,
+            slots: Vec::new(),
+            indices: FnvHashMap::default(),
+        };
+        // Never released, keeping the default sampler at index 0.
+        manager.acquire(device, SamplerOptions::default());
+        manager
+    }
+
+    /// Index of the sampler with the given options, creating it if it doesn't exist yet. Every index returned must be
+    /// given back to [`release`](Self::release) once it is no longer used.
+    ///
+    /// Returns None in gpu mode if the sampler table is full. Try again once [`release`](Self::release) frees a slot.
+    pub fn acquire(&mut self, device: &Device, options: SamplerOptions) -> Option<usize> {
+        if let Some(&index) = self.indices.get(&options) {
+            self.slots[index].as_mut().unwrap().refs += 1;
+            return Some(index);
+        }
+
+        let free_slot = self.slots.iter().position(Option::is_none);
+        if self.mode == RendererMode::GPUPowered && free_slot.is_none() && self.slots.len() == MAX_GPU_SAMPLERS {
+            return None;
+        }
+
+        let slot = SamplerSlot {
+            options,
+            sampler: util::create_material_sampler(device, options),
+            refs: 1,
+        };
+        let index = match free_slot {
+            Some(index) => {
+                self.slots[index] = Some(slot);
+                index
+            }
+            None => {
+                self.slots.push(Some(slot));
+                self.slots.len() - 1
+            }
+        };
+        self.indices.insert(options, index);
+        Some(index)
+    }
+
+    /// Releases an index returned by [`acquire`](Self::acquire), freeing the sampler if nothing else uses it. Returns
+    /// if a slot was freed.
+    pub fn release(&mut self, index: usize) -> bool {
+        let slot = self.slots[index]
+            .as_mut()
+            .expect("Released a sampler which was already freed");
+        slot.refs -= 1;
+        if slot.refs == 0 {
+            self.indices.remove(&slot.options);
+            self.slots[index] = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn get(&self, index: usize) -> &Sampler {
+        &self.slots[index].as_ref().unwrap().sampler
+    }
+
+    /// Appends the whole sampler table, filling unused slots with the default sampler.
+    pub fn gpu_append_to_bgb<'a>(&'a self, material_bgb: &mut BindGroupBuilder<'a>) {
+        for index in 0..MAX_GPU_SAMPLERS {
+            let sampler = match self.slots.get(index) {
+                Some(Some(slot)) => &slot.sampler,
+                _ => self.get(0),
+            };
+            material_bgb.append(BindingResource::Sampler(sampler));
+        }
+    }
+}
@@ -3,16 +3,21 @@ use crate::{
     renderer::{
         copy::GpuCopy,
         culling,
+        equirect::EquirectConverter,
+        ibl::IblManager,
         info::ExtendedAdapterInfo,
-        light::DirectionalLightManager,
+        light::{DirectionalLightManager, PointLightManager, SpotLightManager},
         limits::{check_features, check_limits},
         list::RenderListCache,
         material::MaterialManager,
         mesh::MeshManager,
+        mipmap::MipmapGenerator,
         object::ObjectManager,
         pipeline::PipelineManager,
+        profiler::GpuProfiler,
         resources::RendererGlobalResources,
         shaders::ShaderManager,
+        skeleton::SkeletonManager,
         texture::{TextureManager, STARTING_2D_TEXTURES, STARTING_CUBE_TEXTURES},
     },
     JobPriorities, Renderer, RendererBuilder, RendererInitializationError, RendererMode,
@@ -208,6 +213,7 @@ pub async fn create_renderer<W: HasRawWindowHandle, TLD: 'static>(
     let mut global_resources = RwLock::new(RendererGlobalResources::new(
         &device,
         surface.as_ref(),
+        builder.headless,
         mode,
         &builder.options,
     ));
@@ -215,6 +221,12 @@ pub async fn create_renderer<W: HasRawWindowHandle, TLD: 'static>(
 
     let gpu_copy = GpuCopy::new(&device, &shader_manager, adapter_info.subgroup_size());
 
+    let equirect_converter = EquirectConverter::new(&device, &shader_manager);
+
+    let mipmap_generator = MipmapGenerator::new(&device, &shader_manager);
+
+    let ibl_manager = IblManager::new(&device, &queue, &shader_manager);
+
     let culling_pass = culling::CullingPass::new(
         &device,
         culling::CullingPassCreationArgs {
@@ -224,6 +236,7 @@ pub async fn create_renderer<W: HasRawWindowHandle, TLD: 'static>(
             pre_cull_bgl: &global_resource_guard.pre_cull_bgl,
             object_input_bgl: &global_resource_guard.object_input_bgl,
             output_bgl: &global_resource_guard.object_output_bgl,
+            transparent_sort_bgl: &global_resource_guard.transparent_sort_bgl,
             uniform_bgl: &global_resource_guard.camera_data_bgl,
             subgroup_size: adapter_info.subgroup_size(),
         },
@@ -250,7 +263,10 @@ pub async fn create_renderer<W: HasRawWindowHandle, TLD: 'static>(
     let mesh_manager = RwLock::new(MeshManager::new(&device));
     let material_manager = RwLock::new(MaterialManager::new(&device, mode, buffer_manager.get_mut()));
     let object_manager = RwLock::new(ObjectManager::new(&device, mode, buffer_manager.get_mut()));
+    let skeleton_manager = RwLock::new(SkeletonManager::new(&device, buffer_manager.get_mut()));
     let directional_light_manager = RwLock::new(DirectionalLightManager::new(&device, buffer_manager.get_mut()));
+    let point_light_manager = RwLock::new(PointLightManager::new(&device, buffer_manager.get_mut()));
+    let spot_light_manager = RwLock::new(SpotLightManager::new(&device, buffer_manager.get_mut()));
 
     span_transfer!(_ -> imgui_guard, INFO, "Creating Imgui Renderer");
 
@@ -260,7 +276,15 @@ pub async fn create_renderer<W: HasRawWindowHandle, TLD: 'static>(
 
     let render_list_cache = RwLock::new(RenderListCache::new());
 
-    let (culling_pass, gpu_copy) = futures::join!(culling_pass, gpu_copy);
+    let profiler = GpuProfiler::new(device.features());
+
+    let (culling_pass, gpu_copy, equirect_converter, mipmap_generator, ibl_manager) = futures::join!(
+        culling_pass,
+        gpu_copy,
+        equirect_converter,
+        mipmap_generator,
+        ibl_manager
+    );
 
     Ok(Arc::new(Renderer {
         yard: builder.yard.expect("The yard should be populated by the builder"),
@@ -283,12 +307,19 @@ pub async fn create_renderer<W: HasRawWindowHandle, TLD: 'static>(
         texture_manager_cube,
         material_manager,
         object_manager,
+        skeleton_manager,
         directional_light_manager,
+        point_light_manager,
+        spot_light_manager,
+        ibl_manager: RwLock::new(ibl_manager),
 
         render_list_cache,
 
         gpu_copy,
+        equirect_converter,
+        mipmap_generator,
         culling_pass,
+        profiler,
 
         // _imgui_renderer: imgui_renderer,
         options: RwLock::new(builder.options),
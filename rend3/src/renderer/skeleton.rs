@@ -0,0 +1,130 @@
+use crate::{
+    bind_merge::BindGroupBuilder,
+    datatypes::{Skeleton, SkeletonHandle},
+    registry::ResourceRegistry,
+    renderer::frustum::BoundingSphere,
+};
+use glam::Mat4;
+use std::{mem::size_of, sync::Arc};
+use wgpu::{BufferAddress, BufferUsage, CommandEncoder, Device};
+use wgpu_conveyor::{write_to_buffer1, AutomatedBuffer, AutomatedBufferManager, IdBuffer};
+
+/// Joint offset of objects without a skeleton. If you change this, change NO_SKELETON in skinning.glsl.
+pub const NO_SKELETON: u32 = u32::MAX;
+
+pub struct InternalSkeleton {
+    pub joint_matrices: Vec<Mat4>,
+    /// Index of the first joint matrix of this skeleton in the joint buffer.
+    pub joint_offset: u32,
+}
+impl InternalSkeleton {
+    /// Sphere enclosing the mesh in every pose the joints of the skeleton can move it to. Vertices are blends of
+    /// their joints, so they stay inside the spheres of the mesh moved by each joint.
+    pub fn bounding_sphere(&self, mesh_sphere: BoundingSphere) -> BoundingSphere {
+        let spheres: Vec<_> = self
+            .joint_matrices
+            .iter()
+            .map(|&joint| mesh_sphere.apply_transform(joint))
+            .collect();
+
+        BoundingSphere::enclosing(&spheres).unwrap_or(mesh_sphere)
+    }
+}
+
+pub struct SkeletonManager {
+    buffer_storage: Option<Arc<IdBuffer>>,
+    buffer: AutomatedBuffer,
+
+    registry: ResourceRegistry<InternalSkeleton>,
+}
+impl SkeletonManager {
+    pub fn new(device: &Device, buffer_manager: &mut AutomatedBufferManager) -> Self {
+        span_transfer!(_ -> new_span, INFO, "Creating Skeleton Manager");
+
+        let buffer = buffer_manager.create_new_buffer(device, 0, BufferUsage::STORAGE, Some("joint matrices"));
+
+        let registry = ResourceRegistry::new();
+
+        Self {
+            buffer_storage: None,
+            buffer,
+            registry,
+        }
+    }
+
+    pub fn allocate(&self) -> SkeletonHandle {
+        SkeletonHandle(self.registry.allocate())
+    }
+
+    pub fn fill(&mut self, handle: SkeletonHandle, skeleton: Skeleton) {
+        self.registry.insert(
+            handle.0,
+            InternalSkeleton {
+                joint_matrices: skeleton.joint_matrices,
+                joint_offset: 0,
+            },
+        );
+    }
+
+    pub fn set_joint_matrices(&mut self, handle: SkeletonHandle, joint_matrices: Vec<Mat4>) {
+        let skeleton = self.registry.get_mut(handle.0);
+        assert_eq!(
+            skeleton.joint_matrices.len(),
+            joint_matrices.len(),
+            "Joint count of a skeleton can't change"
+        );
+        skeleton.joint_matrices = joint_matrices;
+    }
+
+    pub fn remove(&mut self, handle: SkeletonHandle) {
+        self.registry.remove(handle.0);
+    }
+
+    /// Returns the amount of bytes uploaded.
+    pub fn ready(&mut self, device: &Device, encoder: &mut CommandEncoder) -> BufferAddress {
+        span_transfer!(_ -> ready_span, INFO, "Skeleton Manager Ready");
+
+        // Removals shuffle skeletons around, so reassign the offsets every frame.
+        let mut joint_count = 0;
+        for skeleton in self.registry.values_mut() {
+            skeleton.joint_offset = joint_count as u32;
+            joint_count += skeleton.joint_matrices.len();
+        }
+
+        let registry = &self.registry;
+
+        // Never empty, as the buffer is always bound.
+        let size = joint_count.max(1) * size_of::<Mat4>();
+        write_to_buffer1(
+            device,
+            encoder,
+            &mut self.buffer,
+            size as BufferAddress,
+            |_, raw_buffer| {
+                let buffer_body: &mut [Mat4] = bytemuck::cast_slice_mut(raw_buffer);
+
+                for skeleton in registry.values() {
+                    let start = skeleton.joint_offset as usize;
+                    buffer_body[start..start + skeleton.joint_matrices.len()].copy_from_slice(&skeleton.joint_matrices);
+                }
+            },
+        );
+
+        self.buffer_storage = Some(self.buffer.get_current_inner());
+
+        size as BufferAddress
+    }
+
+    /// The skeleton, or None if it has been removed.
+    pub fn try_get(&self, handle: SkeletonHandle) -> Option<&InternalSkeleton> {
+        self.registry.try_get(handle.0)
+    }
+
+    pub fn append_to_bgb<'a>(&'a self, builder: &mut BindGroupBuilder<'a>) {
+        builder.append(self.buffer_storage.as_ref().unwrap().inner.as_entire_binding());
+    }
+
+    pub fn count(&self) -> usize {
+        self.registry.count()
+    }
+}
@@ -105,6 +105,10 @@ impl TextureManager {
         self.registry.get_index_of(handle.0)
     }
 
+    pub fn count(&self) -> usize {
+        self.registry.count()
+    }
+
     pub(crate) fn ready(&mut self, device: &Device) -> TextureManagerReadyOutput {
         span_transfer!(_ -> ready_span, INFO, "Material Manager Ready");
 
@@ -1,4 +1,7 @@
-use crate::renderer::{camera::CameraManager, frustum::ShaderFrustum};
+use crate::{
+    renderer::{camera::CameraManager, frustum::ShaderFrustum},
+    ShadowFilter,
+};
 use glam::{Mat4, Vec4};
 use std::mem::size_of;
 use wgpu::{
@@ -15,11 +18,38 @@ pub struct ShaderCommonUniform {
     inv_origin_view_proj: Mat4,
     frustum: ShaderFrustum,
     ambient: Vec4,
+    shadow_filter: ShaderShadowFilter,
 }
 
 unsafe impl bytemuck::Zeroable for ShaderCommonUniform {}
 unsafe impl bytemuck::Pod for ShaderCommonUniform {}
 
+/// If you change this, change the SHADOW_FILTER defines in structures.glsl.
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+struct ShaderShadowFilter {
+    mode: u32,
+    /// Kernel size for pcf, sample count for poisson pcf and pcss.
+    samples: u32,
+    /// Radius in texels for poisson pcf, light size for pcss.
+    radius: f32,
+}
+
+unsafe impl bytemuck::Zeroable for ShaderShadowFilter {}
+unsafe impl bytemuck::Pod for ShaderShadowFilter {}
+
+impl From<ShadowFilter> for ShaderShadowFilter {
+    fn from(filter: ShadowFilter) -> Self {
+        let (mode, samples, radius) = match filter {
+            ShadowFilter::Hard => (0, 1, 0.0),
+            ShadowFilter::Pcf { kernel_size } => (1, kernel_size.clamp(1, 9), 0.0),
+            ShadowFilter::PoissonPcf { samples, radius } => (2, samples.clamp(1, 32), radius),
+            ShadowFilter::Pcss { light_size } => (3, 32, light_size),
+        };
+        Self { mode, samples, radius }
+    }
+}
+
 pub struct WrappedUniform {
     buffer: Buffer,
     pub uniform_bg: BindGroup,
@@ -47,7 +77,7 @@ impl WrappedUniform {
         Self { buffer, uniform_bg }
     }
 
-    pub fn upload<'a>(&'a self, queue: &Queue, camera: &CameraManager, ambient: Vec4) {
+    pub fn upload<'a>(&'a self, queue: &Queue, camera: &CameraManager, ambient: Vec4, shadow_filter: ShadowFilter) {
         span_transfer!(_ -> upload_span, WARN, "Uploading WrappedUniform");
 
         let view = camera.view();
@@ -59,6 +89,7 @@ impl WrappedUniform {
             inv_origin_view_proj: camera.origin_view_proj().inverse(),
             frustum: ShaderFrustum::from_matrix(camera.proj()),
             ambient,
+            shadow_filter: shadow_filter.into(),
         };
 
         queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&uniforms));
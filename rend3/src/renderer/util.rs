@@ -1,4 +1,9 @@
-use crate::{output::SWAPCHAIN_FORMAT, RendererMode, VSyncMode};
+use crate::{
+    datatypes::{SamplerAddressMode, SamplerFilter, SamplerOptions},
+    output::SWAPCHAIN_FORMAT,
+    renderer::sampler::MAX_GPU_SAMPLERS,
+    RendererMode, ShadowFilter, VSyncMode,
+};
 use std::num::NonZeroU8;
 use wgpu::{
     AddressMode, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType,
@@ -104,6 +109,34 @@ pub fn create_object_output_bgl(device: &Device) -> BindGroupLayout {
             entry(2, false),
             entry(3, false),
             entry(4, false),
+            entry(5, false),
+            entry(6, false),
+            entry(7, false),
+        ],
+    })
+}
+
+pub fn create_transparent_sort_bgl(device: &Device) -> BindGroupLayout {
+    let entry = BindGroupLayoutEntry {
+        binding: 0,
+        visibility: ShaderStage::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("transparent sort bgl"),
+        entries: &[
+            entry.clone(),
+            BindGroupLayoutEntry {
+                binding: 1,
+                ..entry.clone()
+            },
+            BindGroupLayoutEntry { binding: 2, ..entry },
         ],
     })
 }
@@ -146,16 +179,51 @@ pub fn create_general_bind_group_layout(device: &Device) -> BindGroupLayout {
 pub fn create_object_data_bgl(device: &Device) -> BindGroupLayout {
     device.create_bind_group_layout(&BindGroupLayoutDescriptor {
         label: Some("object data bgl"),
-        entries: &[BindGroupLayoutEntry {
-            binding: 0,
-            visibility: ShaderStage::VERTEX | ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
-            ty: BindingType::Buffer {
-                ty: BufferBindingType::Storage { read_only: true },
-                has_dynamic_offset: false,
-                min_binding_size: None,
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStage::VERTEX | ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             },
-            count: None,
-        }],
+            // Joint matrices
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStage::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Morph deltas
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStage::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Morph weights
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStage::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
     })
 }
 
@@ -225,12 +293,20 @@ pub fn create_material_bgl(device: &Device, mode: RendererMode) -> BindGroupLayo
                         },
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 11,
+                        visibility: ShaderStage::VERTEX | ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
+                        ty: BindingType::Sampler {
+                            filtering: true,
+                            comparison: false,
+                        },
+                        count: None,
+                    },
                 ],
             })
         }
-        RendererMode::GPUPowered => device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("material data bgl"),
-            entries: &[BindGroupLayoutEntry {
+        RendererMode::GPUPowered => {
+            let mut entries = vec![BindGroupLayoutEntry {
                 binding: 0,
                 visibility: ShaderStage::VERTEX | ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
                 ty: BindingType::Buffer {
@@ -239,8 +315,22 @@ pub fn create_material_bgl(device: &Device, mode: RendererMode) -> BindGroupLayo
                     min_binding_size: None,
                 },
                 count: None,
-            }],
-        }),
+            }];
+            // Every sampler of the table gets its own binding, as arrays of samplers aren't supported.
+            entries.extend((1..=MAX_GPU_SAMPLERS as u32).map(|binding| BindGroupLayoutEntry {
+                binding,
+                visibility: ShaderStage::VERTEX | ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
+                ty: BindingType::Sampler {
+                    filtering: true,
+                    comparison: false,
+                },
+                count: None,
+            }));
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("material data bgl"),
+                entries: &entries,
+            })
+        }
     }
 }
 
@@ -263,10 +353,12 @@ pub fn create_camera_data_bgl(device: &Device) -> BindGroupLayout {
 pub fn create_shadow_texture_bgl(device: &Device) -> BindGroupLayout {
     device.create_bind_group_layout(&BindGroupLayoutDescriptor {
         label: Some("shadow texture bgl"),
+        // Light buffers aren't visible to the vertex stage, leaving room there for the joint matrices within the
+        // storage buffer limit of cpu mode.
         entries: &[
             BindGroupLayoutEntry {
                 binding: 0,
-                visibility: ShaderStage::VERTEX | ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
+                visibility: ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
                 ty: BindingType::Buffer {
                     ty: BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
@@ -284,6 +376,46 @@ pub fn create_shadow_texture_bgl(device: &Device) -> BindGroupLayout {
                 },
                 count: None,
             },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStage::VERTEX | ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
+                ty: BindingType::Texture {
+                    view_dimension: TextureViewDimension::CubeArray,
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 5,
+                visibility: ShaderStage::VERTEX | ShaderStage::FRAGMENT | ShaderStage::COMPUTE,
+                ty: BindingType::Texture {
+                    view_dimension: TextureViewDimension::D2Array,
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    multisampled: false,
+                },
+                count: None,
+            },
         ],
     })
 }
@@ -304,29 +436,53 @@ pub fn create_skybox_bgl(device: &Device) -> BindGroupLayout {
     })
 }
 
+pub fn create_environment_bgl(device: &Device) -> BindGroupLayout {
+    let entry = |binding: u32, view_dimension: TextureViewDimension| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStage::FRAGMENT,
+        ty: BindingType::Texture {
+            view_dimension,
+            sample_type: TextureSampleType::Float { filterable: true },
+            multisampled: false,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("environment bgl"),
+        entries: &[
+            entry(0, TextureViewDimension::Cube),
+            entry(1, TextureViewDimension::Cube),
+            entry(2, TextureViewDimension::D2),
+        ],
+    })
+}
+
 #[allow(dead_code)]
 pub enum SamplerType {
     Nearest,
     Linear,
-    Shadow,
+    Shadow(ShadowFilter),
 }
 
 pub fn create_sampler(device: &Device, ty: SamplerType) -> Sampler {
     let filter = match ty {
         SamplerType::Nearest => FilterMode::Nearest,
         SamplerType::Linear => FilterMode::Linear,
-        SamplerType::Shadow => FilterMode::Linear,
+        // Every tap of the soft filters is bilinearly filtered by the hardware, which smooths out the kernel.
+        SamplerType::Shadow(ShadowFilter::Hard) => FilterMode::Nearest,
+        SamplerType::Shadow(_) => FilterMode::Linear,
     };
 
     let compare = match ty {
         SamplerType::Nearest | SamplerType::Linear => None,
-        SamplerType::Shadow => Some(CompareFunction::LessEqual),
+        SamplerType::Shadow(_) => Some(CompareFunction::LessEqual),
     };
 
     device.create_sampler(&SamplerDescriptor {
         label: Some(match ty {
             SamplerType::Linear => "linear sampler",
-            SamplerType::Shadow => "shadow sampler",
+            SamplerType::Shadow(_) => "shadow sampler",
             SamplerType::Nearest => "nearest sampler",
         }),
         address_mode_u: AddressMode::Repeat,
@@ -340,12 +496,51 @@ pub fn create_sampler(device: &Device, ty: SamplerType) -> Sampler {
         compare,
         anisotropy_clamp: match ty {
             SamplerType::Linear => NonZeroU8::new(16),
-            SamplerType::Shadow | SamplerType::Nearest => None,
+            SamplerType::Shadow(_) | SamplerType::Nearest => None,
         },
         border_color: None,
     })
 }
 
+pub fn create_material_sampler(device: &Device, options: SamplerOptions) -> Sampler {
+    let address_mode = |mode| match mode {
+        SamplerAddressMode::Repeat => AddressMode::Repeat,
+        SamplerAddressMode::MirrorRepeat => AddressMode::MirrorRepeat,
+        SamplerAddressMode::ClampToEdge => AddressMode::ClampToEdge,
+    };
+    let filter = |filter| match filter {
+        SamplerFilter::Nearest => FilterMode::Nearest,
+        SamplerFilter::Linear => FilterMode::Linear,
+    };
+
+    let all_linear = options.mag_filter == SamplerFilter::Linear
+        && options.min_filter == SamplerFilter::Linear
+        && options.mipmap_filter == SamplerFilter::Linear;
+    let anisotropy_clamp = match options.anisotropy {
+        _ if !all_linear => None,
+        0 | 1 => None,
+        2..=3 => NonZeroU8::new(2),
+        4..=7 => NonZeroU8::new(4),
+        8..=15 => NonZeroU8::new(8),
+        _ => NonZeroU8::new(16),
+    };
+
+    device.create_sampler(&SamplerDescriptor {
+        label: Some("material sampler"),
+        address_mode_u: address_mode(options.address_mode_u),
+        address_mode_v: address_mode(options.address_mode_v),
+        address_mode_w: AddressMode::Repeat,
+        mag_filter: filter(options.mag_filter),
+        min_filter: filter(options.min_filter),
+        mipmap_filter: filter(options.mipmap_filter),
+        lod_min_clamp: -100.0,
+        lod_max_clamp: 100.0,
+        compare: None,
+        anisotropy_clamp,
+        border_color: None,
+    })
+}
+
 // Take a value and round it upwards to x * multiple, like memory address alignment
 pub(crate) fn round_to_multiple(value: u32, multiple: u32) -> u32 {
     // We could also use a faster version that only works for multiples that are pow2
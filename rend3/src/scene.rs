@@ -0,0 +1,418 @@
+//! Optional transform hierarchy on top of the renderer.
+//!
+//! The renderer only knows about the world transform of each object. A [`SceneGraph`] holds a tree of nodes with
+//! local transforms, and objects and lights attached to them. Moving a node marks it dirty, and
+//! [`SceneGraph::update`] recomputes the world transforms of all dirty nodes and their descendants, sending the new
+//! transforms of their objects to the renderer in one batch.
+
+use crate::{
+    datatypes::{
+        AffineTransform, DirectionalLightChange, DirectionalLightHandle, ObjectHandle, PointLightChange,
+        PointLightHandle, SpotLightChange, SpotLightHandle,
+    },
+    registry::ResourceRegistry,
+    Renderer,
+};
+use glam::{Mat4, Vec3};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct NodeHandle(usize);
+
+impl NodeHandle {
+    pub fn get(&self) -> usize {
+        self.0
+    }
+}
+
+/// Light attached to a node. Lights sit at the origin of the node and shine down its -Z axis.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NodeLight {
+    Directional(DirectionalLightHandle),
+    Point(PointLightHandle),
+    Spot(SpotLightHandle),
+}
+
+struct SceneNode {
+    parent: Option<NodeHandle>,
+    children: Vec<NodeHandle>,
+    local_transform: Mat4,
+    world_transform: Mat4,
+    objects: Vec<ObjectHandle>,
+    lights: Vec<NodeLight>,
+    /// The world transform of this node and all its descendants needs to be recomputed.
+    dirty: bool,
+    /// Some descendant of this node is dirty.
+    child_dirty: bool,
+}
+
+#[derive(Default)]
+struct PendingChanges {
+    objects: Vec<(ObjectHandle, AffineTransform)>,
+    lights: Vec<(NodeLight, Mat4)>,
+}
+
+pub struct SceneGraph {
+    registry: ResourceRegistry<SceneNode>,
+    roots: Vec<NodeHandle>,
+}
+
+impl Default for SceneGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self {
+            registry: ResourceRegistry::new(),
+            roots: Vec::new(),
+        }
+    }
+
+    /// Adds a node under `parent`, or as a root if there is no parent.
+    pub fn add_node(&mut self, parent: Option<NodeHandle>, local_transform: Mat4) -> NodeHandle {
+        let handle = NodeHandle(self.registry.allocate());
+        self.registry.insert(
+            handle.0,
+            SceneNode {
+                parent: None,
+                children: Vec::new(),
+                local_transform,
+                world_transform: local_transform,
+                objects: Vec::new(),
+                lights: Vec::new(),
+                dirty: false,
+                child_dirty: false,
+            },
+        );
+        self.link(handle, parent);
+        self.mark_dirty(handle);
+        handle
+    }
+
+    /// Removes the node and all its descendants. Attached objects and lights are left in the renderer at their last
+    /// transform.
+    pub fn remove_node(&mut self, handle: NodeHandle) {
+        self.unlink(handle);
+        self.remove_subtree(handle);
+    }
+
+    fn remove_subtree(&mut self, handle: NodeHandle) {
+        let (_, node) = self.registry.remove(handle.0);
+        for child in node.children {
+            self.remove_subtree(child);
+        }
+    }
+
+    /// Moves the node and its descendants under a new parent, or makes it a root if there is no parent.
+    ///
+    /// # Panic
+    ///
+    /// Will panic if the new parent is the node or one of its descendants.
+    pub fn set_parent(&mut self, handle: NodeHandle, parent: Option<NodeHandle>) {
+        let mut ancestor = parent;
+        while let Some(current) = ancestor {
+            assert_ne!(current, handle, "Node can't be its own ancestor");
+            ancestor = self.registry.get(current.0).parent;
+        }
+
+        self.unlink(handle);
+        self.link(handle, parent);
+        self.mark_dirty(handle);
+    }
+
+    pub fn parent(&self, handle: NodeHandle) -> Option<NodeHandle> {
+        self.registry.get(handle.0).parent
+    }
+
+    pub fn children(&self, handle: NodeHandle) -> &[NodeHandle] {
+        &self.registry.get(handle.0).children
+    }
+
+    pub fn roots(&self) -> &[NodeHandle] {
+        &self.roots
+    }
+
+    pub fn set_local_transform(&mut self, handle: NodeHandle, local_transform: Mat4) {
+        self.registry.get_mut(handle.0).local_transform = local_transform;
+        self.mark_dirty(handle);
+    }
+
+    pub fn local_transform(&self, handle: NodeHandle) -> Mat4 {
+        self.registry.get(handle.0).local_transform
+    }
+
+    /// World transform as of the last [`SceneGraph::update`].
+    pub fn world_transform(&self, handle: NodeHandle) -> Mat4 {
+        self.registry.get(handle.0).world_transform
+    }
+
+    /// Attaches an object to the node. The object's transform is replaced by the node's world transform on the next
+    /// [`SceneGraph::update`].
+    pub fn attach_object(&mut self, handle: NodeHandle, object: ObjectHandle) {
+        self.registry.get_mut(handle.0).objects.push(object);
+        self.mark_dirty(handle);
+    }
+
+    pub fn detach_object(&mut self, handle: NodeHandle, object: ObjectHandle) {
+        self.registry.get_mut(handle.0).objects.retain(|&o| o != object);
+    }
+
+    pub fn objects(&self, handle: NodeHandle) -> &[ObjectHandle] {
+        &self.registry.get(handle.0).objects
+    }
+
+    /// Attaches a light to the node. The light's position and direction are replaced on the next
+    /// [`SceneGraph::update`].
+    pub fn attach_light(&mut self, handle: NodeHandle, light: NodeLight) {
+        self.registry.get_mut(handle.0).lights.push(light);
+        self.mark_dirty(handle);
+    }
+
+    pub fn detach_light(&mut self, handle: NodeHandle, light: NodeLight) {
+        self.registry.get_mut(handle.0).lights.retain(|&l| l != light);
+    }
+
+    pub fn lights(&self, handle: NodeHandle) -> &[NodeLight] {
+        &self.registry.get(handle.0).lights
+    }
+
+    /// Recomputes the world transforms of every node that moved since the last update, and of their descendants, then
+    /// sends the transforms of their objects and lights to the renderer.
+    pub fn update<TLD>(&mut self, renderer: &Renderer<TLD>)
+    where
+        TLD: 'static,
+    {
+        span_transfer!(_ -> update_span, INFO, "Scene Graph Update");
+
+        let changes = self.update_transforms();
+
+        renderer.set_object_transforms(changes.objects);
+
+        for (light, transform) in changes.lights {
+            let position = transform.transform_point3(Vec3::ZERO);
+            let direction = transform.transform_vector3(-Vec3::Z).normalize();
+            match light {
+                NodeLight::Directional(handle) => renderer.update_directional_light(
+                    handle,
+                    DirectionalLightChange {
+                        direction: Some(direction),
+                        ..DirectionalLightChange::default()
+                    },
+                ),
+                NodeLight::Point(handle) => renderer.update_point_light(
+                    handle,
+                    PointLightChange {
+                        position: Some(position),
+                        ..PointLightChange::default()
+                    },
+                ),
+                NodeLight::Spot(handle) => renderer.update_spot_light(
+                    handle,
+                    SpotLightChange {
+                        position: Some(position),
+                        direction: Some(direction),
+                        ..SpotLightChange::default()
+                    },
+                ),
+            }
+        }
+    }
+
+    /// Recomputes the world transforms of all dirty nodes, returning the changed transforms of their objects and lights.
+    fn update_transforms(&mut self) -> PendingChanges {
+        let mut changes = PendingChanges::default();
+        for idx in 0..self.roots.len() {
+            let root = self.roots[idx];
+            self.update_node(root, Mat4::IDENTITY, false, &mut changes);
+        }
+        changes
+    }
+
+    fn update_node(
+        &mut self,
+        handle: NodeHandle,
+        parent_transform: Mat4,
+        parent_changed: bool,
+        changes: &mut PendingChanges,
+    ) {
+        let node = self.registry.get_mut(handle.0);
+        let changed = parent_changed || node.dirty;
+        let visit_children = changed || node.child_dirty;
+        node.dirty = false;
+        node.child_dirty = false;
+
+        if changed {
+            let transform = parent_transform * node.local_transform;
+            node.world_transform = transform;
+            changes.objects.extend(
+                node.objects
+                    .iter()
+                    .map(|&object| (object, AffineTransform { transform })),
+            );
+            changes
+                .lights
+                .extend(node.lights.iter().map(|&light| (light, transform)));
+        }
+
+        if visit_children {
+            let world_transform = node.world_transform;
+            // Taken out so the children can borrow the registry mutably.
+            let children = std::mem::take(&mut node.children);
+            for &child in &children {
+                self.update_node(child, world_transform, changed, changes);
+            }
+            self.registry.get_mut(handle.0).children = children;
+        }
+    }
+
+    fn mark_dirty(&mut self, handle: NodeHandle) {
+        self.registry.get_mut(handle.0).dirty = true;
+
+        // Mark the path from the root, so the update only walks the branches with dirty nodes.
+        let mut ancestor = self.registry.get(handle.0).parent;
+        while let Some(current) = ancestor {
+            let node = self.registry.get_mut(current.0);
+            if node.child_dirty {
+                break;
+            }
+            node.child_dirty = true;
+            ancestor = node.parent;
+        }
+    }
+
+    fn link(&mut self, handle: NodeHandle, parent: Option<NodeHandle>) {
+        self.registry.get_mut(handle.0).parent = parent;
+        match parent {
+            Some(parent) => self.registry.get_mut(parent.0).children.push(handle),
+            None => self.roots.push(handle),
+        }
+    }
+
+    fn unlink(&mut self, handle: NodeHandle) {
+        let siblings = match self.registry.get(handle.0).parent {
+            Some(parent) => &mut self.registry.get_mut(parent.0).children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|&sibling| sibling != handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(x: f32) -> Mat4 {
+        Mat4::from_translation(Vec3::new(x, 0.0, 0.0))
+    }
+
+    /// Objects whose transform changed in the update, with their new x translation.
+    fn changed_objects(graph: &mut SceneGraph) -> Vec<(usize, f32)> {
+        let mut objects: Vec<_> = graph
+            .update_transforms()
+            .objects
+            .into_iter()
+            .map(|(object, transform)| (object.get(), transform.transform.w_axis.x))
+            .collect();
+        objects.sort_by_key(|&(object, _)| object);
+        objects
+    }
+
+    #[test]
+    fn dirty_propagates_to_descendants() {
+        let mut graph = SceneGraph::new();
+        let parent = graph.add_node(None, translation(1.0));
+        let child = graph.add_node(Some(parent), translation(2.0));
+        graph.attach_object(parent, ObjectHandle(0));
+        graph.attach_object(child, ObjectHandle(1));
+
+        assert_eq!(changed_objects(&mut graph), vec![(0, 1.0), (1, 3.0)]);
+        // Nothing moved since the last update.
+        assert!(changed_objects(&mut graph).is_empty());
+
+        graph.set_local_transform(parent, translation(5.0));
+        assert_eq!(changed_objects(&mut graph), vec![(0, 5.0), (1, 7.0)]);
+        assert_eq!(graph.world_transform(child), translation(7.0));
+    }
+
+    #[test]
+    fn reparenting_moves_subtree() {
+        let mut graph = SceneGraph::new();
+        let first = graph.add_node(None, translation(1.0));
+        let second = graph.add_node(None, translation(10.0));
+        let node = graph.add_node(Some(first), translation(2.0));
+        let child = graph.add_node(Some(node), translation(3.0));
+        graph.attach_object(child, ObjectHandle(0));
+        changed_objects(&mut graph);
+
+        graph.set_parent(node, Some(second));
+        assert_eq!(graph.parent(node), Some(second));
+        assert!(graph.children(first).is_empty());
+        assert_eq!(graph.children(second), &[node]);
+        assert_eq!(changed_objects(&mut graph), vec![(0, 15.0)]);
+
+        graph.set_parent(node, None);
+        assert_eq!(graph.roots(), &[first, second, node]);
+        assert_eq!(changed_objects(&mut graph), vec![(0, 5.0)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Node can't be its own ancestor")]
+    fn reparenting_under_descendant_panics() {
+        let mut graph = SceneGraph::new();
+        let parent = graph.add_node(None, Mat4::IDENTITY);
+        let child = graph.add_node(Some(parent), Mat4::IDENTITY);
+
+        graph.set_parent(parent, Some(child));
+    }
+
+    #[test]
+    fn removing_node_removes_subtree() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node(None, translation(1.0));
+        let removed = graph.add_node(Some(root), translation(2.0));
+        let removed_child = graph.add_node(Some(removed), translation(3.0));
+        let kept = graph.add_node(Some(root), translation(4.0));
+        graph.attach_object(removed_child, ObjectHandle(0));
+        graph.attach_object(kept, ObjectHandle(1));
+        changed_objects(&mut graph);
+
+        // Removing a dirty subtree mustn't leave anything behind for the update to visit.
+        graph.set_local_transform(removed_child, translation(6.0));
+        graph.remove_node(removed);
+        assert_eq!(graph.registry.count(), 2);
+        assert_eq!(graph.children(root), &[kept]);
+        assert!(changed_objects(&mut graph).is_empty());
+
+        graph.set_local_transform(root, translation(0.0));
+        assert_eq!(changed_objects(&mut graph), vec![(1, 4.0)]);
+    }
+
+    #[test]
+    fn deep_change_only_updates_its_branch() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node(None, translation(1.0));
+        let sibling = graph.add_node(Some(root), translation(1.0));
+        let mut deepest = root;
+        for _ in 0..4 {
+            deepest = graph.add_node(Some(deepest), translation(1.0));
+        }
+        let leaf = graph.add_node(Some(deepest), translation(1.0));
+        graph.attach_object(root, ObjectHandle(0));
+        graph.attach_object(sibling, ObjectHandle(1));
+        graph.attach_object(deepest, ObjectHandle(2));
+        graph.attach_object(leaf, ObjectHandle(3));
+        assert_eq!(
+            changed_objects(&mut graph),
+            vec![(0, 1.0), (1, 2.0), (2, 5.0), (3, 6.0)]
+        );
+
+        graph.set_local_transform(deepest, translation(2.0));
+        assert_eq!(changed_objects(&mut graph), vec![(2, 6.0), (3, 7.0)]);
+
+        // The path to the change is clean again, so the next update has nothing to do.
+        assert!(graph.registry.values().all(|node| !node.dirty && !node.child_dirty));
+        assert!(changed_objects(&mut graph).is_empty());
+    }
+}
@@ -1 +1,124 @@
-pub struct RendererStatistics {}
+use crate::datatypes::PipelineHandle;
+use std::time::{Duration, Instant};
+
+/// Statistics about a single culling pass and all the render passes that used its output.
+#[derive(Debug, Clone, Default)]
+pub struct PassStatistics {
+    /// Name of the pass, "camera pass", "shadow pass {light index} cascade {cascade}",
+    /// "spot shadow pass {light index}" or "point shadow pass {light index} face {face}".
+    pub name: String,
+    /// Object instances considered for culling.
+    pub objects_submitted: usize,
+    /// Object instances removed by culling. Culling happens on the gpu in gpu mode, so this is only known in cpu mode.
+    pub objects_culled: Option<usize>,
+    /// Draw calls recorded. Each indirect multi-draw in gpu mode counts as a single draw call.
+    pub draw_calls: usize,
+}
+
+/// Cpu time spent in a single span of the render loop.
+#[derive(Debug, Clone)]
+pub struct SpanTiming {
+    pub name: &'static str,
+    pub duration: Duration,
+}
+
+/// What a gpu timing measured.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GpuTimingSource {
+    /// The gpu culling compute passes.
+    Culling,
+    /// A single render op. `render_pass` indexes the passes of the render list and `op` the ops of that pass.
+    RenderOp {
+        render_pass: usize,
+        op: usize,
+        pipeline: PipelineHandle,
+    },
+}
+
+/// Gpu time spent on a single part of a pass.
+#[derive(Debug, Clone)]
+pub struct GpuTiming {
+    /// Index into [`RendererStatistics::passes`] of the frame these timings came from.
+    pub pass: usize,
+    pub source: GpuTimingSource,
+    pub duration: Duration,
+}
+
+/// All gpu timings of a single frame.
+#[derive(Debug, Clone)]
+pub struct GpuFrameTimings {
+    /// Matches [`RendererStatistics::frame`] of the frame these timings came from.
+    pub frame: u64,
+    pub timings: Vec<GpuTiming>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RendererStatistics {
+    /// Index of this frame, counting up from zero.
+    pub frame: u64,
+    /// A pass for every shadow map, directional lights having one for every cascade and point lights one for every
+    /// cube face, followed by the camera pass.
+    pub passes: Vec<PassStatistics>,
+    pub instructions_processed: usize,
+
+    pub mesh_count: usize,
+    pub texture_2d_count: usize,
+    pub texture_cube_count: usize,
+    pub material_count: usize,
+    pub object_count: usize,
+    /// Instances across all objects. Regular objects count as one instance.
+    pub instance_count: usize,
+    pub directional_light_count: usize,
+    pub point_light_count: usize,
+    pub spot_light_count: usize,
+    pub skeleton_count: usize,
+
+    /// Bytes of mesh, texture, object, material and light data uploaded to the gpu this frame.
+    pub bytes_uploaded: u64,
+    /// Amount of times the mesh buffers were reallocated this frame.
+    pub buffer_reallocations: usize,
+
+    /// Cpu timings of each span of the render loop, in the order they ran.
+    pub timings: Vec<SpanTiming>,
+    /// Gpu timings of previous frames which finished since the last frame. The gpu results take a while to come
+    /// back, so these are always from earlier frames. Always empty if the adapter doesn't support
+    /// [`TIMESTAMP_QUERY`](wgpu::Features::TIMESTAMP_QUERY).
+    pub gpu_timings: Vec<GpuFrameTimings>,
+}
+impl RendererStatistics {
+    /// Sum of draw calls over all passes.
+    pub fn draw_calls(&self) -> usize {
+        self.passes.iter().map(|p| p.draw_calls).sum()
+    }
+}
+
+/// Times consecutive spans of the render loop.
+pub(crate) struct SpanTimer {
+    current: Option<(&'static str, Instant)>,
+    timings: Vec<SpanTiming>,
+}
+impl SpanTimer {
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            timings: Vec::new(),
+        }
+    }
+
+    /// Ends the current span, if any, and starts timing the given span.
+    pub fn transfer(&mut self, next: Option<&'static str>) {
+        let now = Instant::now();
+        if let Some((name, start)) = self.current.take() {
+            self.timings.push(SpanTiming {
+                name,
+                duration: now - start,
+            });
+        }
+        self.current = next.map(|name| (name, now));
+    }
+
+    pub fn finish(mut self) -> Vec<SpanTiming> {
+        self.transfer(None);
+        self.timings
+    }
+}